@@ -98,8 +98,37 @@ fn generate_manpages(out_dir: PathBuf) -> std::io::Result<()> {
 
     generate_command_manpage(crunchy_cli_core::Cli::command(), &out_dir, "")?;
     generate_command_manpage(crunchy_cli_core::Archive::command(), &out_dir, "archive")?;
+    generate_command_manpage(
+        crunchy_cli_core::Benchmark::command(),
+        &out_dir,
+        "benchmark",
+    )?;
+    generate_command_manpage(
+        crunchy_cli_core::Crunchylist::command(),
+        &out_dir,
+        "crunchylist",
+    )?;
     generate_command_manpage(crunchy_cli_core::Download::command(), &out_dir, "download")?;
+    generate_command_manpage(crunchy_cli_core::Episodes::command(), &out_dir, "episodes")?;
+    generate_command_manpage(crunchy_cli_core::Feed::command(), &out_dir, "feed")?;
+    generate_command_manpage(crunchy_cli_core::Info::command(), &out_dir, "info")?;
     generate_command_manpage(crunchy_cli_core::Login::command(), &out_dir, "login")?;
+    generate_command_manpage(crunchy_cli_core::Logout::command(), &out_dir, "logout")?;
+    generate_command_manpage(crunchy_cli_core::Play::command(), &out_dir, "play")?;
+    generate_command_manpage(crunchy_cli_core::Search::command(), &out_dir, "search")?;
+    generate_command_manpage(crunchy_cli_core::Seasons::command(), &out_dir, "seasons")?;
+    generate_command_manpage(crunchy_cli_core::Session::command(), &out_dir, "session")?;
+    generate_command_manpage(
+        crunchy_cli_core::Speedtest::command(),
+        &out_dir,
+        "speedtest",
+    )?;
+    generate_command_manpage(crunchy_cli_core::Watch::command(), &out_dir, "watch")?;
+    generate_command_manpage(
+        crunchy_cli_core::Watchlist::command(),
+        &out_dir,
+        "watchlist",
+    )?;
 
     Ok(())
 }
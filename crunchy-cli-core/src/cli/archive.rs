@@ -1,20 +1,40 @@
-use crate::cli::log::tab_info;
-use crate::cli::utils::{download_segments, find_resolution, FFmpegPreset};
+use crate::cli::log::{console_level, tab_info};
+use crate::cli::utils::{
+    batch_summary, download_segments, download_subtitle, download_video, episode_summary,
+    escape_filter_path, fallback_variant, find_resolution, region_error, AudioCodec, EncodePreset,
+    FFmpegOptions, FFmpegPreset, FFmpegProgress, ResolutionPolicy, VariantCriteria,
+    FFMPEG_PROGRESS_ARGS,
+};
+use crate::utils::cancel::PartialFile;
+use crate::utils::chapters::{fetch_chapters, ffmpeg_chapters_file};
+use crate::utils::clap::{
+    clap_parse_bandwidth, clap_parse_bitrate, clap_parse_ffmpeg_args, clap_parse_resolution,
+};
+use crate::utils::config::{apply_config, ArchiveConfig};
 use crate::utils::context::Context;
-use crate::utils::format::{format_string, Format};
+use crate::utils::cover::CoverArt;
+use crate::utils::download_archive::{ArchiveFormat, DownloadArchive};
+use crate::utils::filter::{is_released_between, skip_special};
+use crate::utils::format::{check_format_string, format_path, output_template, Format};
+use crate::utils::hook::{batch_env, episode_env, run_hook};
 use crate::utils::log::progress;
-use crate::utils::os::{free_file, has_ffmpeg, is_special_file, tempfile};
-use crate::utils::parse::{parse_url, UrlFilter};
+use crate::utils::nfo::write_nfo;
+use crate::utils::notify::Notification;
+use crate::utils::os::{
+    check_free_space, free_file, has_ffmpeg, is_special_file, part_file, OverwritePolicy,
+};
+use crate::utils::parse::{parse_ffmpeg_args, parse_url, UrlFilter};
+use crate::utils::progress::{emit_progress, ProgressEvent, ProgressFormat, TransferSummary};
+use crate::utils::sanitize::{Normalization, SanitizeMode, Sanitizer};
 use crate::utils::sort::{sort_formats_after_seasons, sort_seasons_after_number};
 use crate::Execute;
-use anyhow::{bail, Result};
-use chrono::NaiveTime;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, NaiveTime, Utc};
+use clap::ArgMatches;
 use crunchyroll_rs::media::{Resolution, StreamSubtitle};
 use crunchyroll_rs::{Locale, Media, MediaCollection, Series};
 use log::{debug, error, info, warn};
-use regex::Regex;
 use std::collections::BTreeMap;
-use std::io::Write;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use tempfile::TempPath;
@@ -56,23 +76,76 @@ pub struct Archive {
     subtitle: Vec<Locale>,
 
     #[arg(help = "Name of the output file")]
-    #[arg(long_help = "Name of the output file.\
+    #[arg(long_help = "Name of the output file. \
+    May contain directories (e.g. '{series}/Season {season:02}/{title}.mkv') which are created if they do not exist. \
     If you use one of the following pattern they will get replaced:\n  \
       {title}                   → Title of the video\n  \
-      {series_name}             → Name of the series\n  \
-      {season_name}             → Name of the season\n  \
+      {series} / {series_name}  → Name of the series\n  \
+      {season_name} / {season_title} → Name of the season\n  \
       {audio}                   → Audio language of the video\n  \
       {resolution}              → Resolution of the video\n  \
       {padded_season_number}    → Number of the season padded to double digits\n  \
-      {season_number}           → Number of the season\n  \
+      {season} / {season_number} → Number of the season\n  \
       {padded_episode_number}   → Number of the episode padded to double digits\n  \
-      {episode_number}          → Number of the episode\n  \
+      {episode} / {episode_number} → Number of the episode\n  \
       {series_id}               → ID of the series\n  \
       {season_id}               → ID of the season\n  \
-      {episode_id}              → ID of the episode")]
+      {episode_id}              → ID of the episode\n\
+    Numbers can be padded with zeros by appending ':0' and the width to a pattern, e.g. {episode:02} or {episode:03}")]
     #[arg(short, long, default_value = "{title}.mkv")]
     output: String,
 
+    #[arg(help = "Directory the output file is put into")]
+    #[arg(
+        long_help = "Directory the output file is put into, e.g. '{series}/{season_title}'. \
+    Supports the same patterns as '--output' and missing directories are created. \
+    The output file name is relative to this directory"
+    )]
+    #[arg(long)]
+    output_dir_template: Option<String>,
+    #[arg(help = "Directory specials are put into instead of '--output-dir-template'")]
+    #[arg(
+        long_help = "Directory specials (recaps, PVs, OVAs, ...) are put into instead of '--output-dir-template', e.g. '{series}/Specials'. \
+    Supports the same patterns as '--output'"
+    )]
+    #[arg(long)]
+    specials_dir: Option<String>,
+    #[arg(
+        help = "Characters which are replaced in file names. Valid modes are 'windows', 'posix' and 'minimal'"
+    )]
+    #[arg(
+        long_help = "Characters which are replaced in the values inserted into the output file name. Valid modes are: \
+    'windows' (the characters ':?\"<>|*\\/' and control characters, trailing dots and spaces and reserved names like 'CON'), \
+    'posix' ('/' and the null character) and \
+    'minimal' ('/' and '\\'). \
+    Use 'windows' if the files should stay copyable to Windows or SMB shares"
+    )]
+    #[arg(long, default_value = "windows", value_parser = SanitizeMode::parse)]
+    sanitize: SanitizeMode,
+    #[arg(
+        help = "Text which replaces invalid characters in file names. Default is to remove them"
+    )]
+    #[arg(long, default_value = "")]
+    sanitize_replacement: String,
+    #[arg(
+        help = "Unicode normalization form of file names. Valid forms are 'nfc', 'nfd', 'nfkc' and 'nfkd'"
+    )]
+    #[arg(
+        long_help = "Unicode normalization form of the values inserted into the output file name. Valid forms are 'nfc', 'nfd', 'nfkc' and 'nfkd'. \
+    macOS stores file names as 'nfd', most other systems as 'nfc'. \
+    'nfkc' and 'nfkd' additionally replace full-width latin letters and digits with their ascii counterparts"
+    )]
+    #[arg(long, value_parser = Normalization::parse)]
+    normalize: Option<Normalization>,
+    #[arg(help = "Transliterate file names to ascii")]
+    #[arg(
+        long_help = "Transliterate the values inserted into the output file name to ascii. \
+    Kana are converted to romaji, other characters to their closest ascii representation. \
+    Note that kanji are transliterated by their chinese reading since they have no unambiguous japanese one"
+    )]
+    #[arg(long)]
+    ascii_filenames: bool,
+
     #[arg(help = "Video resolution")]
     #[arg(long_help = "The video resolution.\
     Can either be specified via the pixels (e.g. 1920x1080), the abbreviation for pixels (e.g. 1080p) or 'common-use' words (e.g. best). \
@@ -83,6 +156,58 @@ pub struct Archive {
     #[arg(value_parser = crate::utils::clap::clap_parse_resolution)]
     resolution: Resolution,
 
+    #[arg(help = "What to do if the requested resolution is not available. \
+    Valid policies are 'exact', 'best-below' and 'nearest'")]
+    #[arg(
+        long_help = "What to do if the requested resolution is not available. \
+    'exact' (default) fails, 'best-below' uses the highest resolution below the requested one and 'nearest' uses the resolution closest to the requested one. \
+    The resolution which is used instead is logged"
+    )]
+    #[arg(long, default_value = "exact")]
+    #[arg(value_parser = ResolutionPolicy::parse)]
+    resolution_policy: ResolutionPolicy,
+
+    #[arg(help = "Maximum bitrate of the video stream")]
+    #[arg(
+        long_help = "Maximum bitrate of the video stream (per second). \
+    Can be specified as plain bits or with a unit suffix (e.g. 3M or 500k). \
+    If multiple streams have the requested resolution, the one with the highest bitrate below this limit is used. \
+    If no stream is below the limit, the one with the lowest bitrate is used"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_bandwidth)]
+    max_bitrate: Option<u64>,
+
+    #[arg(help = "Download segments which keep failing from a lower resolution stream")]
+    #[arg(
+        long_help = "Download segments which keep failing from a lower resolution stream. \
+    Segments which fail are retried after all other segments are downloaded. \
+    If a segment still fails then, it's taken from the next lower resolution stream instead of failing the whole download. \
+    The video briefly has a lower quality at this position"
+    )]
+    #[arg(long, default_value_t = false)]
+    pub(crate) segment_fallback: bool,
+
+    #[arg(
+        help = "Download the rest of the stream from a lower resolution stream after this many segments failed in a row"
+    )]
+    #[arg(
+        long_help = "Download the rest of the stream from a lower resolution stream after this many segments failed in a row. \
+    Useful if the cdn has problems with one resolution: instead of retrying every segment and failing the download, everything from the current position on is downloaded from the next lower resolution stream. \
+    Segments which failed before are taken from the lower resolution stream too (like with '--segment-fallback')"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) downgrade_after: Option<u32>,
+
+    #[arg(help = "Preferred framerate of the video stream, e.g. 30 or 60")]
+    #[arg(
+        long_help = "Preferred framerate of the video stream, e.g. 30 or 60. \
+    If multiple streams have the requested resolution, the one with the closest framerate is used"
+    )]
+    #[arg(long)]
+    fps: Option<u32>,
+
     #[arg(
         help = "Sets the behavior of the stream merging. Valid behaviors are 'auto', 'audio' and 'video'"
     )]
@@ -104,6 +229,59 @@ pub struct Archive {
     #[arg(long)]
     #[arg(value_parser = FFmpegPreset::parse)]
     ffmpeg_preset: Vec<FFmpegPreset>,
+    #[arg(help = "Device which is used for hardware acceleration")]
+    #[arg(long_help = "Device which is used for hardware acceleration. \
+    Only used by the 'vaapi' (defaults to '/dev/dri/renderD128') and 'qsv' presets")]
+    #[arg(long)]
+    hwaccel_device: Option<String>,
+    #[arg(help = "Constant quality of the encoded video, lower values mean better quality")]
+    #[arg(
+        long_help = "Constant quality of the encoded video, lower values mean better quality. \
+    Requires a codec preset. It is translated to the quality option of the used encoder (crf for software encoders, cq for nvidia, qp for vaapi and global_quality for qsv). \
+    Sensible values are around 18-28 for h264 / h265 and 25-35 for av1"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = clap::value_parser!(u8).range(0..=63))]
+    crf: Option<u8>,
+    #[arg(help = "Bitrate of the encoded video (e.g. 5M or 2500k)")]
+    #[arg(long_help = "Bitrate of the encoded video (e.g. 5M or 2500k). \
+    Requires a codec preset and cannot be used together with '--crf'")]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_bitrate)]
+    video_bitrate: Option<String>,
+    #[arg(help = "Encoding speed. Valid presets are 'slow', 'medium' and 'fast'")]
+    #[arg(
+        long_help = "Encoding speed. Valid presets are 'slow', 'medium' and 'fast'. \
+    Slower presets result in smaller files with the same quality. Requires a codec preset"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = EncodePreset::parse)]
+    encode_preset: Option<EncodePreset>,
+    #[arg(help = "Audio codec. Valid codecs are 'aac', 'opus' and 'copy'")]
+    #[arg(long_help = "Audio codec. Valid codecs are 'aac', 'opus' and 'copy'. \
+    By default, the audio is copied without re-encoding it ('copy'). \
+    Re-encoding the audio, e.g. with opus and a lower bitrate, can noticeably reduce the file size")]
+    #[arg(long)]
+    #[arg(value_parser = AudioCodec::parse)]
+    audio_codec: Option<AudioCodec>,
+    #[arg(help = "Bitrate of the encoded audio (e.g. 128k). Requires '--audio-codec'")]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_bitrate)]
+    audio_bitrate: Option<String>,
+    #[arg(help = "Additional ffmpeg input arguments")]
+    #[arg(long_help = "Additional ffmpeg input arguments. \
+    They are added after the arguments of the ffmpeg presets and before the input video. \
+    Arguments containing whitespaces must be quoted, e.g. --ffmpeg-input-args=\"-hwaccel_flags allow_profile_mismatch\"")]
+    #[arg(long, allow_hyphen_values = true)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_ffmpeg_args)]
+    ffmpeg_input_args: Option<String>,
+    #[arg(help = "Additional ffmpeg output arguments")]
+    #[arg(long_help = "Additional ffmpeg output arguments. \
+    They are added after all other output arguments, so they can overwrite them. \
+    Arguments containing whitespaces must be quoted, e.g. --ffmpeg-output-args=\"-pix_fmt yuv420p10le -tune animation\"")]
+    #[arg(long, allow_hyphen_values = true)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_ffmpeg_args)]
+    ffmpeg_output_args: Option<String>,
 
     #[arg(
         help = "Set which subtitle language should be set as default / auto shown when starting a video"
@@ -118,11 +296,357 @@ pub struct Archive {
     )]
     #[arg(long)]
     no_subtitle_optimizations: bool,
+    #[arg(help = "Burn the subtitle of the given language into the video")]
+    #[arg(
+        long_help = "Burn the subtitle of the given language into the video so that it cannot be disabled. \
+    Useful for devices which cannot render ass subtitles. \
+    The video must be re-encoded for this, if no codec preset is given via '--ffmpeg-preset' the h264 codec is used"
+    )]
+    #[arg(long)]
+    hardsub: Option<Locale>,
+
+    #[arg(help = "Add chapters (intro, episode, ending, ...) to the output file")]
+    #[arg(
+        long_help = "Add chapters (recap, intro, episode, ending and preview) to the output file. \
+    They are generated from the skip events Crunchyroll provides for its 'skip intro' buttons, so not every episode has them"
+    )]
+    #[arg(long)]
+    chapters: bool,
+
+    #[arg(help = "Embed the series poster and episode thumbnail as cover art")]
+    #[arg(long)]
+    embed_cover: bool,
+
+    #[arg(help = "Write nfo metadata files for media centers like Kodi or Jellyfin")]
+    #[arg(
+        long_help = "Write nfo metadata files for media centers like Kodi or Jellyfin. \
+    An nfo file is written next to every archived episode and a 'tvshow.nfo' to the series directory. \
+    The series directory is the first directory in the output path which contains the series name, or the directory of the episode if no such directory exists"
+    )]
+    #[arg(long)]
+    write_nfo: bool,
+
+    #[arg(
+        help = "File which records the ids of all archived episodes, they're skipped on subsequent runs"
+    )]
+    #[arg(
+        long_help = "File which records the ids of all archived episodes, they're skipped on subsequent runs. \
+    The file contains one episode id per line and is created if it does not exist. \
+    Useful to run crunchy-cli on a schedule against ongoing series"
+    )]
+    #[arg(long)]
+    download_archive: Option<PathBuf>,
+    #[arg(help = "Line format of the download archive. Valid formats are 'plain' and 'yt-dlp'")]
+    #[arg(
+        long_help = "Line format of the download archive. Valid formats are 'plain' (one episode id per line) and 'yt-dlp' ('crunchyrollbeta <id>' per line, like yt-dlp writes it). \
+    Archives in both formats are read regardless of this option, so an archive of yt-dlp can be passed to '--download-archive' directly. \
+    If not set, new episodes are recorded in the format of the existing entries"
+    )]
+    #[arg(long, value_parser = ArchiveFormat::parse)]
+    download_archive_format: Option<ArchiveFormat>,
+
+    #[arg(help = "Command which is executed before the episodes of an url are archived")]
+    #[arg(
+        long_help = "Command which is executed before the episodes of an url are archived. \
+    The command is run by the system shell, the environment variables CRUNCHY_URL, CRUNCHY_SERIES and CRUNCHY_EPISODES (number of episodes) describe the url. \
+    If the command fails, nothing is archived"
+    )]
+    #[arg(long)]
+    exec_before: Option<String>,
+
+    #[arg(help = "Command which is executed after every archived episode")]
+    #[arg(long_help = "Command which is executed after every archived episode. \
+    The command is run by the system shell, the environment variables CRUNCHY_PATH, CRUNCHY_TITLE, CRUNCHY_ID, CRUNCHY_SERIES, CRUNCHY_SERIES_ID, CRUNCHY_SEASON, CRUNCHY_SEASON_TITLE, CRUNCHY_EPISODE and CRUNCHY_AUDIO describe the episode. \
+    Useful to e.g. rename, transcode or refresh a media library")]
+    #[arg(long)]
+    exec_after: Option<String>,
+
+    #[arg(
+        help = "Only archive the given seasons / episodes (e.g. 'S2E3-S3E5', 'E10+' or 'S1,S3')"
+    )]
+    #[arg(long_help = "Only archive the given seasons / episodes. \
+    Ranges are given as 'S2E3-S3E5' (season 2, episode 3 until season 3, episode 5), open ranges as 'E10+' or 'S2-' and multiple selections are separated by a comma (e.g. 'S1,S3'). \
+    The filter is applied to every url, in addition to a filter given at the end of an url")]
+    #[arg(short, long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_filter)]
+    filter: Option<UrlFilter>,
+
+    #[arg(help = "Skip specials (recaps, PVs, ...) when archiving whole seasons")]
+    #[arg(long_help = "Skip specials when archiving whole seasons. \
+    Specials are episodes without a regular episode number (e.g. recaps, PVs or OVAs which are part of a season)")]
+    #[arg(long, default_value_t = false)]
+    skip_specials: bool,
+    #[arg(
+        help = "Only archive specials (recaps, PVs, ...) of whole seasons. The inverse of '--skip-specials'"
+    )]
+    #[arg(long, default_value_t = false)]
+    only_specials: bool,
+
+    #[arg(help = "Do not check if there is enough free disk space before downloading")]
+    #[arg(
+        long_help = "Do not check if there is enough free disk space before downloading. \
+    By default the size of all episodes of an url is estimated before they're downloaded and the archive is aborted if there is not enough free space on the target filesystem"
+    )]
+    #[arg(long, default_value_t = false)]
+    ignore_space: bool,
+
+    #[arg(help = "Only episodes which were released at or after the given date are archived")]
+    #[arg(
+        long_help = "Only episodes which were released at or after the given date are archived. \
+    The date can be given as 'YYYY-MM-DD' or relative to now as duration, e.g. '7d' for everything which was released in the last week"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_date)]
+    released_after: Option<DateTime<Utc>>,
+    #[arg(help = "Only episodes which were released at or before the given date are archived")]
+    #[arg(
+        long_help = "Only episodes which were released at or before the given date are archived. \
+    The date can be given as 'YYYY-MM-DD' or relative to now as duration (e.g. '7d')"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_date)]
+    released_before: Option<DateTime<Utc>>,
 
     #[arg(help = "Crunchyroll series url(s)")]
     urls: Vec<String>,
 }
 
+impl Archive {
+    pub(crate) fn apply_config(
+        &mut self,
+        config: ArchiveConfig,
+        matches: &ArgMatches,
+    ) -> Result<()> {
+        apply_config(
+            matches,
+            "locale",
+            &mut self.locale,
+            config.locale,
+            |locales| Ok(locales.into_iter().map(Locale::from).collect()),
+        )?;
+        apply_config(
+            matches,
+            "subtitle",
+            &mut self.subtitle,
+            config.subtitle,
+            |locales| Ok(locales.into_iter().map(Locale::from).collect()),
+        )?;
+        apply_config(matches, "output", &mut self.output, config.output, Ok)?;
+        apply_config(
+            matches,
+            "output_dir_template",
+            &mut self.output_dir_template,
+            config.output_dir_template,
+            |d| Ok(Some(d)),
+        )?;
+        apply_config(
+            matches,
+            "specials_dir",
+            &mut self.specials_dir,
+            config.specials_dir,
+            |d| Ok(Some(d)),
+        )?;
+        apply_config(
+            matches,
+            "sanitize",
+            &mut self.sanitize,
+            config.sanitize,
+            |s| SanitizeMode::parse(&s),
+        )?;
+        apply_config(
+            matches,
+            "sanitize_replacement",
+            &mut self.sanitize_replacement,
+            config.sanitize_replacement,
+            Ok,
+        )?;
+        apply_config(
+            matches,
+            "normalize",
+            &mut self.normalize,
+            config.normalize,
+            |n| Normalization::parse(&n).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "resolution",
+            &mut self.resolution,
+            config.resolution,
+            |r| clap_parse_resolution(&r),
+        )?;
+        apply_config(
+            matches,
+            "resolution_policy",
+            &mut self.resolution_policy,
+            config.resolution_policy,
+            |p| ResolutionPolicy::parse(&p),
+        )?;
+        apply_config(
+            matches,
+            "max_bitrate",
+            &mut self.max_bitrate,
+            config.max_bitrate,
+            |b| clap_parse_bandwidth(&b).map(Some),
+        )?;
+        apply_config(matches, "fps", &mut self.fps, config.fps, |f| Ok(Some(f)))?;
+        apply_config(matches, "merge", &mut self.merge, config.merge, |m| {
+            MergeBehavior::parse(&m)
+        })?;
+        apply_config(
+            matches,
+            "ffmpeg_preset",
+            &mut self.ffmpeg_preset,
+            config.ffmpeg_preset,
+            |presets| presets.iter().map(|p| FFmpegPreset::parse(p)).collect(),
+        )?;
+        apply_config(
+            matches,
+            "hwaccel_device",
+            &mut self.hwaccel_device,
+            config.hwaccel_device,
+            |d| Ok(Some(d)),
+        )?;
+        apply_config(matches, "crf", &mut self.crf, config.crf, |c| {
+            if c > 63 {
+                Err("must be between 0 and 63".to_string())
+            } else {
+                Ok(Some(c))
+            }
+        })?;
+        apply_config(
+            matches,
+            "video_bitrate",
+            &mut self.video_bitrate,
+            config.video_bitrate,
+            |b| clap_parse_bitrate(&b).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "encode_preset",
+            &mut self.encode_preset,
+            config.encode_preset,
+            |p| EncodePreset::parse(&p).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "audio_codec",
+            &mut self.audio_codec,
+            config.audio_codec,
+            |c| AudioCodec::parse(&c).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "audio_bitrate",
+            &mut self.audio_bitrate,
+            config.audio_bitrate,
+            |b| clap_parse_bitrate(&b).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "ffmpeg_input_args",
+            &mut self.ffmpeg_input_args,
+            config.ffmpeg_input_args,
+            |a| clap_parse_ffmpeg_args(&a).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "ffmpeg_output_args",
+            &mut self.ffmpeg_output_args,
+            config.ffmpeg_output_args,
+            |a| clap_parse_ffmpeg_args(&a).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "default_subtitle",
+            &mut self.default_subtitle,
+            config.default_subtitle,
+            |l| Ok(Some(Locale::from(l))),
+        )?;
+        apply_config(matches, "hardsub", &mut self.hardsub, config.hardsub, |l| {
+            Ok(Some(Locale::from(l)))
+        })?;
+        apply_config(
+            matches,
+            "download_archive",
+            &mut self.download_archive,
+            config.download_archive,
+            |p| Ok(Some(p)),
+        )?;
+        apply_config(
+            matches,
+            "download_archive_format",
+            &mut self.download_archive_format,
+            config.download_archive_format,
+            |f| ArchiveFormat::parse(&f).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "exec_before",
+            &mut self.exec_before,
+            config.exec_before,
+            |c| Ok(Some(c)),
+        )?;
+        apply_config(
+            matches,
+            "exec_after",
+            &mut self.exec_after,
+            config.exec_after,
+            |c| Ok(Some(c)),
+        )?;
+
+        Ok(())
+    }
+
+    /// Output path of `format`, see '--output', '--output-dir-template' and '--specials-dir'.
+    fn output_path(&self, format: &Format) -> PathBuf {
+        format_path(
+            &self.output_template(format),
+            "{title}.mkv",
+            format,
+            &self.sanitizer(),
+        )
+    }
+
+    /// Sanitizer of the values inserted into file names, see '--sanitize'.
+    fn sanitizer(&self) -> Sanitizer {
+        Sanitizer {
+            mode: self.sanitize.clone(),
+            replacement: self.sanitize_replacement.clone(),
+            normalization: self.normalize.clone(),
+            ascii: self.ascii_filenames,
+        }
+    }
+
+    /// Output template of `format` including its directory, see [`output_template`].
+    fn output_template(&self, format: &Format) -> String {
+        output_template(
+            &self.output,
+            self.output_dir_template.as_deref(),
+            self.specials_dir.as_deref(),
+            format,
+        )
+    }
+
+    fn ffmpeg_options(&self) -> FFmpegOptions {
+        FFmpegOptions {
+            reencode_video: self.hardsub.is_some(),
+            hwaccel_device: self.hwaccel_device.clone(),
+            crf: self.crf,
+            video_bitrate: self.video_bitrate.clone(),
+            encode_preset: self.encode_preset.clone(),
+            audio_codec: self.audio_codec.clone(),
+            audio_bitrate: self.audio_bitrate.clone(),
+        }
+    }
+
+    fn variant_criteria(&self) -> VariantCriteria {
+        VariantCriteria {
+            max_bitrate: self.max_bitrate,
+            fps: self.fps,
+        }
+    }
+}
+
 #[async_trait::async_trait(?Send)]
 impl Execute for Archive {
     fn pre_check(&self) -> Result<()> {
@@ -137,11 +661,32 @@ impl Execute for Archive {
         {
             bail!("File extension is not '.mkv'. Currently only matroska / '.mkv' files are supported")
         }
-        let _ = FFmpegPreset::ffmpeg_presets(self.ffmpeg_preset.clone())?;
+        check_format_string(&self.output)?;
+        for dir in [&self.output_dir_template, &self.specials_dir]
+            .into_iter()
+            .flatten()
+        {
+            check_format_string(dir)?;
+        }
+        if !self.sanitizer().is_valid_replacement() {
+            bail!(
+                "The sanitize replacement '{}' contains characters which are invalid in file names itself",
+                self.sanitize_replacement
+            )
+        }
+        if self.skip_specials && self.only_specials {
+            bail!("'--skip-specials' and '--only-specials' cannot be used together")
+        }
+        let _ = FFmpegPreset::ffmpeg_presets(self.ffmpeg_preset.clone(), &self.ffmpeg_options())?;
         if self.ffmpeg_preset.len() == 1
-            && self.ffmpeg_preset.get(0).unwrap() == &FFmpegPreset::Nvidia
+            && self.hardsub.is_none()
+            && [FFmpegPreset::Nvidia, FFmpegPreset::Vaapi, FFmpegPreset::Qsv]
+                .contains(&self.ffmpeg_preset[0])
         {
-            warn!("Skipping 'nvidia' hardware acceleration preset since no other codec preset was specified")
+            warn!(
+                "Skipping '{}' hardware acceleration preset since no other codec preset was specified",
+                self.ffmpeg_preset[0].to_string()
+            )
         }
 
         Ok(())
@@ -149,11 +694,22 @@ impl Execute for Archive {
 
     async fn execute(self, ctx: Context) -> Result<()> {
         let mut parsed_urls = vec![];
+        let mut finished = 0;
+        let mut size = 0;
+        let start = ctx.pause.stopwatch();
+        let download_archive = self
+            .download_archive
+            .as_deref()
+            .map(|path| DownloadArchive::open(path, self.download_archive_format))
+            .transpose()?;
 
         for (i, url) in self.urls.iter().enumerate() {
             let progress_handler = progress!("Parsing url {}", i + 1);
             match parse_url(&ctx.crunchy, url.clone(), true).await {
-                Ok((media_collection, url_filter)) => {
+                Ok((media_collection, mut url_filter)) => {
+                    if let Some(filter) = &self.filter {
+                        url_filter = url_filter.and(filter.clone())
+                    }
                     parsed_urls.push((media_collection, url_filter));
                     progress_handler.stop(format!("Parsed url {}", i + 1))
                 }
@@ -163,7 +719,7 @@ impl Execute for Archive {
 
         for (i, (media_collection, url_filter)) in parsed_urls.into_iter().enumerate() {
             let progress_handler = progress!("Fetching series details");
-            let archive_formats = match media_collection {
+            let mut archive_formats = match media_collection {
                 MediaCollection::Series(series) => {
                     formats_from_series(&self, series, &url_filter).await?
                 }
@@ -180,9 +736,31 @@ impl Execute for Archive {
                 ));
                 continue;
             }
+            if let Some(download_archive) = &download_archive {
+                archive_formats.retain(|(formats, _)| !download_archive.contains(&formats[0].id));
+                if archive_formats.is_empty() {
+                    progress_handler.stop(format!(
+                        "Skipping url {} (all episodes are already in the download archive)",
+                        i + 1
+                    ));
+                    continue;
+                }
+            }
             progress_handler.stop(format!("Loaded series information for url {}", i + 1));
 
-            if log::max_level() == log::Level::Debug {
+            if !self.ignore_space {
+                let path = self.output_path(&archive_formats[0].0[0]);
+                if !is_special_file(&path) {
+                    let size = archive_formats
+                        .iter()
+                        .flat_map(|(formats, _)| formats.iter().map(|f| f.estimated_size()))
+                        .sum();
+                    check_free_space(&path, size)
+                        .map_err(|e| anyhow!("{}. Use '--ignore-space' to archive anyway", e))?
+                }
+            }
+
+            if console_level() == log::Level::Debug {
                 let seasons = sort_formats_after_seasons(
                     archive_formats
                         .clone()
@@ -232,22 +810,43 @@ impl Execute for Archive {
                 }
             }
 
+            if let Some(exec_before) = &self.exec_before {
+                run_hook(
+                    exec_before,
+                    batch_env(
+                        &self.urls[i],
+                        &archive_formats
+                            .iter()
+                            .map(|(formats, _)| &formats[0])
+                            .collect::<Vec<&Format>>(),
+                    ),
+                )?
+            }
+
             for (formats, subtitles) in archive_formats {
+                ctx.cancellation.check()?;
                 let (primary, additionally) = formats.split_first().unwrap();
 
-                let mut path = PathBuf::from(&self.output);
-                path = free_file(
-                    path.with_file_name(format_string(
-                        if let Some(fname) = path.file_name() {
-                            fname.to_str().unwrap()
-                        } else {
-                            "{title}.mkv"
-                        }
-                        .to_string(),
-                        primary,
-                        true,
-                    )),
-                );
+                // the hardsub subtitle is only added as separate track if it was explicitly
+                // requested
+                let hardsub = if let Some(locale) = &self.hardsub {
+                    let hardsub = subtitles.iter().find(|s| &s.locale == locale).cloned();
+                    if hardsub.is_none() {
+                        warn!(
+                            "Episode {} (S{:02}E{:02}) has no {} subtitles to burn in",
+                            primary.title, primary.season_number, primary.number, locale
+                        )
+                    }
+                    hardsub
+                } else {
+                    None
+                };
+                let subtitles: Vec<StreamSubtitle> = subtitles
+                    .into_iter()
+                    .filter(|s| self.subtitle.contains(&s.locale))
+                    .collect();
+
+                let path = free_file(self.output_path(primary));
 
                 info!(
                     "Downloading {} to '{}'",
@@ -287,14 +886,17 @@ impl Execute for Archive {
                         .collect::<Vec<String>>()
                         .join(", ")
                 );
+                if let Some(hardsub) = &hardsub {
+                    tab_info!("Hardsub: {}", hardsub.locale)
+                }
                 tab_info!("Resolution: {}", primary.stream.resolution);
                 tab_info!("FPS: {:.2}", primary.stream.fps);
 
+                let episode_start = ctx.pause.stopwatch();
                 let mut video_paths = vec![];
                 let mut audio_paths = vec![];
                 let mut subtitle_paths = vec![];
 
-                video_paths.push((download_video(&ctx, primary, false).await?, primary));
                 for additional in additionally {
                     let only_audio = match self.merge {
                         MergeBehavior::Auto => additionally
@@ -303,7 +905,14 @@ impl Execute for Archive {
                         MergeBehavior::Audio => true,
                         MergeBehavior::Video => false,
                     };
-                    let path = download_video(&ctx, additional, only_audio).await?;
+                    let path = download_video(
+                        &ctx,
+                        additional.stream.clone(),
+                        format!("Download {}", additional.audio),
+                        only_audio,
+                        None,
+                    )
+                    .await?;
                     if only_audio {
                         audio_paths.push((path, additional))
                     } else {
@@ -311,21 +920,151 @@ impl Execute for Archive {
                     }
                 }
 
-                let (primary_video, _) = video_paths.get(0).unwrap();
-                let primary_video_length = get_video_length(primary_video.to_path_buf()).unwrap();
+                // sometimes subtitles have an unnecessary entry long after the actual video ends,
+                // which artificially extends the video length on some video players. To prevent
+                // this, the subtitles are cut at the video length. See
+                // [crunchy-labs/crunchy-cli#32](https://github.com/crunchy-labs/crunchy-cli/issues/32)
+                let primary_video_length = NaiveTime::from_num_seconds_from_midnight_opt(
+                    primary.duration.as_secs() as u32,
+                    primary.duration.subsec_nanos(),
+                )
+                .unwrap_or_default();
                 for subtitle in subtitles {
                     subtitle_paths.push((
-                        download_subtitle(&self, subtitle.clone(), primary_video_length).await?,
+                        download_subtitle(
+                            subtitle.clone(),
+                            primary_video_length,
+                            !self.no_subtitle_optimizations,
+                            0,
+                        )
+                        .await?,
                         subtitle,
                     ))
                 }
 
-                let progess_handler = progress!("Generating mkv");
-                generate_mkv(&self, path, video_paths, audio_paths, subtitle_paths)?;
-                progess_handler.stop("Mkv generated")
+                let hardsub_path = if let Some(hardsub) = hardsub {
+                    Some(
+                        download_subtitle(
+                            hardsub,
+                            primary_video_length,
+                            !self.no_subtitle_optimizations,
+                            0,
+                        )
+                        .await?,
+                    )
+                } else {
+                    None
+                };
+
+                let chapters_path = if self.chapters {
+                    let chapters = fetch_chapters(&ctx, &primary.id, primary.duration).await?;
+                    if chapters.is_empty() {
+                        None
+                    } else {
+                        Some(ffmpeg_chapters_file(&chapters)?)
+                    }
+                } else {
+                    None
+                };
+
+                let cover_art = if self.embed_cover {
+                    Some(CoverArt::download(&ctx, primary).await?)
+                } else {
+                    None
+                };
+
+                if ctx.progress_format == ProgressFormat::Json {
+                    emit_progress(ProgressEvent::MuxStarted { output: &path })
+                }
+                // pipes cannot be renamed, so they're written directly
+                let part = if is_special_file(&path) {
+                    path.clone()
+                } else {
+                    part_file(&path)
+                };
+                let _partial = PartialFile::new(&part, &ctx.cancellation);
+                generate_mkv(
+                    &ctx,
+                    &self,
+                    part.clone(),
+                    primary,
+                    video_paths,
+                    audio_paths,
+                    subtitle_paths,
+                    hardsub_path,
+                    chapters_path,
+                    cover_art,
+                )
+                .await?;
+                if part != path {
+                    std::fs::rename(&part, &path)?
+                }
+                if ctx.progress_format == ProgressFormat::Json {
+                    emit_progress(ProgressEvent::MuxFinished { output: &path })
+                } else {
+                    info!("Mkv generated")
+                }
+                if !is_special_file(&path) {
+                    let episode_size = std::fs::metadata(&path)?.len();
+                    size += episode_size;
+                    episode_summary(
+                        &ctx,
+                        &path,
+                        &TransferSummary {
+                            size: episode_size,
+                            elapsed: episode_start.elapsed(),
+                        },
+                        None,
+                    )?
+                }
+
+                if self.write_nfo && !is_special_file(&path) {
+                    write_nfo(
+                        &ctx,
+                        &self.output_template(primary),
+                        &path,
+                        primary,
+                        OverwritePolicy::Overwrite,
+                        &self.sanitizer(),
+                    )
+                    .await?
+                }
+                if let Some(download_archive) = &download_archive {
+                    download_archive.record(&primary.id)?
+                }
+                ctx.persist_session().await;
+
+                if let Some(exec_after) = &self.exec_after {
+                    if let Err(e) = run_hook(exec_after, episode_env(primary, &path)) {
+                        warn!("Failed to execute command after {}: {}", primary.title, e)
+                    }
+                }
+
+                if let Some(notifier) = &ctx.notifier {
+                    notifier
+                        .notify(Notification::episode_finished(primary, &path))
+                        .await
+                }
+                finished += 1
             }
         }
 
+        if finished > 0 {
+            batch_summary(
+                &ctx,
+                finished,
+                &TransferSummary {
+                    size,
+                    elapsed: start.elapsed(),
+                },
+            )
+        }
+        if let Some(notifier) = ctx.notifier.as_ref().filter(|_| finished > 0) {
+            notifier
+                .notify(Notification::BatchFinished { episodes: finished })
+                .await
+        }
+
         Ok(())
     }
 }
@@ -385,11 +1124,67 @@ async fn formats_from_series(
                 episode.metadata.season_number,
             ) {
                 continue;
+            } else if skip_special(&episode, archive.skip_specials, archive.only_specials) {
+                debug!(
+                    "Skipping episode {} ({}) of season {} because of the specials filter",
+                    episode.metadata.episode, episode.title, episode.metadata.season_number
+                );
+                continue;
+            } else if !is_released_between(
+                &episode,
+                archive.released_after.as_ref(),
+                archive.released_before.as_ref(),
+            ) {
+                debug!(
+                    "Skipping episode {} ({}) of season {} because it was released at {}",
+                    episode.metadata.episode,
+                    episode.title,
+                    episode.metadata.season_number,
+                    episode.metadata.episode_air_date
+                );
+                continue;
             }
 
-            let streams = episode.streams().await?;
+            // premium only episodes cannot be streamed without a premium account (or
+            // anonymously), they are skipped instead of aborting the whole archive
+            let streams = match episode.streams().await {
+                Ok(streams) => streams,
+                Err(e) if episode.metadata.is_premium_only => {
+                    warn!(
+                        "Episode {} ({}) of season {} ({}) of {} is only available with a premium account, skipping it",
+                        episode.metadata.episode_number,
+                        episode.title,
+                        episode.metadata.season_number,
+                        episode.metadata.season_title,
+                        episode.metadata.series_title
+                    );
+                    debug!("Failed to get streams of premium only episode: {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    return Err(region_error(
+                        e,
+                        format!(
+                            "Episode {} ({}) of season {} ({}) of {}",
+                            episode.metadata.episode_number,
+                            episode.title,
+                            episode.metadata.season_number,
+                            episode.metadata.season_title,
+                            episode.metadata.series_title
+                        ),
+                        &episode.metadata.eligible_region,
+                    ))
+                }
+            };
             let streaming_data = streams.hls_streaming_data(None).await?;
-            let Some(stream) = find_resolution(streaming_data, &archive.resolution) else {
+            let variants = (archive.segment_fallback || archive.downgrade_after.is_some())
+                .then(|| streaming_data.clone());
+            let Some(stream) = find_resolution(
+                streaming_data,
+                &archive.resolution,
+                &archive.resolution_policy,
+                &archive.variant_criteria(),
+            ) else {
                 bail!(
                     "Resolution ({}x{}) is not available for episode {} ({}) of season {} ({}) of {}",
                     archive.resolution.width,
@@ -410,166 +1205,53 @@ async fn formats_from_series(
                     let subtitles: Vec<StreamSubtitle> = archive
                         .subtitle
                         .iter()
+                        .chain(archive.hardsub.iter())
                         .filter_map(|l| streams.subtitles.get(l).cloned())
                         .collect();
                     (vec![], subtitles)
                 });
-            formats.push(Format::new_from_episode(episode, stream));
+            let fallback_stream = variants.and_then(|v| fallback_variant(&v, &stream));
+            let mut format = Format::new_from_episode(episode, stream);
+            format.fallback_stream = fallback_stream;
+            format.downgrade_after = archive.downgrade_after;
+            formats.push(format);
         }
     }
 
     Ok(result.into_values().flat_map(|v| v.into_values()).collect())
 }
 
-async fn download_video(ctx: &Context, format: &Format, only_audio: bool) -> Result<TempPath> {
-    let tempfile = if only_audio {
-        tempfile(".aac")?
-    } else {
-        tempfile(".ts")?
-    };
-    let (_, path) = tempfile.into_parts();
-
-    let ffmpeg = Command::new("ffmpeg")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .stderr(Stdio::piped())
-        .arg("-y")
-        .args(["-f", "mpegts"])
-        .args(["-i", "pipe:"])
-        .args(["-c", "copy"])
-        .args(if only_audio { vec!["-vn"] } else { vec![] })
-        .arg(path.to_str().unwrap())
-        .spawn()?;
-
-    download_segments(
-        ctx,
-        &mut ffmpeg.stdin.unwrap(),
-        Some(format!("Download {}", format.audio)),
-        format.stream.clone(),
-    )
-    .await?;
-
-    Ok(path)
-}
-
-async fn download_subtitle(
-    archive: &Archive,
-    subtitle: StreamSubtitle,
-    max_length: NaiveTime,
-) -> Result<TempPath> {
-    let tempfile = tempfile(".ass")?;
-    let (mut file, path) = tempfile.into_parts();
-
-    let mut buf = vec![];
-    subtitle.write_to(&mut buf).await?;
-    if !archive.no_subtitle_optimizations {
-        buf = fix_subtitle_look_and_feel(buf)
-    }
-    buf = fix_subtitle_length(buf, max_length);
-
-    file.write_all(buf.as_slice())?;
-
-    Ok(path)
-}
-
-/// Add `ScaledBorderAndShadows: yes` to subtitles; without it they look very messy on some video
-/// players. See [crunchy-labs/crunchy-cli#66](https://github.com/crunchy-labs/crunchy-cli/issues/66)
-/// for more information.
-fn fix_subtitle_look_and_feel(raw: Vec<u8>) -> Vec<u8> {
-    let mut script_info = false;
-    let mut new = String::new();
-
-    for line in String::from_utf8_lossy(raw.as_slice()).split('\n') {
-        if line.trim().starts_with('[') && script_info {
-            new.push_str("ScaledBorderAndShadows: yes\n");
-            script_info = false
-        } else if line.trim() == "[Script Info]" {
-            script_info = true
-        }
-        new.push_str(line);
-        new.push('\n')
-    }
-
-    new.into_bytes()
-}
-
-/// Fix the length of subtitles to a specified maximum amount. This is required because sometimes
-/// subtitles have an unnecessary entry long after the actual video ends with artificially extends
-/// the video length on some video players. To prevent this, the video length must be hard set. See
-/// [crunchy-labs/crunchy-cli#32](https://github.com/crunchy-labs/crunchy-cli/issues/32) for more
-/// information.
-fn fix_subtitle_length(raw: Vec<u8>, max_length: NaiveTime) -> Vec<u8> {
-    let re =
-        Regex::new(r#"^Dialogue:\s\d+,(?P<start>\d+:\d+:\d+\.\d+),(?P<end>\d+:\d+:\d+\.\d+),"#)
-            .unwrap();
-
-    // chrono panics if we try to format NaiveTime with `%2f` and the nano seconds has more than 2
-    // digits so them have to be reduced manually to avoid the panic
-    fn format_naive_time(native_time: NaiveTime) -> String {
-        let formatted_time = native_time.format("%f").to_string();
-        format!(
-            "{}.{}",
-            native_time.format("%T"),
-            if formatted_time.len() <= 2 {
-                native_time.format("%2f").to_string()
-            } else {
-                formatted_time.split_at(2).0.parse().unwrap()
-            }
-        )
-    }
-
-    let length_as_string = format_naive_time(max_length);
-    let mut new = String::new();
-
-    for line in String::from_utf8_lossy(raw.as_slice()).split('\n') {
-        if let Some(capture) = re.captures(line) {
-            let start = capture.name("start").map_or(NaiveTime::default(), |s| {
-                NaiveTime::parse_from_str(s.as_str(), "%H:%M:%S.%f").unwrap()
-            });
-            let end = capture.name("end").map_or(NaiveTime::default(), |s| {
-                NaiveTime::parse_from_str(s.as_str(), "%H:%M:%S.%f").unwrap()
-            });
-
-            if start > max_length {
-                continue;
-            } else if end > max_length {
-                new.push_str(
-                    re.replace(
-                        line,
-                        format!(
-                            "Dialogue: {},{},",
-                            format_naive_time(start),
-                            &length_as_string
-                        ),
-                    )
-                    .to_string()
-                    .as_str(),
-                )
-            } else {
-                new.push_str(line)
-            }
-        } else {
-            new.push_str(line)
-        }
-        new.push('\n')
-    }
-
-    new.into_bytes()
-}
-
-fn generate_mkv(
+#[allow(clippy::too_many_arguments)]
+async fn generate_mkv(
+    ctx: &Context,
     archive: &Archive,
     target: PathBuf,
+    primary: &Format,
     video_paths: Vec<(TempPath, &Format)>,
     audio_paths: Vec<(TempPath, &Format)>,
     subtitle_paths: Vec<(TempPath, StreamSubtitle)>,
+    hardsub_path: Option<TempPath>,
+    chapters_path: Option<TempPath>,
+    cover_art: Option<CoverArt>,
 ) -> Result<()> {
+    // the primary video is not downloaded to a temporary file but piped into ffmpeg while it's
+    // downloaded, so only the additional videos and audios take up temporary disk space
+    let videos: Vec<(String, &Format)> = std::iter::once(("pipe:".to_string(), primary))
+        .chain(
+            video_paths
+                .iter()
+                .map(|(path, format)| (path.to_string_lossy().to_string(), *format)),
+        )
+        .collect();
     let mut input = vec![];
     let mut maps = vec![];
     let mut metadata = vec![];
 
-    for (i, (video_path, format)) in video_paths.iter().enumerate() {
-        input.extend(["-i".to_string(), video_path.to_string_lossy().to_string()]);
+    for (i, (video_input, format)) in videos.iter().enumerate() {
+        if i == 0 {
+            input.extend(["-f".to_string(), "mpegts".to_string()])
+        }
+        input.extend(["-i".to_string(), video_input.clone()]);
         maps.extend(["-map".to_string(), i.to_string()]);
         metadata.extend([
             format!("-metadata:s:v:{}", i),
@@ -590,13 +1272,13 @@ fn generate_mkv(
     }
     for (i, (audio_path, format)) in audio_paths.iter().enumerate() {
         input.extend(["-i".to_string(), audio_path.to_string_lossy().to_string()]);
-        maps.extend(["-map".to_string(), (i + video_paths.len()).to_string()]);
+        maps.extend(["-map".to_string(), (i + videos.len()).to_string()]);
         metadata.extend([
-            format!("-metadata:s:a:{}", i + video_paths.len()),
+            format!("-metadata:s:a:{}", i + videos.len()),
             format!("language={}", format.audio),
         ]);
         metadata.extend([
-            format!("-metadata:s:a:{}", i + video_paths.len()),
+            format!("-metadata:s:a:{}", i + videos.len()),
             format!("title={}", format.audio.to_human_readable()),
         ]);
     }
@@ -607,7 +1289,7 @@ fn generate_mkv(
         ]);
         maps.extend([
             "-map".to_string(),
-            (i + video_paths.len() + audio_paths.len()).to_string(),
+            (i + videos.len() + audio_paths.len()).to_string(),
         ]);
         metadata.extend([
             format!("-metadata:s:s:{}", i),
@@ -618,12 +1300,31 @@ fn generate_mkv(
             format!("title={}", subtitle.locale.to_human_readable()),
         ]);
     }
+    if let Some(chapters_path) = &chapters_path {
+        input.extend([
+            "-i".to_string(),
+            chapters_path.to_string_lossy().to_string(),
+        ]);
+        maps.extend([
+            "-map_chapters".to_string(),
+            (videos.len() + audio_paths.len() + subtitle_paths.len()).to_string(),
+        ]);
+    }
 
-    let (input_presets, output_presets) =
-        FFmpegPreset::ffmpeg_presets(archive.ffmpeg_preset.clone())?;
+    let (input_presets, output_presets) = FFmpegPreset::ffmpeg_presets(
+        archive.ffmpeg_preset.clone(),
+        &FFmpegOptions {
+            // the hardsub may not be available for every episode
+            reencode_video: hardsub_path.is_some(),
+            ..archive.ffmpeg_options()
+        },
+    )?;
 
     let mut command_args = vec!["-y".to_string()];
     command_args.extend(input_presets);
+    command_args.extend(parse_ffmpeg_args(
+        archive.ffmpeg_input_args.as_deref().unwrap_or_default(),
+    )?);
     command_args.extend(input);
     command_args.extend(maps);
     command_args.extend(metadata);
@@ -643,7 +1344,24 @@ fn generate_mkv(
         command_args.extend(["-disposition:s:0".to_string(), "0".to_string()])
     }
 
+    if let Some(cover_art) = &cover_art {
+        command_args.extend(cover_art.matroska_args(0))
+    }
+
+    if let Some(hardsub_path) = &hardsub_path {
+        command_args.extend([
+            "-filter:v".to_string(),
+            FFmpegPreset::video_filter(
+                &archive.ffmpeg_preset,
+                &format!("subtitles={}", escape_filter_path(hardsub_path)),
+            ),
+        ])
+    }
+
     command_args.extend(output_presets);
+    command_args.extend(parse_ffmpeg_args(
+        archive.ffmpeg_output_args.as_deref().unwrap_or_default(),
+    )?);
     command_args.extend([
         "-f".to_string(),
         "matroska".to_string(),
@@ -659,34 +1377,42 @@ fn generate_mkv(
         }
     }
 
-    let ffmpeg = Command::new("ffmpeg")
+    let mut ffmpeg = Command::new("ffmpeg")
+        .stdin(Stdio::piped())
         .stdout(Stdio::null())
         .stderr(Stdio::piped())
+        .args(FFMPEG_PROGRESS_ARGS)
         .args(command_args)
-        .output()?;
-    if !ffmpeg.status.success() {
-        bail!("{}", String::from_utf8_lossy(ffmpeg.stderr.as_slice()))
-    }
-
-    Ok(())
-}
+        .spawn()?;
+    let ffmpeg_progress = FFmpegProgress::new(&mut ffmpeg);
 
-/// Get the length of a video. This is required because sometimes subtitles have an unnecessary entry
-/// long after the actual video ends with artificially extends the video length on some video players.
-/// To prevent this, the video length must be hard set. See
-/// [crunchy-labs/crunchy-cli#32](https://github.com/crunchy-labs/crunchy-cli/issues/32) for more
-/// information.
-fn get_video_length(path: PathBuf) -> Result<NaiveTime> {
-    let video_length = Regex::new(r"Duration:\s(?P<time>\d+:\d+:\d+\.\d+),")?;
+    if let Err(e) = download_segments(
+        ctx,
+        ffmpeg.stdin.take().unwrap(),
+        Some(format!("Download {}", primary.audio)),
+        primary.stream.clone(),
+        Some(primary),
+        None,
+        None,
+    )
+    .await
+    {
+        let _ = ffmpeg.kill();
+        return Err(e);
+    }
 
-    let ffmpeg = Command::new("ffmpeg")
-        .stdout(Stdio::null())
-        .stderr(Stdio::piped())
-        .arg("-y")
-        .args(["-i", path.to_str().unwrap()])
-        .output()?;
-    let ffmpeg_output = String::from_utf8(ffmpeg.stderr)?;
-    let caps = video_length.captures(ffmpeg_output.as_str()).unwrap();
+    let (status, log) = ffmpeg_progress
+        .wait(
+            ctx,
+            &mut ffmpeg,
+            primary.duration,
+            "Generating mkv".to_string(),
+            None,
+        )
+        .await?;
+    if !status.success() {
+        bail!("{}", log)
+    }
 
-    Ok(NaiveTime::parse_from_str(caps.name("time").unwrap().as_str(), "%H:%M:%S%.f").unwrap())
+    Ok(())
 }
@@ -0,0 +1,93 @@
+use crate::cli::download::Download;
+use crate::cli::utils::benchmark_segments;
+use crate::utils::context::Context;
+use crate::utils::log::progress;
+use crate::utils::table::print_table;
+use crate::Execute;
+use anyhow::{bail, Result};
+use indicatif::HumanBytes;
+use log::info;
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Measure the download throughput with different numbers of download threads")]
+#[command(arg_required_else_help(true))]
+pub struct Benchmark {
+    #[arg(help = "Number of segments which are downloaded in every run")]
+    #[arg(
+        long_help = "Number of segments which are downloaded in every run. \
+    More segments give more accurate results but the benchmark takes longer"
+    )]
+    #[arg(long, default_value_t = 20)]
+    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    segments: u16,
+
+    #[arg(help = "Numbers of download threads which are benchmarked, comma separated")]
+    #[arg(
+        long_help = "Numbers of download threads which are benchmarked, comma separated. \
+    The same segments are downloaded once with every number of threads. \
+    Connection settings like '--http2', '--max-connections-per-host' or '--speed-limit' are applied to all runs, \
+    run the benchmark multiple times with different settings to compare them"
+    )]
+    #[arg(long, value_delimiter = ',', default_value = "1,2,4,8,16")]
+    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    threads: Vec<u16>,
+
+    #[clap(flatten)]
+    pub(crate) download: Download,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Benchmark {
+    async fn execute(self, ctx: Context) -> Result<()> {
+        let Some(format) = self.download.formats(&ctx).await?.into_iter().next() else {
+            bail!("No episodes found")
+        };
+
+        let segments = format.stream.segments().await?;
+        let sample = &segments[..segments.len().min(self.segments as usize)];
+
+        let mut rows = vec![];
+        let mut results = vec![];
+        for threads in &self.threads {
+            let progress_handler = progress!(
+                "Downloading {} segments with {} thread(s)",
+                sample.len(),
+                threads
+            );
+            let (bytes, elapsed) = benchmark_segments(&ctx, sample, *threads as usize).await?;
+            progress_handler.stop(format!(
+                "Downloaded {} segments with {} thread(s)",
+                sample.len(),
+                threads
+            ));
+
+            let speed = (bytes as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+            rows.push(vec![
+                threads.to_string(),
+                HumanBytes(bytes).to_string(),
+                format!("{:.1}s", elapsed.as_secs_f64()),
+                format!("{}/s", HumanBytes(speed)),
+            ]);
+            results.push((*threads, speed))
+        }
+        print_table(&["Threads", "Downloaded", "Time", "Speed"], &rows)?;
+
+        // more threads than needed only put more load on the cdn, so the lowest number of threads
+        // which is nearly as fast as the fastest run is recommended
+        let max_speed = results.iter().map(|(_, s)| *s).max().unwrap_or_default();
+        if let Some((threads, speed)) = results
+            .iter()
+            .filter(|(_, s)| *s as f64 >= max_speed as f64 * 0.95)
+            .min_by_key(|(t, _)| *t)
+        {
+            info!(
+                "{} thread(s) reach {}/s, use '--download-threads {}' (and '--speed-limit' below this speed if you want to keep bandwidth for other things)",
+                threads,
+                HumanBytes(*speed),
+                threads
+            )
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use crate::cli::watchlist::ListEntry;
+use crate::utils::context::Context;
+use crate::utils::log::progress;
+use crate::utils::table::print_table;
+use crate::Execute;
+use anyhow::{bail, Result};
+use crunchyroll_rs::Crunchyroll;
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Show the crunchylists of your account")]
+#[command(arg_required_else_help(true))]
+pub struct Crunchylist {
+    #[command(subcommand)]
+    command: CrunchylistCommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum CrunchylistCommand {
+    #[clap(about = "List all crunchylists")]
+    List,
+    #[clap(about = "Show the content of a crunchylist")]
+    Show {
+        #[arg(help = "Name or id of the crunchylist")]
+        list: String,
+    },
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Crunchylist {
+    async fn execute(self, ctx: Context) -> Result<()> {
+        match self.command {
+            CrunchylistCommand::List => {
+                let rows: Vec<Vec<String>> = ctx
+                    .crunchy
+                    .crunchylists()
+                    .await?
+                    .items
+                    .into_iter()
+                    .map(|list| vec![list.title, list.list_id, list.total.to_string()])
+                    .collect();
+                print_table(&["Title", "Id", "Entries"], &rows)?;
+            }
+            CrunchylistCommand::Show { list } => {
+                let progress_handler = progress!("Fetching crunchylist");
+                let entries = crunchylist(&ctx.crunchy, &list).await?;
+                progress_handler.stop(format!("Crunchylist contains {} entries", entries.len()));
+
+                let rows: Vec<Vec<String>> = entries
+                    .into_iter()
+                    .map(|entry| vec![entry.title, entry.kind.to_string(), entry.url])
+                    .collect();
+                print_table(&["Title", "Type", "Url"], &rows)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// All entries of the crunchylist with the given name (case insensitive) or id. Requires a logged
+/// in account.
+pub(crate) async fn crunchylist(crunchy: &Crunchyroll, name_or_id: &str) -> Result<Vec<ListEntry>> {
+    let Some(preview) = crunchy
+        .crunchylists()
+        .await?
+        .items
+        .into_iter()
+        .find(|list| {
+            list.list_id == name_or_id || list.title.to_lowercase() == name_or_id.to_lowercase()
+        })
+    else {
+        bail!("No crunchylist with name or id '{}' found", name_or_id)
+    };
+
+    Ok(preview
+        .crunchylist()
+        .await?
+        .items
+        .into_iter()
+        .filter_map(|entry| ListEntry::from_panel(entry.panel))
+        .collect())
+}
@@ -1,61 +1,236 @@
-use crate::cli::log::tab_info;
-use crate::cli::utils::{download_cc, download_segments, find_resolution, FFmpegPreset};
+use crate::cli::crunchylist::crunchylist;
+use crate::cli::log::{console_level, tab_info};
+use crate::cli::utils::{
+    batch_summary, download_cc, download_cc_subtitle, download_raw, download_segments,
+    download_subtitle, download_video, episode_summary, fallback_variant, ffmpeg_muxer,
+    find_resolution, region_error, streaming_data, write_subtitle, AudioCodec, EncodePreset,
+    FFmpegOptions, FFmpegPreset, FFmpegProgress, ResolutionPolicy, StreamType, VariantCriteria,
+    FFMPEG_PROGRESS_ARGS,
+};
+use crate::cli::watchlist::watchlist;
+use crate::utils::cancel::{is_cancelled, PartialFile};
+use crate::utils::chapters::{fetch_chapters, ffmpeg_chapters_file, ChapterFormat};
+use crate::utils::clap::{
+    clap_parse_bandwidth, clap_parse_bitrate, clap_parse_ffmpeg_args, clap_parse_resolution,
+};
+use crate::utils::config::{apply_config, DownloadConfig};
 use crate::utils::context::Context;
-use crate::utils::format::{format_string, Format};
+use crate::utils::cover::CoverArt;
+use crate::utils::download_archive::{ArchiveFormat, DownloadArchive};
+use crate::utils::filter::{
+    is_movie_season, is_released_between, skip_movie_season, skip_special, MovieFilter,
+};
+use crate::utils::font::Font;
+use crate::utils::format::{check_format_string, format_path, output_template, Format};
+use crate::utils::history::{continue_watching, mark_watched};
+use crate::utils::hook::{batch_env, episode_env, run_hook};
+use crate::utils::locale::LocaleChain;
 use crate::utils::log::progress;
-use crate::utils::os::{free_file, has_ffmpeg, is_special_file};
-use crate::utils::parse::{parse_url, UrlFilter};
+use crate::utils::matroska::{Attachment, MatroskaRemuxer, SubtitleInput};
+use crate::utils::nfo::write_nfo;
+use crate::utils::notify::Notification;
+use crate::utils::os::{
+    check_free_space, has_ffmpeg, has_ffprobe, is_broken_pipe, is_special_file, part_file,
+    OverwritePolicy,
+};
+use crate::utils::parse::{
+    parse_batch_file, parse_ffmpeg_args, parse_music_url, parse_url, MusicUrl, UrlFilter,
+};
+use crate::utils::progress::{emit_progress, ProgressEvent, ProgressFormat, TransferSummary};
+use crate::utils::resume::ResumeJournal;
+use crate::utils::sanitize::{Normalization, SanitizeMode, Sanitizer};
 use crate::utils::sort::{sort_formats_after_seasons, sort_seasons_after_number};
+use crate::utils::subtitle::{is_forced_subtitle, SubtitleFormat, SubtitleOffset, SubtitleStyle};
+use crate::utils::verify::verify_output;
 use crate::Execute;
-use anyhow::{bail, Result};
-use crunchyroll_rs::media::{Resolution, VariantData};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, NaiveTime, Utc};
+use clap::{ArgMatches, Parser};
+use crunchyroll_rs::media::{Resolution, Stream, StreamSubtitle, VariantData};
 use crunchyroll_rs::{
-    Episode, Locale, Media, MediaCollection, Movie, MovieListing, Season, Series,
+    Concert, Episode, Locale, Media, MediaCollection, Movie, MovieListing, MusicVideo, Season,
+    Series,
 };
+use futures_util::{stream, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressFinish, ProgressStyle};
 use log::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
-#[derive(Debug, clap::Parser)]
+#[derive(Clone, Debug, clap::Parser)]
 #[clap(about = "Download a video")]
 #[command(arg_required_else_help(true))]
 pub struct Download {
-    #[arg(help = format!("Audio language. Can only be used if the provided url(s) point to a series. \
+    #[arg(help = format!("Audio language. Can only be used if the provided url(s) point to a series. Can be used multiple times. \
+    Fallback languages can be given as comma separated list (e.g. 'en-US,ja-JP'). \
     Available languages are: {}", Locale::all().into_iter().map(|l| l.to_string()).collect::<Vec<String>>().join(", ")))]
     #[arg(long_help = format!("Audio language. Can only be used if the provided url(s) point to a series. \
+    Can be used multiple times, every additional language is added as separate audio track to the output file. \
+    The first language is the default audio track. This requires ffmpeg and a matroska ('.mkv') output file. \
+    Fallback languages can be given as comma separated list (e.g. 'en-US,ja-JP'), if an episode is not available with the first language the next one is used. \
     Available languages are:\n{}", Locale::all().into_iter().map(|l| format!("{:<6} → {}", l.to_string(), l.to_human_readable())).collect::<Vec<String>>().join("\n  ")))]
-    #[arg(short, long, default_value_t = crate::utils::locale::system_locale())]
-    audio: Locale,
-    #[arg(help = format!("Subtitle language. Available languages are: {}", Locale::all().into_iter().map(|l| l.to_string()).collect::<Vec<String>>().join(", ")))]
-    #[arg(long_help = format!("Subtitle language. If set, the subtitle will be burned into the video and cannot be disabled. \
+    #[arg(short, long, default_values_t = vec![LocaleChain::from(crate::utils::locale::system_locale())])]
+    #[arg(value_parser = LocaleChain::parse)]
+    pub(crate) audio: Vec<LocaleChain>,
+    #[arg(help = format!("Subtitle language. Can be used multiple times, 'all' selects every available subtitle. \
+    Available languages are: {}", Locale::all().into_iter().map(|l| l.to_string()).collect::<Vec<String>>().join(", ")))]
+    #[arg(long_help = format!("Subtitle language. Can be used multiple times. \
+    If set once, the subtitle will be burned into the video and cannot be disabled. \
+    If set multiple times or set to 'all' (every available subtitle), the subtitles are added as separate tracks which can be turned on and off. \
+    This requires ffmpeg and a matroska ('.mkv') output file. \
+    Fallback languages can be given as comma separated list (e.g. 'en-US,en-GB'), if an episode has no subtitle in the first language the next one is used. \
+    Dubs have forced subtitles (signs & songs) in their own language, which are shown by default. Append ':forced' to select only them (e.g. 'en-US:forced'). \
     Available languages are: {}", Locale::all().into_iter().map(|l| l.to_string()).collect::<Vec<String>>().join(", ")))]
     #[arg(short, long)]
-    subtitle: Option<Locale>,
+    #[arg(value_parser = LocaleChain::parse)]
+    pub(crate) subtitle: Vec<LocaleChain>,
     #[arg(help = format!("Closed Caption language. Available languages are: {}", Locale::all().into_iter().map(|l| l.to_string()).collect::<Vec<String>>().join(", ")))]
     #[arg(long_help = format!("Closed Caption language. If set, the cc will be burned into the video and cannot be disabled. \
     Available languages are: {}", Locale::all().into_iter().map(|l| l.to_string()).collect::<Vec<String>>().join(", ")))]
     #[arg(short, long)]
     closedcaption: Option<Locale>,
+    #[arg(
+        help = "Closed caption languages which are added as separate tracks next to the subtitles. Can be used multiple times"
+    )]
+    #[arg(
+        long_help = "Closed caption languages which are added as separate tracks next to the subtitles. Can be used multiple times. \
+    Closed captions are transcripts of the spoken audio (including sounds), subtitles are translations. \
+    Their tracks are labeled with '[CC]' (e.g. 'English [CC]') to tell them apart. \
+    This requires a matroska ('.mkv') output file"
+    )]
+    #[arg(long)]
+    cc: Vec<Locale>,
+    #[arg(help = "Don't add any closed captions, not even the ones set in the config file")]
+    #[arg(long, conflicts_with_all = ["closedcaption", "cc"])]
+    no_cc: bool,
+    #[arg(
+        help = "Format of the closed caption file and of the subtitles downloaded with '--subs-only'. Valid formats are 'ass', 'srt' and 'vtt'"
+    )]
+    #[arg(
+        long_help = "Format of the closed caption file and of the subtitles downloaded with '--subs-only'. Valid formats are 'ass', 'srt' and 'vtt'. \
+    Use 'srt' if your player or tv cannot handle ass subtitles. \
+    Captions which are positioned at the top of the screen keep their position in srt files via alignment tags"
+    )]
+    #[arg(long, default_value = "ass")]
+    #[arg(value_parser = SubtitleFormat::parse)]
+    subtitle_format: SubtitleFormat,
+    #[arg(help = "Shift the subtitle and closed caption timestamps by the given milliseconds")]
+    #[arg(
+        long_help = "Shift the subtitle and closed caption timestamps by the given milliseconds, e.g. '500' to show them half a second later or '-300' to show them earlier. \
+    To shift only the subtitles of one language, prefix the offset with the language (e.g. 'de-DE=-300'). \
+    Can be used multiple times, an offset for a specific language takes precedence over one without language"
+    )]
+    #[arg(long = "sub-offset", allow_hyphen_values = true)]
+    #[arg(value_parser = SubtitleOffset::parse)]
+    sub_offset: Vec<SubtitleOffset>,
+    #[arg(help = "Font of closed captions which are converted to ass")]
+    #[arg(
+        long_help = "Font of closed captions which are converted to ass (see '--subtitle-format'). \
+    The font must be installed on the device which plays the video"
+    )]
+    #[arg(long)]
+    sub_font: Option<String>,
+    #[arg(help = "Font size of closed captions which are converted to ass")]
+    #[arg(
+        long_help = "Font size of closed captions which are converted to ass (see '--subtitle-format'). \
+    The default conversion produces very small text on TVs, a size around 60 is a good starting point"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    sub_size: Option<u32>,
+    #[arg(help = "Text color of closed captions which are converted to ass, e.g. '#FFFF00'")]
+    #[arg(long)]
+    #[arg(value_parser = SubtitleStyle::parse_color)]
+    sub_color: Option<String>,
+    #[arg(help = "Vertical margin (in pixels) of closed captions which are converted to ass")]
+    #[arg(long)]
+    sub_margin: Option<u32>,
+    #[arg(help = "Attach a font file to the output file. Can be used multiple times")]
+    #[arg(
+        long_help = "Attach a TrueType / OpenType font file to the output file, so subtitles which use it render identically on every player. Can be used multiple times. \
+    Closed captions which are converted to ass use the first attached font, unless '--sub-font' is set. \
+    This requires a matroska ('.mkv') output file"
+    )]
+    #[arg(long)]
+    attach_font: Vec<PathBuf>,
 
     #[arg(help = "Name of the output file")]
-    #[arg(long_help = "Name of the output file.\
+    #[arg(long_help = "Name of the output file. \
+    May contain directories (e.g. '{series}/Season {season:02}/{title}.mkv') which are created if they do not exist. \
     If you use one of the following pattern they will get replaced:\n  \
       {title}                   → Title of the video\n  \
-      {series_name}             → Name of the series\n  \
-      {season_name}             → Name of the season\n  \
+      {series} / {series_name}  → Name of the series\n  \
+      {season_name} / {season_title} → Name of the season\n  \
       {audio}                   → Audio language of the video\n  \
       {resolution}              → Resolution of the video\n  \
       {padded_season_number}    → Number of the season padded to double digits\n  \
-      {season_number}           → Number of the season\n  \
+      {season} / {season_number} → Number of the season\n  \
       {padded_episode_number}   → Number of the episode padded to double digits\n  \
-      {episode_number}          → Number of the episode\n  \
+      {episode} / {episode_number} → Number of the episode\n  \
       {series_id}               → ID of the series\n  \
       {season_id}               → ID of the season\n  \
-      {episode_id}              → ID of the episode")]
+      {episode_id}              → ID of the episode\n  \
+      {artist}                  → Artist of music videos and concerts\n  \
+      {movie_title}             → Title of the movie (empty if the video is no movie)\n  \
+      {year}                    → Release year of the video\n\
+    Numbers can be padded with zeros by appending ':0' and the width to a pattern, e.g. {episode:02} or {episode:03}")]
     #[arg(short, long, default_value = "{title}.ts")]
     output: String,
 
+    #[arg(help = "Directory the output file is put into")]
+    #[arg(
+        long_help = "Directory the output file is put into, e.g. '{series}/{season_title}'. \
+    Supports the same patterns as '--output' and missing directories are created. \
+    The output file name is relative to this directory"
+    )]
+    #[arg(long)]
+    output_dir_template: Option<String>,
+    #[arg(help = "Directory specials are put into instead of '--output-dir-template'")]
+    #[arg(
+        long_help = "Directory specials (recaps, PVs, OVAs, ...) are put into instead of '--output-dir-template', e.g. '{series}/Specials'. \
+    Supports the same patterns as '--output'"
+    )]
+    #[arg(long)]
+    specials_dir: Option<String>,
+    #[arg(
+        help = "Characters which are replaced in file names. Valid modes are 'windows', 'posix' and 'minimal'"
+    )]
+    #[arg(
+        long_help = "Characters which are replaced in the values inserted into the output file name. Valid modes are: \
+    'windows' (the characters ':?\"<>|*\\/' and control characters, trailing dots and spaces and reserved names like 'CON'), \
+    'posix' ('/' and the null character) and \
+    'minimal' ('/' and '\\'). \
+    Use 'windows' if the files should stay copyable to Windows or SMB shares"
+    )]
+    #[arg(long, default_value = "windows", value_parser = SanitizeMode::parse)]
+    sanitize: SanitizeMode,
+    #[arg(
+        help = "Text which replaces invalid characters in file names. Default is to remove them"
+    )]
+    #[arg(long, default_value = "")]
+    sanitize_replacement: String,
+    #[arg(
+        help = "Unicode normalization form of file names. Valid forms are 'nfc', 'nfd', 'nfkc' and 'nfkd'"
+    )]
+    #[arg(
+        long_help = "Unicode normalization form of the values inserted into the output file name. Valid forms are 'nfc', 'nfd', 'nfkc' and 'nfkd'. \
+    macOS stores file names as 'nfd', most other systems as 'nfc'. \
+    'nfkc' and 'nfkd' additionally replace full-width latin letters and digits with their ascii counterparts"
+    )]
+    #[arg(long, value_parser = Normalization::parse)]
+    normalize: Option<Normalization>,
+    #[arg(help = "Transliterate file names to ascii")]
+    #[arg(
+        long_help = "Transliterate the values inserted into the output file name to ascii. \
+    Kana are converted to romaji, other characters to their closest ascii representation. \
+    Note that kanji are transliterated by their chinese reading since they have no unambiguous japanese one"
+    )]
+    #[arg(long)]
+    ascii_filenames: bool,
+
     #[arg(help = "Video resolution")]
     #[arg(long_help = "The video resolution.\
     Can either be specified via the pixels (e.g. 1920x1080), the abbreviation for pixels (e.g. 1080p) or 'common-use' words (e.g. best). \
@@ -64,7 +239,68 @@ pub struct Download {
     The available common-use words are 'best' (choose the best resolution available) and 'worst' (worst resolution available)")]
     #[arg(short, long, default_value = "best")]
     #[arg(value_parser = crate::utils::clap::clap_parse_resolution)]
-    resolution: Resolution,
+    pub(crate) resolution: Resolution,
+
+    #[arg(help = "What to do if the requested resolution is not available. \
+    Valid policies are 'exact', 'best-below' and 'nearest'")]
+    #[arg(
+        long_help = "What to do if the requested resolution is not available. \
+    'exact' (default) fails, 'best-below' uses the highest resolution below the requested one and 'nearest' uses the resolution closest to the requested one. \
+    The resolution which is used instead is logged"
+    )]
+    #[arg(long, default_value = "exact")]
+    #[arg(value_parser = ResolutionPolicy::parse)]
+    pub(crate) resolution_policy: ResolutionPolicy,
+
+    #[arg(help = "Maximum bitrate of the video stream")]
+    #[arg(
+        long_help = "Maximum bitrate of the video stream (per second). \
+    Can be specified as plain bits or with a unit suffix (e.g. 3M or 500k). \
+    If multiple streams have the requested resolution, the one with the highest bitrate below this limit is used. \
+    If no stream is below the limit, the one with the lowest bitrate is used"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_bandwidth)]
+    pub(crate) max_bitrate: Option<u64>,
+
+    #[arg(help = "Preferred framerate of the video stream, e.g. 30 or 60")]
+    #[arg(
+        long_help = "Preferred framerate of the video stream, e.g. 30 or 60. \
+    If multiple streams have the requested resolution, the one with the closest framerate is used"
+    )]
+    #[arg(long)]
+    pub(crate) fps: Option<u32>,
+
+    #[arg(help = "Download segments which keep failing from a lower resolution stream")]
+    #[arg(
+        long_help = "Download segments which keep failing from a lower resolution stream. \
+    Segments which fail are retried after all other segments are downloaded. \
+    If a segment still fails then, it's taken from the next lower resolution stream instead of failing the whole download. \
+    The video briefly has a lower quality at this position"
+    )]
+    #[arg(long, default_value_t = false)]
+    pub(crate) segment_fallback: bool,
+
+    #[arg(
+        help = "Download the rest of the stream from a lower resolution stream after this many segments failed in a row"
+    )]
+    #[arg(
+        long_help = "Download the rest of the stream from a lower resolution stream after this many segments failed in a row. \
+    Useful if the cdn has problems with one resolution: instead of retrying every segment and failing the download, everything from the current position on is downloaded from the next lower resolution stream. \
+    Segments which failed before are taken from the lower resolution stream too (like with '--segment-fallback')"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) downgrade_after: Option<u32>,
+
+    #[arg(help = "Type of the stream which is downloaded. Either 'hls' or 'dash'")]
+    #[arg(
+        long_help = "Type of the stream which is downloaded. Either 'hls' (default) or 'dash'. \
+    DASH streams have separate video and audio streams which are downloaded in parallel and muxed together with ffmpeg afterwards, so ffmpeg is required for them"
+    )]
+    #[arg(long, default_value = "hls")]
+    #[arg(value_parser = StreamType::parse)]
+    pub(crate) stream_type: StreamType,
 
     #[arg(help = format!("Presets for video converting. Can be used multiple times. \
     Available presets: \n  {}", FFmpegPreset::all().into_iter().map(|p| format!("{}: {}", p.to_string(), p.description())).collect::<Vec<String>>().join("\n  ")))]
@@ -75,65 +311,692 @@ pub struct Download {
     #[arg(long)]
     #[arg(value_parser = FFmpegPreset::parse)]
     ffmpeg_preset: Vec<FFmpegPreset>,
+    #[arg(help = "Device which is used for hardware acceleration")]
+    #[arg(long_help = "Device which is used for hardware acceleration. \
+    Only used by the 'vaapi' (defaults to '/dev/dri/renderD128') and 'qsv' presets")]
+    #[arg(long)]
+    hwaccel_device: Option<String>,
+    #[arg(help = "Constant quality of the encoded video, lower values mean better quality")]
+    #[arg(
+        long_help = "Constant quality of the encoded video, lower values mean better quality. \
+    Requires a codec preset. It is translated to the quality option of the used encoder (crf for software encoders, cq for nvidia, qp for vaapi and global_quality for qsv). \
+    Sensible values are around 18-28 for h264 / h265 and 25-35 for av1"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = clap::value_parser!(u8).range(0..=63))]
+    crf: Option<u8>,
+    #[arg(help = "Bitrate of the encoded video (e.g. 5M or 2500k)")]
+    #[arg(long_help = "Bitrate of the encoded video (e.g. 5M or 2500k). \
+    Requires a codec preset and cannot be used together with '--crf'")]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_bitrate)]
+    video_bitrate: Option<String>,
+    #[arg(help = "Encoding speed. Valid presets are 'slow', 'medium' and 'fast'")]
+    #[arg(
+        long_help = "Encoding speed. Valid presets are 'slow', 'medium' and 'fast'. \
+    Slower presets result in smaller files with the same quality. Requires a codec preset"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = EncodePreset::parse)]
+    encode_preset: Option<EncodePreset>,
+    #[arg(help = "Audio codec. Valid codecs are 'aac', 'opus' and 'copy'")]
+    #[arg(long_help = "Audio codec. Valid codecs are 'aac', 'opus' and 'copy'. \
+    By default, the audio is copied without re-encoding it ('copy'). \
+    Re-encoding the audio, e.g. with opus and a lower bitrate, can noticeably reduce the file size")]
+    #[arg(long)]
+    #[arg(value_parser = AudioCodec::parse)]
+    audio_codec: Option<AudioCodec>,
+    #[arg(help = "Bitrate of the encoded audio (e.g. 128k). Requires '--audio-codec'")]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_bitrate)]
+    audio_bitrate: Option<String>,
+    #[arg(help = "Additional ffmpeg input arguments")]
+    #[arg(long_help = "Additional ffmpeg input arguments. \
+    They are added after the arguments of the ffmpeg presets and before the input video. \
+    Arguments containing whitespaces must be quoted, e.g. --ffmpeg-input-args=\"-hwaccel_flags allow_profile_mismatch\"")]
+    #[arg(long, allow_hyphen_values = true)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_ffmpeg_args)]
+    ffmpeg_input_args: Option<String>,
+    #[arg(help = "Additional ffmpeg output arguments")]
+    #[arg(long_help = "Additional ffmpeg output arguments. \
+    They are added after all other output arguments, so they can overwrite them. \
+    Arguments containing whitespaces must be quoted, e.g. --ffmpeg-output-args=\"-pix_fmt yuv420p10le -tune animation\"")]
+    #[arg(long, allow_hyphen_values = true)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_ffmpeg_args)]
+    ffmpeg_output_args: Option<String>,
     #[arg(help = format!("Specify a season id if known."))]
     #[arg(long_help = format!("Specify a season ID if known, those usually look similar to episode or series IDs. \
     Example: \
     GRZXCMJKG - Chainsaw Man English Dub Season 1"))]
     #[arg(long)]
     seasonid: Option<String>,
-    #[arg(help = format!("Specify video skip and only get CC."))]
-    #[arg(long_help = format!("Specify wether to download only the subtitles or not."))]
+    #[arg(help = "Download only the subtitles")]
+    #[arg(
+        long_help = "Skip the video and download only the subtitles given with '--subtitle' (and the closed captions given with '--closedcaption'). \
+    Every subtitle is written to its own file, named like the video would be with the subtitle language appended (e.g. 'Episode 1.en-US.ass'), so the output template can be used to organize them into folders. \
+    The format of the files can be set with '--subtitle-format'"
+    )]
+    #[arg(long)]
+    subs_only: bool,
+    #[arg(help = "Write the subtitles as separate files next to the video")]
+    #[arg(
+        long_help = "Write the subtitles given with '--subtitle' as separate files next to the video, named like the video with the subtitle language appended (e.g. 'Episode 1.en-US.ass'). \
+    Plex, Jellyfin and most players pick them up automatically. \
+    Matroska ('.mkv') files still get the subtitles as tracks, every other format gets only the external files. \
+    The format of the files can be set with '--subtitle-format'"
+    )]
+    #[arg(long)]
+    external_subs: bool,
+
+    #[arg(help = "Download only the audio")]
+    #[arg(
+        long_help = "Download only the audio of the episodes / movies. \
+    The audio is written to a '.m4a' file without re-encoding it, if the output file has the '.opus' extension it is re-encoded to opus instead. \
+    DASH streams (see '--stream-type') have a separate audio stream, so only the audio is downloaded. \
+    HLS streams contain video and audio, the video is discarded after the download then. \
+    Requires ffmpeg"
+    )]
+    #[arg(long)]
+    audio_only: bool,
+
+    #[arg(help = "Add chapters (intro, episode, ending, ...) to the output file")]
+    #[arg(
+        long_help = "Add chapters (recap, intro, episode, ending and preview) to the output file. \
+    They are generated from the skip events Crunchyroll provides for its 'skip intro' buttons, so not every episode has them. \
+    Requires ffmpeg and an output format which supports chapters (e.g. '.mkv' or '.mp4')"
+    )]
+    #[arg(long)]
+    chapters: bool,
+    #[arg(
+        help = "Write the chapters to a separate file next to the video. Valid formats are 'txt' (default) and 'xml'"
+    )]
+    #[arg(
+        long_help = "Write the chapters (see '--chapters') to a separate file next to the video, e.g. to post-process the video with mkvtoolnix. \
+    The file is named like the video with '.chapters.txt' or '.chapters.xml' appended. \
+    Valid formats are 'txt' (OGM chapters, default) and 'xml' (Matroska XML chapters), e.g. '--write-chapters=xml'"
+    )]
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "txt")]
+    #[arg(value_parser = ChapterFormat::parse)]
+    write_chapters: Option<ChapterFormat>,
+
+    #[arg(help = "Embed the series poster and episode thumbnail as cover art")]
+    #[arg(
+        long_help = "Embed the series poster and episode thumbnail as cover art. \
+    Requires ffmpeg and a matroska ('.mkv') or mp4 ('.mp4') output file. \
+    Mp4 files can only contain one cover, the series poster is used then"
+    )]
+    #[arg(long)]
+    embed_cover: bool,
+
+    #[arg(help = "Write nfo metadata files for media centers like Kodi or Jellyfin")]
+    #[arg(
+        long_help = "Write nfo metadata files for media centers like Kodi or Jellyfin. \
+    An nfo file is written next to every downloaded episode and a 'tvshow.nfo' to the series directory. \
+    The series directory is the first directory in the output path which contains the series name, or the directory of the episode if no such directory exists"
+    )]
+    #[arg(long)]
+    write_nfo: bool,
+
+    #[arg(
+        help = "File which records the ids of all downloaded episodes, they're skipped on subsequent runs"
+    )]
+    #[arg(
+        long_help = "File which records the ids of all downloaded episodes, they're skipped on subsequent runs. \
+    The file contains one episode id per line and is created if it does not exist. \
+    Useful to run crunchy-cli on a schedule against ongoing series"
+    )]
+    #[arg(long)]
+    pub(crate) download_archive: Option<PathBuf>,
+    #[arg(help = "Line format of the download archive. Valid formats are 'plain' and 'yt-dlp'")]
+    #[arg(
+        long_help = "Line format of the download archive. Valid formats are 'plain' (one episode id per line) and 'yt-dlp' ('crunchyrollbeta <id>' per line, like yt-dlp writes it). \
+    Archives in both formats are read regardless of this option, so an archive of yt-dlp can be passed to '--download-archive' directly. \
+    If not set, new episodes are recorded in the format of the existing entries"
+    )]
+    #[arg(long, value_parser = ArchiveFormat::parse)]
+    pub(crate) download_archive_format: Option<ArchiveFormat>,
+    #[arg(help = "Skip episodes whose output file already exists and is complete")]
+    #[arg(
+        long_help = "Skip episodes whose output file already exists and is complete, before anything of them is downloaded. \
+    A file is complete if it is recorded in the download archive (see '--download-archive') or if ffprobe reports a duration close to the episode length. \
+    Without ffprobe every existing file counts as complete, since files are only renamed to their final name after the download finished. \
+    Incomplete files are downloaded again to a new file"
+    )]
+    #[arg(long)]
+    skip_existing: bool,
+    #[arg(help = "Overwrite existing output files")]
+    #[arg(long_help = "Overwrite existing output files. \
+    Applies to the video file as well as to subtitle, chapter and nfo files which are written next to it")]
+    #[arg(long, conflicts_with_all = ["no_overwrite", "rename_on_conflict"])]
+    overwrite: bool,
+    #[arg(help = "Don't write output files which already exist")]
+    #[arg(long_help = "Don't write output files which already exist. \
+    If the video file exists, the episode is skipped entirely. \
+    Subtitle, chapter and nfo files which exist are left untouched. \
+    Unlike '--skip-existing' this does not check if the existing file is complete")]
+    #[arg(long, conflicts_with = "rename_on_conflict")]
+    no_overwrite: bool,
+    #[arg(help = "Write to a new file if an output file already exists (default)")]
+    #[arg(long_help = "Write to a new file if an output file already exists. \
+    ' (1)', ' (2)', ... is appended to the file name until it does not exist. \
+    This is the default behavior")]
+    #[arg(long)]
+    rename_on_conflict: bool,
+    #[arg(help = "Mark downloaded episodes as watched on Crunchyroll")]
+    #[arg(
+        long_help = "Mark downloaded episodes as watched on Crunchyroll. \
+    After an episode was downloaded successfully, its playback is reported as completed so the watch progress on the website stays in sync. \
+    Requires a logged in account"
+    )]
+    #[arg(long)]
+    mark_watched: bool,
+
+    #[arg(help = "Command which is executed before the episodes of an url are downloaded")]
+    #[arg(
+        long_help = "Command which is executed before the episodes of an url are downloaded. \
+    The command is run by the system shell, the environment variables CRUNCHY_URL, CRUNCHY_SERIES and CRUNCHY_EPISODES (number of episodes) describe the url. \
+    If the command fails, nothing is downloaded"
+    )]
+    #[arg(long)]
+    exec_before: Option<String>,
+
+    #[arg(help = "Command which is executed after every downloaded episode")]
+    #[arg(
+        long_help = "Command which is executed after every downloaded episode. \
+    The command is run by the system shell, the environment variables CRUNCHY_PATH, CRUNCHY_TITLE, CRUNCHY_ID, CRUNCHY_SERIES, CRUNCHY_SERIES_ID, CRUNCHY_SEASON, CRUNCHY_SEASON_TITLE, CRUNCHY_EPISODE and CRUNCHY_AUDIO describe the episode. \
+    Useful to e.g. rename, transcode or refresh a media library"
+    )]
+    #[arg(long)]
+    exec_after: Option<String>,
+
+    #[arg(help = "Number of episodes which are downloaded simultaneously")]
+    #[arg(long, default_value_t = 1)]
+    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+
+    #[arg(help = "File with one url per line, optionally followed by arguments for this url")]
+    #[arg(
+        long_help = "File with one url per line, optionally followed by arguments for this url (e.g. 'https://www.crunchyroll.com/series/... -r 720p -a en-US'). \
+    Arguments of a line only apply to its url and take precedence over the arguments given on the command line. \
+    Empty lines and lines starting with '#' are ignored. \
+    The urls are processed one after another, use '--jobs' to download multiple episodes of a url simultaneously. \
+    A summary of all urls is shown when every url was processed"
+    )]
+    #[arg(long)]
+    batch_file: Option<PathBuf>,
+
+    #[arg(
+        help = "Only download the given seasons / episodes of series urls (e.g. 'S2E3-S3E5', 'E10+' or 'S1,S3')"
+    )]
+    #[arg(
+        long_help = "Only download the given seasons / episodes of series urls. \
+    Ranges are given as 'S2E3-S3E5' (season 2, episode 3 until season 3, episode 5), open ranges as 'E10+' or 'S2-' and multiple selections are separated by a comma (e.g. 'S1,S3'). \
+    The filter is applied to every url, in addition to a filter given at the end of an url"
+    )]
+    #[arg(short, long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_filter)]
+    pub(crate) filter: Option<UrlFilter>,
+
+    #[arg(help = "Skip specials (recaps, PVs, ...) when downloading whole seasons")]
+    #[arg(long_help = "Skip specials when downloading whole seasons. \
+    Specials are episodes without a regular episode number (e.g. recaps, PVs or OVAs which are part of a season)")]
+    #[arg(long, default_value_t = false)]
+    skip_specials: bool,
+    #[arg(
+        help = "Only download specials (recaps, PVs, ...) of whole seasons. The inverse of '--skip-specials'"
+    )]
+    #[arg(long, default_value_t = false)]
+    only_specials: bool,
+    #[arg(
+        help = "What to do with seasons of series urls which are movies. Either 'include', 'skip' or 'only'"
+    )]
+    #[arg(
+        long_help = "What to do with seasons of series urls which are movies. \
+    Some series bundle their movies as own season, 'include' (default) downloads them like every other season, 'skip' skips them and 'only' downloads only them. \
+    Movie seasons are recognized by their title"
+    )]
+    #[arg(long, default_value = "include")]
+    #[arg(value_parser = MovieFilter::parse)]
+    movies: MovieFilter,
+
+    #[arg(help = "Do not check if there is enough free disk space before downloading")]
+    #[arg(
+        long_help = "Do not check if there is enough free disk space before downloading. \
+    By default the size of all episodes of an url is estimated before they're downloaded and the download is aborted if there is not enough free space on the target filesystem"
+    )]
+    #[arg(long, default_value_t = false)]
+    ignore_space: bool,
+
+    #[arg(help = "Only episodes which were released at or after the given date are downloaded")]
+    #[arg(
+        long_help = "Only episodes which were released at or after the given date are downloaded. \
+    The date can be given as 'YYYY-MM-DD' or relative to now as duration, e.g. '7d' for everything which was released in the last week"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_date)]
+    released_after: Option<DateTime<Utc>>,
+    #[arg(help = "Only episodes which were released at or before the given date are downloaded")]
+    #[arg(
+        long_help = "Only episodes which were released at or before the given date are downloaded. \
+    The date can be given as 'YYYY-MM-DD' or relative to now as duration (e.g. '7d')"
+    )]
+    #[arg(long)]
+    #[arg(value_parser = crate::utils::clap::clap_parse_date)]
+    released_before: Option<DateTime<Utc>>,
+
+    #[arg(help = "Do not verify the downloaded files")]
+    #[arg(long_help = "Do not verify the downloaded files. \
+    By default every downloaded file is checked with ffprobe (if installed) for missing tracks and a truncated duration. \
+    Corrupted files are downloaded again (see '--verify-retries')")]
+    #[arg(long, default_value_t = false)]
+    no_verify: bool,
+    #[arg(help = "How often a corrupted file is downloaded again before giving up")]
+    #[arg(long, default_value_t = 1)]
+    verify_retries: u32,
+
+    #[arg(help = "Download everything on the watchlist of your account")]
+    #[arg(
+        long_help = "Download all series and movies on the watchlist of your account, in addition to the given urls. \
+    Together with the 'watch' command and a download archive, the watchlist becomes the list of series which are checked for new episodes. \
+    Requires a logged in account"
+    )]
+    #[arg(long)]
+    from_watchlist: bool,
+    #[arg(help = "Download everything on the given crunchylist of your account")]
+    #[arg(
+        long_help = "Download all entries of the crunchylist with the given name or id, in addition to the given urls. \
+    The name is matched case insensitive, all crunchylists can be shown with 'crunchylist list'. \
+    Requires a logged in account"
+    )]
+    #[arg(long)]
+    crunchylist: Option<String>,
+
+    #[arg(help = "Download the next episodes of every series you are currently watching")]
+    #[arg(
+        long_help = "Download the next unwatched episodes of every series in the watch history of your account, in addition to the given urls. \
+    The episodes continue after the last watched episode, in the same audio language. \
+    How many episodes per series are downloaded can be set with '--count'. \
+    Requires a logged in account"
+    )]
     #[arg(long)]
-    subs_only: Option<bool>,
+    continue_watching: bool,
+    #[arg(help = "Number of episodes per series which are downloaded with '--continue-watching'")]
+    #[arg(long, default_value_t = 1, requires = "continue_watching")]
+    count: usize,
 
     #[arg(help = "Url(s) to Crunchyroll episodes or series")]
-    urls: Vec<String>,
+    pub(crate) urls: Vec<String>,
 }
 
-#[async_trait::async_trait(?Send)]
-impl Execute for Download {
-    fn pre_check(&self) -> Result<()> {
-        if has_ffmpeg() {
-            debug!("FFmpeg detected")
-        } else if PathBuf::from(&self.output)
-            .extension()
-            .unwrap_or_default()
-            .to_string_lossy()
-            != "ts"
-        {
-            bail!("File extension is not '.ts'. If you want to use a custom file format, please install ffmpeg")
-        } else if !self.ffmpeg_preset.is_empty() {
-            bail!("FFmpeg is required to use (ffmpeg) presets")
+impl Download {
+    pub(crate) fn apply_config(
+        &mut self,
+        config: DownloadConfig,
+        matches: &ArgMatches,
+    ) -> Result<()> {
+        apply_config(matches, "audio", &mut self.audio, config.audio, |locales| {
+            if locales.is_empty() {
+                Err("must contain at least one language".to_string())
+            } else {
+                locales.iter().map(|l| LocaleChain::parse(l)).collect()
+            }
+        })?;
+        apply_config(
+            matches,
+            "subtitle",
+            &mut self.subtitle,
+            config.subtitle,
+            |locales| locales.iter().map(|l| LocaleChain::parse(l)).collect(),
+        )?;
+        apply_config(
+            matches,
+            "subtitle_format",
+            &mut self.subtitle_format,
+            config.subtitle_format,
+            |f| SubtitleFormat::parse(&f),
+        )?;
+        apply_config(matches, "cc", &mut self.cc, config.cc, |locales| {
+            Ok(locales.into_iter().map(Locale::from).collect())
+        })?;
+        // closed captions of the config file are dropped if they were explicitly turned off
+        if self.no_cc {
+            self.cc.clear()
         }
+        apply_config(
+            matches,
+            "sub_font",
+            &mut self.sub_font,
+            config.sub_font,
+            |f| Ok(Some(f)),
+        )?;
+        apply_config(
+            matches,
+            "sub_size",
+            &mut self.sub_size,
+            config.sub_size,
+            |s| {
+                if s == 0 {
+                    Err("must be at least 1".to_string())
+                } else {
+                    Ok(Some(s))
+                }
+            },
+        )?;
+        apply_config(
+            matches,
+            "sub_color",
+            &mut self.sub_color,
+            config.sub_color,
+            |c| SubtitleStyle::parse_color(&c).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "sub_margin",
+            &mut self.sub_margin,
+            config.sub_margin,
+            |m| Ok(Some(m)),
+        )?;
+        apply_config(matches, "output", &mut self.output, config.output, Ok)?;
+        apply_config(
+            matches,
+            "output_dir_template",
+            &mut self.output_dir_template,
+            config.output_dir_template,
+            |d| Ok(Some(d)),
+        )?;
+        apply_config(
+            matches,
+            "specials_dir",
+            &mut self.specials_dir,
+            config.specials_dir,
+            |d| Ok(Some(d)),
+        )?;
+        apply_config(
+            matches,
+            "sanitize",
+            &mut self.sanitize,
+            config.sanitize,
+            |s| SanitizeMode::parse(&s),
+        )?;
+        apply_config(
+            matches,
+            "sanitize_replacement",
+            &mut self.sanitize_replacement,
+            config.sanitize_replacement,
+            Ok,
+        )?;
+        apply_config(
+            matches,
+            "normalize",
+            &mut self.normalize,
+            config.normalize,
+            |n| Normalization::parse(&n).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "resolution",
+            &mut self.resolution,
+            config.resolution,
+            |r| clap_parse_resolution(&r),
+        )?;
+        apply_config(
+            matches,
+            "resolution_policy",
+            &mut self.resolution_policy,
+            config.resolution_policy,
+            |p| ResolutionPolicy::parse(&p),
+        )?;
+        apply_config(
+            matches,
+            "max_bitrate",
+            &mut self.max_bitrate,
+            config.max_bitrate,
+            |b| clap_parse_bandwidth(&b).map(Some),
+        )?;
+        apply_config(matches, "fps", &mut self.fps, config.fps, |f| Ok(Some(f)))?;
+        apply_config(
+            matches,
+            "stream_type",
+            &mut self.stream_type,
+            config.stream_type,
+            |t| StreamType::parse(&t),
+        )?;
+        apply_config(
+            matches,
+            "ffmpeg_preset",
+            &mut self.ffmpeg_preset,
+            config.ffmpeg_preset,
+            |presets| presets.iter().map(|p| FFmpegPreset::parse(p)).collect(),
+        )?;
+        apply_config(
+            matches,
+            "hwaccel_device",
+            &mut self.hwaccel_device,
+            config.hwaccel_device,
+            |d| Ok(Some(d)),
+        )?;
+        apply_config(matches, "crf", &mut self.crf, config.crf, |c| {
+            if c > 63 {
+                Err("must be between 0 and 63".to_string())
+            } else {
+                Ok(Some(c))
+            }
+        })?;
+        apply_config(
+            matches,
+            "video_bitrate",
+            &mut self.video_bitrate,
+            config.video_bitrate,
+            |b| clap_parse_bitrate(&b).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "encode_preset",
+            &mut self.encode_preset,
+            config.encode_preset,
+            |p| EncodePreset::parse(&p).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "audio_codec",
+            &mut self.audio_codec,
+            config.audio_codec,
+            |c| AudioCodec::parse(&c).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "audio_bitrate",
+            &mut self.audio_bitrate,
+            config.audio_bitrate,
+            |b| clap_parse_bitrate(&b).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "ffmpeg_input_args",
+            &mut self.ffmpeg_input_args,
+            config.ffmpeg_input_args,
+            |a| clap_parse_ffmpeg_args(&a).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "ffmpeg_output_args",
+            &mut self.ffmpeg_output_args,
+            config.ffmpeg_output_args,
+            |a| clap_parse_ffmpeg_args(&a).map(Some),
+        )?;
+        apply_config(
+            matches,
+            "download_archive",
+            &mut self.download_archive,
+            config.download_archive,
+            |p| Ok(Some(p)),
+        )?;
+        apply_config(
+            matches,
+            "download_archive_format",
+            &mut self.download_archive_format,
+            config.download_archive_format,
+            |f| ArchiveFormat::parse(&f).map(Some),
+        )?;
+        apply_config(matches, "jobs", &mut self.jobs, config.jobs, |j| {
+            if j == 0 {
+                Err("must be at least 1".to_string())
+            } else {
+                Ok(j)
+            }
+        })?;
+        apply_config(
+            matches,
+            "exec_before",
+            &mut self.exec_before,
+            config.exec_before,
+            |c| Ok(Some(c)),
+        )?;
+        apply_config(
+            matches,
+            "exec_after",
+            &mut self.exec_after,
+            config.exec_after,
+            |c| Ok(Some(c)),
+        )?;
 
-        let _ = FFmpegPreset::ffmpeg_presets(self.ffmpeg_preset.clone())?;
-        if self.ffmpeg_preset.len() == 1
-            && self.ffmpeg_preset.get(0).unwrap() == &FFmpegPreset::Nvidia
-        {
-            warn!("Skipping 'nvidia' hardware acceleration preset since no other codec preset was specified")
+        Ok(())
+    }
+
+    /// The given urls and, if `--from-watchlist`, `--crunchylist` or `--continue-watching` is
+    /// set, the urls of everything on the watchlist, the crunchylist or the next episodes of the
+    /// watch history.
+    async fn all_urls(&self, ctx: &Context) -> Result<Vec<String>> {
+        let mut urls = self.urls.clone();
+        if self.from_watchlist {
+            let progress_handler = progress!("Fetching watchlist");
+            let watchlist = watchlist(&ctx.crunchy).await?;
+            progress_handler.stop(format!(
+                "Found {} series and movies on the watchlist",
+                watchlist.len()
+            ));
+            urls.extend(watchlist.into_iter().map(|entry| entry.url))
+        }
+        if let Some(list) = &self.crunchylist {
+            let progress_handler = progress!("Fetching crunchylist");
+            let entries = crunchylist(&ctx.crunchy, list).await?;
+            progress_handler.stop(format!(
+                "Found {} entries on the crunchylist",
+                entries.len()
+            ));
+            urls.extend(entries.into_iter().map(|entry| entry.url))
+        }
+        if self.continue_watching {
+            let progress_handler = progress!("Fetching watch history");
+            let episodes = continue_watching(&ctx.crunchy, self.count).await?;
+            progress_handler.stop(format!(
+                "Found {} episodes to continue watching",
+                episodes.len()
+            ));
+            urls.extend(
+                episodes
+                    .into_iter()
+                    .map(|e| format!("https://www.crunchyroll.com/watch/{}", e.id)),
+            )
         }
+        Ok(urls)
+    }
 
-        Ok(())
+    /// Resolve the formats of all episodes of the given urls without downloading them.
+    pub(crate) async fn formats(&self, ctx: &Context) -> Result<Vec<Format>> {
+        let mut formats = vec![];
+
+        for url in &self.all_urls(ctx).await? {
+            if let Some(music_url) = parse_music_url(url) {
+                formats.extend(format_from_music(ctx, self, music_url).await?);
+                continue;
+            }
+            let (media_collection, mut url_filter) =
+                match parse_url(&ctx.crunchy, url.clone(), true).await {
+                    Ok(parsed) => parsed,
+                    Err(e) => bail!("url {} could not be parsed: {}", url, e),
+                };
+            if let Some(filter) = &self.filter {
+                url_filter = url_filter.and(filter.clone())
+            }
+
+            let url_formats = match media_collection {
+                MediaCollection::Series(series) => {
+                    formats_from_series(self, series, &url_filter).await?
+                }
+                MediaCollection::Season(season) => {
+                    formats_from_season(self, season, &url_filter).await?
+                }
+                MediaCollection::Episode(episode) => {
+                    format_from_episode(self, episode, &url_filter, None)
+                        .await?
+                        .map(|fmt| vec![fmt])
+                }
+                MediaCollection::MovieListing(movie_listing) => {
+                    format_from_movie_listing(self, movie_listing, &url_filter).await?
+                }
+                MediaCollection::Movie(movie) => format_from_movie(self, movie, None)
+                    .await?
+                    .map(|fmt| vec![fmt]),
+            };
+            formats.extend(url_formats.unwrap_or_default())
+        }
+
+        Ok(formats)
     }
 
-    async fn execute(self, ctx: Context) -> Result<()> {
+    /// Download all episodes of the given urls and return the number of downloaded episodes. Unlike
+    /// [`Execute::execute`], the download can be run multiple times (e.g. by `watch`).
+    pub(crate) async fn download(&self, ctx: &Context) -> Result<usize> {
         let mut parsed_urls = vec![];
+        let mut finished = 0;
+        let mut size = 0;
+        let start = ctx.pause.stopwatch();
+        let download_archive = self
+            .download_archive
+            .as_deref()
+            .map(|path| DownloadArchive::open(path, self.download_archive_format))
+            .transpose()?;
 
-        for (i, url) in self.urls.iter().enumerate() {
+        let urls = self.all_urls(ctx).await?;
+        for (i, url) in urls.iter().enumerate() {
             let progress_handler = progress!("Parsing url {}", i + 1);
+            if let Some(music_url) = parse_music_url(url) {
+                parsed_urls.push((ParsedUrl::Music(music_url), UrlFilter::default()));
+                progress_handler.stop(format!("Parsed url {}", i + 1));
+                continue;
+            }
             match parse_url(&ctx.crunchy, url.clone(), true).await {
-                Ok((media_collection, url_filter)) => {
-                    parsed_urls.push((media_collection, url_filter));
+                Ok((media_collection, mut url_filter)) => {
+                    if let Some(filter) = &self.filter {
+                        url_filter = url_filter.and(filter.clone())
+                    }
+                    parsed_urls.push((ParsedUrl::Media(media_collection), url_filter));
                     progress_handler.stop(format!("Parsed url {}", i + 1))
                 }
                 Err(e) => bail!("url {} could not be parsed: {}", url, e),
             }
         }
         let mut ep_collection: Option<Vec<Media<crunchyroll_rs::Episode>>> = None;
-        for (i, (media_collection, url_filter)) in parsed_urls.into_iter().enumerate() {
+        for (i, (parsed_url, url_filter)) in parsed_urls.into_iter().enumerate() {
+            if self.audio.len() > 1
+                && !matches!(parsed_url, ParsedUrl::Media(MediaCollection::Series(_)))
+            {
+                warn!(
+                    "Multiple audio languages are only supported for series urls, url {} is downloaded without additional audio",
+                    i + 1
+                )
+            }
+
             let progress_handler = progress!("Fetching series details");
-            let formats = match media_collection {
-                MediaCollection::Series(series) => {
+            let formats = match parsed_url {
+                ParsedUrl::Music(music_url) => {
+                    debug!("Url {} is {:?}", i + 1, music_url);
+                    format_from_music(ctx, self, music_url)
+                        .await?
+                        .map(|fmt| vec![fmt])
+                }
+                ParsedUrl::Media(MediaCollection::Series(series)) => {
                     debug!("Url {} is series ({})", i + 1, series.title);
 
                     let test = series.seasons().await?;
@@ -167,9 +1030,9 @@ impl Execute for Download {
                             }
                         }
                     }
-                    formats_from_series(&self, series, &url_filter).await?
+                    formats_from_series(self, series, &url_filter).await?
                 }
-                MediaCollection::Season(season) => {
+                ParsedUrl::Media(MediaCollection::Season(season)) => {
                     debug!(
                         "Url {} is season {} ({})",
                         i + 1,
@@ -177,9 +1040,9 @@ impl Execute for Download {
                         season.title
                     );
                     ep_collection = Some(season.episodes().await?);
-                    formats_from_season(&self, season, &url_filter).await?
+                    formats_from_season(self, season, &url_filter).await?
                 }
-                MediaCollection::Episode(episode) => {
+                ParsedUrl::Media(MediaCollection::Episode(episode)) => {
                     debug!(
                         "Url {} is episode {} ({}) of season {} ({}) of {}",
                         i + 1,
@@ -191,29 +1054,55 @@ impl Execute for Download {
                     );
 
                     ep_collection = Some(vec![episode.clone()]);
-                    format_from_episode(&self, episode, &url_filter, false)
+                    format_from_episode(self, episode, &url_filter, None)
                         .await?
                         .map(|fmt| vec![fmt])
                 }
-                MediaCollection::MovieListing(movie_listing) => {
+                ParsedUrl::Media(MediaCollection::MovieListing(movie_listing)) => {
                     debug!("Url {} is movie listing ({})", i + 1, movie_listing.title);
-                    format_from_movie_listing(&self, movie_listing, &url_filter).await?
+                    format_from_movie_listing(self, movie_listing, &url_filter).await?
                 }
-                MediaCollection::Movie(movie) => {
+                ParsedUrl::Media(MediaCollection::Movie(movie)) => {
                     debug!("Url {} is movie ({})", i + 1, movie.title);
-                    format_from_movie(&self, movie, &url_filter)
+                    format_from_movie(self, movie, None)
                         .await?
                         .map(|fmt| vec![fmt])
                 }
             };
 
-            let Some(formats) = formats else {
-                progress_handler.stop(format!("Skipping url {} (no matching episodes found)", i + 1));
+            let Some(mut formats) = formats else {
+                progress_handler.stop(format!(
+                    "Skipping url {} (no matching episodes found)",
+                    i + 1
+                ));
                 continue;
             };
+            if let Some(download_archive) = &download_archive {
+                formats.retain(|f| !download_archive.contains(&f.id));
+                if formats.is_empty() {
+                    progress_handler.stop(format!(
+                        "Skipping url {} (all episodes are already in the download archive)",
+                        i + 1
+                    ));
+                    continue;
+                }
+            }
             progress_handler.stop(format!("Loaded series information for url {}", i + 1));
 
-            if log::max_level() == log::Level::Debug {
+            if !self.ignore_space
+                && !self.writes_to_stdout()
+                && !self.subs_only
+                && !formats.is_empty()
+            {
+                let path = self.output_path(&formats[0]);
+                if !is_special_file(&path) {
+                    let size = formats.iter().map(|f| f.estimated_size()).sum();
+                    check_free_space(&path, size)
+                        .map_err(|e| anyhow!("{}. Use '--ignore-space' to download anyway", e))?
+                }
+            }
+
+            if console_level() == log::Level::Debug {
                 let seasons = sort_formats_after_seasons(formats.clone());
                 debug!("Series has {} seasons", seasons.len());
                 for (i, season) in seasons.into_iter().enumerate() {
@@ -256,228 +1145,1719 @@ impl Execute for Download {
                 }
             }
 
-            for format in formats {
-                let mut path = PathBuf::from(&self.output);
-                path = free_file(
-                    path.with_file_name(format_string(
-                        if let Some(fname) = path.file_name() {
-                            fname.to_str().unwrap()
-                        } else {
-                            "{title}.ts"
-                        }
-                        .to_string(),
-                        &format,
-                        true,
-                    )),
-                );
+            if let Some(exec_before) = &self.exec_before {
+                run_hook(
+                    exec_before,
+                    batch_env(&urls[i], &formats.iter().collect::<Vec<&Format>>()),
+                )?
+            }
 
-                if self.closedcaption.is_some() && ep_collection.is_some() {
-                    let ccpath: &mut PathBuf = &mut path.clone();
-                    ccpath.set_extension("ass");
-                    let ep = ep_collection
-                        .clone()
-                        .unwrap()
-                        .remove(format.number as usize - 1);
-                    download_cc(
-                        &ctx,
-                        ccpath.to_str().unwrap().to_string(),
-                        ep.streams()
-                            .await?
-                            .closed_captions
-                            .get(&self.closedcaption.as_ref().unwrap())
-                            .unwrap()
-                            .url
-                            .to_string(),
-                    )
-                    .await?;
-                };
-                tab_info!(
-                    "Downloading {} to '{}'",
-                    format.title,
-                    if is_special_file(&path) {
-                        path.to_str().unwrap()
-                    } else {
-                        path.file_name().unwrap().to_str().unwrap()
+            let formats_len = formats.len();
+            if self.jobs > 1 {
+                // concurrent downloads resolve their output path (overwrite policy, resume
+                // journal) independently, two of them with the same target would write into the
+                // same file
+                let mut targets = HashSet::new();
+                for format in &formats {
+                    let mut path = self.output_path(format);
+                    if self.audio_only {
+                        path = audio_only_path(path)
                     }
-                );
-                tab_info!("Episode: S{:02}E{:02}", format.season_number, format.number);
-                tab_info!("Audio: {}", format.audio);
-                tab_info!(
-                    "Subtitles: {}",
-                    self.subtitle
-                        .clone()
-                        .map_or("None".to_string(), |l| l.to_string())
-                );
-                tab_info!(
-                    "Closed Captions: {}",
-                    self.closedcaption
-                        .clone()
-                        .map_or("None".to_string(), |l| l.to_string())
-                );
-                tab_info!("Resolution: {}", format.stream.resolution);
-                tab_info!("FPS: {:.2}", format.stream.fps);
-                if self.subs_only.is_some() {
-                    if self.subs_only.unwrap() {
-                        tab_info!("Skipping video");
-                        continue;
+                    if !targets.insert(path.clone()) {
+                        bail!(
+                            "Multiple episodes would be written to {} at the same time. Use an output template which is unique per episode (e.g. with {{episode_number}}) or '--jobs 1'",
+                            path.to_string_lossy()
+                        )
                     }
                 }
-                let extension = path.extension().unwrap_or_default().to_string_lossy();
 
-                if (!extension.is_empty() && extension != "ts") || !self.ffmpeg_preset.is_empty() {
-                    download_ffmpeg(&ctx, &self, format.stream, path.as_path()).await?;
-                } else if path.to_str().unwrap() == "-" {
-                    let mut stdout = std::io::stdout().lock();
-                    download_segments(&ctx, &mut stdout, None, format.stream).await?;
+                let multi_progress = MultiProgress::new();
+                let overall_progress = if ctx.progress_format == ProgressFormat::Json {
+                    ProgressBar::hidden()
                 } else {
-                    // create parent directory if it does not exist
-                    if let Some(parent) = path.parent() {
-                        if !parent.exists() {
-                            std::fs::create_dir_all(parent)?
-                        }
-                    }
-                    let mut file = File::options().create(true).write(true).open(&path)?;
-                    download_segments(&ctx, &mut file, None, format.stream).await?
+                    multi_progress.add(
+                        ProgressBar::new(formats.len() as u64)
+                            .with_style(
+                                ProgressStyle::with_template(":: {msg} [{wide_bar}] {pos}/{len}")
+                                    .unwrap()
+                                    .progress_chars("##-"),
+                            )
+                            .with_message("Episodes")
+                            .with_finish(ProgressFinish::Abandon),
+                    )
+                };
+
+                let mut jobs = stream::iter(formats)
+                    .map(|format| {
+                        download_format(
+                            ctx,
+                            self,
+                            format,
+                            &ep_collection,
+                            download_archive.as_ref(),
+                            Some(&multi_progress),
+                        )
+                    })
+                    .buffer_unordered(self.jobs as usize);
+                while let Some(result) = jobs.next().await {
+                    size += result?;
+                    overall_progress.inc(1)
+                }
+            } else {
+                for format in formats {
+                    size += download_format(
+                        ctx,
+                        self,
+                        format,
+                        &ep_collection,
+                        download_archive.as_ref(),
+                        None,
+                    )
+                    .await?
                 }
             }
+            finished += formats_len
         }
 
-        Ok(())
-    }
-}
-
-async fn download_ffmpeg(
-    ctx: &Context,
-    download: &Download,
-    variant_data: VariantData,
-    target: &Path,
-) -> Result<()> {
-    let (input_presets, output_presets) =
-        FFmpegPreset::ffmpeg_presets(download.ffmpeg_preset.clone())?;
-
-    // create parent directory if it does not exist
-    if let Some(parent) = target.parent() {
-        if !parent.exists() {
-            std::fs::create_dir_all(parent)?
+        if finished > 0 {
+            batch_summary(
+                ctx,
+                finished,
+                &TransferSummary {
+                    size,
+                    elapsed: start.elapsed(),
+                },
+            )
         }
+        if let Some(notifier) = ctx.notifier.as_ref().filter(|_| finished > 0) {
+            notifier
+                .notify(Notification::BatchFinished { episodes: finished })
+                .await
+        }
+
+        Ok(finished)
     }
 
-    let mut ffmpeg = Command::new("ffmpeg")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .stderr(Stdio::piped())
-        .arg("-y")
-        .args(input_presets)
-        .args(["-f", "mpegts", "-i", "pipe:"])
-        .args(
-            if target
-                .extension()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .is_empty()
+    /// Parse the batch file into one [`Download`] per line. The arguments of a line are applied on
+    /// top of the current arguments.
+    fn batch_entries(&self, batch_file: &Path) -> Result<Vec<Download>> {
+        let mut entries = vec![];
+        for args in parse_batch_file(batch_file)? {
+            let mut entry = self.clone();
+            entry.batch_file = None;
+            if let Err(e) =
+                entry.try_update_from(std::iter::once("download".to_string()).chain(args.clone()))
             {
-                vec!["-f", "mpegts"]
-            } else {
-                vec![]
+                // the first line of the clap error contains the actual error message, the rest is
+                // usage information
+                bail!(
+                    "Invalid batch file line '{}': {}",
+                    args.join(" "),
+                    e.to_string().lines().next().unwrap_or_default()
+                )
             }
-            .as_slice(),
-        )
-        .args(output_presets)
-        .arg(target.to_str().unwrap())
-        .spawn()?;
+            entries.push(entry)
+        }
+        Ok(entries)
+    }
 
-    download_segments(ctx, &mut ffmpeg.stdin.take().unwrap(), None, variant_data).await?;
+    async fn download_batch(&self, ctx: &Context, batch_file: &Path) -> Result<()> {
+        let entries = self.batch_entries(batch_file)?;
+        for entry in &entries {
+            entry.pre_check()?
+        }
 
-    let _progress_handler = progress!("Generating output file");
-    ffmpeg.wait()?;
-    info!("Output file generated");
+        let mut results = vec![];
+        for (i, entry) in entries.iter().enumerate() {
+            ctx.cancellation.check()?;
+            info!("Processing batch entry {} of {}", i + 1, entries.len());
+            let result = entry.download(ctx).await;
+            if matches!(&result, Err(e) if is_cancelled(e)) {
+                return result.map(|_| ());
+            }
+            if let Err(e) = &result {
+                error!("Batch entry {} failed: {}", i + 1, e)
+            }
+            results.push(result)
+        }
 
-    Ok(())
-}
+        info!("Batch summary");
+        let mut failed = 0;
+        for (i, (entry, result)) in entries.iter().zip(results).enumerate() {
+            match result {
+                Ok(episodes) => tab_info!(
+                    "{}. {} » {} episode(s) downloaded",
+                    i + 1,
+                    entry.urls.join(" "),
+                    episodes
+                ),
+                Err(e) => {
+                    failed += 1;
+                    tab_info!("{}. {} » failed: {}", i + 1, entry.urls.join(" "), e)
+                }
+            }
+        }
 
-async fn formats_from_series(
-    download: &Download,
-    series: Media<Series>,
-    url_filter: &UrlFilter,
-) -> Result<Option<Vec<Format>>> {
-    if !series.metadata.audio_locales.is_empty()
-        && !series.metadata.audio_locales.contains(&download.audio)
-    {
-        error!(
-            "Series {} is not available with {} audio",
-            series.title, download.audio
-        );
-        return Ok(None);
+        if failed > 0 {
+            bail!("{} of {} batch entries failed", failed, entries.len())
+        }
+        Ok(())
     }
-    let mut seasons = series.seasons().await?;
-    if download.seasonid.is_some() {
-        seasons.retain(|s| s.id == download.seasonid.clone().unwrap())
-    } else {
-        // filter any season out which does not contain the specified audio language
-        for season in sort_seasons_after_number(seasons.clone()) {
-            // check if the current iterated season has the specified audio language
-            if !season
-                .iter()
-                .any(|s| s.metadata.audio_locales.contains(&download.audio))
-            {
-                error!(
-                    "Season {} of series {} is not available with {} audio",
-                    season.first().unwrap().metadata.season_number,
-                    series.title,
-                    download.audio
-                );
-            }
 
-            // remove all seasons with the wrong audio for the current iterated season number
-            seasons.retain(|s| {
-                s.metadata.season_number != season.first().unwrap().metadata.season_number
-                    || s.metadata.audio_locales.contains(&download.audio)
-            })
+    fn variant_criteria(&self) -> VariantCriteria {
+        VariantCriteria {
+            max_bitrate: self.max_bitrate,
+            fps: self.fps,
         }
     }
-    let mut formats = vec![];
-    for season in seasons {
-        if let Some(fmts) = formats_from_season(download, season, url_filter).await? {
-            formats.extend(fmts)
+
+    fn ffmpeg_options(&self) -> FFmpegOptions {
+        FFmpegOptions {
+            reencode_video: false,
+            hwaccel_device: self.hwaccel_device.clone(),
+            crf: self.crf,
+            video_bitrate: self.video_bitrate.clone(),
+            encode_preset: self.encode_preset.clone(),
+            audio_codec: self.audio_codec.clone(),
+            audio_bitrate: self.audio_bitrate.clone(),
         }
     }
 
-    Ok(some_vec_or_none(formats))
-}
+    /// The audio language of the video, all other audio languages are added as audio only tracks.
+    fn primary_audio(&self) -> &LocaleChain {
+        &self.audio[0]
+    }
 
-async fn formats_from_season(
-    download: &Download,
-    season: Media<Season>,
-    url_filter: &UrlFilter,
-) -> Result<Option<Vec<Format>>> {
-    if !season.metadata.audio_locales.contains(&download.audio) {
-        error!(
-            "Season {} ({}) is not available with {} audio",
-            season.metadata.season_number, season.title, download.audio
-        );
-        return Ok(None);
-    } else if !url_filter.is_season_valid(season.metadata.season_number) {
-        return Ok(None);
+    /// If every available subtitle should be added to the output file.
+    fn all_subtitles(&self) -> bool {
+        self.subtitle
+            .iter()
+            .any(|l| l.preferred().to_string() == "all")
     }
 
-    let mut formats = vec![];
+    /// If DASH streams are downloaded. Their video and audio must be muxed together by ffmpeg.
+    pub(crate) fn uses_dash(&self) -> bool {
+        self.stream_type == StreamType::Dash
+    }
 
-    for episode in season.episodes().await? {
-        if let Some(fmt) = format_from_episode(download, episode, url_filter, true).await? {
-            formats.push(fmt)
+    /// Output path of `format`, see '--output', '--output-dir-template' and '--specials-dir'.
+    fn output_path(&self, format: &Format) -> PathBuf {
+        format_path(
+            &self.output_template(format),
+            "{title}.ts",
+            format,
+            &self.sanitizer(),
+        )
+    }
+
+    /// Sanitizer of the values inserted into file names, see '--sanitize'.
+    fn sanitizer(&self) -> Sanitizer {
+        Sanitizer {
+            mode: self.sanitize.clone(),
+            replacement: self.sanitize_replacement.clone(),
+            normalization: self.normalize.clone(),
+            ascii: self.ascii_filenames,
         }
     }
 
-    Ok(some_vec_or_none(formats))
+    /// Output template of `format` including its directory, see [`output_template`].
+    fn output_template(&self, format: &Format) -> String {
+        output_template(
+            &self.output,
+            self.output_dir_template.as_deref(),
+            self.specials_dir.as_deref(),
+            format,
+        )
+    }
+
+    /// If the video is written to stdout. All log output has to go to stderr then.
+    pub(crate) fn writes_to_stdout(&self) -> bool {
+        self.output == "-"
+    }
+
+    /// If the subtitles should be added as separate tracks instead of being burned into the video.
+    /// Subtitle only downloads have no video, so their subtitles are never burned in.
+    fn soft_subtitles(&self) -> bool {
+        self.subs_only
+            || self.external_subs
+            || self.subtitle.len() > 1
+            || self.all_subtitles()
+            || !self.cc.is_empty()
+    }
+
+    /// How existing output files are handled, see '--overwrite', '--no-overwrite' and
+    /// '--rename-on-conflict'.
+    fn overwrite_policy(&self) -> OverwritePolicy {
+        if self.overwrite {
+            OverwritePolicy::Overwrite
+        } else if self.no_overwrite {
+            OverwritePolicy::Skip
+        } else {
+            OverwritePolicy::Rename
+        }
+    }
+
+    /// Style overrides of closed captions which are converted to ass. Without an explicit font, the
+    /// first attached font is used.
+    fn subtitle_style(&self) -> SubtitleStyle {
+        SubtitleStyle {
+            font: self.sub_font.clone().or_else(|| {
+                self.attach_font
+                    .first()
+                    .and_then(|path| Font::load(path).ok())
+                    .map(|font| font.family)
+            }),
+            size: self.sub_size,
+            color: self.sub_color.clone(),
+            margin: self.sub_margin,
+        }
+    }
+
+    /// The subtitle which gets burned into the video.
+    fn hardsub(&self) -> Option<&LocaleChain> {
+        if self.soft_subtitles() {
+            None
+        } else {
+            self.subtitle.first()
+        }
+    }
 }
 
-async fn format_from_episode(
+#[async_trait::async_trait(?Send)]
+impl Execute for Download {
+    fn pre_check(&self) -> Result<()> {
+        if self.audio.iter().any(|a| a.forced()) {
+            bail!("':forced' can only be used with subtitles")
+        }
+        if self.subs_only && self.audio_only {
+            bail!("'--subs-only' and '--audio-only' cannot be used together")
+        }
+        if self.audio_only {
+            if !has_ffmpeg() {
+                bail!("FFmpeg is required to download only the audio")
+            } else if self.writes_to_stdout() {
+                bail!("Cannot download only the audio to stdout")
+            } else if self.audio.len() > 1 {
+                bail!("Only one audio language can be downloaded with '--audio-only'")
+            } else if !self.subtitle.is_empty() || !self.cc.is_empty() {
+                bail!("Subtitles cannot be used with '--audio-only'")
+            } else if !self.ffmpeg_preset.is_empty() {
+                bail!("Video presets cannot be used with '--audio-only'")
+            } else if self.embed_cover {
+                bail!("Cover art cannot be embedded with '--audio-only'")
+            }
+        }
+
+        if self.skip_existing && self.writes_to_stdout() {
+            bail!("'--skip-existing' cannot be used if the video is written to stdout")
+        }
+        if self.write_chapters.is_some() && self.writes_to_stdout() {
+            bail!("Chapter files cannot be written if the video is written to stdout")
+        }
+
+        if self.external_subs {
+            if self.subtitle.is_empty() {
+                bail!("'--external-subs' requires subtitles ('--subtitle')")
+            } else if self.writes_to_stdout() {
+                bail!("External subtitles cannot be written if the video is written to stdout")
+            }
+        }
+
+        if self.subs_only {
+            if self.subtitle.is_empty() && self.closedcaption.is_none() {
+                bail!("'--subs-only' requires subtitles ('--subtitle') or closed captions ('--closedcaption')")
+            } else if self.writes_to_stdout() {
+                bail!("Cannot download only the subtitles to stdout")
+            } else if !self.cc.is_empty() {
+                bail!("'--cc' cannot be used with '--subs-only', use '--closedcaption' to download closed captions")
+            }
+        } else {
+            let extension = PathBuf::from(&self.output)
+                .extension()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            if has_ffmpeg() {
+                debug!("FFmpeg detected")
+            } else if extension != "ts" && extension != "mkv" && !self.writes_to_stdout() {
+                bail!("File extension is not '.ts' or '.mkv'. If you want to use a custom file format, please install ffmpeg")
+            } else if extension == "mkv" && self.chapters {
+                bail!("FFmpeg is required to add chapters")
+            } else if extension == "mkv" && self.embed_cover {
+                bail!("FFmpeg is required to embed cover art")
+            } else if !self.ffmpeg_preset.is_empty() {
+                bail!("FFmpeg is required to use (ffmpeg) presets")
+            } else if self.ffmpeg_input_args.is_some() || self.ffmpeg_output_args.is_some() {
+                bail!("FFmpeg is required to use ffmpeg arguments")
+            } else if self.audio_codec.is_some() {
+                bail!("FFmpeg is required to re-encode the audio")
+            } else if self.uses_dash() {
+                bail!("FFmpeg is required to download DASH streams")
+            }
+
+            // external subtitles are only muxed into matroska files, other formats are fine
+            if (self.soft_subtitles() && !self.external_subs)
+                || !self.cc.is_empty()
+                || self.audio.len() > 1
+            {
+                // without ffmpeg, subtitle tracks are added by the built-in matroska remuxer
+                if self.audio.len() > 1 && !has_ffmpeg() {
+                    bail!("FFmpeg is required to add multiple audio tracks")
+                } else if extension != "mkv" {
+                    bail!("File extension is not '.mkv'. Multiple audio or subtitle tracks can only be added to matroska / '.mkv' files")
+                }
+            }
+        }
+
+        if !self.attach_font.is_empty() {
+            if !self.output.ends_with(".mkv") {
+                bail!("Fonts can only be attached to matroska / '.mkv' files")
+            }
+            for path in &self.attach_font {
+                Font::load(path)?;
+            }
+        }
+
+        check_format_string(&self.output)?;
+        for dir in [&self.output_dir_template, &self.specials_dir]
+            .into_iter()
+            .flatten()
+        {
+            check_format_string(dir)?;
+        }
+        if !self.sanitizer().is_valid_replacement() {
+            bail!(
+                "The sanitize replacement '{}' contains characters which are invalid in file names itself",
+                self.sanitize_replacement
+            )
+        }
+        if self.skip_specials && self.only_specials {
+            bail!("'--skip-specials' and '--only-specials' cannot be used together")
+        }
+        if self.batch_file.is_some()
+            && (!self.urls.is_empty()
+                || self.from_watchlist
+                || self.crunchylist.is_some()
+                || self.continue_watching)
+        {
+            bail!("Urls cannot be given on the command line if a batch file is used")
+        } else if self.batch_file.is_some() && self.output == "-" {
+            bail!("Cannot download a batch file to stdout")
+        }
+        if self.count == 0 {
+            bail!("'--count' must be at least 1")
+        }
+        if self.jobs > 1 && self.output == "-" {
+            bail!("Cannot download multiple episodes simultaneously to stdout")
+        }
+
+        let _ = FFmpegPreset::ffmpeg_presets(self.ffmpeg_preset.clone(), &self.ffmpeg_options())?;
+        if self.ffmpeg_preset.len() == 1
+            && [FFmpegPreset::Nvidia, FFmpegPreset::Vaapi, FFmpegPreset::Qsv]
+                .contains(&self.ffmpeg_preset[0])
+        {
+            warn!(
+                "Skipping '{}' hardware acceleration preset since no other codec preset was specified",
+                self.ffmpeg_preset[0].to_string()
+            )
+        }
+
+        Ok(())
+    }
+
+    async fn execute(self, ctx: Context) -> Result<()> {
+        if let Some(batch_file) = &self.batch_file {
+            self.download_batch(&ctx, batch_file).await
+        } else {
+            self.download(&ctx).await.map(|_| ())
+        }
+    }
+}
+
+/// Download a single episode / movie and return the size of the written file. If `multi_progress`
+/// is set, multiple downloads may run at the same time and every progress output must go through
+/// it.
+async fn download_format(
+    ctx: &Context,
+    download: &Download,
+    mut format: Format,
+    ep_collection: &Option<Vec<Media<Episode>>>,
+    download_archive: Option<&DownloadArchive>,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<u64> {
+    ctx.cancellation.check()?;
+    let mut path = download.output_path(&format);
+    if download.audio_only {
+        path = audio_only_path(path)
+    }
+    if download.skip_existing && is_complete(download, &format, &path, download_archive) {
+        print_skip(&format, &path, multi_progress)?;
+        return Ok(0);
+    }
+    // an existing file with a resume journal is an interrupted download which should
+    // be continued instead of being written to a new file
+    if !ResumeJournal::exists_for(part_file(&path)) {
+        match download.overwrite_policy().resolve(path.clone()) {
+            Some(resolved) => path = resolved,
+            None => {
+                print_skip(&format, &path, multi_progress)?;
+                return Ok(0);
+            }
+        }
+    }
+
+    if download.subs_only {
+        // create parent directory if it does not exist
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?
+            }
+        }
+    }
+
+    if download.closedcaption.is_some() && ep_collection.is_some() {
+        let mut ccpath = path.clone();
+        ccpath.set_extension(download.subtitle_format.extension());
+        match download.overwrite_policy().resolve(ccpath) {
+            Some(ccpath) => {
+                let ep = ep_collection
+                    .clone()
+                    .unwrap()
+                    .remove(format.number as usize - 1);
+                download_cc(
+                    ctx,
+                    ccpath.to_str().unwrap().to_string(),
+                    ep.streams()
+                        .await?
+                        .closed_captions
+                        .get(download.closedcaption.as_ref().unwrap())
+                        .unwrap()
+                        .url
+                        .to_string(),
+                    &download.subtitle_format,
+                    SubtitleOffset::for_locale(
+                        &download.sub_offset,
+                        download.closedcaption.as_ref().unwrap(),
+                    ),
+                    &download.subtitle_style(),
+                )
+                .await?
+            }
+            None => debug!(
+                "Closed captions of {} already exist, skipping them",
+                format.title
+            ),
+        }
+    };
+    if download.subs_only {
+        return download_subtitles(download, &format, &path, multi_progress).await;
+    }
+    let display_path = if is_special_file(&path) {
+        path.to_str().unwrap()
+    } else {
+        path.file_name().unwrap().to_str().unwrap()
+    };
+    if let Some(multi_progress) = multi_progress {
+        // the detailed episode information would be mixed up if multiple downloads are running
+        multi_progress.println(format!(
+            ":: Downloading {} (S{:02}E{:02}) to '{}'",
+            format.title, format.season_number, format.number, display_path
+        ))?;
+    } else {
+        tab_info!("Downloading {} to '{}'", format.title, display_path);
+        tab_info!("Episode: S{:02}E{:02}", format.season_number, format.number);
+        tab_info!(
+            "Audio: {}",
+            [format.audio.to_string()]
+                .into_iter()
+                .chain(format.additional_audio.iter().map(|(l, _)| l.to_string()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        tab_info!(
+            "Subtitles: {}",
+            if download.soft_subtitles() {
+                format
+                    .subtitles
+                    .iter()
+                    .map(|s| s.locale.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            } else {
+                format
+                    .hardsub
+                    .as_ref()
+                    .map_or("None".to_string(), |l| l.to_string())
+            }
+        );
+        tab_info!(
+            "Closed Captions: {}",
+            download
+                .closedcaption
+                .iter()
+                .chain(format.closed_captions.iter().map(|(l, _)| l))
+                .map(|l| l.to_string())
+                .reduce(|a, b| format!("{}, {}", a, b))
+                .unwrap_or("None".to_string())
+        );
+        if !download.audio_only {
+            tab_info!("Resolution: {}", format.stream.resolution);
+            tab_info!("FPS: {:.2}", format.stream.fps);
+        }
+    }
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+
+    // pipes and stdout cannot be renamed, so they're written directly
+    let write_part = !is_special_file(&path) && path.to_str().unwrap() != "-";
+    let part = if write_part {
+        part_file(&path)
+    } else {
+        path.clone()
+    };
+    let verify = write_part && !download.no_verify && has_ffprobe();
+    // only matroska can contain the subtitles, every other format gets the external files only
+    let external_subtitles = if !download.external_subs {
+        vec![]
+    } else if extension == "mkv" {
+        format.subtitles.clone()
+    } else {
+        std::mem::take(&mut format.subtitles)
+    };
+    let _partial = PartialFile::new(&part, &ctx.cancellation);
+    let start = ctx.pause.stopwatch();
+    let mut verify_retries = 0;
+    loop {
+        if download.audio_only {
+            download_audio(
+                ctx,
+                download,
+                &format,
+                path.as_path(),
+                part.as_path(),
+                multi_progress,
+            )
+            .await?;
+        } else if extension == "mkv" && !has_ffmpeg() {
+            download_remux(
+                ctx,
+                download,
+                &format,
+                path.as_path(),
+                part.as_path(),
+                multi_progress,
+            )
+            .await?;
+        } else if (!extension.is_empty() && extension != "ts")
+            || !download.ffmpeg_preset.is_empty()
+            || download.ffmpeg_input_args.is_some()
+            || download.ffmpeg_output_args.is_some()
+            || download.audio_codec.is_some()
+            || format.audio_stream.is_some()
+        {
+            download_ffmpeg(
+                ctx,
+                download,
+                &format,
+                path.as_path(),
+                part.as_path(),
+                multi_progress,
+            )
+            .await?;
+        } else if path.to_str().unwrap() == "-" {
+            if let Err(e) = download_segments(
+                ctx,
+                std::io::stdout(),
+                None,
+                format.stream.clone(),
+                Some(&format),
+                None,
+                None,
+            )
+            .await
+            {
+                // the program which reads from stdout (e.g. a player) was closed, no reason to fail
+                if is_broken_pipe(&e) {
+                    info!("Output was closed, stopping download");
+                    return Ok(0);
+                }
+                return Err(e);
+            }
+        } else {
+            // create parent directory if it does not exist
+            if let Some(parent) = path.parent() {
+                if !parent.exists() {
+                    std::fs::create_dir_all(parent)?
+                }
+            }
+            let segment_count = format.stream.segments().await?.len();
+            let resume = ResumeJournal::open(&part, &format.id, &format.stream, segment_count)?;
+            if resume.next_segment() > 0 {
+                tab_info!("Resuming interrupted download of {}", format.title)
+            }
+            let mut file = File::options().create(true).write(true).open(&part)?;
+            // cut off everything which was written after the last recorded segment
+            file.set_len(resume.written_bytes())?;
+            file.seek(SeekFrom::End(0))?;
+            download_segments(
+                ctx,
+                file,
+                multi_progress.map(|_| format.title.clone()),
+                format.stream.clone(),
+                Some(&format),
+                Some(resume),
+                multi_progress,
+            )
+            .await?;
+        }
+
+        if !verify {
+            break;
+        }
+        match verify_output(&part, &format, download.audio_only) {
+            Ok(()) => {
+                debug!("Verified {}", part.to_string_lossy());
+                break;
+            }
+            Err(e) if verify_retries < download.verify_retries => {
+                verify_retries += 1;
+                warn!(
+                    "{} is corrupted ({}), downloading it again ({}/{})",
+                    display_path, e, verify_retries, download.verify_retries
+                );
+                std::fs::remove_file(&part)?
+            }
+            Err(e) => bail!("{} is corrupted: {}", display_path, e),
+        }
+    }
+    // the size of pipes and stdout is unknown
+    let mut size = 0;
+    if write_part {
+        std::fs::rename(&part, &path)?;
+        size = std::fs::metadata(&path)?.len();
+        episode_summary(
+            ctx,
+            &path,
+            &TransferSummary {
+                size,
+                elapsed: start.elapsed(),
+            },
+            multi_progress,
+        )?;
+        size += write_subtitle_files(
+            download,
+            &external_subtitles,
+            &format.audio,
+            &path,
+            multi_progress,
+        )
+        .await?;
+        if let Some(chapter_format) = &download.write_chapters {
+            write_chapter_file(
+                ctx,
+                chapter_format,
+                &format,
+                &path,
+                download.overwrite_policy(),
+            )
+            .await?
+        }
+    }
+
+    if download.write_nfo && !is_special_file(&path) && path.to_str().unwrap() != "-" {
+        write_nfo(
+            ctx,
+            &download.output_template(&format),
+            &path,
+            &format,
+            download.overwrite_policy(),
+            &download.sanitizer(),
+        )
+        .await?
+    }
+    if let Some(download_archive) = download_archive {
+        download_archive.record(&format.id)?
+    }
+    if download.mark_watched {
+        if let Err(e) = mark_watched(&ctx.crunchy, &format).await {
+            warn!("Failed to mark {} as watched: {}", format.title, e)
+        }
+    }
+    ctx.persist_session().await;
+
+    if let Some(exec_after) = download
+        .exec_after
+        .as_ref()
+        .filter(|_| path.to_str() != Some("-"))
+    {
+        if let Err(e) = run_hook(exec_after, episode_env(&format, &path)) {
+            warn!("Failed to execute command after {}: {}", format.title, e)
+        }
+    }
+
+    if let Some(notifier) = &ctx.notifier {
+        notifier
+            .notify(Notification::episode_finished(&format, &path))
+            .await
+    }
+
+    Ok(size)
+}
+
+/// Download the format and mux it with ffmpeg into `part`. Also used by [`crate::Downloader`], so
+/// the library writes the same files as the cli.
+pub(crate) async fn download_ffmpeg(
+    ctx: &Context,
+    download: &Download,
+    format: &Format,
+    target: &Path,
+    part: &Path,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<()> {
+    let (input_presets, output_presets) =
+        FFmpegPreset::ffmpeg_presets(download.ffmpeg_preset.clone(), &download.ffmpeg_options())?;
+
+    let chapters_path = if download.chapters {
+        let chapters = fetch_chapters(ctx, &format.id, format.duration).await?;
+        if chapters.is_empty() {
+            None
+        } else {
+            Some(ffmpeg_chapters_file(&chapters)?)
+        }
+    } else {
+        None
+    };
+    let extension = target
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+
+    // DASH streams have separate video and audio streams. Only the (much smaller) audio is
+    // downloaded to a temporary file, the video is piped to ffmpeg like HLS streams are
+    let dash_audio_path = if let Some(audio_stream) = &format.audio_stream {
+        Some(
+            download_raw(
+                ctx,
+                audio_stream.clone(),
+                ".m4a",
+                format!("Download {} audio", format.audio),
+                multi_progress,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    let mut input = if let Some(audio_path) = &dash_audio_path {
+        vec![
+            "-f".to_string(),
+            "mp4".to_string(),
+            "-i".to_string(),
+            "pipe:".to_string(),
+            "-i".to_string(),
+            audio_path.to_string_lossy().to_string(),
+        ]
+    } else {
+        vec![
+            "-f".to_string(),
+            "mpegts".to_string(),
+            "-i".to_string(),
+            "pipe:".to_string(),
+        ]
+    };
+    let mut output = vec![];
+    if extension.is_empty() || part != target {
+        output.extend(["-f".to_string(), ffmpeg_muxer(&extension)])
+    }
+    // number of ffmpeg inputs, the video (from stdin) is the first one. DASH streams have an
+    // additional input for the audio
+    let mut input_count = if dash_audio_path.is_some() { 2 } else { 1 };
+    // stream specifier of the primary audio
+    let primary_audio = if dash_audio_path.is_some() {
+        "1:a"
+    } else {
+        "0:a"
+    };
+
+    if let Some(chapters_path) = &chapters_path {
+        input.extend([
+            "-i".to_string(),
+            chapters_path.to_string_lossy().to_string(),
+        ]);
+        output.extend(["-map_chapters".to_string(), input_count.to_string()]);
+        input_count += 1;
+    }
+
+    let mut audio_paths = vec![];
+    for (locale, stream) in &format.additional_audio {
+        audio_paths.push((
+            download_video(
+                ctx,
+                stream.clone(),
+                format!("Download {} audio", locale),
+                true,
+                multi_progress,
+            )
+            .await?,
+            locale,
+        ))
+    }
+
+    let max_length = NaiveTime::from_num_seconds_from_midnight_opt(
+        format.duration.as_secs() as u32,
+        format.duration.subsec_nanos(),
+    )
+    .unwrap_or_default();
+    let mut subtitle_paths = vec![];
+    for subtitle in &format.subtitles {
+        subtitle_paths.push((
+            download_subtitle(
+                subtitle.clone(),
+                max_length,
+                true,
+                SubtitleOffset::for_locale(&download.sub_offset, &subtitle.locale),
+            )
+            .await?,
+            &subtitle.locale,
+            is_forced_subtitle(&subtitle.locale, &format.audio),
+            false,
+        ))
+    }
+    for (locale, url) in &format.closed_captions {
+        subtitle_paths.push((
+            download_cc_subtitle(
+                ctx,
+                url.clone(),
+                SubtitleOffset::for_locale(&download.sub_offset, locale),
+                &download.subtitle_style(),
+            )
+            .await?,
+            locale,
+            false,
+            true,
+        ))
+    }
+    // once any stream is mapped explicitly, ffmpeg doesn't select the video and audio itself anymore
+    let base_mapped =
+        !audio_paths.is_empty() || !subtitle_paths.is_empty() || dash_audio_path.is_some();
+    if base_mapped {
+        output.extend([
+            "-map".to_string(),
+            "0:v".to_string(),
+            "-map".to_string(),
+            primary_audio.to_string(),
+        ]);
+    }
+    if !audio_paths.is_empty() {
+        output.extend([
+            "-metadata:s:a:0".to_string(),
+            format!("language={}", format.audio),
+            "-metadata:s:a:0".to_string(),
+            format!("title={}", format.audio.to_human_readable()),
+            "-disposition:a:0".to_string(),
+            "default".to_string(),
+        ]);
+        for (i, (audio_path, locale)) in audio_paths.iter().enumerate() {
+            input.extend(["-i".to_string(), audio_path.to_string_lossy().to_string()]);
+            output.extend([
+                "-map".to_string(),
+                input_count.to_string(),
+                format!("-metadata:s:a:{}", i + 1),
+                format!("language={}", locale),
+                format!("-metadata:s:a:{}", i + 1),
+                format!("title={}", locale.to_human_readable()),
+                format!("-disposition:a:{}", i + 1),
+                "0".to_string(),
+            ]);
+            input_count += 1;
+        }
+    }
+    if !subtitle_paths.is_empty() {
+        for (i, (subtitle_path, locale, forced, cc)) in subtitle_paths.iter().enumerate() {
+            input.extend([
+                "-i".to_string(),
+                subtitle_path.to_string_lossy().to_string(),
+            ]);
+            // forced subtitles are shown by default, all others only if they are turned on
+            let disposition = if *forced { "default+forced" } else { "0" };
+            let title = subtitle_track_name(locale, *forced, *cc);
+            output.extend([
+                "-map".to_string(),
+                input_count.to_string(),
+                format!("-metadata:s:s:{}", i),
+                format!("language={}", locale),
+                format!("-metadata:s:s:{}", i),
+                format!("title={}", title),
+                format!("-disposition:s:{}", i),
+                disposition.to_string(),
+            ]);
+            input_count += 1;
+        }
+    }
+
+    let fonts = download
+        .attach_font
+        .iter()
+        .map(|path| Font::load(path))
+        .collect::<Result<Vec<Font>>>()?;
+    output.extend(Font::matroska_args(&fonts, 0));
+
+    let cover_art = if download.embed_cover {
+        Some(CoverArt::download(ctx, format).await?)
+    } else {
+        None
+    };
+    if let Some(cover_art) = &cover_art {
+        match extension.as_str() {
+            "mkv" | "mka" => output.extend(cover_art.matroska_args(fonts.len())),
+            "mp4" | "m4v" | "mov" => {
+                if let Some(cover) = cover_art.primary() {
+                    input.extend(["-i".to_string(), cover.to_string_lossy().to_string()]);
+                    if !base_mapped {
+                        output.extend([
+                            "-map".to_string(),
+                            "0:v".to_string(),
+                            "-map".to_string(),
+                            primary_audio.to_string(),
+                        ])
+                    }
+                    output.extend([
+                        "-map".to_string(),
+                        input_count.to_string(),
+                        // the cover must not be re-encoded by any preset
+                        "-c:v:1".to_string(),
+                        "copy".to_string(),
+                        "-disposition:v:1".to_string(),
+                        "attached_pic".to_string(),
+                    ])
+                }
+            }
+            _ => warn!(
+                "Cover art cannot be embedded into '.{}' files, only matroska and mp4 files are supported",
+                extension
+            ),
+        }
+    }
+
+    let to_stdout = target.to_str().unwrap() == "-";
+    if !to_stdout {
+        // create parent directory if it does not exist
+        if let Some(parent) = target.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?
+            }
+        }
+    }
+
+    let mut ffmpeg = Command::new("ffmpeg")
+        .stdin(Stdio::piped())
+        .stdout(if to_stdout {
+            Stdio::inherit()
+        } else {
+            Stdio::null()
+        })
+        .stderr(Stdio::piped())
+        .arg("-y")
+        .args(FFMPEG_PROGRESS_ARGS)
+        .args(input_presets)
+        .args(parse_ffmpeg_args(
+            download.ffmpeg_input_args.as_deref().unwrap_or_default(),
+        )?)
+        .args(input)
+        .args(output_presets)
+        .args(output)
+        .args(parse_ffmpeg_args(
+            download.ffmpeg_output_args.as_deref().unwrap_or_default(),
+        )?)
+        .arg(part.to_str().unwrap())
+        .spawn()?;
+    let ffmpeg_progress = FFmpegProgress::new(&mut ffmpeg);
+
+    if let Err(e) = download_segments(
+        ctx,
+        ffmpeg.stdin.take().unwrap(),
+        multi_progress.map(|_| {
+            target
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        }),
+        format.stream.clone(),
+        Some(format),
+        None,
+        multi_progress,
+    )
+    .await
+    {
+        let _ = ffmpeg.kill();
+        return Err(e);
+    }
+
+    if ctx.progress_format == ProgressFormat::Json {
+        emit_progress(ProgressEvent::MuxStarted { output: target });
+    }
+    let (status, log) = ffmpeg_progress
+        .wait(
+            ctx,
+            &mut ffmpeg,
+            format.duration,
+            multi_progress.map_or("Generating output file".to_string(), |_| {
+                target
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            }),
+            multi_progress,
+        )
+        .await?;
+    if !status.success() {
+        bail!("{}", log)
+    }
+    if ctx.progress_format == ProgressFormat::Json {
+        emit_progress(ProgressEvent::MuxFinished { output: target });
+    } else if multi_progress.is_none() {
+        info!("Output file generated");
+    }
+
+    Ok(())
+}
+
+/// Download the stream and remux it, together with the subtitles, into a matroska file without
+/// ffmpeg. Only used if ffmpeg is not installed, the pre check makes sure that nothing else which
+/// requires ffmpeg is requested. Also used by [`crate::Downloader`].
+pub(crate) async fn download_remux(
+    ctx: &Context,
+    download: &Download,
+    format: &Format,
+    target: &Path,
+    part: &Path,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<()> {
+    // create parent directory if it does not exist
+    if let Some(parent) = target.parent() {
+        if !parent.exists() {
+            std::fs::create_dir_all(parent)?
+        }
+    }
+
+    let max_length = NaiveTime::from_num_seconds_from_midnight_opt(
+        format.duration.as_secs() as u32,
+        format.duration.subsec_nanos(),
+    )
+    .unwrap_or_default();
+    let mut subtitles = vec![];
+    for subtitle in &format.subtitles {
+        let path = download_subtitle(
+            subtitle.clone(),
+            max_length,
+            true,
+            SubtitleOffset::for_locale(&download.sub_offset, &subtitle.locale),
+        )
+        .await?;
+        let forced = is_forced_subtitle(&subtitle.locale, &format.audio);
+        subtitles.push(SubtitleInput {
+            locale: subtitle.locale.clone(),
+            name: subtitle_track_name(&subtitle.locale, forced, false),
+            forced,
+            ass: std::fs::read_to_string(&path)?,
+        })
+    }
+    for (locale, url) in &format.closed_captions {
+        let path = download_cc_subtitle(
+            ctx,
+            url.clone(),
+            SubtitleOffset::for_locale(&download.sub_offset, locale),
+            &download.subtitle_style(),
+        )
+        .await?;
+        subtitles.push(SubtitleInput {
+            locale: locale.clone(),
+            name: subtitle_track_name(locale, false, true),
+            forced: false,
+            ass: std::fs::read_to_string(&path)?,
+        })
+    }
+
+    let mut remuxer = MatroskaRemuxer::new(
+        BufWriter::new(File::create(part)?),
+        format.audio.clone(),
+        format.stream.resolution.width,
+        format.stream.resolution.height,
+        format.duration,
+        subtitles,
+    );
+    for path in &download.attach_font {
+        let font = Font::load(path)?;
+        remuxer.attach(Attachment {
+            file_name: font.file_name(),
+            mime_type: font.mime_type().to_string(),
+            data: std::fs::read(path)?,
+        })
+    }
+    let remuxer = download_segments(
+        ctx,
+        remuxer,
+        multi_progress.map(|_| {
+            target
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        }),
+        format.stream.clone(),
+        Some(format),
+        None,
+        multi_progress,
+    )
+    .await?;
+    remuxer.finish()?;
+
+    Ok(())
+}
+
+/// Download the subtitles of the format next to `path`, the path the video would be written to.
+/// Returns the size of all written subtitle files.
+async fn download_subtitles(
+    download: &Download,
+    format: &Format,
+    path: &Path,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<u64> {
+    if let Some(multi_progress) = multi_progress {
+        multi_progress.println(format!(
+            ":: Downloading subtitles of {} (S{:02}E{:02})",
+            format.title, format.season_number, format.number
+        ))?;
+    } else {
+        tab_info!("Downloading subtitles of {}", format.title);
+        tab_info!("Episode: S{:02}E{:02}", format.season_number, format.number);
+    }
+    if format.subtitles.is_empty() {
+        warn!("{} has none of the requested subtitles", format.title);
+        return Ok(0);
+    }
+
+    write_subtitle_files(
+        download,
+        &format.subtitles,
+        &format.audio,
+        path,
+        multi_progress,
+    )
+    .await
+}
+
+/// Write every subtitle to its own file next to `path`, with the subtitle language appended to the
+/// file name. `audio` is the audio locale of the video, it's required to detect forced subtitles.
+/// Returns the size of all written files.
+async fn write_subtitle_files(
+    download: &Download,
+    subtitles: &[StreamSubtitle],
+    audio: &Locale,
+    path: &Path,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<u64> {
+    let mut size = 0;
+    for subtitle in subtitles {
+        let mut subtitle_path = path.to_path_buf();
+        // plex and jellyfin recognize forced subtitles by the '.forced' suffix
+        subtitle_path.set_extension(format!(
+            "{}{}.{}",
+            subtitle.locale,
+            if is_forced_subtitle(&subtitle.locale, audio) {
+                ".forced"
+            } else {
+                ""
+            },
+            download.subtitle_format.extension()
+        ));
+        let Some(subtitle_path) = download.overwrite_policy().resolve(subtitle_path) else {
+            debug!(
+                "{} subtitles of {} already exist, skipping them",
+                subtitle.locale,
+                path.to_string_lossy()
+            );
+            continue;
+        };
+        write_subtitle(
+            subtitle.clone(),
+            &subtitle_path,
+            &download.subtitle_format,
+            SubtitleOffset::for_locale(&download.sub_offset, &subtitle.locale),
+        )
+        .await?;
+        size += std::fs::metadata(&subtitle_path)?.len();
+        if multi_progress.is_none() {
+            tab_info!(
+                "{} subtitles written to '{}'",
+                subtitle.locale,
+                subtitle_path.file_name().unwrap().to_string_lossy()
+            )
+        }
+    }
+
+    Ok(size)
+}
+
+/// Report that `format` is not downloaded because `path` already exists.
+fn print_skip(format: &Format, path: &Path, multi_progress: Option<&MultiProgress>) -> Result<()> {
+    if let Some(multi_progress) = multi_progress {
+        multi_progress.println(format!(
+            ":: Skipping {} (S{:02}E{:02}), '{}' already exists",
+            format.title,
+            format.season_number,
+            format.number,
+            path.to_string_lossy()
+        ))?;
+    } else {
+        info!(
+            "Skipping {}, '{}' already exists",
+            format.title,
+            path.to_string_lossy()
+        )
+    }
+    Ok(())
+}
+
+/// If `path` is a complete download of `format`, see '--skip-existing'.
+fn is_complete(
+    download: &Download,
+    format: &Format,
+    path: &Path,
+    download_archive: Option<&DownloadArchive>,
+) -> bool {
+    if !path.is_file() {
+        return false;
+    } else if download_archive.map_or(false, |a| a.contains(&format.id)) {
+        return true;
+    } else if !has_ffprobe() {
+        // downloads are written to a part file which is only renamed when it's finished
+        return true;
+    }
+
+    match verify_output(path, format, download.audio_only) {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("{} is incomplete: {}", path.to_string_lossy(), e);
+            false
+        }
+    }
+}
+
+/// Write the chapters of `format` to a file next to `path`. Nothing is written if the episode has
+/// no chapters.
+async fn write_chapter_file(
+    ctx: &Context,
+    chapter_format: &ChapterFormat,
+    format: &Format,
+    path: &Path,
+    overwrite_policy: OverwritePolicy,
+) -> Result<()> {
+    let chapters = fetch_chapters(ctx, &format.id, format.duration).await?;
+    if chapters.is_empty() {
+        debug!("{} has no chapters, skipping chapter file", format.title);
+        return Ok(());
+    }
+
+    let mut chapters_path = path.to_path_buf();
+    chapters_path.set_extension(format!("chapters.{}", chapter_format.extension()));
+    let Some(chapters_path) = overwrite_policy.resolve(chapters_path) else {
+        debug!(
+            "Chapter file of {} already exists, skipping it",
+            format.title
+        );
+        return Ok(());
+    };
+    chapter_format.write(&chapters, &chapters_path)?;
+    debug!("Wrote chapters to {}", chapters_path.to_string_lossy());
+
+    Ok(())
+}
+
+/// Output path of audio only downloads. Every extension other than `.m4a` and `.opus` is replaced
+/// with `.m4a`.
+fn audio_only_path(mut path: PathBuf) -> PathBuf {
+    let extension = path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    if extension != "m4a" && extension != "opus" {
+        path.set_extension("m4a");
+    }
+    path
+}
+
+/// Download only the audio of the format. DASH streams have a separate audio stream, HLS streams
+/// must be downloaded completely and the video is dropped by ffmpeg.
+async fn download_audio(
+    ctx: &Context,
+    download: &Download,
+    format: &Format,
+    target: &Path,
+    part: &Path,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<()> {
+    let (_, output_presets) = FFmpegPreset::ffmpeg_presets(vec![], &download.ffmpeg_options())?;
+    let extension = target
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+
+    let audio_path = if let Some(audio_stream) = &format.audio_stream {
+        Some(
+            download_raw(
+                ctx,
+                audio_stream.clone(),
+                ".m4a",
+                format!("Download {} audio", format.audio),
+                multi_progress,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    let mut input = if let Some(audio_path) = &audio_path {
+        vec!["-i".to_string(), audio_path.to_string_lossy().to_string()]
+    } else {
+        vec![
+            "-f".to_string(),
+            "mpegts".to_string(),
+            "-i".to_string(),
+            "pipe:".to_string(),
+        ]
+    };
+    let mut output = vec![
+        "-map".to_string(),
+        "0:a".to_string(),
+        "-vn".to_string(),
+        "-metadata:s:a:0".to_string(),
+        format!("language={}", format.audio),
+        "-f".to_string(),
+        ffmpeg_muxer(&extension),
+    ];
+    output.extend(output_presets);
+    // opus files can only contain opus audio, crunchyroll delivers aac
+    if extension == "opus" && download.audio_codec.is_none() {
+        output.extend(["-c:a".to_string(), "libopus".to_string()])
+    }
+
+    let chapters_path = if download.chapters {
+        let chapters = fetch_chapters(ctx, &format.id, format.duration).await?;
+        if chapters.is_empty() {
+            None
+        } else {
+            Some(ffmpeg_chapters_file(&chapters)?)
+        }
+    } else {
+        None
+    };
+    if let Some(chapters_path) = &chapters_path {
+        input.extend([
+            "-i".to_string(),
+            chapters_path.to_string_lossy().to_string(),
+        ]);
+        output.extend(["-map_chapters".to_string(), "1".to_string()]);
+    }
+
+    // create parent directory if it does not exist
+    if let Some(parent) = target.parent() {
+        if !parent.exists() {
+            std::fs::create_dir_all(parent)?
+        }
+    }
+
+    let mut ffmpeg = Command::new("ffmpeg")
+        .stdin(if audio_path.is_some() {
+            Stdio::null()
+        } else {
+            Stdio::piped()
+        })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .arg("-y")
+        .args(FFMPEG_PROGRESS_ARGS)
+        .args(parse_ffmpeg_args(
+            download.ffmpeg_input_args.as_deref().unwrap_or_default(),
+        )?)
+        .args(input)
+        .args(output)
+        .args(parse_ffmpeg_args(
+            download.ffmpeg_output_args.as_deref().unwrap_or_default(),
+        )?)
+        .arg(part.to_str().unwrap())
+        .spawn()?;
+    let ffmpeg_progress = FFmpegProgress::new(&mut ffmpeg);
+
+    if audio_path.is_none() {
+        if let Err(e) = download_segments(
+            ctx,
+            ffmpeg.stdin.take().unwrap(),
+            multi_progress.map(|_| {
+                target
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            }),
+            format.stream.clone(),
+            Some(format),
+            None,
+            multi_progress,
+        )
+        .await
+        {
+            let _ = ffmpeg.kill();
+            return Err(e);
+        }
+    }
+
+    if ctx.progress_format == ProgressFormat::Json {
+        emit_progress(ProgressEvent::MuxStarted { output: target });
+    }
+    let (status, log) = ffmpeg_progress
+        .wait(
+            ctx,
+            &mut ffmpeg,
+            format.duration,
+            multi_progress.map_or("Generating output file".to_string(), |_| {
+                target
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            }),
+            multi_progress,
+        )
+        .await?;
+    if !status.success() {
+        bail!("{}", log)
+    }
+    if ctx.progress_format == ProgressFormat::Json {
+        emit_progress(ProgressEvent::MuxFinished { output: target });
+    } else if multi_progress.is_none() {
+        info!("Output file generated");
+    }
+
+    Ok(())
+}
+
+async fn formats_from_series(
+    download: &Download,
+    series: Media<Series>,
+    url_filter: &UrlFilter,
+) -> Result<Option<Vec<Format>>> {
+    if !series.metadata.audio_locales.is_empty()
+        && !series
+            .metadata
+            .audio_locales
+            .iter()
+            .any(|l| download.primary_audio().contains(l))
+    {
+        error!(
+            "Series {} is not available with {} audio",
+            series.title,
+            download.primary_audio()
+        );
+        return Ok(None);
+    }
+    let all_seasons = series.seasons().await?;
+    let mut seasons = all_seasons.clone();
+    if download.seasonid.is_some() {
+        seasons.retain(|s| s.id == download.seasonid.clone().unwrap())
+    } else {
+        // filter any season out which does not contain the specified audio language
+        for season in sort_seasons_after_number(seasons.clone()) {
+            let season_number = season.first().unwrap().metadata.season_number;
+            // the first language of the audio chain which is available for the current iterated
+            // season number
+            let audio = download
+                .primary_audio()
+                .find(|l| season.iter().any(|s| s.metadata.audio_locales.contains(l)));
+            if audio.is_none() {
+                error!(
+                    "Season {} of series {} is not available with {} audio",
+                    season_number,
+                    series.title,
+                    download.primary_audio()
+                );
+            }
+
+            // remove all seasons with the wrong audio for the current iterated season number
+            seasons.retain(|s| {
+                s.metadata.season_number != season_number
+                    || audio.map_or(false, |a| s.metadata.audio_locales.contains(a))
+            })
+        }
+    }
+    seasons.retain(|s| {
+        let skip = skip_movie_season(s, &download.movies);
+        if skip {
+            debug!(
+                "Skipping season {} ({}) because of the movie filter",
+                s.metadata.season_number, s.title
+            )
+        }
+        !skip
+    });
+    let mut formats = vec![];
+    for season in seasons {
+        if let Some(fmts) = formats_from_season(download, season, url_filter).await? {
+            formats.extend(fmts)
+        }
+    }
+
+    for audio in download.audio.iter().skip(1) {
+        add_additional_audio(&mut formats, &all_seasons, audio).await?
+    }
+
+    Ok(some_vec_or_none(formats))
+}
+
+/// Add the audio of the given language to every format. Crunchyroll has a separate season (and
+/// separate episodes) for every audio language, so the episodes are matched via their season and
+/// episode number. If an episode is not available with the preferred language of the chain, the
+/// next language of the chain is tried.
+async fn add_additional_audio(
+    formats: &mut [Format],
+    seasons: &[Media<Season>],
+    audio: &LocaleChain,
+) -> Result<()> {
+    for locale in audio.iter() {
+        for season in seasons {
+            if !season.metadata.audio_locales.contains(locale)
+                || !formats.iter().any(|f| {
+                    f.season_number == season.metadata.season_number
+                        && !f.additional_audio.iter().any(|(l, _)| audio.contains(l))
+                })
+            {
+                continue;
+            }
+
+            for episode in season.episodes().await? {
+                if &episode.metadata.audio_locale != locale {
+                    continue;
+                }
+                let Some(format) = formats.iter_mut().find(|f| {
+                    f.season_number == episode.metadata.season_number
+                        && f.number == episode.metadata.episode_number
+                        && !f.additional_audio.iter().any(|(l, _)| audio.contains(l))
+                }) else {
+                    continue;
+                };
+
+                // the audio is the same for every resolution, so the smallest stream is sufficient
+                let streaming_data = episode.streams().await?.hls_streaming_data(None).await?;
+                if let Some(stream) = streaming_data.into_iter().min_by_key(|v| v.bandwidth) {
+                    format.additional_audio.push((locale.clone(), stream))
+                }
+            }
+        }
+    }
+
+    for format in formats.iter() {
+        match format
+            .additional_audio
+            .iter()
+            .find(|(l, _)| audio.contains(l))
+        {
+            Some((locale, _)) if locale != audio.preferred() => info!(
+                "Episode {} (S{:02}E{:02}) has no {} audio, using {} instead",
+                format.title,
+                format.season_number,
+                format.number,
+                audio.preferred(),
+                locale
+            ),
+            Some(_) => (),
+            None => warn!(
+                "Episode {} (S{:02}E{:02}) has no {} audio",
+                format.title, format.season_number, format.number, audio
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+async fn formats_from_season(
+    download: &Download,
+    season: Media<Season>,
+    url_filter: &UrlFilter,
+) -> Result<Option<Vec<Format>>> {
+    if !url_filter.is_season_valid(season.metadata.season_number) {
+        return Ok(None);
+    }
+    let Some(audio) = download
+        .primary_audio()
+        .find(|l| season.metadata.audio_locales.contains(l))
+    else {
+        error!(
+            "Season {} ({}) is not available with {} audio",
+            season.metadata.season_number,
+            season.title,
+            download.primary_audio()
+        );
+        return Ok(None);
+    };
+    if audio != download.primary_audio().preferred() {
+        info!(
+            "Season {} ({}) is not available with {} audio, using {} instead",
+            season.metadata.season_number,
+            season.title,
+            download.primary_audio().preferred(),
+            audio
+        )
+    }
+
+    let movie = is_movie_season(&season);
+    let mut formats = vec![];
+
+    for episode in season.episodes().await? {
+        if skip_special(&episode, download.skip_specials, download.only_specials) {
+            debug!(
+                "Skipping episode {} ({}) of season {} because of the specials filter",
+                episode.metadata.episode, episode.title, episode.metadata.season_number
+            );
+            continue;
+        } else if !is_released_between(
+            &episode,
+            download.released_after.as_ref(),
+            download.released_before.as_ref(),
+        ) {
+            debug!(
+                "Skipping episode {} ({}) of season {} because it was released at {}",
+                episode.metadata.episode,
+                episode.title,
+                episode.metadata.season_number,
+                episode.metadata.episode_air_date
+            );
+            continue;
+        }
+        if let Some(mut fmt) =
+            format_from_episode(download, episode, url_filter, Some(audio)).await?
+        {
+            fmt.movie = movie;
+            formats.push(fmt)
+        }
+    }
+
+    Ok(some_vec_or_none(formats))
+}
+
+async fn format_from_episode(
     download: &Download,
     episode: Media<Episode>,
     url_filter: &UrlFilter,
-    filter_audio: bool,
+    audio: Option<&Locale>,
 ) -> Result<Option<Format>> {
-    if filter_audio && episode.metadata.audio_locale != download.audio {
+    if let Some(audio) = audio.filter(|a| *a != &episode.metadata.audio_locale) {
         error!(
             "Episode {} ({}) of season {} ({}) of {} has no {} audio",
             episode.metadata.episode_number,
@@ -485,7 +2865,7 @@ async fn format_from_episode(
             episode.metadata.season_number,
             episode.metadata.season_title,
             episode.metadata.series_title,
-            download.audio
+            audio
         );
         return Ok(None);
     } else if !url_filter.is_episode_valid(
@@ -495,9 +2875,42 @@ async fn format_from_episode(
         return Ok(None);
     }
 
-    let streams = episode.streams().await?;
-    let streaming_data = if let Some(subtitle) = &download.subtitle {
-        if !streams.subtitles.keys().cloned().any(|x| &x == subtitle) {
+    // premium only episodes cannot be streamed without a premium account (or anonymously), they
+    // are skipped instead of aborting the whole download
+    let streams = match episode.streams().await {
+        Ok(streams) => streams,
+        Err(e) if episode.metadata.is_premium_only => {
+            warn!(
+                "Episode {} ({}) of season {} ({}) of {} is only available with a premium account, skipping it",
+                episode.metadata.episode_number,
+                episode.title,
+                episode.metadata.season_number,
+                episode.metadata.season_title,
+                episode.metadata.series_title
+            );
+            debug!("Failed to get streams of premium only episode: {}", e);
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(region_error(
+                e,
+                format!(
+                    "Episode {} ({}) of season {} ({}) of {}",
+                    episode.metadata.episode_number,
+                    episode.title,
+                    episode.metadata.season_number,
+                    episode.metadata.season_title,
+                    episode.metadata.series_title
+                ),
+                &episode.metadata.eligible_region,
+            ))
+        }
+    };
+    let hardsub = if let Some(subtitle) = download.hardsub() {
+        let Some(locale) = subtitle.find(|l| {
+            streams.subtitles.contains_key(l)
+                && (!subtitle.forced() || is_forced_subtitle(l, &episode.metadata.audio_locale))
+        }) else {
             error!(
                 "Episode {} ({}) of season {} ({}) of {} has no {} subtitles",
                 episode.metadata.episode_number,
@@ -508,8 +2921,25 @@ async fn format_from_episode(
                 subtitle
             );
             return Ok(None);
+        };
+        if locale != subtitle.preferred() {
+            info!(
+                "Episode {} ({}) of season {} ({}) of {} has no {} subtitles, using {} instead",
+                episode.metadata.episode_number,
+                episode.title,
+                episode.metadata.season_number,
+                episode.metadata.season_title,
+                episode.metadata.series_title,
+                subtitle.preferred(),
+                locale
+            )
         }
-        streams.hls_streaming_data(Some(subtitle.clone())).await?
+        Some(locale.clone())
+    } else {
+        None
+    };
+    let (streaming_data, audio_stream) = if let Some(subtitle) = &hardsub {
+        streaming_data(&streams, Some(subtitle.clone()), &download.stream_type).await?
     } else if let Some(subtitle) = &download.closedcaption {
         if !streams
             .closed_captions
@@ -528,12 +2958,19 @@ async fn format_from_episode(
             );
             return Ok(None);
         }
-        streams.hls_streaming_data(None).await?
+        streaming_data(&streams, None, &download.stream_type).await?
     } else {
-        streams.hls_streaming_data(None).await?
+        streaming_data(&streams, None, &download.stream_type).await?
     };
 
-    let Some(stream) = find_resolution(streaming_data, &download.resolution) else {
+    let variants = (download.segment_fallback || download.downgrade_after.is_some())
+        .then(|| streaming_data.clone());
+    let Some(stream) = find_resolution(
+        streaming_data,
+        &download.resolution,
+        &download.resolution_policy,
+        &download.variant_criteria(),
+    ) else {
         bail!(
             "Resolution ({}x{}) is not available for episode {} ({}) of season {} ({}) of {}",
             download.resolution.width,
@@ -546,7 +2983,92 @@ async fn format_from_episode(
         )
     };
 
-    Ok(Some(Format::new_from_episode(episode, stream)))
+    let fallback_stream = variants.and_then(|v| fallback_variant(&v, &stream));
+    let mut format = Format::new_from_episode(episode, stream);
+    format.audio_stream = audio_stream;
+    format.fallback_stream = fallback_stream;
+    format.downgrade_after = download.downgrade_after;
+    format.subtitles = subtitles_to_mux(download, &streams.subtitles, &format.audio, &format.title);
+    format.closed_captions =
+        closed_captions_to_mux(download, &streams.closed_captions, &format.title);
+    format.hardsub = hardsub;
+
+    Ok(Some(format))
+}
+
+/// A parsed url. Music videos and concerts are not part of [`MediaCollection`], see [`MusicUrl`].
+enum ParsedUrl {
+    Media(MediaCollection),
+    Music(MusicUrl),
+}
+
+async fn format_from_music(
+    ctx: &Context,
+    download: &Download,
+    music_url: MusicUrl,
+) -> Result<Option<Format>> {
+    // music has no subtitles, neither soft nor hard ones
+    if !download.subtitle.is_empty() {
+        warn!("Music videos and concerts have no subtitles, ignoring the subtitle languages")
+    }
+
+    let (mut format, audio_stream, fallback_stream) = match music_url {
+        MusicUrl::MusicVideo(id) => {
+            let music_video: Media<MusicVideo> = ctx.crunchy.media_from_id(id).await?;
+            let streams = music_video.streams().await?;
+            let (stream, audio_stream, fallback_stream) =
+                music_stream(download, &streams, &music_video.title).await?;
+            (
+                Format::new_from_music_video(music_video, stream),
+                audio_stream,
+                fallback_stream,
+            )
+        }
+        MusicUrl::Concert(id) => {
+            let concert: Media<Concert> = ctx.crunchy.media_from_id(id).await?;
+            let streams = concert.streams().await?;
+            let (stream, audio_stream, fallback_stream) =
+                music_stream(download, &streams, &concert.title).await?;
+            (
+                Format::new_from_concert(concert, stream),
+                audio_stream,
+                fallback_stream,
+            )
+        }
+    };
+    format.audio_stream = audio_stream;
+    format.fallback_stream = fallback_stream;
+    format.downgrade_after = download.downgrade_after;
+
+    Ok(Some(format))
+}
+
+/// Video stream (and audio stream if it's a DASH stream) of a music video or concert. The last
+/// element is the fallback stream, see '--segment-fallback'.
+async fn music_stream(
+    download: &Download,
+    streams: &Stream,
+    title: &str,
+) -> Result<(VariantData, Option<VariantData>, Option<VariantData>)> {
+    let (streaming_data, audio_stream) =
+        streaming_data(streams, None, &download.stream_type).await?;
+    let variants = (download.segment_fallback || download.downgrade_after.is_some())
+        .then(|| streaming_data.clone());
+    let Some(stream) = find_resolution(
+        streaming_data,
+        &download.resolution,
+        &download.resolution_policy,
+        &download.variant_criteria(),
+    ) else {
+        bail!(
+            "Resolution ({}x{}) is not available for {}",
+            download.resolution.width,
+            download.resolution.height,
+            title
+        )
+    };
+    let fallback_stream = variants.and_then(|v| fallback_variant(&v, &stream));
+    Ok((stream, audio_stream, fallback_stream))
 }
 
 async fn format_from_movie_listing(
@@ -556,8 +3078,19 @@ async fn format_from_movie_listing(
 ) -> Result<Option<Vec<Format>>> {
     let mut formats = vec![];
 
-    for movie in movie_listing.movies().await? {
-        if let Some(fmt) = format_from_movie(download, movie, url_filter).await? {
+    // movies have no number, their position in the listing is used instead
+    for (i, movie) in movie_listing.movies().await?.into_iter().enumerate() {
+        if !url_filter.is_episode_valid(i as u32 + 1, 1) {
+            continue;
+        }
+        if let Some(mut fmt) = format_from_movie(
+            download,
+            movie,
+            Some(movie_listing.metadata.movie_release_year),
+        )
+        .await?
+        {
+            fmt.number = i as u32 + 1;
             formats.push(fmt)
         }
     }
@@ -565,46 +3098,174 @@ async fn format_from_movie_listing(
     Ok(some_vec_or_none(formats))
 }
 
+/// Format of the movie. The release year is only known by the movie listing the movie belongs to,
+/// it's fetched if not given.
 async fn format_from_movie(
     download: &Download,
     movie: Media<Movie>,
-    _: &UrlFilter,
+    release_year: Option<u32>,
 ) -> Result<Option<Format>> {
-    let streams = movie.streams().await?;
-    let mut streaming_data = if let Some(subtitle) = &download.subtitle {
-        if !streams.subtitles.keys().cloned().any(|x| &x == subtitle) {
+    let streams = match movie.streams().await {
+        Ok(streams) => streams,
+        Err(e) if movie.metadata.is_premium_only => {
+            warn!(
+                "Movie {} is only available with a premium account, skipping it",
+                movie.title
+            );
+            debug!("Failed to get streams of premium only movie: {}", e);
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(region_error(
+                e,
+                format!("Movie {}", movie.title),
+                &movie.metadata.eligible_region,
+            ))
+        }
+    };
+    let hardsub = if let Some(subtitle) = download.hardsub() {
+        // movies have no dubs and therefore no forced subtitles
+        let Some(locale) =
+            subtitle.find(|l| streams.subtitles.contains_key(l) && !subtitle.forced())
+        else {
             error!("Movie {} has no {} subtitles", movie.title, subtitle);
             return Ok(None);
+        };
+        if locale != subtitle.preferred() {
+            info!(
+                "Movie {} has no {} subtitles, using {} instead",
+                movie.title,
+                subtitle.preferred(),
+                locale
+            )
         }
-        streams.hls_streaming_data(Some(subtitle.clone())).await?
+        Some(locale.clone())
     } else {
-        streams.hls_streaming_data(None).await?
+        None
     };
+    let (streaming_data, audio_stream) =
+        streaming_data(&streams, hardsub.clone(), &download.stream_type).await?;
+    let variants = (download.segment_fallback || download.downgrade_after.is_some())
+        .then(|| streaming_data.clone());
 
-    streaming_data.sort_by(|a, b| a.resolution.width.cmp(&b.resolution.width).reverse());
-    let stream = {
-        match download.resolution.height {
-            u64::MAX => streaming_data.into_iter().next().unwrap(),
-            u64::MIN => streaming_data.into_iter().last().unwrap(),
-            _ => {
-                if let Some(streaming_data) = streaming_data.into_iter().find(|v| {
-                    download.resolution.height == u64::MAX
-                        || v.resolution.height == download.resolution.height
-                }) {
-                    streaming_data
-                } else {
-                    bail!(
-                        "Resolution ({}x{}) is not available for movie {}",
-                        download.resolution.width,
-                        download.resolution.height,
-                        movie.title
-                    )
-                }
+    let Some(stream) = find_resolution(
+        streaming_data,
+        &download.resolution,
+        &download.resolution_policy,
+        &download.variant_criteria(),
+    ) else {
+        bail!(
+            "Resolution ({}x{}) is not available for movie {}",
+            download.resolution.width,
+            download.resolution.height,
+            movie.title
+        )
+    };
+
+    let subtitles = subtitles_to_mux(download, &streams.subtitles, &Locale::ja_JP, &movie.title);
+    let release_year = match release_year {
+        Some(release_year) => Some(release_year),
+        None => match movie.movie_listing().await {
+            Ok(movie_listing) => Some(movie_listing.metadata.movie_release_year),
+            Err(e) => {
+                debug!(
+                    "Failed to get movie listing of movie {}: {}",
+                    movie.title, e
+                );
+                None
             }
-        }
+        },
     };
+    let closed_captions = closed_captions_to_mux(download, &streams.closed_captions, &movie.title);
+    let fallback_stream = variants.and_then(|v| fallback_variant(&v, &stream));
+    let mut format = Format::new_from_movie(movie, stream);
+    format.audio_stream = audio_stream;
+    format.fallback_stream = fallback_stream;
+    format.downgrade_after = download.downgrade_after;
+    format.subtitles = subtitles;
+    format.closed_captions = closed_captions;
+    format.hardsub = hardsub;
+    format.release_year = release_year;
 
-    Ok(Some(Format::new_from_movie(movie, stream)))
+    Ok(Some(format))
+}
+
+/// Subtitles which should be added as separate tracks to the output file. `audio` is the audio
+/// locale of the video, it's required to detect forced subtitles.
+fn subtitles_to_mux(
+    download: &Download,
+    available: &HashMap<Locale, StreamSubtitle>,
+    audio: &Locale,
+    title: &str,
+) -> Vec<StreamSubtitle> {
+    if !download.soft_subtitles() {
+        return vec![];
+    }
+
+    if download.all_subtitles() {
+        let mut subtitles: Vec<StreamSubtitle> = available.values().cloned().collect();
+        subtitles.sort_by_key(|s| s.locale.to_string());
+        subtitles
+    } else {
+        download
+            .subtitle
+            .iter()
+            .filter_map(|subtitle| {
+                let Some(locale) = subtitle.find(|l| {
+                    available.contains_key(l)
+                        && (!subtitle.forced() || is_forced_subtitle(l, audio))
+                }) else {
+                    if subtitle.forced() {
+                        warn!("{} has no forced {} subtitles", title, subtitle.preferred())
+                    } else {
+                        warn!("{} has no {} subtitles", title, subtitle)
+                    }
+                    return None;
+                };
+                if locale != subtitle.preferred() {
+                    info!(
+                        "{} has no {} subtitles, using {} instead",
+                        title,
+                        subtitle.preferred(),
+                        locale
+                    )
+                }
+                available.get(locale).cloned()
+            })
+            .collect()
+    }
+}
+
+/// Closed captions (their locale and url) which should be added as separate tracks to the output
+/// file.
+fn closed_captions_to_mux(
+    download: &Download,
+    available: &HashMap<Locale, StreamSubtitle>,
+    title: &str,
+) -> Vec<(Locale, String)> {
+    download
+        .cc
+        .iter()
+        .filter_map(|locale| {
+            let Some(closed_caption) = available.get(locale) else {
+                warn!("{} has no {} closed captions", title, locale);
+                return None;
+            };
+            Some((locale.clone(), closed_caption.url.to_string()))
+        })
+        .collect()
+}
+
+/// Name of a subtitle track. Forced subtitles and closed captions are labeled, so they can be told
+/// apart from the regular subtitles of the same language.
+fn subtitle_track_name(locale: &Locale, forced: bool, cc: bool) -> String {
+    if forced {
+        format!("{} [Forced]", locale.to_human_readable())
+    } else if cc {
+        format!("{} [CC]", locale.to_human_readable())
+    } else {
+        locale.to_human_readable()
+    }
 }
 
 fn some_vec_or_none<T>(v: Vec<T>) -> Option<Vec<T>> {
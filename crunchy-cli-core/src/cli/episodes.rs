@@ -0,0 +1,108 @@
+use crate::cli::seasons::join_locales;
+use crate::utils::context::Context;
+use crate::utils::log::progress;
+use crate::utils::parse::{parse_url, UrlFilter};
+use crate::utils::sort::sort_seasons_after_number;
+use crate::utils::table::print_table;
+use crate::Execute;
+use anyhow::{bail, Result};
+use crunchyroll_rs::{Episode, Locale, Media, MediaCollection, Season};
+use std::collections::BTreeMap;
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "List all episodes of a series or season")]
+#[command(arg_required_else_help(true))]
+pub struct Episodes {
+    #[arg(help = "Crunchyroll series or season url")]
+    url: String,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Episodes {
+    async fn execute(self, ctx: Context) -> Result<()> {
+        let progress_handler = progress!("Fetching episodes");
+        let (media_collection, url_filter) =
+            match parse_url(&ctx.crunchy, self.url.clone(), true).await {
+                Ok(parsed) => parsed,
+                Err(e) => bail!("url {} could not be parsed: {}", self.url, e),
+            };
+        let (title, seasons) = match media_collection {
+            MediaCollection::Series(series) => (series.title.clone(), series.seasons().await?),
+            MediaCollection::Season(season) => (season.title.clone(), vec![season]),
+            _ => bail!("Only series and season urls are supported ({})", self.url),
+        };
+
+        let mut rows = vec![];
+        for season in sort_seasons_after_number(seasons) {
+            if !url_filter.is_season_valid(season[0].metadata.season_number) {
+                continue;
+            }
+            rows.extend(season_rows(season, &url_filter).await?)
+        }
+        progress_handler.stop(format!("Episodes of {}", title));
+
+        print_table(
+            &[
+                "Season", "Episode", "Title", "Air date", "Dubs", "Subs", "Id",
+            ],
+            &rows,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Table rows of all episodes of the given seasons. All seasons must have the same season number,
+/// every episode is shown only once with the dubs of all seasons.
+async fn season_rows(
+    seasons: Vec<Media<Season>>,
+    url_filter: &UrlFilter,
+) -> Result<Vec<Vec<String>>> {
+    // the episode number alone is not unique, specials may share it with a regular episode
+    let mut episodes: BTreeMap<(u32, String), (Media<Episode>, Vec<Locale>, Vec<Locale>)> =
+        BTreeMap::new();
+    for season in seasons {
+        for episode in season.episodes().await? {
+            if !url_filter.is_episode_valid(
+                episode.metadata.episode_number,
+                episode.metadata.season_number,
+            ) {
+                continue;
+            }
+
+            let (_, audio, subtitles) = episodes
+                .entry((
+                    episode.metadata.episode_number,
+                    episode.metadata.episode.clone(),
+                ))
+                .or_insert_with(|| (episode.clone(), vec![], vec![]));
+            if !audio.contains(&episode.metadata.audio_locale) {
+                audio.push(episode.metadata.audio_locale.clone())
+            }
+            for locale in &episode.metadata.subtitle_locales {
+                if !subtitles.contains(locale) {
+                    subtitles.push(locale.clone())
+                }
+            }
+        }
+    }
+
+    Ok(episodes
+        .into_values()
+        .map(|(episode, audio, subtitles)| {
+            vec![
+                episode.metadata.season_number.to_string(),
+                episode.metadata.episode.clone(),
+                episode.title.clone(),
+                episode
+                    .metadata
+                    .episode_air_date
+                    .format("%Y-%m-%d")
+                    .to_string(),
+                join_locales(&audio),
+                join_locales(&subtitles),
+                episode.id,
+            ]
+        })
+        .collect())
+}
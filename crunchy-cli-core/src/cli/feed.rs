@@ -0,0 +1,121 @@
+use crate::utils::context::Context;
+use crate::utils::feed::rss_feed;
+use crate::utils::parse::{parse_url, UrlFilter};
+use crate::Execute;
+use anyhow::{bail, Result};
+use crunchyroll_rs::{Episode, Locale, Media, MediaCollection, Season};
+use log::debug;
+use std::io::Write;
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Generate a RSS feed of the newest episodes of series")]
+#[command(arg_required_else_help(true))]
+pub struct Feed {
+    #[arg(help = format!("Audio language of the episodes in the feed. \
+    Available languages are: {}", Locale::all().into_iter().map(|l| l.to_string()).collect::<Vec<String>>().join(", ")))]
+    #[arg(short, long, default_value_t = crate::utils::locale::system_locale())]
+    audio: Locale,
+
+    #[arg(help = "Maximum number of episodes in the feed")]
+    #[arg(long, default_value_t = 25)]
+    limit: usize,
+
+    #[arg(help = "Name of the output file. If '-' (default), the feed is written to stdout")]
+    #[arg(
+        long_help = "Name of the output file. If '-' (default), the feed is written to stdout. \
+    Use '-q' then to suppress all other output"
+    )]
+    #[arg(short, long, default_value = "-")]
+    output: String,
+
+    #[arg(help = "Url(s) to Crunchyroll series or seasons")]
+    urls: Vec<String>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Feed {
+    async fn execute(self, ctx: Context) -> Result<()> {
+        let mut titles = vec![];
+        let mut episodes = vec![];
+
+        // nothing is logged here besides debug messages since the feed may be written to stdout
+        for url in &self.urls {
+            let (media_collection, url_filter) =
+                match parse_url(&ctx.crunchy, url.clone(), true).await {
+                    Ok(parsed) => parsed,
+                    Err(e) => bail!("url {} could not be parsed: {}", url, e),
+                };
+
+            match media_collection {
+                MediaCollection::Series(series) => {
+                    debug!("Url {} is series ({})", url, series.title);
+                    titles.push(series.title.clone());
+                    for season in series.seasons().await? {
+                        episodes.extend(self.season_episodes(season, &url_filter).await?)
+                    }
+                }
+                MediaCollection::Season(season) => {
+                    debug!("Url {} is season ({})", url, season.title);
+                    titles.push(season.title.clone());
+                    episodes.extend(self.season_episodes(season, &url_filter).await?)
+                }
+                _ => bail!("Only series and season urls are supported ({})", url),
+            }
+        }
+
+        episodes.sort_by(|a, b| {
+            b.metadata
+                .episode_air_date
+                .cmp(&a.metadata.episode_air_date)
+        });
+        episodes.truncate(self.limit);
+        debug!("Feed contains {} episodes", episodes.len());
+
+        let link = if self.urls.len() == 1 {
+            self.urls[0].clone()
+        } else {
+            "https://www.crunchyroll.com".to_string()
+        };
+        let feed = rss_feed(
+            &titles.join(", "),
+            &link,
+            &format!("Newest episodes of {}", titles.join(", ")),
+            &episodes,
+        );
+
+        if self.output == "-" {
+            std::io::stdout().lock().write_all(feed.as_bytes())?
+        } else {
+            std::fs::write(&self.output, feed)?
+        }
+
+        Ok(())
+    }
+}
+
+impl Feed {
+    /// Episodes of the given season which have the requested audio and are not filtered out by the
+    /// url filter.
+    async fn season_episodes(
+        &self,
+        season: Media<Season>,
+        url_filter: &UrlFilter,
+    ) -> Result<Vec<Media<Episode>>> {
+        if !season.metadata.audio_locales.contains(&self.audio)
+            || !url_filter.is_season_valid(season.metadata.season_number)
+        {
+            return Ok(vec![]);
+        }
+
+        Ok(season
+            .episodes()
+            .await?
+            .into_iter()
+            .filter(|e| {
+                e.metadata.audio_locale == self.audio
+                    && url_filter
+                        .is_episode_valid(e.metadata.episode_number, e.metadata.season_number)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,397 @@
+use crate::cli::log::tab_info;
+use crate::cli::utils::region_error;
+use crate::utils::context::Context;
+use crate::utils::log::progress;
+use crate::utils::parse::{parse_url, UrlFilter};
+use crate::Execute;
+use anyhow::{bail, Result};
+use crunchyroll_rs::media::Stream;
+use crunchyroll_rs::{
+    Episode, Locale, Media, MediaCollection, Movie, MovieListing, Season, Series,
+};
+use log::{debug, info};
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InfoFormat {
+    Text,
+    Json,
+}
+
+impl ToString for InfoFormat {
+    fn to_string(&self) -> String {
+        match self {
+            InfoFormat::Text => "text",
+            InfoFormat::Json => "json",
+        }
+        .to_string()
+    }
+}
+
+impl InfoFormat {
+    pub(crate) fn parse(s: &str) -> Result<InfoFormat, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "text" => InfoFormat::Text,
+            "json" => InfoFormat::Json,
+            _ => return Err(format!("'{}' is not a valid info output format", s)),
+        })
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Show information about series, seasons, episodes and movies")]
+#[command(arg_required_else_help(true))]
+pub struct Info {
+    #[arg(help = "Format of the output. Either 'text' or 'json'")]
+    #[arg(long_help = "Format of the output. Either 'text' or 'json'. \
+    'json' prints the information as json object to stdout (all other messages are printed to stderr then), which can be consumed by scripts")]
+    #[arg(short, long, default_value = "text")]
+    #[arg(value_parser = InfoFormat::parse)]
+    pub(crate) output: InfoFormat,
+
+    #[arg(help = "Crunchyroll url")]
+    url: String,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Info {
+    async fn execute(self, ctx: Context) -> Result<()> {
+        let progress_handler = progress!("Parsing url");
+        let (media_collection, url_filter) =
+            match parse_url(&ctx.crunchy, self.url.clone(), true).await {
+                Ok(parsed) => parsed,
+                Err(e) => bail!("url {} could not be parsed: {}", self.url, e),
+            };
+        progress_handler.stop("Parsed url");
+
+        let progress_handler = progress!("Fetching information");
+        let media_info = match media_collection {
+            MediaCollection::Series(series) => {
+                MediaInfo::Series(SeriesInfo::new(series, &url_filter).await?)
+            }
+            MediaCollection::Season(season) => {
+                MediaInfo::Season(SeasonInfo::new(season, &url_filter).await?)
+            }
+            MediaCollection::Episode(episode) => {
+                MediaInfo::Episode(EpisodeInfo::new(episode).await?)
+            }
+            MediaCollection::MovieListing(movie_listing) => {
+                MediaInfo::MovieListing(MovieListingInfo::new(movie_listing).await?)
+            }
+            MediaCollection::Movie(movie) => MediaInfo::Movie(MovieInfo::new(movie).await?),
+        };
+        progress_handler.stop("Fetched information");
+
+        match self.output {
+            InfoFormat::Text => media_info.print(),
+            InfoFormat::Json => {
+                let mut stdout = std::io::stdout().lock();
+                serde_json::to_writer_pretty(&mut stdout, &media_info)?;
+                writeln!(stdout)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MediaInfo {
+    Series(SeriesInfo),
+    Season(SeasonInfo),
+    Episode(EpisodeInfo),
+    MovieListing(MovieListingInfo),
+    Movie(MovieInfo),
+}
+
+impl MediaInfo {
+    fn print(&self) {
+        match self {
+            MediaInfo::Series(series) => series.print(),
+            MediaInfo::Season(season) => season.print(),
+            MediaInfo::Episode(episode) => episode.print(),
+            MediaInfo::MovieListing(movie_listing) => movie_listing.print(),
+            MediaInfo::Movie(movie) => movie.print(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SeriesInfo {
+    id: String,
+    title: String,
+    description: String,
+    audio_locales: Vec<String>,
+    subtitle_locales: Vec<String>,
+    seasons: Vec<SeasonInfo>,
+}
+
+impl SeriesInfo {
+    async fn new(series: Media<Series>, url_filter: &UrlFilter) -> Result<Self> {
+        let mut seasons = vec![];
+        for season in series.seasons().await? {
+            if url_filter.is_season_valid(season.metadata.season_number) {
+                seasons.push(SeasonInfo::new(season, url_filter).await?)
+            }
+        }
+
+        Ok(Self {
+            id: series.id,
+            title: series.title,
+            description: series.description,
+            audio_locales: locales_to_strings(&series.metadata.audio_locales),
+            subtitle_locales: locales_to_strings(&series.metadata.subtitle_locales),
+            seasons,
+        })
+    }
+
+    fn print(&self) {
+        info!("Series: {} [{}]", self.title, self.id);
+        tab_info!("Audio: {}", join_or_none(&self.audio_locales));
+        tab_info!("Subtitles: {}", join_or_none(&self.subtitle_locales));
+        for season in &self.seasons {
+            season.print()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SeasonInfo {
+    id: String,
+    title: String,
+    number: u32,
+    audio_locales: Vec<String>,
+    episodes: Vec<EpisodeInfo>,
+}
+
+impl SeasonInfo {
+    async fn new(season: Media<Season>, url_filter: &UrlFilter) -> Result<Self> {
+        let mut episodes = vec![];
+        for episode in season.episodes().await? {
+            if url_filter.is_episode_valid(
+                episode.metadata.episode_number,
+                episode.metadata.season_number,
+            ) {
+                episodes.push(EpisodeInfo::new(episode).await?)
+            }
+        }
+
+        Ok(Self {
+            id: season.id,
+            title: season.title,
+            number: season.metadata.season_number,
+            audio_locales: locales_to_strings(&season.metadata.audio_locales),
+            episodes,
+        })
+    }
+
+    fn print(&self) {
+        info!("Season {}: {} [{}]", self.number, self.title, self.id);
+        tab_info!("Audio: {}", join_or_none(&self.audio_locales));
+        for episode in &self.episodes {
+            episode.print()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EpisodeInfo {
+    id: String,
+    title: String,
+    series_title: String,
+    season_number: u32,
+    number: u32,
+    audio_locale: String,
+    subtitle_locales: Vec<String>,
+    resolutions: Vec<String>,
+    /// Duration in seconds.
+    duration: u64,
+    air_date: String,
+    premium_only: bool,
+}
+
+impl EpisodeInfo {
+    async fn new(episode: Media<Episode>) -> Result<Self> {
+        let (subtitle_locales, resolutions) = match episode.streams().await {
+            Ok(streams) => stream_info(streams).await?,
+            // premium only episodes have no streams if no premium account is used
+            Err(e) if episode.metadata.is_premium_only => {
+                debug!(
+                    "Failed to get streams of premium only episode {}: {}",
+                    episode.id, e
+                );
+                (vec![], vec![])
+            }
+            Err(e) => {
+                return Err(region_error(
+                    e,
+                    format!("Episode {}", episode.title),
+                    &episode.metadata.eligible_region,
+                ))
+            }
+        };
+
+        Ok(Self {
+            id: episode.id,
+            title: episode.title,
+            series_title: episode.metadata.series_title,
+            season_number: episode.metadata.season_number,
+            number: episode.metadata.episode_number,
+            audio_locale: episode.metadata.audio_locale.to_string(),
+            subtitle_locales,
+            resolutions,
+            duration: episode.metadata.duration.num_seconds() as u64,
+            air_date: episode.metadata.episode_air_date.to_rfc3339(),
+            premium_only: episode.metadata.is_premium_only,
+        })
+    }
+
+    fn print(&self) {
+        tab_info!(
+            "S{:02}E{:02} {} [{}]",
+            self.season_number,
+            self.number,
+            self.title,
+            self.id
+        );
+        tab_info!(
+            "\tAudio: {}, Subtitles: {}",
+            self.audio_locale,
+            join_or_none(&self.subtitle_locales)
+        );
+        tab_info!(
+            "\tResolutions: {}, Duration: {}",
+            join_or_none(&self.resolutions),
+            format_duration(self.duration)
+        );
+        tab_info!("\tAir date: {}", self.air_date);
+        if self.premium_only {
+            tab_info!("\tPremium only")
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MovieListingInfo {
+    id: String,
+    title: String,
+    description: String,
+    movies: Vec<MovieInfo>,
+}
+
+impl MovieListingInfo {
+    async fn new(movie_listing: Media<MovieListing>) -> Result<Self> {
+        let mut movies = vec![];
+        for movie in movie_listing.movies().await? {
+            movies.push(MovieInfo::new(movie).await?)
+        }
+
+        Ok(Self {
+            id: movie_listing.id,
+            title: movie_listing.title,
+            description: movie_listing.description,
+            movies,
+        })
+    }
+
+    fn print(&self) {
+        info!("Movie listing: {} [{}]", self.title, self.id);
+        for movie in &self.movies {
+            movie.print()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MovieInfo {
+    id: String,
+    title: String,
+    subtitle_locales: Vec<String>,
+    resolutions: Vec<String>,
+    /// Duration in seconds.
+    duration: u64,
+    premium_only: bool,
+}
+
+impl MovieInfo {
+    async fn new(movie: Media<Movie>) -> Result<Self> {
+        let (subtitle_locales, resolutions) = match movie.streams().await {
+            Ok(streams) => stream_info(streams).await?,
+            Err(e) if movie.metadata.is_premium_only => {
+                debug!(
+                    "Failed to get streams of premium only movie {}: {}",
+                    movie.id, e
+                );
+                (vec![], vec![])
+            }
+            Err(e) => {
+                return Err(region_error(
+                    e,
+                    format!("Movie {}", movie.title),
+                    &movie.metadata.eligible_region,
+                ))
+            }
+        };
+
+        Ok(Self {
+            id: movie.id,
+            title: movie.title,
+            subtitle_locales,
+            resolutions,
+            duration: movie.metadata.duration.num_seconds() as u64,
+            premium_only: movie.metadata.is_premium_only,
+        })
+    }
+
+    fn print(&self) {
+        tab_info!("{} [{}]", self.title, self.id);
+        tab_info!("\tSubtitles: {}", join_or_none(&self.subtitle_locales));
+        tab_info!(
+            "\tResolutions: {}, Duration: {}",
+            join_or_none(&self.resolutions),
+            format_duration(self.duration)
+        );
+        if self.premium_only {
+            tab_info!("\tPremium only")
+        }
+    }
+}
+
+/// Available subtitle locales and resolutions (highest first) of a stream.
+async fn stream_info(streams: Stream) -> Result<(Vec<String>, Vec<String>)> {
+    let mut subtitle_locales: Vec<Locale> = streams.subtitles.keys().cloned().collect();
+    subtitle_locales.sort_by_key(|l| l.to_string());
+
+    let mut streaming_data = streams.hls_streaming_data(None).await?;
+    streaming_data.sort_by(|a, b| b.resolution.height.cmp(&a.resolution.height));
+    let mut resolutions: Vec<String> = streaming_data
+        .iter()
+        .map(|s| s.resolution.to_string())
+        .collect();
+    resolutions.dedup();
+
+    Ok((locales_to_strings(&subtitle_locales), resolutions))
+}
+
+fn locales_to_strings(locales: &[Locale]) -> Vec<String> {
+    locales.iter().map(|l| l.to_string()).collect()
+}
+
+fn join_or_none(values: &[String]) -> String {
+    if values.is_empty() {
+        "None".to_string()
+    } else {
+        values.join(", ")
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
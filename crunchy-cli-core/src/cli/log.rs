@@ -2,21 +2,36 @@ use indicatif::{ProgressBar, ProgressStyle};
 use log::{
     set_boxed_logger, set_max_level, Level, LevelFilter, Log, Metadata, Record, SetLoggerError,
 };
-use std::io::{stdout, Write};
+use std::fs::File;
+use std::io::{stderr, stdout, Write};
 use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
+/// Level of the messages shown in the console. If a log file is used, [`log::max_level`] is always
+/// debug, so this must be used to decide what is shown to the user.
+static CONSOLE_LEVEL: Mutex<LevelFilter> = Mutex::new(LevelFilter::Info);
+
+pub fn console_level() -> LevelFilter {
+    *CONSOLE_LEVEL.lock().unwrap()
+}
+
 #[allow(clippy::type_complexity)]
 pub struct CliLogger {
     all: bool,
     level: LevelFilter,
+    /// Every debug message is written to this file, regardless of the console level.
+    file: Option<Mutex<File>>,
+    /// Show progress messages as spinner. If disabled, they're printed as normal messages.
+    spinner: bool,
+    /// Print every message to stderr, e.g. because stdout is used for the video output.
+    stderr: bool,
     progress: Mutex<Option<ProgressBar>>,
 }
 
 impl Log for CliLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        metadata.level() <= self.level || (self.file.is_some() && metadata.level() <= Level::Debug)
     }
 
     fn log(&self, record: &Record) {
@@ -28,6 +43,16 @@ impl Log for CliLogger {
             return;
         }
 
+        if let Some(file) = &self.file {
+            // 'progress_end' may be used without message
+            if !record.args().to_string().is_empty() {
+                let _ = writeln!(file.lock().unwrap(), "{}", self.extended_message(record));
+            }
+            if record.level() > self.level {
+                return;
+            }
+        }
+
         if self.level >= LevelFilter::Debug {
             self.extended(record);
             return;
@@ -49,26 +74,65 @@ impl Log for CliLogger {
     }
 
     fn flush(&self) {
-        let _ = stdout().flush();
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+        if self.stderr {
+            let _ = stderr().flush();
+        } else {
+            let _ = stdout().flush();
+        }
     }
 }
 
 impl CliLogger {
-    pub fn new(all: bool, level: LevelFilter) -> Self {
+    pub fn new(
+        all: bool,
+        level: LevelFilter,
+        file: Option<File>,
+        spinner: bool,
+        stderr: bool,
+    ) -> Self {
         Self {
             all,
             level,
+            file: file.map(Mutex::new),
+            spinner,
+            stderr,
             progress: Mutex::new(None),
         }
     }
 
-    pub fn init(all: bool, level: LevelFilter) -> Result<(), SetLoggerError> {
-        set_max_level(level);
-        set_boxed_logger(Box::new(CliLogger::new(all, level)))
+    pub fn init(
+        all: bool,
+        level: LevelFilter,
+        file: Option<File>,
+        spinner: bool,
+        stderr: bool,
+    ) -> Result<(), SetLoggerError> {
+        *CONSOLE_LEVEL.lock().unwrap() = level;
+        if file.is_some() {
+            set_max_level(level.max(LevelFilter::Debug))
+        } else {
+            set_max_level(level)
+        }
+        set_boxed_logger(Box::new(CliLogger::new(all, level, file, spinner, stderr)))
+    }
+
+    fn println(&self, msg: String) {
+        if self.stderr {
+            eprintln!("{}", msg)
+        } else {
+            println!("{}", msg)
+        }
     }
 
     fn extended(&self, record: &Record) {
-        println!(
+        self.println(self.extended_message(record))
+    }
+
+    fn extended_message(&self, record: &Record) -> String {
+        format!(
             "[{}] {}  {} ({}) {}",
             chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
             record.level(),
@@ -86,7 +150,7 @@ impl CliLogger {
     }
 
     fn normal(&self, record: &Record) {
-        println!(":: {}", record.args())
+        self.println(format!(":: {}", record.args()))
     }
 
     fn error(&self, record: &Record) {
@@ -94,6 +158,13 @@ impl CliLogger {
     }
 
     fn progress(&self, record: &Record, stop: bool) {
+        if !self.spinner {
+            if !stop || !record.args().to_string().is_empty() {
+                self.normal(record)
+            }
+            return;
+        }
+
         let mut progress = self.progress.lock().unwrap();
 
         let msg = format!("{}", record.args());
@@ -128,7 +199,7 @@ impl CliLogger {
 
 macro_rules! tab_info {
     ($($arg:tt)+) => {
-        if log::max_level() == log::LevelFilter::Debug {
+        if $crate::cli::log::console_level() == log::LevelFilter::Debug {
             info!($($arg)+)
         } else {
             info!("\t{}", format!($($arg)+))
@@ -1,39 +1,90 @@
 use crate::utils::context::Context;
+use crate::utils::credentials::session_to_string;
 use crate::Execute;
 use anyhow::bail;
 use anyhow::Result;
-use crunchyroll_rs::crunchyroll::SessionToken;
-use std::fs;
-use std::path::PathBuf;
+use chrono::Utc;
+use log::info;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, clap::Parser)]
 #[clap(about = "Save your login credentials persistent on disk")]
 pub struct Login {
-    #[arg(help = "Remove your stored credentials (instead of save them)")]
+    #[arg(help = "Remove your stored credentials (instead of save them). Same as 'logout'")]
     #[arg(long)]
     pub remove: bool,
+
+    #[arg(help = "Login with the cookies of your browser, exported to a cookies.txt file")]
+    #[arg(
+        long_help = "Login with the cookies of your browser, exported to a cookies.txt file (Netscape format, like browser extensions such as 'Get cookies.txt' or tools like yt-dlp export them). \
+    The 'etp_rt' cookie of crunchyroll.com is used to create the session. \
+    This also works for accounts which cannot login with a password (e.g. because of captchas or single sign-on)"
+    )]
+    #[arg(long)]
+    pub cookies: Option<PathBuf>,
 }
 
 #[async_trait::async_trait(?Send)]
 impl Execute for Login {
     async fn execute(self, ctx: Context) -> Result<()> {
-        if let Some(login_file_path) = login_file_path() {
-            match ctx.crunchy.session_token().await {
-                SessionToken::RefreshToken(refresh_token) => Ok(fs::write(
-                    login_file_path,
-                    format!("refresh_token:{}", refresh_token),
-                )?),
-                SessionToken::EtpRt(etp_rt) => {
-                    Ok(fs::write(login_file_path, format!("etp_rt:{}", etp_rt))?)
-                }
-                SessionToken::Anonymous => bail!("Anonymous login cannot be saved"),
+        let Some(session) = session_to_string(ctx.crunchy.session_token().await) else {
+            bail!("Anonymous login cannot be saved")
+        };
+        ctx.credentials.store(&session)?;
+        info!("Stored login");
+        Ok(())
+    }
+}
+
+/// Read the `etp_rt` cookie of crunchyroll.com from a Netscape cookie file.
+pub fn cookies_login(path: &Path) -> Result<String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => bail!(
+            "Failed to read cookie file {}: {}",
+            path.to_string_lossy(),
+            e
+        ),
+    };
+
+    let mut expired = false;
+    for line in content.lines() {
+        // cookies which are only accessible via http (which etp_rt is) are prefixed with
+        // '#HttpOnly_', every other line which starts with '#' is a comment
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // domain, include subdomains, path, secure, expiration, name, value
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            continue;
+        }
+        let (domain, expiration, name, value) = (fields[0], fields[4], fields[5], fields[6]);
+        let domain = domain.trim_start_matches('.');
+        if name != "etp_rt"
+            || (domain != "crunchyroll.com" && !domain.ends_with(".crunchyroll.com"))
+        {
+            continue;
+        }
+        // session cookies have an expiration of 0. the file may contain an old cookie next to the
+        // current one (e.g. of another subdomain), so expired cookies are skipped
+        if let Ok(expiration) = expiration.parse::<i64>() {
+            if expiration != 0 && expiration < Utc::now().timestamp() {
+                expired = true;
+                continue;
             }
-        } else {
-            bail!("Cannot find config path")
         }
+        return Ok(value.trim().to_string());
+    }
+
+    if expired {
+        bail!("The etp_rt cookie in the cookie file is expired, please login in your browser and export the cookies again")
     }
-}
 
-pub fn login_file_path() -> Option<PathBuf> {
-    dirs::config_dir().map(|config_dir| config_dir.join(".crunchy-cli-core"))
+    bail!(
+        "No etp_rt cookie of crunchyroll.com found in {}. Make sure you're logged in in the browser you exported the cookies from",
+        path.to_string_lossy()
+    )
 }
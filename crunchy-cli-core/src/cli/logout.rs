@@ -0,0 +1,3 @@
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Remove your stored login credentials")]
+pub struct Logout {}
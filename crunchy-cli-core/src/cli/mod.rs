@@ -1,5 +1,18 @@
 pub mod archive;
+pub mod benchmark;
+pub mod crunchylist;
 pub mod download;
+pub mod episodes;
+pub mod feed;
+pub mod info;
 pub mod log;
 pub mod login;
-mod utils;
+pub mod logout;
+pub mod play;
+pub mod search;
+pub mod seasons;
+pub mod session;
+pub mod speedtest;
+pub mod watch;
+pub mod watchlist;
+pub(crate) mod utils;
@@ -0,0 +1,195 @@
+use crate::cli::download::Download;
+use crate::cli::log::tab_info;
+use crate::cli::utils::download_segments;
+use crate::utils::context::Context;
+use crate::utils::format::Format;
+use crate::utils::os::is_broken_pipe;
+use crate::Execute;
+use anyhow::{bail, Result};
+use log::{debug, info};
+use std::io::ErrorKind;
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Player {
+    Mpv,
+    Vlc,
+}
+
+impl ToString for Player {
+    fn to_string(&self) -> String {
+        match self {
+            Player::Mpv => "mpv",
+            Player::Vlc => "vlc",
+        }
+        .to_string()
+    }
+}
+
+impl Player {
+    pub(crate) fn parse(s: &str) -> Result<Player, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "mpv" => Player::Mpv,
+            "vlc" => Player::Vlc,
+            _ => return Err(format!("'{}' is not a valid player", s)),
+        })
+    }
+
+    /// Arguments to play `input` (an url or '-' for stdin) with the given title and subtitles.
+    fn args(&self, input: &str, title: &str, subtitles: &[String]) -> Vec<String> {
+        let mut args = vec![];
+        match self {
+            Player::Mpv => {
+                args.push(format!("--force-media-title={}", title));
+                for subtitle in subtitles {
+                    args.push(format!("--sub-file={}", subtitle))
+                }
+            }
+            Player::Vlc => {
+                args.extend([
+                    "--play-and-exit".to_string(),
+                    format!("--meta-title={}", title),
+                ]);
+                // vlc supports only one external subtitle
+                if let Some(subtitle) = subtitles.first() {
+                    args.push(format!("--sub-file={}", subtitle))
+                }
+            }
+        }
+        args.push(input.to_string());
+        args
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Play episodes directly in a video player (mpv or vlc)")]
+#[command(arg_required_else_help(true))]
+pub struct Play {
+    #[arg(help = "Player which is used. Valid players are 'mpv' and 'vlc'")]
+    #[arg(long, default_value = "mpv")]
+    #[arg(value_parser = Player::parse)]
+    player: Player,
+
+    #[arg(
+        help = "Download the video and pipe it into the player instead of letting the player stream it"
+    )]
+    #[arg(
+        long_help = "Download the video and pipe it into the player instead of letting the player stream it. \
+    Use this if the player has problems to stream the video itself. \
+    Subtitles are only added to the video if they're burned into it then"
+    )]
+    #[arg(long, default_value_t = false)]
+    pipe: bool,
+
+    #[clap(flatten)]
+    pub(crate) download: Download,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Play {
+    fn pre_check(&self) -> Result<()> {
+        if self.download.uses_dash() {
+            bail!("DASH streams cannot be played since their video and audio are separated, use '--stream-type hls' instead")
+        }
+        Ok(())
+    }
+
+    async fn execute(self, ctx: Context) -> Result<()> {
+        let formats = self.download.formats(&ctx).await?;
+        if formats.is_empty() {
+            bail!("No episodes found")
+        }
+
+        for format in formats {
+            info!(
+                "Playing {} (S{:02}E{:02}) with {}",
+                format.title,
+                format.season_number,
+                format.number,
+                self.player.to_string()
+            );
+            tab_info!("Resolution: {}", format.stream.resolution);
+            tab_info!("Audio: {}", format.audio);
+
+            // closing the player skips to the next episode, ctrl-c stops the playback completely
+            if self.pipe {
+                self.play_pipe(&ctx, &format).await?
+            } else {
+                self.play_url(&format)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Play {
+    /// Let the player stream the hls url itself.
+    fn play_url(&self, format: &Format) -> Result<()> {
+        let subtitles: Vec<String> = format.subtitles.iter().map(|s| s.url.clone()).collect();
+        let status = self
+            .command(
+                &self
+                    .player
+                    .args(&format.stream.url, &title(format), &subtitles),
+            )?
+            .wait()?;
+        debug!("{} exited with {}", self.player.to_string(), status);
+        Ok(())
+    }
+
+    /// Download the video and pipe it into the player.
+    async fn play_pipe(&self, ctx: &Context, format: &Format) -> Result<()> {
+        let mut player = self.command(&self.player.args("-", &title(format), &[]))?;
+        let result = download_segments(
+            ctx,
+            player.stdin.take().unwrap(),
+            None,
+            format.stream.clone(),
+            Some(format),
+            None,
+            None,
+        )
+        .await;
+
+        match result {
+            Ok(_) => (),
+            // the player was closed before the video was completely downloaded
+            Err(e) if is_broken_pipe(&e) => debug!("Player closed the pipe"),
+            Err(e) => {
+                let _ = player.kill();
+                return Err(e);
+            }
+        }
+        let status = player.wait()?;
+        debug!("{} exited with {}", self.player.to_string(), status);
+        Ok(())
+    }
+
+    fn command(&self, args: &[String]) -> Result<std::process::Child> {
+        match Command::new(self.player.to_string())
+            .args(args)
+            .stdin(if self.pipe {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => Ok(child),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                bail!("{} is not installed", self.player.to_string())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn title(format: &Format) -> String {
+    format!(
+        "{} - S{:02}E{:02} - {}",
+        format.series_name, format.season_number, format.number, format.title
+    )
+}
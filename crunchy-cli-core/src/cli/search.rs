@@ -0,0 +1,88 @@
+use crate::cli::log::tab_info;
+use crate::utils::context::Context;
+use crate::utils::log::progress;
+use crate::Execute;
+use anyhow::Result;
+use crunchyroll_rs::search::QueryOptions;
+use crunchyroll_rs::Locale;
+use log::info;
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Search for series and movies")]
+#[command(arg_required_else_help(true))]
+pub struct Search {
+    #[arg(help = "Maximum number of results which are shown per result type")]
+    #[arg(long, default_value_t = 10)]
+    limit: u32,
+
+    #[arg(help = "Search query")]
+    query: String,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Search {
+    async fn execute(self, ctx: Context) -> Result<()> {
+        let progress_handler = progress!("Searching");
+        let results = ctx
+            .crunchy
+            .query(&self.query, QueryOptions::default().limit(self.limit))
+            .await?;
+        progress_handler.stop(format!("Search results for '{}'", self.query));
+
+        let series = results.series.map(|s| s.items).unwrap_or_default();
+        let movie_listings = results.movie_listing.map(|m| m.items).unwrap_or_default();
+
+        if series.is_empty() && movie_listings.is_empty() {
+            info!("No results found");
+            return Ok(());
+        }
+
+        if !series.is_empty() {
+            info!("Series");
+            for s in series {
+                tab_info!("{} [{}]", s.title, s.id);
+                tab_info!(
+                    "\tSeasons: {}, Episodes: {}",
+                    s.metadata.season_count,
+                    s.metadata.episode_count
+                );
+                tab_info!("\tAudio: {}", join_locales(&s.metadata.audio_locales));
+                tab_info!(
+                    "\tSubtitles: {}",
+                    join_locales(&s.metadata.subtitle_locales)
+                );
+                tab_info!(
+                    "\tUrl: https://www.crunchyroll.com/series/{}/{}",
+                    s.id,
+                    s.slug_title
+                )
+            }
+        }
+
+        if !movie_listings.is_empty() {
+            info!("Movies");
+            for movie_listing in movie_listings {
+                tab_info!("{} [{}]", movie_listing.title, movie_listing.id);
+                tab_info!(
+                    "\tUrl: https://www.crunchyroll.com/series/{}/{}",
+                    movie_listing.id,
+                    movie_listing.slug_title
+                )
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn join_locales(locales: &[Locale]) -> String {
+    if locales.is_empty() {
+        "None".to_string()
+    } else {
+        locales
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
@@ -0,0 +1,89 @@
+use crate::utils::context::Context;
+use crate::utils::log::progress;
+use crate::utils::parse::parse_url;
+use crate::utils::sort::sort_seasons_after_number;
+use crate::utils::table::print_table;
+use crate::Execute;
+use anyhow::{bail, Result};
+use crunchyroll_rs::{Locale, MediaCollection};
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "List all seasons of a series")]
+#[command(arg_required_else_help(true))]
+pub struct Seasons {
+    #[arg(help = "Crunchyroll series url")]
+    url: String,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Seasons {
+    async fn execute(self, ctx: Context) -> Result<()> {
+        let progress_handler = progress!("Fetching seasons");
+        let (media_collection, url_filter) =
+            match parse_url(&ctx.crunchy, self.url.clone(), true).await {
+                Ok(parsed) => parsed,
+                Err(e) => bail!("url {} could not be parsed: {}", self.url, e),
+            };
+        let MediaCollection::Series(series) = media_collection else {
+            bail!("Only series urls are supported ({})", self.url)
+        };
+        let seasons = series.seasons().await?;
+        progress_handler.stop(format!("Seasons of {}", series.title));
+
+        let mut rows = vec![];
+        // crunchyroll has an own season for every dub, they're merged into one row here
+        for season in sort_seasons_after_number(seasons) {
+            let first = season.first().unwrap();
+            if !url_filter.is_season_valid(first.metadata.season_number) {
+                continue;
+            }
+
+            let mut audio: Vec<Locale> = vec![];
+            let mut subtitles: Vec<Locale> = vec![];
+            for s in &season {
+                for locale in &s.metadata.audio_locales {
+                    if !audio.contains(locale) {
+                        audio.push(locale.clone())
+                    }
+                }
+                for locale in &s.metadata.subtitle_locales {
+                    if !subtitles.contains(locale) {
+                        subtitles.push(locale.clone())
+                    }
+                }
+            }
+
+            rows.push(vec![
+                first.metadata.season_number.to_string(),
+                first.title.clone(),
+                first.metadata.number_of_episodes.to_string(),
+                join_locales(&audio),
+                join_locales(&subtitles),
+                season
+                    .iter()
+                    .map(|s| s.id.clone())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ])
+        }
+
+        print_table(
+            &["Season", "Title", "Episodes", "Dubs", "Subs", "Id"],
+            &rows,
+        )?;
+
+        Ok(())
+    }
+}
+
+pub(crate) fn join_locales(locales: &[Locale]) -> String {
+    if locales.is_empty() {
+        "-".to_string()
+    } else {
+        locales
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
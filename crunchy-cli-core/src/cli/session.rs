@@ -0,0 +1,99 @@
+use crate::utils::credentials::Credentials;
+use anyhow::{bail, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Move your stored login to another machine")]
+#[command(arg_required_else_help(true))]
+pub struct Session {
+    #[command(subcommand)]
+    command: SessionCommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum SessionCommand {
+    #[clap(about = "Write the stored login to a file")]
+    Export {
+        #[arg(help = "File the login is written to")]
+        file: PathBuf,
+    },
+    #[clap(about = "Store the login of a file which was created with 'session export'")]
+    Import {
+        #[arg(help = "File the login is read from")]
+        file: PathBuf,
+    },
+}
+
+/// Content of an exported session file.
+#[derive(Deserialize, Serialize)]
+struct SessionFile {
+    /// Stored session token, in the format of [`crate::utils::credentials::session_to_string`].
+    session: String,
+}
+
+impl Session {
+    /// Export or import the session. Both work with the stored session directly, no login is
+    /// required (and none must be made, since it would rotate the refresh token).
+    pub fn execute(&self, credentials: &Credentials) -> Result<()> {
+        match &self.command {
+            SessionCommand::Export { file } => export(credentials, file),
+            SessionCommand::Import { file } => import(credentials, file),
+        }
+    }
+}
+
+fn export(credentials: &Credentials, path: &Path) -> Result<()> {
+    let Some(session) = credentials.load()? else {
+        bail!("No stored login found, login with 'crunchy login' first")
+    };
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // the file contains a token which gives full access to the account
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(serde_json::to_string_pretty(&SessionFile { session })?.as_bytes())?;
+
+    info!("Exported login to {}", path.to_string_lossy());
+    // crunchyroll rotates the refresh token every time it's used, so only one machine can use the
+    // session
+    warn!("The login can only be used on one machine. Once it's used on the other machine, it's invalid here, run 'crunchy logout' here afterwards");
+    Ok(())
+}
+
+fn import(credentials: &Credentials, path: &Path) -> Result<()> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => bail!(
+            "Failed to read session file {}: {}",
+            path.to_string_lossy(),
+            e
+        ),
+    };
+    let session_file: SessionFile = match serde_json::from_str(&content) {
+        Ok(session_file) => session_file,
+        Err(e) => bail!(
+            "{} is not a valid session file: {}",
+            path.to_string_lossy(),
+            e
+        ),
+    };
+    if !session_file.session.starts_with("refresh_token:")
+        && !session_file.session.starts_with("etp_rt:")
+    {
+        bail!(
+            "{} contains an unknown session type",
+            path.to_string_lossy()
+        )
+    }
+
+    credentials.store(&session_file.session)?;
+    info!("Imported login from {}", path.to_string_lossy());
+    info!("The session file contains your login, delete it if it's not needed anymore");
+    Ok(())
+}
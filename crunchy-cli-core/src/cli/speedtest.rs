@@ -0,0 +1,67 @@
+use crate::cli::download::Download;
+use crate::utils::cdn::{benchmark, candidate_hosts, fastest};
+use crate::utils::context::Context;
+use crate::utils::log::progress;
+use crate::utils::table::print_table;
+use crate::Execute;
+use anyhow::{bail, Result};
+use indicatif::HumanBytes;
+use log::info;
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Measure the download speed of the available CDN hosts")]
+#[command(arg_required_else_help(true))]
+pub struct Speedtest {
+    #[arg(help = "Number of segments which are downloaded from every host")]
+    #[arg(
+        long_help = "Number of segments which are downloaded from every host. \
+    More segments give more accurate results but the test takes longer"
+    )]
+    #[arg(long, default_value_t = 5)]
+    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    segments: u16,
+
+    #[clap(flatten)]
+    pub(crate) download: Download,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Speedtest {
+    async fn execute(self, ctx: Context) -> Result<()> {
+        let Some(format) = self.download.formats(&ctx).await?.into_iter().next() else {
+            bail!("No episodes found")
+        };
+
+        let progress_handler = progress!("Benchmarking cdn hosts with {}", format.title);
+        let segments = format.stream.segments().await?;
+        let sample = &segments[..segments.len().min(self.segments as usize)];
+        let hosts = candidate_hosts(sample);
+        let results = benchmark(&ctx.crunchy.client(), sample, &hosts).await;
+        progress_handler.stop(format!("Benchmarked {} cdn hosts", hosts.len()));
+
+        let rows: Vec<Vec<String>> = results
+            .iter()
+            .map(|r| {
+                vec![
+                    r.host.clone(),
+                    r.latency
+                        .map_or("-".to_string(), |l| format!("{}ms", l.as_millis())),
+                    r.speed
+                        .map_or("-".to_string(), |s| format!("{}/s", HumanBytes(s))),
+                    r.error.clone().unwrap_or_else(|| "ok".to_string()),
+                ]
+            })
+            .collect();
+        print_table(&["Host", "Latency", "Speed", "Status"], &rows)?;
+
+        match fastest(&results) {
+            Some(fastest) => info!(
+                "Fastest host is {}, use '--cdn {}' to download from it (or '--cdn auto' to benchmark automatically before downloading)",
+                fastest.host, fastest.host
+            ),
+            None => bail!("None of the cdn hosts could be reached"),
+        }
+
+        Ok(())
+    }
+}
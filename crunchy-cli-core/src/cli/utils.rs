@@ -1,190 +1,1629 @@
+use crate::cli::log::console_level;
+use crate::utils::buffer::SegmentBuffer;
+use crate::utils::cancel::Cancelled;
+use crate::utils::cdn::with_host;
+use crate::utils::client::SEGMENT_TIMEOUT;
 use crate::utils::context::Context;
-use anyhow::{bail, Result};
-use crunchyroll_rs::media::{Resolution, VariantData, VariantSegment};
-use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
-use log::{debug, LevelFilter};
-use rsubs_lib::vtt;
-use std::borrow::{Borrow, BorrowMut};
-use std::collections::BTreeMap;
-use std::io::Write;
-use std::sync::{mpsc, Arc, Mutex};
+use crate::utils::format::{Format, MediaKind};
+use crate::utils::os::tempfile;
+use crate::utils::pause::Pause;
+use crate::utils::progress::{
+    emit_progress, ProgressEvent, ProgressFormat, SmoothedRate, TransferSummary,
+};
+use crate::utils::rate_limit::RateLimiter;
+use crate::utils::resume::ResumeJournal;
+use crate::utils::retry::{Backoff, RetryPolicy};
+use crate::utils::subtitle::{shift_ass, shift_vtt, vtt_to_srt, SubtitleFormat, SubtitleStyle};
+use crate::utils::writer::SegmentWriter;
+use anyhow::{anyhow, bail, Result};
+use bytes::{Bytes, BytesMut};
+use chrono::NaiveTime;
+use crunchyroll_rs::media::{Resolution, Stream, StreamSubtitle, VariantData, VariantSegment};
+use crunchyroll_rs::{Concert, Episode, Locale, Media, Movie, MusicVideo};
+use indicatif::{
+    FormattedDuration, HumanBytes, MultiProgress, ProgressBar, ProgressFinish, ProgressState,
+    ProgressStyle,
+};
+use log::{debug, info, warn, LevelFilter};
+use regex::Regex;
+use reqwest::{Client, StatusCode};
+use rsubs_lib::{ssa, vtt};
+use std::cmp::Reverse;
+use std::fmt::Write as FmtWrite;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tempfile::TempPath;
+use tokio::sync::{mpsc, watch, Notify};
 use tokio::task::JoinSet;
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ResolutionPolicy {
+    Exact,
+    BestBelow,
+    Nearest,
+}
+
+impl ToString for ResolutionPolicy {
+    fn to_string(&self) -> String {
+        match self {
+            ResolutionPolicy::Exact => "exact",
+            ResolutionPolicy::BestBelow => "best-below",
+            ResolutionPolicy::Nearest => "nearest",
+        }
+        .to_string()
+    }
+}
+
+impl ResolutionPolicy {
+    pub(crate) fn parse(s: &str) -> Result<ResolutionPolicy, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "exact" => ResolutionPolicy::Exact,
+            "best-below" => ResolutionPolicy::BestBelow,
+            "nearest" => ResolutionPolicy::Nearest,
+            _ => return Err(format!("'{}' is not a valid resolution policy", s)),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StreamType {
+    Hls,
+    Dash,
+}
+
+impl ToString for StreamType {
+    fn to_string(&self) -> String {
+        match self {
+            StreamType::Hls => "hls",
+            StreamType::Dash => "dash",
+        }
+        .to_string()
+    }
+}
+
+impl StreamType {
+    pub(crate) fn parse(s: &str) -> Result<StreamType, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "hls" => StreamType::Hls,
+            "dash" => StreamType::Dash,
+            _ => return Err(format!("'{}' is not a valid stream type", s)),
+        })
+    }
+}
+
+/// Get the available video streams of the given type. HLS streams contain video and audio, DASH
+/// streams have separate audio streams, the one with the highest bandwidth is returned then.
+pub async fn streaming_data(
+    streams: &Stream,
+    hardsub: Option<Locale>,
+    stream_type: &StreamType,
+) -> Result<(Vec<VariantData>, Option<VariantData>)> {
+    match stream_type {
+        StreamType::Hls => Ok((streams.hls_streaming_data(hardsub).await?, None)),
+        StreamType::Dash => {
+            let (video, audio) = streams.dash_streaming_data(hardsub).await?;
+            let Some(audio) = audio.into_iter().max_by_key(|a| a.bandwidth) else {
+                bail!("DASH stream has no audio")
+            };
+            Ok((video, Some(audio)))
+        }
+    }
+}
+
+/// Turn the error of a failed streams request into a more helpful one if the media is not
+/// available in the region of the account, Crunchyroll answers with 403 then. `eligible_region`
+/// are the regions in which the media is available, as returned by the api.
+pub fn region_error(
+    error: crunchyroll_rs::error::Error,
+    name: String,
+    eligible_region: &str,
+) -> anyhow::Error {
+    if !matches!(
+        error,
+        crunchyroll_rs::error::Error::Request {
+            status: Some(reqwest::StatusCode::FORBIDDEN),
+            ..
+        }
+    ) {
+        return error.into();
+    }
+    debug!("Failed to get streams of {}: {}", name, error);
+
+    let regions: Vec<&str> = eligible_region
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|r| !r.is_empty())
+        .collect();
+    if regions.is_empty() {
+        anyhow!("{} is not available in your region", name)
+    } else {
+        anyhow!(
+            "{} is not available in your region (available in: {}). Use '--proxy' to route the traffic through one of these regions",
+            name,
+            regions.join(", ")
+        )
+    }
+}
+
+/// Criteria to choose between multiple variants of a stream which have the same resolution.
+#[derive(Clone, Debug, Default)]
+pub struct VariantCriteria {
+    /// Maximum bandwidth in bits per second. Variants above it are only used if no other variant
+    /// is available.
+    pub max_bitrate: Option<u64>,
+    /// Preferred framerate. Variants with the closest framerate are preferred.
+    pub fps: Option<u32>,
+}
+
+impl VariantCriteria {
+    /// Sort key of a variant, lower is better. Variants with the framerate closest to the
+    /// preferred one come first, ties are ordered by the highest bandwidth.
+    fn rank(&self, variant: &VariantData) -> (u64, Reverse<u64>) {
+        let fps_diff = self
+            .fps
+            .map(|fps| ((variant.fps - fps as f64).abs() * 1000.0) as u64)
+            .unwrap_or_default();
+        (fps_diff, Reverse(variant.bandwidth))
+    }
+}
+
+/// Find the stream with the requested resolution. If it's not available, `policy` decides which
+/// stream is used instead: [`ResolutionPolicy::BestBelow`] chooses the highest resolution below the
+/// requested one, [`ResolutionPolicy::Nearest`] the one with the smallest height difference.
+/// If multiple streams have the same resolution, `criteria` decides which one is used.
 pub fn find_resolution(
     mut streaming_data: Vec<VariantData>,
     resolution: &Resolution,
+    policy: &ResolutionPolicy,
+    criteria: &VariantCriteria,
 ) -> Option<VariantData> {
-    streaming_data.sort_by(|a, b| a.resolution.width.cmp(&b.resolution.width).reverse());
+    if streaming_data.is_empty() {
+        return None;
+    }
+
+    if let Some(max_bitrate) = criteria.max_bitrate {
+        if streaming_data.iter().any(|v| v.bandwidth <= max_bitrate) {
+            streaming_data.retain(|v| v.bandwidth <= max_bitrate)
+        } else {
+            let lowest = streaming_data.iter().map(|v| v.bandwidth).min().unwrap();
+            info!(
+                "No stream is below the maximum bitrate of {} kbit/s, using the lowest available ({} kbit/s)",
+                max_bitrate / 1000,
+                lowest / 1000
+            );
+            streaming_data.retain(|v| v.bandwidth == lowest)
+        }
+    }
+
+    // variants with the same resolution are ordered by how good they match the criteria, so the
+    // first variant of a resolution is always the preferred one
+    streaming_data.sort_by(|a, b| {
+        b.resolution
+            .width
+            .cmp(&a.resolution.width)
+            .then_with(|| criteria.rank(a).cmp(&criteria.rank(b)))
+    });
     match resolution.height {
-        u64::MAX => Some(streaming_data.into_iter().next().unwrap()),
-        u64::MIN => Some(streaming_data.into_iter().last().unwrap()),
-        _ => streaming_data
+        u64::MAX => return Some(streaming_data.into_iter().next().unwrap()),
+        u64::MIN => {
+            let lowest = streaming_data.last().unwrap().resolution.width;
+            return streaming_data
+                .into_iter()
+                .find(|v| v.resolution.width == lowest);
+        }
+        _ => (),
+    }
+
+    if let Some(pos) = streaming_data
+        .iter()
+        .position(|v| v.resolution.height == resolution.height)
+    {
+        return Some(streaming_data.remove(pos));
+    }
+
+    let fallback = match policy {
+        ResolutionPolicy::Exact => None,
+        // the streams are sorted from the highest to the lowest resolution, so the first one below
+        // the requested resolution is the best one
+        ResolutionPolicy::BestBelow => streaming_data
+            .into_iter()
+            .find(|v| v.resolution.height < resolution.height),
+        ResolutionPolicy::Nearest => streaming_data
             .into_iter()
-            .find(|v| resolution.height == u64::MAX || v.resolution.height == resolution.height),
+            .min_by_key(|v| v.resolution.height.abs_diff(resolution.height)),
+    };
+    if let Some(stream) = &fallback {
+        info!(
+            "Resolution {} is not available, using {} instead",
+            resolution, stream.resolution
+        )
     }
+    fallback
+}
+
+/// Variant which is used for segments of `stream` which cannot be downloaded: the one with the
+/// highest bandwidth below the bandwidth of `stream`, or the lowest other one if `stream` already
+/// is the lowest.
+pub fn fallback_variant(variants: &[VariantData], stream: &VariantData) -> Option<VariantData> {
+    let others = variants.iter().filter(|v| v.url != stream.url);
+    others
+        .clone()
+        .filter(|v| v.bandwidth < stream.bandwidth)
+        .max_by_key(|v| v.bandwidth)
+        .or_else(|| others.min_by_key(|v| v.bandwidth))
+        .cloned()
 }
 
-pub async fn download_cc(ctx: &Context, path: String, ccurl: String) -> Result<()> {
+/// Download a closed caption and write it to the given path. The timestamps are shifted by
+/// `offset` milliseconds before it is converted to the requested format, `style` is applied to the
+/// styles of the converted ass file.
+pub async fn download_cc(
+    ctx: &Context,
+    path: String,
+    ccurl: String,
+    subtitle_format: &SubtitleFormat,
+    offset: i64,
+    style: &SubtitleStyle,
+) -> Result<()> {
     let client = Arc::new(ctx.crunchy.client());
-    let data = vtt::parse(client.get(ccurl).send().await?.text().await?).to_ass();
-    data.to_file(path)?;
+    let raw = shift_vtt(&client.get(ccurl).send().await?.text().await?, offset);
+    match subtitle_format {
+        SubtitleFormat::Ass => {
+            vtt::parse(raw).to_ass().to_file(path.clone())?;
+            if !style.is_empty() {
+                let ass = std::fs::read_to_string(&path)?;
+                std::fs::write(&path, style.apply(&ass))?
+            }
+        }
+        SubtitleFormat::Srt => std::fs::write(path, vtt_to_srt(&raw))?,
+        SubtitleFormat::Vtt => std::fs::write(path, raw)?,
+    }
     Ok(())
 }
 
-pub async fn download_segments(
+/// Download a closed caption to a temporary `.ass` file, so it can be muxed like a subtitle.
+pub async fn download_cc_subtitle(
     ctx: &Context,
-    writer: &mut impl Write,
+    ccurl: String,
+    offset: i64,
+    style: &SubtitleStyle,
+) -> Result<TempPath> {
+    let path = tempfile(".ass")?.into_temp_path();
+    download_cc(
+        ctx,
+        path.to_string_lossy().to_string(),
+        ccurl,
+        &SubtitleFormat::Ass,
+        offset,
+        style,
+    )
+    .await?;
+    Ok(path)
+}
+
+/// Write a subtitle to the given path. Crunchyroll delivers its subtitles as ass, they're converted
+/// if another format is requested. The timestamps are shifted by `offset` milliseconds.
+pub async fn write_subtitle(
+    subtitle: StreamSubtitle,
+    path: &Path,
+    subtitle_format: &SubtitleFormat,
+    offset: i64,
+) -> Result<()> {
+    let mut buf = vec![];
+    subtitle.write_to(&mut buf).await?;
+    buf = shift_ass(&String::from_utf8_lossy(&buf), offset).into_bytes();
+    let path_string = path.to_string_lossy().to_string();
+    match subtitle_format {
+        SubtitleFormat::Ass => std::fs::write(path, fix_subtitle_look_and_feel(buf))?,
+        SubtitleFormat::Srt => ssa::parse(String::from_utf8_lossy(&buf).to_string())
+            .to_srt()
+            .to_file(path_string)?,
+        SubtitleFormat::Vtt => ssa::parse(String::from_utf8_lossy(&buf).to_string())
+            .to_vtt()
+            .to_file(path_string)?,
+    }
+    Ok(())
+}
+
+/// Download a subtitle to a temporary `.ass` file. `optimize` applies the look and feel fixes of
+/// [`fix_subtitle_look_and_feel`], the timestamps are shifted by `offset` milliseconds.
+pub async fn download_subtitle(
+    subtitle: StreamSubtitle,
+    max_length: NaiveTime,
+    optimize: bool,
+    offset: i64,
+) -> Result<TempPath> {
+    let tempfile = tempfile(".ass")?;
+    let (mut file, path) = tempfile.into_parts();
+
+    let mut buf = vec![];
+    subtitle.write_to(&mut buf).await?;
+    buf = shift_ass(&String::from_utf8_lossy(&buf), offset).into_bytes();
+    if optimize {
+        buf = fix_subtitle_look_and_feel(buf)
+    }
+    buf = fix_subtitle_length(buf, max_length);
+
+    file.write_all(buf.as_slice())?;
+
+    Ok(path)
+}
+
+/// Add `ScaledBorderAndShadows: yes` to subtitles; without it they look very messy on some video
+/// players. See [crunchy-labs/crunchy-cli#66](https://github.com/crunchy-labs/crunchy-cli/issues/66)
+/// for more information.
+fn fix_subtitle_look_and_feel(raw: Vec<u8>) -> Vec<u8> {
+    let mut script_info = false;
+    let mut new = String::new();
+
+    for line in String::from_utf8_lossy(raw.as_slice()).split('\n') {
+        if line.trim().starts_with('[') && script_info {
+            new.push_str("ScaledBorderAndShadows: yes\n");
+            script_info = false
+        } else if line.trim() == "[Script Info]" {
+            script_info = true
+        }
+        new.push_str(line);
+        new.push('\n')
+    }
+
+    new.into_bytes()
+}
+
+/// Fix the length of subtitles to a specified maximum amount. This is required because sometimes
+/// subtitles have an unnecessary entry long after the actual video ends with artificially extends
+/// the video length on some video players. To prevent this, the video length must be hard set. See
+/// [crunchy-labs/crunchy-cli#32](https://github.com/crunchy-labs/crunchy-cli/issues/32) for more
+/// information.
+fn fix_subtitle_length(raw: Vec<u8>, max_length: NaiveTime) -> Vec<u8> {
+    let re =
+        Regex::new(r#"^Dialogue:\s\d+,(?P<start>\d+:\d+:\d+\.\d+),(?P<end>\d+:\d+:\d+\.\d+),"#)
+            .unwrap();
+
+    // chrono panics if we try to format NaiveTime with `%2f` and the nano seconds has more than 2
+    // digits so them have to be reduced manually to avoid the panic
+    fn format_naive_time(native_time: NaiveTime) -> String {
+        let formatted_time = native_time.format("%f").to_string();
+        format!(
+            "{}.{}",
+            native_time.format("%T"),
+            if formatted_time.len() <= 2 {
+                native_time.format("%2f").to_string()
+            } else {
+                formatted_time.split_at(2).0.parse().unwrap()
+            }
+        )
+    }
+
+    let length_as_string = format_naive_time(max_length);
+    let mut new = String::new();
+
+    for line in String::from_utf8_lossy(raw.as_slice()).split('\n') {
+        if let Some(capture) = re.captures(line) {
+            let start = capture.name("start").map_or(NaiveTime::default(), |s| {
+                NaiveTime::parse_from_str(s.as_str(), "%H:%M:%S.%f").unwrap()
+            });
+            let end = capture.name("end").map_or(NaiveTime::default(), |s| {
+                NaiveTime::parse_from_str(s.as_str(), "%H:%M:%S.%f").unwrap()
+            });
+
+            if start > max_length {
+                continue;
+            } else if end > max_length {
+                new.push_str(
+                    re.replace(
+                        line,
+                        format!(
+                            "Dialogue: {},{},",
+                            format_naive_time(start),
+                            &length_as_string
+                        ),
+                    )
+                    .to_string()
+                    .as_str(),
+                )
+            } else {
+                new.push_str(line)
+            }
+        } else {
+            new.push_str(line)
+        }
+        new.push('\n')
+    }
+
+    new.into_bytes()
+}
+
+/// Download a stream to a temporary file. If `only_audio` is set, the video is dropped and only the
+/// audio is kept.
+pub async fn download_video(
+    ctx: &Context,
+    stream: VariantData,
+    message: String,
+    only_audio: bool,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<TempPath> {
+    let tempfile = if only_audio {
+        tempfile(".aac")?
+    } else {
+        tempfile(".ts")?
+    };
+    let (_, path) = tempfile.into_parts();
+
+    let mut ffmpeg = Command::new("ffmpeg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .arg("-y")
+        .args(["-f", "mpegts"])
+        .args(["-i", "pipe:"])
+        .args(["-c", "copy"])
+        .args(if only_audio { vec!["-vn"] } else { vec![] })
+        .arg(path.to_str().unwrap())
+        .spawn()?;
+
+    if let Err(e) = download_segments(
+        ctx,
+        ffmpeg.stdin.take().unwrap(),
+        Some(message),
+        stream,
+        None,
+        None,
+        multi_progress,
+    )
+    .await
+    {
+        let _ = ffmpeg.kill();
+        return Err(e);
+    }
+    ffmpeg.wait()?;
+
+    Ok(path)
+}
+
+/// Download the segments of a stream as they are to a temporary file with the given suffix. Unlike
+/// [`download_video`], the stream doesn't have to be a mpeg transport stream, which is required for
+/// DASH streams (their segments are fragmented mp4).
+pub async fn download_raw(
+    ctx: &Context,
+    stream: VariantData,
+    suffix: &str,
+    message: String,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<TempPath> {
+    let (file, path) = tempfile(suffix)?.into_parts();
+    download_segments(ctx, file, Some(message), stream, None, None, multi_progress).await?;
+    Ok(path)
+}
+
+/// Download, decrypt and write all segments of a stream to the writer. The writer is moved to a
+/// blocking thread (see [`SegmentWriter`]) and returned once everything is written. `source` is
+/// the format `variant_data` is the main stream of, if set, expired segment urls are refreshed
+/// and the fallback stream of the format is used for segments which keep failing.
+pub async fn download_segments<W: Write + Send + 'static>(
+    ctx: &Context,
+    writer: W,
     message: Option<String>,
     variant_data: VariantData,
-) -> Result<()> {
-    let segments = variant_data.segments().await?;
+    source: Option<&Format>,
+    resume: Option<ResumeJournal>,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<W> {
+    let mut segments = variant_data.segments().await?;
+    if let Some(host) = ctx.cdn_host(&segments).await {
+        for segment in &mut segments {
+            segment.url = with_host(&segment.url, &host)
+        }
+    }
     let total_segments = segments.len();
+    let first_segment = resume.as_ref().map_or(0, |r| r.next_segment());
+    if first_segment > total_segments {
+        bail!(
+            "Resume journal is at segment {} but the stream only has {} segments",
+            first_segment,
+            total_segments
+        )
+    }
 
-    let client = Arc::new(ctx.crunchy.client());
-    let count = Arc::new(Mutex::new(0));
+    let fetcher = SegmentFetcher::new(ctx);
+    // the urls can only be refreshed if it's known where the stream came from
+    let mut urls = Arc::new(SegmentUrls::new(&segments, source.is_some()));
+    let mut refreshes = 0;
+    let count = Arc::new(Mutex::new(first_segment));
 
-    let progress = if log::max_level() == LevelFilter::Info {
-        let estimated_file_size = (variant_data.bandwidth / 8)
-            * segments
-                .iter()
-                .map(|s| s.length.unwrap_or_default().as_secs())
-                .sum::<u64>();
+    let estimated_segment_len = |segment: &VariantSegment| {
+        (variant_data.bandwidth / 8) * segment.length.unwrap_or_default().as_secs()
+    };
+    let mut estimated_file_size: u64 = segments.iter().map(estimated_segment_len).sum();
+    let mut downloaded_bytes = 0;
+    if let Some(r) = &resume {
+        let estimated_written_len: u64 = segments[..first_segment]
+            .iter()
+            .map(estimated_segment_len)
+            .sum();
+        estimated_file_size = estimated_file_size - estimated_written_len + r.written_bytes();
+        downloaded_bytes = r.written_bytes()
+    }
+    let resumed_bytes = downloaded_bytes;
+    let mut completed_segments = first_segment;
+    let start = ctx.pause.stopwatch();
+    let rate = Arc::new(Mutex::new(SmoothedRate::new()));
 
+    let progress = if ctx.progress_format == ProgressFormat::Bar
+        && console_level() == LevelFilter::Info
+    {
+        let speed_rate = rate.clone();
+        let eta_rate = rate.clone();
         let progress = ProgressBar::new(estimated_file_size)
-            .with_style(
-                ProgressStyle::with_template(
-                    ":: {msg}{bytes:>10} {bytes_per_sec:>12} [{wide_bar}] {percent:>3}%",
+                .with_style(
+                    ProgressStyle::with_template(
+                        ":: {msg}{bytes:>10} {smoothed_speed:>12} [{wide_bar}] {percent:>3}% {smoothed_eta}",
+                    )
+                    .unwrap()
+                    .with_key(
+                        "smoothed_speed",
+                        move |_: &ProgressState, w: &mut dyn FmtWrite| {
+                            if let Some(rate) = speed_rate.lock().unwrap().rate() {
+                                let _ = write!(w, "{}/s", HumanBytes(rate as u64));
+                            }
+                        },
+                    )
+                    .with_key(
+                        "smoothed_eta",
+                        move |state: &ProgressState, w: &mut dyn FmtWrite| {
+                            let remaining =
+                                state.len().unwrap_or_default().saturating_sub(state.pos());
+                            if let Some(eta) = eta_rate.lock().unwrap().eta(remaining) {
+                                let _ = write!(w, "{}", FormattedDuration(eta));
+                            }
+                        },
+                    )
+                    .progress_chars("##-"),
                 )
-                .unwrap()
-                .progress_chars("##-"),
-            )
-            .with_message(message.map(|m| m + " ").unwrap_or_default())
-            .with_finish(ProgressFinish::Abandon);
-        Some(progress)
+                .with_message(message.clone().map(|m| m + " ").unwrap_or_default())
+                .with_finish(ProgressFinish::Abandon);
+        progress.set_position(downloaded_bytes);
+        if let Some(multi_progress) = multi_progress {
+            Some(multi_progress.add(progress))
+        } else {
+            Some(progress)
+        }
     } else {
         None
     };
 
-    let cpus = num_cpus::get();
-    let mut segs: Vec<Vec<VariantSegment>> = Vec::with_capacity(cpus);
-    for _ in 0..cpus {
+    if first_segment > 0 {
+        debug!(
+            "Resuming download at segment {}/{}",
+            first_segment, total_segments
+        )
+    }
+
+    debug!(
+        "Downloading {} segments with {} tasks ({:?})",
+        total_segments - first_segment,
+        ctx.download_threads,
+        ctx.client_options
+    );
+    let mut join_set: JoinSet<Result<()>> = JoinSet::new();
+    let mut receiver = spawn_download_tasks(
+        ctx,
+        &mut join_set,
+        &fetcher,
+        urls.clone(),
+        segments
+            .clone()
+            .into_iter()
+            .enumerate()
+            .skip(first_segment)
+            .collect(),
+        count.clone(),
+        total_segments,
+    );
+
+    let mut writer = SegmentWriter::new(writer, resume, ctx.max_queued_segments);
+    let mut data_pos = first_segment;
+    let mut buf = SegmentBuffer::new(ctx.max_buffer_memory);
+    let mut paused_time = ctx.pause.paused_time();
+    let mut failed = vec![];
+    let mut retrying = false;
+    // the rest of the stream is downloaded from the fallback stream if this many segments fail in
+    // a row, see '--downgrade-after'
+    let fallback = source.and_then(|f| f.fallback_stream.as_ref());
+    let downgrade_after = source.and_then(|f| fallback.and(f.downgrade_after));
+    let mut consecutive_failures: u32 = 0;
+    let mut downgraded = false;
+    loop {
+        let received = tokio::select! {
+            received = receiver.recv() => received,
+            _ = urls.wait_expired() => {
+                refreshes += 1;
+                // a url which is still rejected after refreshing it multiple times is not expired
+                // but invalid for another reason, e.g. because the cdn host doesn't accept it
+                let refreshed = if refreshes > MAX_URL_REFRESHES {
+                    Err(anyhow!(
+                        "segment urls were refreshed {} times already",
+                        MAX_URL_REFRESHES
+                    ))
+                } else {
+                    refresh_segments(ctx, source.unwrap(), &variant_data).await
+                };
+                match refreshed {
+                    Ok(refreshed) if refreshed.len() == segments.len() => {
+                        info!("Segment urls expired, continuing with refreshed ones");
+                        urls.replace(&refreshed)
+                    }
+                    Ok(_) => {
+                        warn!(
+                            "Refreshed stream has a different number of segments, cannot use it"
+                        );
+                        urls.disable_refresh()
+                    }
+                    Err(e) => {
+                        warn!("Failed to refresh expired segment urls: {}", e);
+                        urls.disable_refresh()
+                    }
+                }
+                continue;
+            }
+            _ = ctx.cancellation.cancelled() => {
+                join_set.abort_all();
+                // everything which is already queued is still written, so the resume journal
+                // covers as much as possible when the download is continued
+                writer.close().await?;
+                bail!(Cancelled)
+            }
+        };
+        let Some((pos, result)) = received else {
+            if failed.is_empty() || retrying {
+                break;
+            }
+            // all tasks are done, only the failed segments are missing now
+            retrying = true;
+            receiver = retry_segments(
+                ctx,
+                &fetcher,
+                urls.clone(),
+                &mut join_set,
+                &segments,
+                // after a downgrade the segments already are the ones of the fallback stream
+                fallback.filter(|_| !downgraded),
+                std::mem::take(&mut failed),
+            )
+            .await;
+            continue;
+        };
+        let bytes = match result {
+            Ok(bytes) => {
+                consecutive_failures = 0;
+                bytes
+            }
+            Err(_) if !retrying => {
+                failed.push(pos);
+                consecutive_failures += 1;
+                if downgraded || !downgrade_after.is_some_and(|n| consecutive_failures >= n) {
+                    continue;
+                }
+                downgraded = true;
+                let fallback = fallback.unwrap();
+                let Some(fallback_segments) =
+                    fallback_segments(ctx, fallback, segments.len()).await
+                else {
+                    continue;
+                };
+                warn!(
+                    "{} segments in a row failed, downloading everything from segment {} on in {} instead of {}",
+                    consecutive_failures,
+                    data_pos,
+                    fallback.resolution,
+                    variant_data.resolution
+                );
+                join_set.abort_all();
+                while join_set.join_next().await.is_some() {}
+                // segments which are already buffered are kept, everything else is downloaded again
+                // from the fallback stream, including the failed segments
+                failed.clear();
+                segments = fallback_segments;
+                urls = Arc::new(SegmentUrls::new(&segments, false));
+                let missing = segments
+                    .iter()
+                    .cloned()
+                    .enumerate()
+                    .skip(data_pos)
+                    .filter(|(pos, _)| !buf.contains(*pos))
+                    .collect();
+                receiver = spawn_download_tasks(
+                    ctx,
+                    &mut join_set,
+                    &fetcher,
+                    urls.clone(),
+                    missing,
+                    count.clone(),
+                    total_segments,
+                );
+                continue;
+            }
+            Err(e) => {
+                join_set.abort_all();
+                return Err(e);
+            }
+        };
+
+        let bytes_len = bytes.len() as u64;
+        estimated_file_size =
+            estimated_file_size - estimated_segment_len(segments.get(pos).unwrap()) + bytes_len;
+        downloaded_bytes += bytes_len;
+        completed_segments += 1;
+        {
+            let mut rate = rate.lock().unwrap();
+            // the time the download was paused must not lower the speed
+            if ctx.pause.paused_time() != paused_time {
+                paused_time = ctx.pause.paused_time();
+                rate.restart_clock()
+            }
+            rate.update(bytes_len)
+        }
+
+        if let Some(p) = &progress {
+            p.set_length(estimated_file_size);
+            p.inc(bytes_len)
+        } else if ctx.progress_format == ProgressFormat::Json || ctx.progress_callback.is_some() {
+            let elapsed = start.elapsed().as_secs_f64();
+            let event = ProgressEvent::Segment {
+                name: message.as_deref(),
+                segment: pos,
+                completed_segments,
+                total_segments,
+                downloaded_bytes,
+                estimated_size: estimated_file_size,
+                percent: (completed_segments as f64 / total_segments as f64) * 100f64,
+                speed: if elapsed > 0f64 {
+                    ((downloaded_bytes - resumed_bytes) as f64 / elapsed) as u64
+                } else {
+                    0
+                },
+                eta: rate
+                    .lock()
+                    .unwrap()
+                    .eta(estimated_file_size.saturating_sub(downloaded_bytes))
+                    .map(|eta| eta.as_secs()),
+            };
+            if let Some(callback) = &ctx.progress_callback {
+                callback(&event)
+            }
+            if ctx.progress_format == ProgressFormat::Json {
+                emit_progress(event)
+            }
+        }
+
+        if data_pos == pos {
+            writer.write(pos, bytes).await?;
+            data_pos += 1;
+        } else {
+            buf.insert(pos, bytes)?;
+        }
+        while let Some(b) = buf.remove(data_pos)? {
+            writer.write(data_pos, b).await?;
+            data_pos += 1;
+        }
+        debug!(
+            "Buf is {:?} ({} bytes in memory) TL is {:?}",
+            buf.len(),
+            buf.memory_usage(),
+            *count.lock().unwrap()
+        );
+    }
+
+    while let Some(joined) = join_set.join_next().await {
+        joined??
+    }
+    if data_pos != total_segments {
+        bail!(
+            "Only {} of {} segments could be downloaded",
+            data_pos,
+            total_segments
+        )
+    }
+
+    writer.finish().await
+}
+
+/// Download and decrypt the given segments with `threads` tasks without writing them anywhere.
+/// Returns the number of downloaded bytes and how long the download took, used by
+/// `crunchy benchmark` to measure the throughput.
+pub(crate) async fn benchmark_segments(
+    ctx: &Context,
+    segments: &[VariantSegment],
+    threads: usize,
+) -> Result<(u64, Duration)> {
+    let mut segments = segments.to_vec();
+    if let Some(host) = ctx.cdn_host(&segments).await {
+        for segment in &mut segments {
+            segment.url = with_host(&segment.url, &host)
+        }
+    }
+    let fetcher = SegmentFetcher::new(ctx);
+    let urls = Arc::new(SegmentUrls::new(&segments, false));
+    let segments = Arc::new(segments);
+    // the tasks take the next segment when they're done with the previous one, so slow segments
+    // don't leave other tasks idle
+    let next = Arc::new(AtomicUsize::new(0));
+
+    let start = ctx.pause.stopwatch();
+    let mut join_set: JoinSet<Result<u64>> = JoinSet::new();
+    for _ in 0..threads {
+        let (fetcher, urls, segments, next) = (
+            fetcher.clone(),
+            urls.clone(),
+            segments.clone(),
+            next.clone(),
+        );
+        join_set.spawn(async move {
+            let mut bytes = 0;
+            loop {
+                let pos = next.fetch_add(1, Ordering::SeqCst);
+                let Some(segment) = segments.get(pos) else {
+                    break;
+                };
+                bytes += fetcher
+                    .fetch_decrypted(&urls, pos, segment.clone())
+                    .await?
+                    .len() as u64
+            }
+            Ok(bytes)
+        });
+    }
+
+    let mut bytes = 0;
+    loop {
+        tokio::select! {
+            joined = join_set.join_next() => match joined {
+                Some(joined) => bytes += joined??,
+                None => break,
+            },
+            _ = ctx.cancellation.cancelled() => {
+                join_set.abort_all();
+                bail!(Cancelled)
+            }
+        }
+    }
+    Ok((bytes, start.elapsed()))
+}
+
+/// Print the summary of a finished episode. If `multi_progress` is set, the summary is printed
+/// through it so it doesn't get mixed up with the progress bars of other running downloads.
+pub fn episode_summary(
+    ctx: &Context,
+    path: &Path,
+    summary: &TransferSummary,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<()> {
+    if ctx.progress_format == ProgressFormat::Json {
+        emit_progress(ProgressEvent::EpisodeFinished {
+            output: path,
+            size: summary.size,
+            elapsed: summary.elapsed.as_secs_f64(),
+            speed: summary.speed(),
+        })
+    } else if let Some(multi_progress) = multi_progress {
+        multi_progress.println(format!(
+            ":: Finished '{}': {}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            summary
+        ))?
+    } else {
+        info!("Finished: {}", summary)
+    }
+    Ok(())
+}
+
+/// Print the summary of all episodes of a download / archive run.
+pub fn batch_summary(ctx: &Context, episodes: usize, summary: &TransferSummary) {
+    if ctx.progress_format == ProgressFormat::Json {
+        emit_progress(ProgressEvent::BatchFinished {
+            episodes,
+            size: summary.size,
+            elapsed: summary.elapsed.as_secs_f64(),
+            speed: summary.speed(),
+        })
+    } else {
+        info!("Finished {} episode(s): {}", episodes, summary)
+    }
+}
+
+/// Download and decrypt the given segments (position and segment) with `download_threads` tasks
+/// which are spawned in `join_set`. `count` is increased for every finished segment, it should
+/// start at the number of segments which are already downloaded. Returns the receiver of the
+/// results.
+fn spawn_download_tasks(
+    ctx: &Context,
+    join_set: &mut JoinSet<Result<()>>,
+    fetcher: &SegmentFetcher,
+    urls: Arc<SegmentUrls>,
+    segments: Vec<(usize, VariantSegment)>,
+    count: Arc<Mutex<usize>>,
+    total_segments: usize,
+) -> mpsc::Receiver<(usize, Result<Bytes>)> {
+    let threads = ctx.download_threads;
+    let mut segs: Vec<Vec<(usize, VariantSegment)>> = Vec::with_capacity(threads);
+    for _ in 0..threads {
         segs.push(vec![])
     }
-    for (i, segment) in segments.clone().into_iter().enumerate() {
-        segs[i - ((i / cpus) * cpus)].push(segment);
+    for (pos, segment) in segments {
+        segs[pos % threads].push((pos, segment));
     }
 
-    let (sender, receiver) = mpsc::channel();
+    // an async channel is required here since multiple downloads may run concurrently in the same
+    // task. it's bounded so that the download tasks wait if the writer can't keep up (e.g. because
+    // of a slow disk), instead of piling up decrypted segments in memory
+    let (sender, receiver) = mpsc::channel(ctx.max_queued_segments);
 
-    let mut join_set: JoinSet<Result<()>> = JoinSet::new();
-    for num in 0..cpus {
-        let thread_client = client.clone();
+    for _ in 0..threads {
+        let thread_fetcher = fetcher.clone();
+        let thread_urls = urls.clone();
         let thread_sender = sender.clone();
         let thread_segments = segs.remove(0);
         let thread_count = count.clone();
         join_set.spawn(async move {
-            for (i, segment) in thread_segments.into_iter().enumerate() {
-                let mut retry_count = 0;
-                let mut buf = loop {
-                    let response = thread_client
-                        .get(&segment.url)
-                        .timeout(Duration::from_secs(60))
-                        .send()
-                        .await
-                        .unwrap();
-
-                    match response.bytes().await {
-                        Ok(b) => break b.to_vec(),
-                        Err(e) => {
-                            if e.is_body() {
-                                if retry_count == 5 {
-                                    panic!("Max retry count reached ({}), multiple errors occured while receiving segment {}: {}", retry_count, num + (i * cpus), e)
-                                }
-                                debug!("Failed to download segment {} ({}). Retrying, {} out of 5 retries left", num + (i * cpus), e, 5 - retry_count)
-                            } else {
-                                panic!("{}", e)
-                            }
+            let (fetcher, urls) = (&thread_fetcher, thread_urls.as_ref());
+            let fetch = move |pos: usize| fetcher.fetch(urls, pos);
+
+            let mut thread_segments = thread_segments.into_iter();
+            let mut current = match thread_segments.next() {
+                Some((pos, segment)) => {
+                    let fetched = fetch(pos).await;
+                    Some((pos, segment, fetched))
+                }
+                None => None,
+            };
+            while let Some((pos, segment, fetched)) = current.take() {
+                let url = segment.url.clone();
+                // two stage pipeline: the next segment is fetched while the current one is
+                // decrypted, so neither the network nor the cpu idles
+                let next = thread_segments.next();
+                let (result, next_fetched) = tokio::join!(
+                    async {
+                        match fetched {
+                            Ok(buf) => decrypt_segment(segment, buf).await,
+                            Err(e) => Err(e),
+                        }
+                    },
+                    async {
+                        match &next {
+                            Some((next_pos, _)) => Some(fetch(*next_pos).await),
+                            None => None,
                         }
                     }
+                );
+                current = next
+                    .zip(next_fetched)
+                    .map(|((next_pos, next_segment), next_fetched)| {
+                        (next_pos, next_segment, next_fetched)
+                    });
 
-                    retry_count += 1;
-                };
+                match &result {
+                    Ok(_) => {
+                        let mut c = thread_count.lock().unwrap();
+                        debug!(
+                            "Downloaded and decrypted segment [{}/{} {:.2}%] {}",
+                            pos,
+                            total_segments,
+                            ((*c + 1) as f64 / total_segments as f64) * 100f64,
+                            url
+                        );
+                        *c += 1;
+                    }
+                    // a failed segment doesn't stop the task, it's retried after all other
+                    // segments are downloaded
+                    Err(e) => debug!("Segment {} failed, retrying it later: {}", pos, e),
+                }
+                if thread_sender.send((pos, result)).await.is_err() {
+                    break;
+                }
+            }
 
-                buf = VariantSegment::decrypt(buf.borrow_mut(), segment.key)?.to_vec();
+            Ok(())
+        });
+    }
+
+    receiver
+}
+
+/// Download the segments at the given positions, which failed during the regular download, one
+/// after another in a new task. If a segment still fails and `fallback` is set, the segment at the
+/// same position of the fallback variant is used instead. Returns the receiver of the results.
+async fn retry_segments(
+    ctx: &Context,
+    fetcher: &SegmentFetcher,
+    urls: Arc<SegmentUrls>,
+    join_set: &mut JoinSet<Result<()>>,
+    segments: &[VariantSegment],
+    fallback: Option<&VariantData>,
+    mut failed: Vec<usize>,
+) -> mpsc::Receiver<(usize, Result<Bytes>)> {
+    failed.sort();
+    info!(
+        "Retrying {} failed segment(s): {}",
+        failed.len(),
+        failed
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+
+    let fallback_segments = match fallback {
+        Some(fallback) => fallback_segments(ctx, fallback, segments.len()).await,
+        None => None,
+    };
+    let fallback_urls = fallback_segments
+        .as_ref()
+        .map(|s| SegmentUrls::new(s, false));
+    let retries: Vec<(usize, VariantSegment, Option<VariantSegment>)> = failed
+        .into_iter()
+        .map(|pos| {
+            (
+                pos,
+                segments[pos].clone(),
+                fallback_segments.as_ref().map(|s| s[pos].clone()),
+            )
+        })
+        .collect();
+
+    let (sender, receiver) = mpsc::channel(1);
+    let fetcher = fetcher.clone();
+    join_set.spawn(async move {
+        for (pos, segment, fallback_segment) in retries {
+            let mut result = fetcher.fetch_decrypted(&urls, pos, segment).await;
+            if let (Err(e), Some(fallback_urls), Some(fallback_segment)) =
+                (&result, &fallback_urls, fallback_segment)
+            {
+                warn!(
+                    "Segment {} failed again ({}), using the fallback stream for it",
+                    pos, e
+                );
+                result = fetcher
+                    .fetch_decrypted(fallback_urls, pos, fallback_segment)
+                    .await
+            }
+            if sender.send((pos, result)).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    });
+    receiver
+}
+
+/// Load the segments of the fallback stream. Variants of the same stream are split at the same
+/// positions, but to be safe the fallback is only used if it has the same number of segments
+/// (`len`) as the main stream.
+async fn fallback_segments(
+    ctx: &Context,
+    fallback: &VariantData,
+    len: usize,
+) -> Option<Vec<VariantSegment>> {
+    match fallback.segments().await {
+        Ok(mut fallback_segments) if fallback_segments.len() == len => {
+            if let Some(host) = ctx.cdn_host(&fallback_segments).await {
+                for segment in &mut fallback_segments {
+                    segment.url = with_host(&segment.url, &host)
+                }
+            }
+            Some(fallback_segments)
+        }
+        Ok(_) => {
+            warn!("Fallback stream has a different number of segments, cannot use it");
+            None
+        }
+        Err(e) => {
+            warn!("Failed to load the segments of the fallback stream: {}", e);
+            None
+        }
+    }
+}
+
+/// Request the stream of `format` again and return the segments of the variant which matches
+/// `variant_data`. The segment urls of a stream are signed and expire after some time, so long
+/// downloads may need fresh ones.
+async fn refresh_segments(
+    ctx: &Context,
+    format: &Format,
+    variant_data: &VariantData,
+) -> Result<Vec<VariantSegment>> {
+    let streams = match format.kind {
+        MediaKind::Episode => {
+            let episode: Media<Episode> = ctx.crunchy.media_from_id(&format.id).await?;
+            episode.streams().await?
+        }
+        MediaKind::Movie => {
+            let movie: Media<Movie> = ctx.crunchy.media_from_id(&format.id).await?;
+            movie.streams().await?
+        }
+        MediaKind::MusicVideo => {
+            let music_video: Media<MusicVideo> = ctx.crunchy.media_from_id(&format.id).await?;
+            music_video.streams().await?
+        }
+        MediaKind::Concert => {
+            let concert: Media<Concert> = ctx.crunchy.media_from_id(&format.id).await?;
+            concert.streams().await?
+        }
+    };
+    // only DASH streams have a separate audio stream
+    let stream_type = if format.audio_stream.is_some() {
+        StreamType::Dash
+    } else {
+        StreamType::Hls
+    };
+    let (variants, _) = streaming_data(&streams, format.hardsub.clone(), &stream_type).await?;
+    let Some(variant) = variants.into_iter().find(|v| {
+        v.resolution.width == variant_data.resolution.width
+            && v.resolution.height == variant_data.resolution.height
+            && v.bandwidth == variant_data.bandwidth
+    }) else {
+        bail!("the stream is no longer available")
+    };
 
-                let mut c = thread_count.lock().unwrap();
+    let mut segments = variant.segments().await?;
+    if let Some(host) = ctx.cdn_host(&segments).await {
+        for segment in &mut segments {
+            segment.url = with_host(&segment.url, &host)
+        }
+    }
+    Ok(segments)
+}
+
+/// How often the segment urls of a single download are refreshed at most, see [`SegmentUrls`].
+const MAX_URL_REFRESHES: usize = 5;
+
+/// Urls of the segments of a download. The urls are signed and Crunchyroll rejects them once the
+/// signature expired, which can happen during long downloads. The download tasks report rejected
+/// urls with [`SegmentUrls::expired`] and wait until [`download_segments`] requested the stream
+/// again and replaced the urls with fresh ones.
+struct SegmentUrls {
+    urls: std::sync::RwLock<Vec<String>>,
+    /// Incremented every time the urls are replaced (or can't be replaced anymore).
+    generation: watch::Sender<u64>,
+    expired: Notify,
+    refreshable: AtomicBool,
+}
+
+impl SegmentUrls {
+    fn new(segments: &[VariantSegment], refreshable: bool) -> Self {
+        Self {
+            urls: std::sync::RwLock::new(segments.iter().map(|s| s.url.clone()).collect()),
+            generation: watch::channel(0).0,
+            expired: Notify::new(),
+            refreshable: AtomicBool::new(refreshable),
+        }
+    }
+
+    /// Url of the segment at the given position and the generation of the url.
+    fn get(&self, pos: usize) -> (String, u64) {
+        let generation = *self.generation.borrow();
+        (self.urls.read().unwrap()[pos].clone(), generation)
+    }
+
+    fn replace(&self, segments: &[VariantSegment]) {
+        *self.urls.write().unwrap() = segments.iter().map(|s| s.url.clone()).collect();
+        self.generation.send_modify(|g| *g += 1)
+    }
+
+    /// Stop refreshing the urls. Tasks which wait for a refresh continue and fail.
+    fn disable_refresh(&self) {
+        self.refreshable.store(false, Ordering::SeqCst);
+        self.generation.send_modify(|g| *g += 1)
+    }
+
+    /// Report that an url of the given generation was rejected and wait until the urls were
+    /// replaced. Returns false if the urls cannot be refreshed.
+    async fn expired(&self, generation: u64) -> bool {
+        if !self.refreshable.load(Ordering::SeqCst) {
+            return false;
+        }
+        let mut receiver = self.generation.subscribe();
+        // another task already reported it and the urls were refreshed in the meantime
+        if *receiver.borrow() != generation {
+            return true;
+        }
+        self.expired.notify_one();
+        let _ = receiver.changed().await;
+        self.refreshable.load(Ordering::SeqCst)
+    }
+
+    /// Wait until a task reported an expired url.
+    async fn wait_expired(&self) {
+        self.expired.notified().await
+    }
+}
+
+/// Everything the download tasks need to download segments, cheap to clone.
+#[derive(Clone)]
+struct SegmentFetcher {
+    client: Arc<Client>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    retry_policy: RetryPolicy,
+    backoff: Arc<Backoff>,
+    pause: Pause,
+    timeout: Duration,
+}
+
+impl SegmentFetcher {
+    fn new(ctx: &Context) -> Self {
+        Self {
+            client: Arc::new(ctx.crunchy.client()),
+            rate_limiter: ctx.rate_limiter.clone(),
+            retry_policy: ctx.retry_policy.clone(),
+            backoff: ctx.backoff.clone(),
+            pause: ctx.pause.clone(),
+            timeout: ctx.client_options.timeout.unwrap_or(SEGMENT_TIMEOUT),
+        }
+    }
+
+    /// Download a single segment without decrypting it (see [`decrypt_segment`]). Failed
+    /// downloads are retried as configured in the retry policy, rate limited downloads pause all
+    /// tasks which share the backoff. The download isn't started while the download is paused.
+    /// Expired urls are refreshed and don't count as retry, rate limited downloads have their own
+    /// limit.
+    async fn fetch(&self, urls: &SegmentUrls, pos: usize) -> Result<BytesMut> {
+        let mut retry_count = 0;
+        let mut rate_limited_count = 0;
+        let buf = loop {
+            self.pause.wait().await;
+            self.backoff.wait().await;
+
+            let (url, generation) = urls.get(pos);
+            let result = match self.client.get(&url).timeout(self.timeout).send().await {
+                // waiting until the rate limit is over does not count as retry
+                Ok(response) if self.backoff.check(&response, &self.retry_policy) => {
+                    if rate_limited_count == self.retry_policy.max_rate_limited {
+                        bail!(
+                            "Still rate limited after waiting {} times while receiving segment {}",
+                            rate_limited_count,
+                            pos
+                        )
+                    }
+                    rate_limited_count += 1;
+                    continue;
+                }
+                Ok(response) => match response.error_for_status() {
+                    Ok(response) => read_body(response, self.rate_limiter.as_deref()).await,
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+
+            let e = match result {
+                Ok(b) => break b,
+                Err(e) => e,
+            };
+            if matches!(
+                e.status(),
+                Some(StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED | StatusCode::GONE)
+            ) && urls.expired(generation).await
+            {
                 debug!(
-                    "Downloaded and decrypted segment [{}/{} {:.2}%] {}",
-                    num + (i * cpus),
-                    total_segments,
-                    ((*c + 1) as f64 / total_segments as f64) * 100f64,
-                    segment.url
+                    "Url of segment {} expired ({}), retrying with a refreshed one",
+                    pos, e
                 );
-                thread_sender.send((num + (i * cpus), buf)).unwrap();
+                continue;
+            }
 
-                *c += 1;
+            if retry_count == self.retry_policy.max_retries {
+                bail!(
+                    "Max retry count reached ({}), multiple errors occurred while receiving segment {}: {}",
+                    retry_count,
+                    pos,
+                    e
+                )
             }
+            retry_count += 1;
 
-            Ok(())
-        });
+            let delay = self.retry_policy.delay(retry_count);
+            debug!(
+                "Failed to download segment {} ({}). Retrying in {}ms, {} out of {} retries left",
+                pos,
+                e,
+                delay.as_millis(),
+                self.retry_policy.max_retries - retry_count + 1,
+                self.retry_policy.max_retries
+            );
+            tokio::time::sleep(delay).await
+        };
+
+        Ok(buf)
     }
 
-    drop(sender);
-    let mut data_pos = 0usize;
-    let mut buf: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
-    for (pos, bytes) in receiver.iter() {
-        if let Some(p) = &progress {
-            let progress_len = p.length().unwrap();
-            let estimated_segment_len = (variant_data.bandwidth / 8)
-                * segments
-                    .get(pos)
-                    .unwrap()
-                    .length
-                    .unwrap_or_default()
-                    .as_secs();
-            let bytes_len = bytes.len() as u64;
+    async fn fetch_decrypted(
+        &self,
+        urls: &SegmentUrls,
+        pos: usize,
+        segment: VariantSegment,
+    ) -> Result<Bytes> {
+        let buf = self.fetch(urls, pos).await?;
+        decrypt_segment(segment, buf).await
+    }
+}
 
-            p.set_length(progress_len - estimated_segment_len + bytes_len);
-            p.inc(bytes_len)
+/// Decrypt a downloaded segment in place. The decryption runs on a blocking thread so it doesn't
+/// stall the downloads of other segments which run on the same runtime thread.
+async fn decrypt_segment(segment: VariantSegment, mut buf: BytesMut) -> Result<Bytes> {
+    tokio::task::spawn_blocking(move || {
+        // the decrypted data is a prefix of the buffer, only the padding is cut off
+        let len = VariantSegment::decrypt(&mut buf[..], segment.key)?.len();
+        buf.truncate(len);
+        Ok(buf.freeze())
+    })
+    .await?
+}
+
+/// Read the body of a response into a buffer which can be decrypted in place. If a rate limiter
+/// is given, every received chunk has to pass it.
+async fn read_body(
+    mut response: reqwest::Response,
+    rate_limiter: Option<&RateLimiter>,
+) -> reqwest::Result<BytesMut> {
+    let mut buf = BytesMut::with_capacity(response.content_length().unwrap_or_default() as usize);
+    while let Some(chunk) = response.chunk().await? {
+        if let Some(rate_limiter) = rate_limiter {
+            rate_limiter.acquire(chunk.len() as u64).await;
         }
+        buf.extend_from_slice(&chunk)
+    }
+    Ok(buf)
+}
 
-        if data_pos == pos {
-            writer.write_all(bytes.borrow())?;
-            data_pos += 1;
-        } else {
-            buf.insert(pos, bytes);
+/// Options which modify the arguments generated by [`FFmpegPreset::ffmpeg_presets`].
+#[derive(Clone, Debug, Default)]
+pub struct FFmpegOptions {
+    /// Never copy the video (e.g. because a subtitle gets burned into it). The video is encoded
+    /// with h264 if no codec preset is given.
+    pub reencode_video: bool,
+    /// Device which is used by hardware acceleration presets which need one.
+    pub hwaccel_device: Option<String>,
+
+    pub crf: Option<u8>,
+    pub video_bitrate: Option<String>,
+    pub encode_preset: Option<EncodePreset>,
+
+    pub audio_codec: Option<AudioCodec>,
+    pub audio_bitrate: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AudioCodec {
+    Aac,
+    Opus,
+    Copy,
+}
+
+impl AudioCodec {
+    pub(crate) fn parse(s: &str) -> Result<AudioCodec, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "aac" => AudioCodec::Aac,
+            "opus" => AudioCodec::Opus,
+            "copy" => AudioCodec::Copy,
+            _ => return Err(format!("'{}' is not a valid audio codec", s)),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EncodePreset {
+    Slow,
+    Medium,
+    Fast,
+}
+
+impl ToString for EncodePreset {
+    fn to_string(&self) -> String {
+        match self {
+            EncodePreset::Slow => "slow",
+            EncodePreset::Medium => "medium",
+            EncodePreset::Fast => "fast",
         }
-        while let Some(b) = buf.remove(&data_pos) {
-            writer.write_all(b.borrow())?;
-            data_pos += 1;
+        .to_string()
+    }
+}
+
+impl EncodePreset {
+    pub(crate) fn parse(s: &str) -> Result<EncodePreset, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "slow" => EncodePreset::Slow,
+            "medium" => EncodePreset::Medium,
+            "fast" => EncodePreset::Fast,
+            _ => return Err(format!("'{}' is not a valid encode preset", s)),
+        })
+    }
+}
+
+/// Arguments which make ffmpeg write its progress machine readable to stderr, required by
+/// [`FFmpegProgress`].
+pub const FFMPEG_PROGRESS_ARGS: [&str; 3] = ["-nostats", "-progress", "pipe:2"];
+
+/// Progress of a running ffmpeg process which was spawned with [`FFMPEG_PROGRESS_ARGS`] and a
+/// piped stderr. stderr is read in a separate thread from the moment this is created, otherwise
+/// ffmpeg would block as soon as the pipe is full, e.g. while segments are still downloaded into it.
+pub struct FFmpegProgress {
+    /// Timestamp of the output in microseconds.
+    out_time: Arc<AtomicU64>,
+    /// Returns everything ffmpeg logged which is not part of the progress.
+    reader: std::thread::JoinHandle<String>,
+}
+
+impl FFmpegProgress {
+    pub fn new(ffmpeg: &mut Child) -> Self {
+        let out_time = Arc::new(AtomicU64::new(0));
+        let reader_out_time = out_time.clone();
+        let stderr = ffmpeg.stderr.take().unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut log = String::new();
+            for line in BufReader::new(stderr).lines().map_while(|l| l.ok()) {
+                // progress lines are 'key=value' pairs, only the timestamp of the output is used
+                match line.split_once('=') {
+                    Some(("out_time_us", us)) => {
+                        if let Ok(us) = us.parse() {
+                            reader_out_time.store(us, Ordering::Relaxed)
+                        }
+                    }
+                    Some((key, _))
+                        if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {}
+                    _ => {
+                        debug!("ffmpeg: {}", line);
+                        log.push_str(&line);
+                        log.push('\n')
+                    }
+                }
+            }
+            log
+        });
+
+        Self { out_time, reader }
+    }
+
+    /// Wait until ffmpeg exits. Meanwhile, a progress bar relative to `duration` (the duration of
+    /// the output) is shown if progress bars are enabled. Returns the exit status of ffmpeg and
+    /// everything it logged.
+    pub async fn wait(
+        self,
+        ctx: &Context,
+        ffmpeg: &mut Child,
+        duration: Duration,
+        message: String,
+        multi_progress: Option<&MultiProgress>,
+    ) -> Result<(ExitStatus, String)> {
+        let progress =
+            if ctx.progress_format == ProgressFormat::Bar && console_level() == LevelFilter::Info {
+                let progress = ProgressBar::new(duration.as_millis() as u64)
+                    .with_style(
+                        ProgressStyle::with_template(":: {msg} [{wide_bar}] {percent:>3}% {eta}")
+                            .unwrap()
+                            .progress_chars("##-"),
+                    )
+                    .with_message(message);
+                if let Some(multi_progress) = multi_progress {
+                    Some(multi_progress.add(progress))
+                } else {
+                    Some(progress)
+                }
+            } else {
+                None
+            };
+
+        let status = loop {
+            if let Some(status) = ffmpeg.try_wait()? {
+                break status;
+            }
+            if ctx.cancellation.is_cancelled() {
+                let _ = ffmpeg.kill();
+                if let Some(progress) = progress {
+                    progress.finish_and_clear()
+                }
+                bail!(Cancelled)
+            }
+            if let Some(progress) = &progress {
+                progress.set_position(self.out_time.load(Ordering::Relaxed) / 1000)
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await
+        };
+        if let Some(progress) = progress {
+            progress.finish_and_clear()
         }
-        debug!("Buf is {:?} TL is {:?}", buf.len(), *count.lock().unwrap());
+
+        Ok((status, self.reader.join().unwrap_or_default()))
     }
+}
 
-    while let Some(joined) = join_set.join_next().await {
-        joined??
+/// Name of the ffmpeg muxer for the given file extension. Required if ffmpeg cannot guess the format
+/// from the output file name, e.g. because a `.part` file is written.
+pub fn ffmpeg_muxer(extension: &str) -> String {
+    match extension {
+        "" | "ts" | "m2ts" => "mpegts",
+        "mkv" | "mka" => "matroska",
+        "mp4" | "m4v" | "m4a" => "mp4",
+        ext => ext,
     }
+    .to_string()
+}
 
-    Ok(())
+/// Translate the quality options to the arguments of the given encoder. Every encoder has its own
+/// way to set a constant quality and the encoding speed.
+fn quality_args(encoder: &str, options: &FFmpegOptions) -> Result<Vec<String>> {
+    let mut args = vec![];
+
+    if options.crf.is_some() && options.video_bitrate.is_some() {
+        bail!("Crf and video bitrate cannot be used together")
+    }
+
+    if let Some(crf) = options.crf {
+        let crf = crf.to_string();
+        args.extend(
+            match encoder {
+                "libaom-av1" | "libvpx-vp9" => vec!["-crf", &crf, "-b:v", "0"],
+                "h264_nvenc" | "hevc_nvenc" => vec!["-rc", "vbr", "-cq", &crf, "-b:v", "0"],
+                "h264_vaapi" | "hevc_vaapi" | "vp9_vaapi" => vec!["-rc_mode", "CQP", "-qp", &crf],
+                "h264_qsv" | "hevc_qsv" | "vp9_qsv" => vec!["-global_quality", &crf],
+                _ => vec!["-crf", &crf],
+            }
+            .into_iter()
+            .map(|a| a.to_string()),
+        )
+    }
+    if let Some(video_bitrate) = &options.video_bitrate {
+        args.extend(["-b:v".to_string(), video_bitrate.clone()])
+    }
+    if let Some(encode_preset) = &options.encode_preset {
+        args.extend(match encoder {
+            "libaom-av1" | "libvpx-vp9" => [
+                "-cpu-used".to_string(),
+                match encode_preset {
+                    EncodePreset::Slow => "2",
+                    EncodePreset::Medium => "4",
+                    EncodePreset::Fast => "6",
+                }
+                .to_string(),
+            ],
+            "h264_vaapi" | "hevc_vaapi" | "vp9_vaapi" => [
+                "-compression_level".to_string(),
+                match encode_preset {
+                    EncodePreset::Slow => "1",
+                    EncodePreset::Medium => "4",
+                    EncodePreset::Fast => "7",
+                }
+                .to_string(),
+            ],
+            _ => ["-preset".to_string(), encode_preset.to_string()],
+        })
+    }
+
+    Ok(args)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum FFmpegPreset {
     Nvidia,
+    Vaapi,
+    Qsv,
 
     Av1,
     H265,
     H264,
+    Vp9,
 }
 
 impl ToString for FFmpegPreset {
     fn to_string(&self) -> String {
         match self {
             &FFmpegPreset::Nvidia => "nvidia",
+            &FFmpegPreset::Vaapi => "vaapi",
+            &FFmpegPreset::Qsv => "qsv",
             &FFmpegPreset::Av1 => "av1",
             &FFmpegPreset::H265 => "h265",
             &FFmpegPreset::H264 => "h264",
+            &FFmpegPreset::Vp9 => "vp9",
         }
         .to_string()
     }
@@ -194,33 +1633,44 @@ impl FFmpegPreset {
     pub(crate) fn all() -> Vec<FFmpegPreset> {
         vec![
             FFmpegPreset::Nvidia,
+            FFmpegPreset::Vaapi,
+            FFmpegPreset::Qsv,
             FFmpegPreset::Av1,
             FFmpegPreset::H265,
             FFmpegPreset::H264,
+            FFmpegPreset::Vp9,
         ]
     }
 
     pub(crate) fn description(self) -> String {
         match self {
             FFmpegPreset::Nvidia => "If you're have a nvidia card, use hardware / gpu accelerated video processing if available",
+            FFmpegPreset::Vaapi => "If you're have an intel or amd gpu on linux, use hardware / gpu accelerated video processing via vaapi if available",
+            FFmpegPreset::Qsv => "If you're have an intel cpu / gpu, use hardware / gpu accelerated video processing via quick sync if available",
             FFmpegPreset::Av1 => "Encode the video(s) with the av1 codec. Hardware acceleration is currently not possible with this",
             FFmpegPreset::H265 => "Encode the video(s) with the h265 codec",
-            FFmpegPreset::H264 => "Encode the video(s) with the h264 codec"
+            FFmpegPreset::H264 => "Encode the video(s) with the h264 codec",
+            FFmpegPreset::Vp9 => "Encode the video(s) with the vp9 codec (e.g. for webm files). Hardware acceleration is only possible with 'vaapi' and 'qsv'"
         }.to_string()
     }
 
     pub(crate) fn parse(s: &str) -> Result<FFmpegPreset, String> {
         Ok(match s.to_lowercase().as_str() {
             "nvidia" => FFmpegPreset::Nvidia,
+            "vaapi" => FFmpegPreset::Vaapi,
+            "qsv" | "quicksync" => FFmpegPreset::Qsv,
             "av1" => FFmpegPreset::Av1,
             "h265" | "h.265" | "hevc" => FFmpegPreset::H265,
             "h264" | "h.264" => FFmpegPreset::H264,
+            "vp9" => FFmpegPreset::Vp9,
             _ => return Err(format!("'{}' is not a valid ffmpeg preset", s)),
         })
     }
 
+    /// Input and output arguments for the given presets.
     pub(crate) fn ffmpeg_presets(
         mut presets: Vec<FFmpegPreset>,
+        options: &FFmpegOptions,
     ) -> Result<(Vec<String>, Vec<String>)> {
         fn preset_check_remove(presets: &mut Vec<FFmpegPreset>, preset: FFmpegPreset) -> bool {
             if let Some(i) = presets.iter().position(|p| p == &preset) {
@@ -232,6 +1682,14 @@ impl FFmpegPreset {
         }
 
         let nvidia = preset_check_remove(&mut presets, FFmpegPreset::Nvidia);
+        let vaapi = preset_check_remove(&mut presets, FFmpegPreset::Vaapi);
+        let qsv = preset_check_remove(&mut presets, FFmpegPreset::Qsv);
+        if [nvidia, vaapi, qsv].iter().filter(|p| **p).count() > 1 {
+            bail!("Can only use one hardware acceleration preset ('nvidia', 'vaapi' or 'qsv')")
+        }
+        if options.reencode_video && presets.is_empty() {
+            presets.push(FFmpegPreset::H264)
+        }
         if presets.len() > 1 {
             bail!(
                 "Can only use one video codec, {} found: {}",
@@ -244,11 +1702,52 @@ impl FFmpegPreset {
             )
         }
 
+        let vaapi_device = format!(
+            "vaapi=va:{}",
+            options
+                .hwaccel_device
+                .as_deref()
+                .unwrap_or("/dev/dri/renderD128")
+        );
+        // the device is named 'va' so that it can be used for decoding, filtering and encoding
+        let vaapi_input = [
+            "-init_hw_device",
+            vaapi_device.as_str(),
+            "-hwaccel",
+            "vaapi",
+            "-hwaccel_output_format",
+            "vaapi",
+            "-hwaccel_device",
+            "va",
+            "-filter_hw_device",
+            "va",
+        ];
+
+        let qsv_device = if let Some(device) = &options.hwaccel_device {
+            format!("qsv=qs:hw,child_device={}", device)
+        } else {
+            "qsv=qs:hw".to_string()
+        };
+        let qsv_input = [
+            "-init_hw_device",
+            qsv_device.as_str(),
+            "-hwaccel",
+            "qsv",
+            "-hwaccel_output_format",
+            "qsv",
+            "-hwaccel_device",
+            "qs",
+            "-filter_hw_device",
+            "qs",
+            "-c:v",
+            "h264_qsv",
+        ];
+
         let (mut input, mut output) = (vec![], vec![]);
         for preset in presets {
             if nvidia {
                 match preset {
-                    FFmpegPreset::Av1 => bail!("'nvidia' hardware acceleration preset is not available in combination with the 'av1' codec preset"),
+                    FFmpegPreset::Av1 | FFmpegPreset::Vp9 => bail!("'nvidia' hardware acceleration preset is not available in combination with the '{}' codec preset", preset.to_string()),
                     FFmpegPreset::H265 => {
                         input.extend(["-hwaccel", "cuvid", "-c:v", "h264_cuvid"]);
                         output.extend(["-c:v", "hevc_nvenc"]);
@@ -259,6 +1758,40 @@ impl FFmpegPreset {
                     }
                     _ => ()
                 }
+            } else if vaapi {
+                match preset {
+                    FFmpegPreset::Av1 => bail!("'vaapi' hardware acceleration preset is not available in combination with the 'av1' codec preset"),
+                    FFmpegPreset::H265 => {
+                        input.extend(vaapi_input);
+                        output.extend(["-c:v", "hevc_vaapi"]);
+                    }
+                    FFmpegPreset::H264 => {
+                        input.extend(vaapi_input);
+                        output.extend(["-c:v", "h264_vaapi"]);
+                    }
+                    FFmpegPreset::Vp9 => {
+                        input.extend(vaapi_input);
+                        output.extend(["-c:v", "vp9_vaapi"]);
+                    }
+                    _ => ()
+                }
+            } else if qsv {
+                match preset {
+                    FFmpegPreset::Av1 => bail!("'qsv' hardware acceleration preset is not available in combination with the 'av1' codec preset"),
+                    FFmpegPreset::H265 => {
+                        input.extend(qsv_input);
+                        output.extend(["-c:v", "hevc_qsv"]);
+                    }
+                    FFmpegPreset::H264 => {
+                        input.extend(qsv_input);
+                        output.extend(["-c:v", "h264_qsv"]);
+                    }
+                    FFmpegPreset::Vp9 => {
+                        input.extend(qsv_input);
+                        output.extend(["-c:v", "vp9_qsv"]);
+                    }
+                    _ => ()
+                }
             } else {
                 match preset {
                     FFmpegPreset::Av1 => {
@@ -270,11 +1803,25 @@ impl FFmpegPreset {
                     FFmpegPreset::H264 => {
                         output.extend(["-c:v", "libx264"]);
                     }
+                    FFmpegPreset::Vp9 => {
+                        output.extend(["-c:v", "libvpx-vp9"]);
+                    }
                     _ => (),
                 }
             }
         }
 
+        let quality = if let Some(i) = output.iter().position(|o| o == &"-c:v") {
+            quality_args(output[i + 1], options)?
+        } else if options.crf.is_some()
+            || options.video_bitrate.is_some()
+            || options.encode_preset.is_some()
+        {
+            bail!("Encoding options (crf, video bitrate and encode preset) can only be used in combination with a codec preset")
+        } else {
+            vec![]
+        };
+
         if input.is_empty() && output.is_empty() {
             output.extend(["-c", "copy"])
         } else {
@@ -285,9 +1832,55 @@ impl FFmpegPreset {
             }
         }
 
+        // these are added after the copy arguments and overwrite them for the audio streams
+        let mut audio = vec![];
+        match &options.audio_codec {
+            Some(AudioCodec::Aac) => audio.extend(["-c:a".to_string(), "aac".to_string()]),
+            Some(AudioCodec::Opus) => audio.extend(["-c:a".to_string(), "libopus".to_string()]),
+            Some(AudioCodec::Copy) | None => {
+                if options.audio_bitrate.is_some() {
+                    bail!("Audio bitrate can only be used in combination with an audio codec")
+                }
+            }
+        }
+        if let Some(audio_bitrate) = &options.audio_bitrate {
+            audio.extend(["-b:a".to_string(), audio_bitrate.clone()])
+        }
+
         Ok((
             input.into_iter().map(|i| i.to_string()).collect(),
-            output.into_iter().map(|o| o.to_string()).collect(),
+            output
+                .into_iter()
+                .map(|o| o.to_string())
+                .chain(quality)
+                .chain(audio)
+                .collect(),
         ))
     }
+
+    /// Wrap a (software) video filter so that it can be used with the given presets. Hardware
+    /// accelerated presets keep the video frames in gpu memory, they must be downloaded before and
+    /// uploaded again after the filter.
+    pub(crate) fn video_filter(presets: &[FFmpegPreset], filter: &str) -> String {
+        if presets.contains(&FFmpegPreset::Vaapi) {
+            format!("hwdownload,format=nv12,{},format=nv12,hwupload", filter)
+        } else if presets.contains(&FFmpegPreset::Qsv) {
+            format!(
+                "hwdownload,format=nv12,{},format=nv12,hwupload=extra_hw_frames=64",
+                filter
+            )
+        } else {
+            filter.to_string()
+        }
+    }
+}
+
+/// Escape a path so that it can be used as argument of a ffmpeg filter (e.g. `subtitles=<path>`).
+pub fn escape_filter_path<P: AsRef<Path>>(path: P) -> String {
+    let path = path
+        .as_ref()
+        .to_string_lossy()
+        .replace('\\', "/")
+        .replace(':', "\\:");
+    format!("'{}'", path)
 }
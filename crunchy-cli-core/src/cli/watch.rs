@@ -0,0 +1,65 @@
+use crate::cli::download::Download;
+use crate::utils::cancel::{is_cancelled, Cancelled};
+use crate::utils::context::Context;
+use crate::utils::notify::Notification;
+use crate::Execute;
+use anyhow::{bail, Result};
+use log::{error, info};
+use std::time::Duration;
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Watch series for new episodes and download them as soon as they're released")]
+#[command(arg_required_else_help(true))]
+pub struct Watch {
+    #[arg(help = "Interval in which the series are checked for new episodes")]
+    #[arg(
+        long_help = "Interval in which the series are checked for new episodes. \
+    Can be specified as plain seconds or with a unit suffix (e.g. 30m, 2h or 1d)"
+    )]
+    #[arg(long, default_value = "1h")]
+    #[arg(value_parser = crate::utils::clap::clap_parse_duration)]
+    interval: Duration,
+
+    #[clap(flatten)]
+    pub(crate) download: Download,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Watch {
+    fn pre_check(&self) -> Result<()> {
+        if self.download.download_archive.is_none() {
+            bail!("A download archive ('--download-archive') is required to detect which episodes are new")
+        }
+        self.download.pre_check()
+    }
+
+    async fn execute(self, ctx: Context) -> Result<()> {
+        loop {
+            info!("Checking for new episodes");
+            // a failed check (e.g. because of network issues) should not stop watching, it's
+            // simply retried with the next check
+            if let Err(e) = self.download.download(&ctx).await {
+                if is_cancelled(&e) {
+                    return Err(e);
+                }
+                error!("Failed to download new episodes: {}", e);
+                if let Some(notifier) = &ctx.notifier {
+                    notifier
+                        .notify(Notification::Error {
+                            message: e.to_string(),
+                        })
+                        .await
+                }
+            }
+
+            ctx.persist_session().await;
+
+            let next_check = chrono::Local::now() + chrono::Duration::from_std(self.interval)?;
+            info!("Next check at {}", next_check.format("%Y-%m-%d %H:%M:%S"));
+            tokio::select! {
+                _ = tokio::time::sleep(self.interval) => (),
+                _ = ctx.cancellation.cancelled() => bail!(Cancelled),
+            }
+        }
+    }
+}
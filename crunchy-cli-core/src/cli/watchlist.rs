@@ -0,0 +1,85 @@
+use crate::utils::context::Context;
+use crate::utils::log::progress;
+use crate::utils::table::print_table;
+use crate::Execute;
+use anyhow::Result;
+use crunchyroll_rs::list::WatchlistOptions;
+use crunchyroll_rs::{Crunchyroll, MediaCollection};
+use log::debug;
+
+#[derive(Debug, clap::Parser)]
+#[clap(about = "Manage the watchlist of your account")]
+#[command(arg_required_else_help(true))]
+pub struct Watchlist {
+    #[command(subcommand)]
+    command: WatchlistCommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum WatchlistCommand {
+    #[clap(about = "List all series and movies on the watchlist")]
+    List,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Execute for Watchlist {
+    async fn execute(self, ctx: Context) -> Result<()> {
+        match self.command {
+            WatchlistCommand::List => {
+                let progress_handler = progress!("Fetching watchlist");
+                let watchlist = watchlist(&ctx.crunchy).await?;
+                progress_handler.stop(format!("Watchlist contains {} entries", watchlist.len()));
+
+                let rows: Vec<Vec<String>> = watchlist
+                    .into_iter()
+                    .map(|entry| vec![entry.title, entry.kind.to_string(), entry.url])
+                    .collect();
+                print_table(&["Title", "Type", "Url"], &rows)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A series, movie listing, episode or movie on the watchlist or a crunchylist.
+pub(crate) struct ListEntry {
+    pub(crate) title: String,
+    pub(crate) kind: &'static str,
+    /// Url which can be passed to the other commands, e.g. `download`.
+    pub(crate) url: String,
+}
+
+impl ListEntry {
+    pub(crate) fn from_panel(panel: MediaCollection) -> Option<Self> {
+        // the urls are only resolved by their id, so the series url works for movie listings too
+        // and the watch url for movies
+        let (title, kind, path, id) = match panel {
+            MediaCollection::Series(series) => (series.title, "series", "series", series.id),
+            MediaCollection::MovieListing(movie_listing) => {
+                (movie_listing.title, "movie", "series", movie_listing.id)
+            }
+            MediaCollection::Episode(episode) => (episode.title, "episode", "watch", episode.id),
+            MediaCollection::Movie(movie) => (movie.title, "movie", "watch", movie.id),
+            media_collection => {
+                debug!("Skipping unsupported list entry {:?}", media_collection);
+                return None;
+            }
+        };
+        Some(Self {
+            title,
+            kind,
+            url: format!("https://www.crunchyroll.com/{}/{}", path, id),
+        })
+    }
+}
+
+/// All series and movie listings on the watchlist of the account. Requires a logged in account.
+pub(crate) async fn watchlist(crunchy: &Crunchyroll) -> Result<Vec<ListEntry>> {
+    Ok(crunchy
+        .watchlist(WatchlistOptions::default())
+        .await?
+        .into_iter()
+        .filter_map(|entry| ListEntry::from_panel(entry.panel))
+        .collect())
+}
@@ -0,0 +1,374 @@
+//! Library interface of crunchy-cli. It exposes the same download pipeline the `download` command
+//! uses, so other tools (GUIs, bots, ...) can embed it without spawning the cli.
+//!
+//! ```no_run
+//! # async fn example(crunchy: crunchyroll_rs::Crunchyroll) -> anyhow::Result<()> {
+//! use crunchy_cli_core::{parse_filter, parse_resolution, Downloader};
+//! use crunchyroll_rs::Locale;
+//!
+//! let downloader = Downloader::builder(crunchy)
+//!     .audio(Locale::ja_JP)
+//!     .subtitle(Locale::en_US)
+//!     .resolution(parse_resolution("1080p".to_string())?)
+//!     .filter(parse_filter("S1E1-5")?)
+//!     .on_progress(|event| println!("{:?}", event))
+//!     .build()?;
+//!
+//! for format in downloader
+//!     .formats("https://www.crunchyroll.com/series/GY8VEQ95Y/darling-in-the-franxx")
+//!     .await?
+//! {
+//!     downloader
+//!         .download_to_file(&format, format!("{}.mkv", format.title))
+//!         .await?
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::cli::download::{download_ffmpeg, download_remux, Download};
+use crate::cli::utils::{download_segments, ResolutionPolicy, StreamType};
+use crate::utils::cancel::{Cancellation, PartialFile};
+use crate::utils::cdn::Cdn;
+use crate::utils::client::ClientOptions;
+use crate::utils::context::Context;
+use crate::utils::credentials::Credentials;
+use crate::utils::format::Format;
+use crate::utils::locale::LocaleChain;
+use crate::utils::os::{has_ffmpeg, part_file};
+use crate::utils::parse::UrlFilter;
+use crate::utils::pause::Pause;
+use crate::utils::progress::{ProgressCallback, ProgressEvent, ProgressFormat, TransferSummary};
+use crate::utils::rate_limit::RateLimiter;
+use crate::utils::retry::RetryPolicy;
+use anyhow::{bail, Result};
+use clap::{CommandFactory, FromArgMatches};
+use crunchyroll_rs::media::Resolution;
+use crunchyroll_rs::{Crunchyroll, Locale};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builder for [`Downloader`]. Every option has the same default as the corresponding option of the
+/// `download` command.
+pub struct DownloaderBuilder {
+    crunchy: Crunchyroll,
+
+    audio: Vec<Locale>,
+    subtitle: Vec<Locale>,
+    resolution: Option<Resolution>,
+    resolution_policy: Option<ResolutionPolicy>,
+    max_bitrate: Option<u64>,
+    fps: Option<u32>,
+    stream_type: Option<StreamType>,
+    filter: Option<UrlFilter>,
+
+    download_threads: usize,
+    max_buffer_memory: u64,
+    max_retries: u32,
+    speed_limit: Option<u64>,
+    segment_timeout: Option<Duration>,
+    cdn: Option<String>,
+    progress_callback: Option<ProgressCallback>,
+}
+
+impl DownloaderBuilder {
+    /// Audio language. Can be called multiple times, every additional language is added as
+    /// separate audio track if the episodes are downloaded with
+    /// [`Downloader::download_to_file`] to a matroska file.
+    pub fn audio(mut self, audio: Locale) -> Self {
+        self.audio.push(audio);
+        self
+    }
+
+    /// Subtitle language. If called once, the subtitle is burned into the video. If called
+    /// multiple times, the subtitles are added as separate tracks if the episodes are downloaded
+    /// with [`Downloader::download_to_file`] to a matroska file.
+    pub fn subtitle(mut self, subtitle: Locale) -> Self {
+        self.subtitle.push(subtitle);
+        self
+    }
+
+    /// Video resolution. Use [`crate::parse_resolution`] to get it from a string like `1080p`,
+    /// `1920x1080` or `best`.
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = Some(resolution);
+        self
+    }
+
+    /// What to do if the requested resolution is not available. Default is
+    /// [`ResolutionPolicy::Exact`].
+    pub fn resolution_policy(mut self, policy: ResolutionPolicy) -> Self {
+        self.resolution_policy = Some(policy);
+        self
+    }
+
+    /// Maximum bitrate of the video stream in bits per second. If multiple streams have the
+    /// requested resolution, the one with the highest bitrate below it is used.
+    pub fn max_bitrate(mut self, max_bitrate: u64) -> Self {
+        self.max_bitrate = Some(max_bitrate);
+        self
+    }
+
+    /// Preferred framerate of the video stream. If multiple streams have the requested
+    /// resolution, the one with the closest framerate is used.
+    pub fn fps(mut self, fps: u32) -> Self {
+        self.fps = Some(fps);
+        self
+    }
+
+    /// Type of the stream which is downloaded. Default is [`StreamType::Hls`]. DASH streams can
+    /// only be downloaded with [`Downloader::download_to_file`].
+    pub fn stream_type(mut self, stream_type: StreamType) -> Self {
+        self.stream_type = Some(stream_type);
+        self
+    }
+
+    /// Filter which episodes are downloaded. Use [`crate::parse_filter`] to get it from a string
+    /// like `S1E1-5`, the same syntax as the filter which can be appended to urls, without the
+    /// brackets.
+    pub fn filter(mut self, filter: UrlFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Number of segments which are downloaded simultaneously. Default is the number of cpus.
+    pub fn download_threads(mut self, download_threads: usize) -> Self {
+        self.download_threads = download_threads.max(1);
+        self
+    }
+
+    /// Maximum number of bytes which out of order downloaded segments may occupy in memory before
+    /// they're spilled to disk.
+    pub fn max_buffer_memory(mut self, max_buffer_memory: u64) -> Self {
+        self.max_buffer_memory = max_buffer_memory;
+        self
+    }
+
+    /// How often a failed segment download is retried before the download is aborted.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Limit the download speed to the given bytes per second. 0 means no limit.
+    pub fn speed_limit(mut self, speed_limit: u64) -> Self {
+        self.speed_limit = (speed_limit > 0).then_some(speed_limit);
+        self
+    }
+
+    /// Timeout of a single segment request, a segment which isn't downloaded within this time is
+    /// retried. Default is 60 seconds. The timeouts of all other requests are the ones of the
+    /// client the [`Crunchyroll`] instance was built with.
+    pub fn segment_timeout(mut self, timeout: Duration) -> Self {
+        self.segment_timeout = Some(timeout);
+        self
+    }
+
+    /// Download all segments from the given CDN host instead of the host a stream points to. Run
+    /// `crunchy speedtest` to find the fastest host.
+    pub fn cdn<S: AsRef<str>>(mut self, host: S) -> Self {
+        self.cdn = Some(host.as_ref().to_string());
+        self
+    }
+
+    /// Callback which receives every progress event. Nothing is printed by the downloader itself.
+    pub fn on_progress<F: Fn(&ProgressEvent) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.progress_callback = Some(Arc::new(f));
+        self
+    }
+
+    pub fn build(self) -> Result<Downloader> {
+        // start with the defaults of the download command and only override what was set
+        let matches = match Download::command()
+            .arg_required_else_help(false)
+            .try_get_matches_from(["download"])
+        {
+            Ok(matches) => matches,
+            Err(e) => bail!("Invalid downloader options: {}", e),
+        };
+        let mut download = Download::from_arg_matches(&matches)?;
+        if !self.audio.is_empty() {
+            download.audio = self.audio.into_iter().map(LocaleChain::from).collect()
+        }
+        if !self.subtitle.is_empty() {
+            download.subtitle = self.subtitle.into_iter().map(LocaleChain::from).collect()
+        }
+        if let Some(resolution) = self.resolution {
+            download.resolution = resolution
+        }
+        if let Some(resolution_policy) = self.resolution_policy {
+            download.resolution_policy = resolution_policy
+        }
+        if let Some(stream_type) = self.stream_type {
+            download.stream_type = stream_type
+        }
+        download.max_bitrate = self.max_bitrate;
+        download.fps = self.fps;
+        download.filter = self.filter;
+
+        Ok(Downloader {
+            ctx: Context {
+                crunchy: self.crunchy,
+                credentials: Credentials::new(),
+                stored_session: false,
+                client_options: ClientOptions {
+                    timeout: self.segment_timeout,
+                    ..Default::default()
+                },
+                download_threads: self.download_threads,
+                max_buffer_memory: self.max_buffer_memory,
+                max_queued_segments: self.download_threads * 2,
+                rate_limiter: self
+                    .speed_limit
+                    .map(|speed_limit| Arc::new(RateLimiter::new(speed_limit))),
+                retry_policy: RetryPolicy {
+                    max_retries: self.max_retries,
+                    ..Default::default()
+                },
+                backoff: Default::default(),
+                cdn: self.cdn.map(Cdn::Host),
+                fastest_cdn: Default::default(),
+                progress_format: ProgressFormat::Callback,
+                progress_callback: self.progress_callback,
+                notifier: None,
+                cancellation: Cancellation::default(),
+                pause: Pause::default(),
+            },
+            download,
+        })
+    }
+}
+
+/// Resolves Crunchyroll urls to downloadable [`Format`]s and downloads them. Create it with
+/// [`Downloader::builder`].
+pub struct Downloader {
+    ctx: Context,
+    download: Download,
+}
+
+impl Downloader {
+    pub fn builder(crunchy: Crunchyroll) -> DownloaderBuilder {
+        DownloaderBuilder {
+            crunchy,
+            audio: vec![],
+            subtitle: vec![],
+            resolution: None,
+            resolution_policy: None,
+            max_bitrate: None,
+            fps: None,
+            stream_type: None,
+            filter: None,
+            download_threads: num_cpus::get(),
+            max_buffer_memory: 128 * 1024 * 1024,
+            max_retries: RetryPolicy::default().max_retries,
+            speed_limit: None,
+            segment_timeout: None,
+            cdn: None,
+            progress_callback: None,
+        }
+    }
+
+    /// Stop all running downloads of this downloader. They fail with an error which can be
+    /// checked with [`crate::is_cancelled`], partially written files are removed.
+    pub fn cancel(&self) {
+        self.ctx.cancellation.cancel()
+    }
+
+    /// Suspend all running downloads of this downloader until [`Downloader::resume`] is called.
+    /// Segments which are already being downloaded are finished.
+    pub fn pause(&self) {
+        self.ctx.pause.pause()
+    }
+
+    pub fn resume(&self) {
+        self.ctx.pause.resume()
+    }
+
+    /// Resolve all episodes / movies of the given url which match the options of the downloader.
+    pub async fn formats<S: AsRef<str>>(&self, url: S) -> Result<Vec<Format>> {
+        let mut download = self.download.clone();
+        download.urls = vec![url.as_ref().to_string()];
+        download.formats(&self.ctx).await
+    }
+
+    /// Download the video stream of the format as mpeg transport stream to the writer. Additional
+    /// audio tracks and soft subtitles are ignored, use [`Downloader::download_to_file`] for them.
+    /// The writer is written to from a blocking thread and returned when the download is finished.
+    pub async fn download<W: Write + Send + 'static>(
+        &self,
+        format: &Format,
+        writer: W,
+    ) -> Result<W> {
+        if format.audio_stream.is_some() {
+            bail!("DASH streams can only be downloaded to a file")
+        }
+        download_segments(
+            &self.ctx,
+            writer,
+            Some(format.title.clone()),
+            format.stream.clone(),
+            Some(format),
+            None,
+            None,
+        )
+        .await
+    }
+
+    /// Download the format to the given path. If the path has another extension than `.ts`, the
+    /// video, all additional audio tracks and (if the output is matroska) all soft subtitles are
+    /// muxed into the output file, exactly like the `download` command does it.
+    pub async fn download_to_file<P: AsRef<Path>>(&self, format: &Format, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let start = self.ctx.pause.stopwatch();
+        let part = part_file(path);
+        let _partial = PartialFile::new(&part, &self.ctx.cancellation);
+        let extension = path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        if (extension.is_empty() || extension == "ts") && format.audio_stream.is_none() {
+            self.download(format, File::create(&part)?).await?;
+        } else {
+            self.mux(format, path, &part, &extension).await?
+        }
+        std::fs::rename(&part, path)?;
+
+        if let Some(callback) = &self.ctx.progress_callback {
+            let summary = TransferSummary {
+                size: std::fs::metadata(path)?.len(),
+                elapsed: start.elapsed(),
+            };
+            callback(&ProgressEvent::EpisodeFinished {
+                output: path,
+                size: summary.size,
+                elapsed: summary.elapsed.as_secs_f64(),
+                speed: summary.speed(),
+            })
+        }
+
+        Ok(())
+    }
+
+    /// Mux the format into `part` with the same pipeline the `download` command uses, so the
+    /// output file is identical to the one of the cli with the same options.
+    async fn mux(&self, format: &Format, path: &Path, part: &Path, extension: &str) -> Result<()> {
+        let mut format = format.clone();
+        // only matroska supports the ass subtitles crunchyroll delivers
+        if extension != "mkv" {
+            format.subtitles.clear()
+        }
+
+        if extension == "mkv" && !has_ffmpeg() && format.audio_stream.is_none() {
+            download_remux(&self.ctx, &self.download, &format, path, part, None).await
+        } else if !has_ffmpeg() {
+            bail!("ffmpeg is required to write .{} files", extension)
+        } else {
+            download_ffmpeg(&self.ctx, &self.download, &format, path, part, None).await
+        }
+    }
+}
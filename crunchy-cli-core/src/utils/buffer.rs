@@ -0,0 +1,80 @@
+use crate::utils::os::tempfile;
+use anyhow::Result;
+use bytes::Bytes;
+use log::debug;
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+use tempfile::NamedTempFile;
+
+enum BufferedSegment {
+    Memory(Bytes),
+    Disk(NamedTempFile),
+}
+
+/// Buffer for segments which were downloaded out of order and cannot be written to the output yet.
+/// Segments are kept in memory until `max_memory` bytes are reached, every segment which exceeds
+/// this limit is spilled to a temporary file and read back from it when it's requested.
+pub struct SegmentBuffer {
+    max_memory: u64,
+    memory_usage: u64,
+
+    segments: BTreeMap<usize, BufferedSegment>,
+}
+
+impl SegmentBuffer {
+    pub fn new(max_memory: u64) -> Self {
+        Self {
+            max_memory,
+            memory_usage: 0,
+            segments: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, pos: usize, data: Bytes) -> Result<()> {
+        let segment = if self.memory_usage + data.len() as u64 > self.max_memory {
+            let mut file = tempfile(".segment")?;
+            file.write_all(&data)?;
+            debug!(
+                "Segment buffer exceeds {} bytes, spilled segment {} to disk",
+                self.max_memory, pos
+            );
+            BufferedSegment::Disk(file)
+        } else {
+            self.memory_usage += data.len() as u64;
+            BufferedSegment::Memory(data)
+        };
+        self.segments.insert(pos, segment);
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, pos: usize) -> Result<Option<Bytes>> {
+        let data = match self.segments.remove(&pos) {
+            Some(BufferedSegment::Memory(data)) => {
+                self.memory_usage -= data.len() as u64;
+                data
+            }
+            Some(BufferedSegment::Disk(mut file)) => {
+                let mut data = vec![];
+                file.seek(SeekFrom::Start(0))?;
+                file.read_to_end(&mut data)?;
+                Bytes::from(data)
+            }
+            None => return Ok(None),
+        };
+
+        Ok(Some(data))
+    }
+
+    pub fn contains(&self, pos: usize) -> bool {
+        self.segments.contains_key(&pos)
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn memory_usage(&self) -> u64 {
+        self.memory_usage
+    }
+}
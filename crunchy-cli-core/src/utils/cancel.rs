@@ -0,0 +1,102 @@
+use crate::utils::resume::ResumeJournal;
+use anyhow::{bail, Result};
+use log::debug;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Exit code if the program was stopped with ctrl-c (128 + SIGINT, like shells report it).
+pub const EXIT_CANCELLED: i32 = 130;
+
+/// Error which is returned by everything that stopped because of a [`Cancellation`].
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+pub fn is_cancelled(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<Cancelled>().is_some()
+}
+
+/// Shared flag which is set when the user presses ctrl-c. Downloads, ffmpeg invocations and batch
+/// loops check it and stop cleanly instead of the process being killed in the middle of writing.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fail with [`Cancelled`] if the cancellation is set.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!(Cancelled)
+        }
+        Ok(())
+    }
+
+    /// Wait until the cancellation is set.
+    pub async fn cancelled(&self) {
+        // the future must be created before the flag is checked, otherwise a cancellation between
+        // the check and the await would be missed
+        let notified = self.notify.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await
+    }
+}
+
+/// Removes a partially written output file when it's dropped after the cancellation was set. Files
+/// which have a resume journal are kept since their download can be continued.
+pub struct PartialFile<'a> {
+    path: PathBuf,
+    cancellation: &'a Cancellation,
+}
+
+impl<'a> PartialFile<'a> {
+    pub fn new<P: AsRef<Path>>(path: P, cancellation: &'a Cancellation) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            cancellation,
+        }
+    }
+}
+
+impl Drop for PartialFile<'_> {
+    fn drop(&mut self) {
+        if !self.cancellation.is_cancelled()
+            || !self.path.is_file()
+            || ResumeJournal::exists_for(&self.path)
+        {
+            return;
+        }
+        let result = std::fs::remove_file(&self.path);
+        debug!(
+            "Removed partial file {} {}",
+            self.path.to_string_lossy(),
+            if result.is_ok() {
+                "successfully"
+            } else {
+                "not successfully"
+            }
+        )
+    }
+}
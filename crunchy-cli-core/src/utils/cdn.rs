@@ -0,0 +1,156 @@
+use anyhow::Result;
+use crunchyroll_rs::media::VariantSegment;
+use log::debug;
+use reqwest::{Client, Url};
+use std::time::{Duration, Instant};
+
+/// Hosts of CDN edges which serve the video segments of Crunchyroll streams, additionally to the
+/// host a stream points to itself. Not every host accepts the signature of every stream, hosts
+/// which reject it are reported as unavailable by [`benchmark`].
+pub const CDN_HOSTS: &[&str] = &[
+    "v.vrv.co",
+    "dl.v.vrv.co",
+    "a-vrv.akamaized.net",
+    "pl.crunchyroll.com",
+];
+
+/// Which CDN host segments are downloaded from, see '--cdn'.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Cdn {
+    /// Segments are downloaded from the given host.
+    Host(String),
+    /// The hosts are benchmarked with the segments of the first download and the fastest one is
+    /// used for all further downloads.
+    Auto,
+}
+
+impl Cdn {
+    pub fn parse(s: &str) -> Result<Cdn, String> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Cdn::Auto);
+        }
+        // only the host is relevant, but copying a whole url out of the speedtest output or a
+        // browser should work too
+        let url = if s.contains("://") {
+            s.to_string()
+        } else {
+            format!("https://{}", s)
+        };
+        match Url::parse(&url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()))
+        {
+            Some(host) => Ok(Cdn::Host(host)),
+            None => Err(format!("'{}' is not a valid host", s)),
+        }
+    }
+}
+
+/// Result of benchmarking a single CDN host.
+pub struct CdnBenchmark {
+    pub host: String,
+    /// Time until the response headers of the first segment were received.
+    pub latency: Option<Duration>,
+    /// Average throughput of all test segments in bytes per second.
+    pub speed: Option<u64>,
+    /// Why the host could not be benchmarked, e.g. because it rejected the stream signature.
+    pub error: Option<String>,
+}
+
+/// Replace the host of the given url.
+pub fn with_host(url: &str, host: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut parsed) if parsed.set_host(Some(host)).is_ok() => parsed.to_string(),
+        _ => url.to_string(),
+    }
+}
+
+/// All hosts which are benchmarked for the given segments: the host the segments point to and
+/// every host of [`CDN_HOSTS`].
+pub fn candidate_hosts(segments: &[VariantSegment]) -> Vec<String> {
+    let mut hosts = vec![];
+    if let Some(host) = segments
+        .first()
+        .and_then(|s| Url::parse(&s.url).ok())
+        .and_then(|u| u.host_str().map(|h| h.to_string()))
+    {
+        hosts.push(host)
+    }
+    for host in CDN_HOSTS {
+        if !hosts.iter().any(|h| h == host) {
+            hosts.push(host.to_string())
+        }
+    }
+    hosts
+}
+
+/// Download the given segments from every host one after another and measure latency and
+/// throughput. The segments are only downloaded, not decrypted.
+pub async fn benchmark(
+    client: &Client,
+    segments: &[VariantSegment],
+    hosts: &[String],
+) -> Vec<CdnBenchmark> {
+    let mut results = vec![];
+    for host in hosts {
+        let result = match benchmark_host(client, segments, host).await {
+            Ok((latency, speed)) => CdnBenchmark {
+                host: host.clone(),
+                latency: Some(latency),
+                speed: Some(speed),
+                error: None,
+            },
+            Err(e) => CdnBenchmark {
+                host: host.clone(),
+                latency: None,
+                speed: None,
+                error: Some(e.to_string()),
+            },
+        };
+        debug!(
+            "Benchmarked cdn host {}: latency {:?}, speed {:?}, error {:?}",
+            result.host, result.latency, result.speed, result.error
+        );
+        results.push(result)
+    }
+    results
+}
+
+/// The host with the highest throughput, [`None`] if no host could be benchmarked.
+pub fn fastest(results: &[CdnBenchmark]) -> Option<&CdnBenchmark> {
+    results
+        .iter()
+        .filter(|r| r.speed.is_some())
+        .max_by_key(|r| r.speed.unwrap())
+}
+
+async fn benchmark_host(
+    client: &Client,
+    segments: &[VariantSegment],
+    host: &str,
+) -> Result<(Duration, u64)> {
+    let mut latency = None;
+    let mut bytes = 0;
+    let start = Instant::now();
+    for segment in segments {
+        let request_start = Instant::now();
+        let response = client
+            .get(with_host(&segment.url, host))
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await?
+            .error_for_status()?;
+        latency.get_or_insert(request_start.elapsed());
+        bytes += response.bytes().await?.len() as u64;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    Ok((
+        latency.unwrap_or_default(),
+        if elapsed > 0f64 {
+            (bytes as f64 / elapsed) as u64
+        } else {
+            0
+        },
+    ))
+}
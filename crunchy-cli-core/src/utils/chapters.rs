@@ -0,0 +1,216 @@
+use crate::utils::context::Context;
+use crate::utils::os::tempfile;
+use anyhow::Result;
+use log::debug;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempPath;
+
+#[derive(Debug, Deserialize)]
+struct SkipEvent {
+    start: f64,
+    end: f64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SkipEvents {
+    recap: Option<SkipEvent>,
+    intro: Option<SkipEvent>,
+    credits: Option<SkipEvent>,
+    preview: Option<SkipEvent>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Chapter {
+    pub title: String,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// Get the chapters of an episode. They are generated from the skip events (intro, credits, ...)
+/// which Crunchyroll uses for its "skip intro" buttons, everything between them is the actual
+/// episode. Returns an empty vec if no skip events are available for the episode.
+pub async fn fetch_chapters(
+    ctx: &Context,
+    episode_id: &str,
+    duration: Duration,
+) -> Result<Vec<Chapter>> {
+    let response = ctx
+        .crunchy
+        .client()
+        .get(format!(
+            "https://static.crunchyroll.com/skip-events/production/{}.json",
+            episode_id
+        ))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        debug!(
+            "No skip events available for {} ({})",
+            episode_id,
+            response.status()
+        );
+        return Ok(vec![]);
+    }
+    let skip_events: SkipEvents = response.json().await?;
+
+    let mut events: Vec<(&str, SkipEvent)> = [
+        ("Recap", skip_events.recap),
+        ("Intro", skip_events.intro),
+        ("Ending", skip_events.credits),
+        ("Preview", skip_events.preview),
+    ]
+    .into_iter()
+    .filter_map(|(title, event)| event.map(|e| (title, e)))
+    .filter(|(_, e)| e.end > e.start)
+    .collect();
+    events.sort_by(|(_, a), (_, b)| a.start.total_cmp(&b.start));
+
+    if events.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut chapters = vec![];
+    let mut position = Duration::ZERO;
+    for (title, event) in events {
+        let start = Duration::from_secs_f64(event.start).min(duration);
+        let end = Duration::from_secs_f64(event.end).min(duration);
+        // events may overlap slightly, the earlier one wins
+        let start = start.max(position);
+        if end <= start {
+            continue;
+        }
+
+        if start > position {
+            chapters.push(Chapter {
+                title: "Episode".to_string(),
+                start: position,
+                end: start,
+            })
+        }
+        chapters.push(Chapter {
+            title: title.to_string(),
+            start,
+            end,
+        });
+        position = end
+    }
+    if position < duration {
+        chapters.push(Chapter {
+            title: "Episode".to_string(),
+            start: position,
+            end: duration,
+        })
+    }
+
+    Ok(chapters)
+}
+
+/// Write the chapters to a temporary file in the ffmpeg metadata format. The file can be passed
+/// to ffmpeg as additional input and be used with `-map_chapters`.
+pub fn ffmpeg_chapters_file(chapters: &[Chapter]) -> Result<TempPath> {
+    let (mut file, path) = tempfile(".ffmetadata")?.into_parts();
+
+    writeln!(file, ";FFMETADATA1")?;
+    for chapter in chapters {
+        writeln!(file, "[CHAPTER]")?;
+        writeln!(file, "TIMEBASE=1/1000")?;
+        writeln!(file, "START={}", chapter.start.as_millis())?;
+        writeln!(file, "END={}", chapter.end.as_millis())?;
+        writeln!(file, "title={}", chapter.title)?;
+    }
+
+    Ok(path)
+}
+
+/// Format of chapter files which are written next to the video.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ChapterFormat {
+    /// The simple OGM format (`CHAPTER01=...`), understood by mkvtoolnix and most other tools.
+    Ogm,
+    /// The Matroska XML chapter format of mkvtoolnix.
+    Xml,
+}
+
+impl ChapterFormat {
+    pub fn parse(s: &str) -> Result<ChapterFormat, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "txt" | "ogm" => ChapterFormat::Ogm,
+            "xml" => ChapterFormat::Xml,
+            _ => return Err(format!("'{}' is not a valid chapter format", s)),
+        })
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ChapterFormat::Ogm => "txt",
+            ChapterFormat::Xml => "xml",
+        }
+    }
+
+    /// Write the chapters to a file in this format.
+    pub fn write(&self, chapters: &[Chapter], path: &Path) -> Result<()> {
+        let mut file = File::create(path)?;
+        match self {
+            ChapterFormat::Ogm => {
+                for (i, chapter) in chapters.iter().enumerate() {
+                    writeln!(
+                        file,
+                        "CHAPTER{:02}={}",
+                        i + 1,
+                        chapter_timestamp(chapter.start, 3)
+                    )?;
+                    writeln!(file, "CHAPTER{:02}NAME={}", i + 1, chapter.title)?;
+                }
+            }
+            ChapterFormat::Xml => {
+                writeln!(file, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+                writeln!(file, r#"<!DOCTYPE Chapters SYSTEM "matroskachapters.dtd">"#)?;
+                writeln!(file, "<Chapters>")?;
+                writeln!(file, "  <EditionEntry>")?;
+                for chapter in chapters {
+                    writeln!(file, "    <ChapterAtom>")?;
+                    writeln!(
+                        file,
+                        "      <ChapterTimeStart>{}</ChapterTimeStart>",
+                        chapter_timestamp(chapter.start, 9)
+                    )?;
+                    writeln!(
+                        file,
+                        "      <ChapterTimeEnd>{}</ChapterTimeEnd>",
+                        chapter_timestamp(chapter.end, 9)
+                    )?;
+                    writeln!(file, "      <ChapterDisplay>")?;
+                    writeln!(
+                        file,
+                        "        <ChapterString>{}</ChapterString>",
+                        chapter.title
+                    )?;
+                    writeln!(file, "        <ChapterLanguage>eng</ChapterLanguage>")?;
+                    writeln!(file, "      </ChapterDisplay>")?;
+                    writeln!(file, "    </ChapterAtom>")?;
+                }
+                writeln!(file, "  </EditionEntry>")?;
+                writeln!(file, "</Chapters>")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `hh:mm:ss.fff` timestamp with `precision` fractional digits (3 or 9).
+fn chapter_timestamp(duration: Duration, precision: usize) -> String {
+    let secs = duration.as_secs();
+    let fraction = format!("{:09}", duration.subsec_nanos());
+    format!(
+        "{:02}:{:02}:{:02}.{}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        &fraction[..precision]
+    )
+}
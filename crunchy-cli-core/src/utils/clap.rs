@@ -1,6 +1,68 @@
-use crate::utils::parse::parse_resolution;
+use crate::utils::parse::{
+    parse_bandwidth, parse_date, parse_duration, parse_ffmpeg_args, parse_filter, parse_resolution,
+    parse_size, UrlFilter,
+};
+use chrono::{DateTime, Utc};
 use crunchyroll_rs::media::Resolution;
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Proxy, Url};
+use std::time::Duration;
 
 pub fn clap_parse_resolution(s: &str) -> Result<Resolution, String> {
     parse_resolution(s.to_string()).map_err(|e| e.to_string())
 }
+
+pub fn clap_parse_filter(s: &str) -> Result<UrlFilter, String> {
+    parse_filter(s).map_err(|e| e.to_string())
+}
+
+pub fn clap_parse_size(s: &str) -> Result<u64, String> {
+    parse_size(s.to_string()).map_err(|e| e.to_string())
+}
+
+pub fn clap_parse_bandwidth(s: &str) -> Result<u64, String> {
+    parse_bandwidth(s.to_string()).map_err(|e| e.to_string())
+}
+
+pub fn clap_parse_duration(s: &str) -> Result<Duration, String> {
+    parse_duration(s.to_string()).map_err(|e| e.to_string())
+}
+
+pub fn clap_parse_date(s: &str) -> Result<DateTime<Utc>, String> {
+    parse_date(s.to_string()).map_err(|e| e.to_string())
+}
+
+pub fn clap_parse_ffmpeg_args(s: &str) -> Result<String, String> {
+    parse_ffmpeg_args(s).map_err(|e| e.to_string())?;
+    Ok(s.to_string())
+}
+
+pub fn clap_parse_bitrate(s: &str) -> Result<String, String> {
+    let re = regex::Regex::new(r"^\d+(\.\d+)?[kKmMgG]?$").unwrap();
+    if re.is_match(s) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("'{}' is not a valid bitrate", s))
+    }
+}
+
+pub fn clap_parse_proxy(s: &str) -> Result<Proxy, String> {
+    Proxy::all(s).map_err(|e| e.to_string())
+}
+
+pub fn clap_parse_url(s: &str) -> Result<Url, String> {
+    Url::parse(s).map_err(|e| e.to_string())
+}
+
+pub fn clap_parse_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let Some((name, value)) = s.split_once(':') else {
+        return Err(format!("'{}' is not in the format 'name: value'", s));
+    };
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("'{}' is not a valid header name", name.trim()))?;
+    let mut value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("'{}' is not a valid header value", value.trim()))?;
+    // headers may contain credentials (e.g. of a proxy), they must not show up in debug logs
+    value.set_sensitive(true);
+    Ok((name, value))
+}
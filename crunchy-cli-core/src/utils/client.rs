@@ -0,0 +1,83 @@
+use crate::utils::dns::{DohResolver, HostOverride};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{ClientBuilder, Url};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Tuning of the http client which is used for all requests, including the segment downloads.
+/// All download tasks share the same client and with it its connection pool.
+#[derive(Clone, Debug, Default)]
+pub struct ClientOptions {
+    /// Let the flow control window of HTTP/2 connections adapt to the bandwidth. HTTP/2 itself is
+    /// negotiated via ALPN, download tasks to hosts which support it are multiplexed over a single
+    /// connection per host.
+    pub http2: bool,
+    /// Maximum number of idle connections per host which are kept open to be reused.
+    pub max_connections_per_host: Option<usize>,
+    /// How long idle connections are kept open. Also the interval of TCP and HTTP/2 keep-alive
+    /// pings.
+    pub keep_alive: Option<Duration>,
+    /// Timeout of a whole request, from connecting until the body is read. Segment requests have
+    /// a default of [`SEGMENT_TIMEOUT`], other requests have no timeout by default.
+    pub timeout: Option<Duration>,
+    /// Timeout of establishing a connection.
+    pub connect_timeout: Option<Duration>,
+    /// Hosts which are resolved to fixed addresses instead of asking DNS.
+    pub resolve: Vec<HostOverride>,
+    /// DNS-over-HTTPS resolver which is used instead of the system resolver.
+    pub doh_url: Option<Url>,
+    /// User agent of all requests, replaces the default one.
+    pub user_agent: Option<String>,
+    /// Additional headers of all requests. Headers with the same name as a default header replace
+    /// it, if a header is given multiple times the last value is used.
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+/// Timeout of a segment request if no timeout is configured.
+pub const SEGMENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl ClientOptions {
+    pub fn apply(&self, mut builder: ClientBuilder) -> reqwest::Result<ClientBuilder> {
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent)
+        }
+        if !self.headers.is_empty() {
+            let mut headers = HeaderMap::new();
+            for (name, value) in &self.headers {
+                headers.insert(name, value.clone());
+            }
+            builder = builder.default_headers(headers)
+        }
+        if self.http2 {
+            // HTTP/2 itself is negotiated via ALPN, so hosts which only speak HTTP/1.1 still work.
+            // the flow control window grows with the bandwidth-delay product, which is what makes
+            // a single multiplexed connection as fast as multiple ones on high latency links
+            builder = builder.http2_adaptive_window(true)
+        }
+        if let Some(max_connections_per_host) = self.max_connections_per_host {
+            builder = builder.pool_max_idle_per_host(max_connections_per_host)
+        }
+        if let Some(keep_alive) = self.keep_alive {
+            builder = builder
+                .pool_idle_timeout(keep_alive)
+                .tcp_keepalive(keep_alive)
+                .http2_keep_alive_interval(keep_alive)
+                .http2_keep_alive_while_idle(true)
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout)
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout)
+        }
+        if let Some(doh_url) = &self.doh_url {
+            builder =
+                builder.dns_resolver(Arc::new(DohResolver::new(doh_url.clone(), &self.resolve)?))
+        }
+        // overrides take precedence over the resolver, also over the DNS-over-HTTPS one
+        for host_override in &self.resolve {
+            builder = host_override.apply(builder)
+        }
+        Ok(builder)
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::{bail, Result};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use log::debug;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Configuration file which stores default values for command line arguments. Arguments which are
+/// given on the command line always take precedence over the values of the config file.
+///
+/// Example:
+/// ```toml
+/// proxy = "socks5://127.0.0.1:1080"
+/// download_threads = 8
+///
+/// [download]
+/// audio = ["ja-JP"]
+/// resolution = "1080p"
+/// ffmpeg_preset = ["h265"]
+///
+/// [archive]
+/// locale = ["ja-JP", "en-US"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub lang: Option<String>,
+    pub download_threads: Option<u16>,
+    pub max_buffer_memory: Option<String>,
+    pub max_queued_segments: Option<u32>,
+    pub max_retries: Option<u32>,
+    pub speed_limit: Option<String>,
+    pub proxy: Option<String>,
+    pub max_connections_per_host: Option<usize>,
+    pub keep_alive: Option<String>,
+    pub timeout: Option<String>,
+    pub connect_timeout: Option<String>,
+    pub resolve: Option<Vec<String>>,
+    pub doh_url: Option<String>,
+    pub user_agent: Option<String>,
+    pub header: Option<Vec<String>>,
+    pub cdn: Option<String>,
+    pub notify_webhook: Option<String>,
+    pub discord_webhook: Option<String>,
+
+    pub download: DownloadConfig,
+    pub archive: ArchiveConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadConfig {
+    pub audio: Option<Vec<String>>,
+    pub subtitle: Option<Vec<String>>,
+    pub subtitle_format: Option<String>,
+    pub cc: Option<Vec<String>>,
+    pub sub_font: Option<String>,
+    pub sub_size: Option<u32>,
+    pub sub_color: Option<String>,
+    pub sub_margin: Option<u32>,
+    pub output: Option<String>,
+    pub output_dir_template: Option<String>,
+    pub specials_dir: Option<String>,
+    pub sanitize: Option<String>,
+    pub sanitize_replacement: Option<String>,
+    pub normalize: Option<String>,
+    pub resolution: Option<String>,
+    pub resolution_policy: Option<String>,
+    pub max_bitrate: Option<String>,
+    pub fps: Option<u32>,
+    pub stream_type: Option<String>,
+    pub ffmpeg_preset: Option<Vec<String>>,
+    pub hwaccel_device: Option<String>,
+    pub ffmpeg_input_args: Option<String>,
+    pub ffmpeg_output_args: Option<String>,
+    pub crf: Option<u8>,
+    pub video_bitrate: Option<String>,
+    pub encode_preset: Option<String>,
+    pub audio_codec: Option<String>,
+    pub audio_bitrate: Option<String>,
+    pub download_archive: Option<PathBuf>,
+    pub download_archive_format: Option<String>,
+    pub jobs: Option<u16>,
+    pub exec_before: Option<String>,
+    pub exec_after: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    pub locale: Option<Vec<String>>,
+    pub subtitle: Option<Vec<String>>,
+    pub output: Option<String>,
+    pub output_dir_template: Option<String>,
+    pub specials_dir: Option<String>,
+    pub sanitize: Option<String>,
+    pub sanitize_replacement: Option<String>,
+    pub normalize: Option<String>,
+    pub resolution: Option<String>,
+    pub resolution_policy: Option<String>,
+    pub max_bitrate: Option<String>,
+    pub fps: Option<u32>,
+    pub merge: Option<String>,
+    pub ffmpeg_preset: Option<Vec<String>>,
+    pub hwaccel_device: Option<String>,
+    pub ffmpeg_input_args: Option<String>,
+    pub ffmpeg_output_args: Option<String>,
+    pub crf: Option<u8>,
+    pub video_bitrate: Option<String>,
+    pub encode_preset: Option<String>,
+    pub audio_codec: Option<String>,
+    pub audio_bitrate: Option<String>,
+    pub default_subtitle: Option<String>,
+    pub hardsub: Option<String>,
+    pub download_archive: Option<PathBuf>,
+    pub download_archive_format: Option<String>,
+    pub exec_before: Option<String>,
+    pub exec_after: Option<String>,
+}
+
+impl Config {
+    /// Default path of the config file.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|config_dir| config_dir.join("crunchy-cli").join("config.toml"))
+    }
+
+    /// Load the config file at the given path or, if no path is given, at the default path. It is
+    /// not an error if no path is given and the default config file does not exist.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = if let Some(path) = path {
+            if !path.is_file() {
+                bail!("Config file {} does not exist", path.to_string_lossy())
+            }
+            path.to_path_buf()
+        } else if let Some(path) = Self::default_path().filter(|p| p.is_file()) {
+            path
+        } else {
+            return Ok(Self::default());
+        };
+
+        let config = toml::from_str(&fs::read_to_string(&path)?).map_err(|e| {
+            anyhow::anyhow!(
+                "Could not parse config file {}: {}",
+                path.to_string_lossy(),
+                e
+            )
+        })?;
+        debug!("Loaded config file {}", path.to_string_lossy());
+
+        Ok(config)
+    }
+}
+
+/// Overwrite `field` with the (parsed) value from the config file if the argument with the id `id`
+/// was not explicitly given on the command line.
+pub fn apply_config<T, V, F>(
+    matches: &ArgMatches,
+    id: &str,
+    field: &mut T,
+    value: Option<V>,
+    parse: F,
+) -> Result<()>
+where
+    F: FnOnce(V) -> Result<T, String>,
+{
+    let Some(value) = value else {
+        return Ok(())
+    };
+    if matches.value_source(id) == Some(ValueSource::CommandLine) {
+        return Ok(());
+    }
+
+    match parse(value) {
+        Ok(parsed) => *field = parsed,
+        Err(e) => bail!("Invalid value for '{}' in config file: {}", id, e),
+    }
+    Ok(())
+}
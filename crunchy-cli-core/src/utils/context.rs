@@ -1,5 +1,106 @@
+use crate::utils::cancel::Cancellation;
+use crate::utils::cdn::{benchmark, candidate_hosts, fastest, Cdn};
+use crate::utils::client::ClientOptions;
+use crate::utils::credentials::{session_to_string, Credentials};
+use crate::utils::notify::Notifier;
+use crate::utils::pause::Pause;
+use crate::utils::progress::{ProgressCallback, ProgressFormat};
+use crate::utils::rate_limit::RateLimiter;
+use crate::utils::retry::{Backoff, RetryPolicy};
+use crunchyroll_rs::media::VariantSegment;
 use crunchyroll_rs::Crunchyroll;
+use log::{debug, info, warn};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
 
 pub struct Context {
     pub crunchy: Crunchyroll,
+    /// Storage of the session token.
+    pub credentials: Credentials,
+    /// If the session was created from the token in [`Context::credentials`]. The stored token is
+    /// kept up to date then, see [`Context::persist_session`].
+    pub stored_session: bool,
+
+    /// Tuning of the http client of [`Context::crunchy`]. The options are applied when the client
+    /// is created, they're only kept here to log them.
+    pub client_options: ClientOptions,
+
+    /// Number of tasks which download segments simultaneously.
+    pub download_threads: usize,
+
+    /// Maximum number of bytes which out of order downloaded segments may occupy in memory
+    /// before they're spilled to disk.
+    pub max_buffer_memory: u64,
+    /// Maximum number of downloaded segments which wait to be written. If reached, the download
+    /// tasks pause until the writer caught up.
+    pub max_queued_segments: usize,
+    /// Limits the download speed of all segment downloads combined. [`None`] if the speed is not
+    /// limited.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// How failed segment downloads are retried.
+    pub retry_policy: RetryPolicy,
+    /// Pauses all segment downloads if Crunchyroll rate limits one of them.
+    pub backoff: Arc<Backoff>,
+    /// CDN host segments are downloaded from. [`None`] if the host of the stream is used.
+    pub cdn: Option<Cdn>,
+    /// Fastest host if [`Context::cdn`] is [`Cdn::Auto`], determined once on the first download.
+    pub fastest_cdn: OnceCell<Option<String>>,
+    /// How the download progress is displayed.
+    pub progress_format: ProgressFormat,
+    /// Receives every progress event additionally to the displayed progress. Only set if
+    /// crunchy-cli-core is used as library.
+    pub progress_callback: Option<ProgressCallback>,
+    /// Sends notifications about finished downloads and errors. [`None`] if neither a webhook nor
+    /// a discord webhook is configured.
+    pub notifier: Option<Notifier>,
+    /// Set on ctrl-c, running downloads stop as soon as possible if it's set.
+    pub cancellation: Cancellation,
+    /// Suspends all segment downloads while it's paused.
+    pub pause: Pause,
+}
+
+impl Context {
+    /// The host the given segments should be downloaded from, see [`Context::cdn`]. [`None`] if
+    /// the host of the segments should be kept.
+    pub async fn cdn_host(&self, segments: &[VariantSegment]) -> Option<String> {
+        match &self.cdn {
+            None => None,
+            Some(Cdn::Host(host)) => Some(host.clone()),
+            Some(Cdn::Auto) => self
+                .fastest_cdn
+                .get_or_init(|| async {
+                    // a few segments are enough to see differences, more would only delay the
+                    // actual download
+                    let sample = &segments[..segments.len().min(3)];
+                    let results =
+                        benchmark(&self.crunchy.client(), sample, &candidate_hosts(sample)).await;
+                    let host = fastest(&results).map(|r| r.host.clone());
+                    if let Some(host) = &host {
+                        info!("Using cdn host {}", host)
+                    } else {
+                        warn!("No cdn host could be benchmarked, using the host of the stream")
+                    }
+                    host
+                })
+                .await
+                .clone(),
+        }
+    }
+
+    /// Update the stored session token if the session was created from it. Expired sessions are
+    /// refreshed transparently by crunchyroll-rs, but Crunchyroll rotates the refresh token with
+    /// every refresh (and login), so the stored token would be invalid on the next run. Should be
+    /// called regularly by long running commands. Failures are only logged.
+    pub async fn persist_session(&self) {
+        if !self.stored_session {
+            return;
+        }
+
+        if let Some(session) = session_to_string(self.crunchy.session_token().await) {
+            match self.credentials.store(&session) {
+                Ok(()) => debug!("Updated stored session"),
+                Err(e) => warn!("Failed to update stored session: {}", e),
+            }
+        }
+    }
 }
@@ -0,0 +1,92 @@
+use crate::utils::context::Context;
+use crate::utils::format::{largest_image, Format};
+use crate::utils::os::tempfile;
+use anyhow::Result;
+use crunchyroll_rs::MediaCollection;
+use log::debug;
+use std::io::Write;
+use tempfile::TempPath;
+
+/// Cover art of an episode / movie. The poster is the (portrait) poster of the series or movie
+/// listing, the thumbnail the (landscape) thumbnail of the episode or movie itself.
+pub struct CoverArt {
+    pub poster: Option<TempPath>,
+    pub thumbnail: Option<TempPath>,
+}
+
+impl CoverArt {
+    pub async fn download(ctx: &Context, format: &Format) -> Result<Self> {
+        let poster_url = match ctx
+            .crunchy
+            .media_collection_from_id(&format.series_id)
+            .await?
+        {
+            MediaCollection::Series(series) => largest_image(&series.images.poster_tall),
+            MediaCollection::MovieListing(movie_listing) => {
+                largest_image(&movie_listing.images.poster_tall)
+            }
+            _ => None,
+        };
+
+        let poster = if let Some(url) = poster_url {
+            Some(download_image(ctx, &url).await?)
+        } else {
+            None
+        };
+        let thumbnail = if let Some(url) = &format.thumbnail {
+            Some(download_image(ctx, url).await?)
+        } else {
+            None
+        };
+
+        Ok(Self { poster, thumbnail })
+    }
+
+    /// The image to use if only one image can be embedded. The poster is preferred.
+    pub fn primary(&self) -> Option<&TempPath> {
+        self.poster.as_ref().or(self.thumbnail.as_ref())
+    }
+
+    /// Ffmpeg output arguments to attach the cover art to a matroska file. The file names follow
+    /// the matroska specification for cover art. `attachment_offset` is the number of attachments
+    /// which are already added to the output.
+    pub fn matroska_args(&self, attachment_offset: usize) -> Vec<String> {
+        let mut args = vec![];
+        let mut index = attachment_offset;
+        for (path, file_name) in [
+            (&self.poster, "cover.jpg"),
+            (&self.thumbnail, "cover_land.jpg"),
+        ] {
+            if let Some(path) = path {
+                args.extend([
+                    "-attach".to_string(),
+                    path.to_string_lossy().to_string(),
+                    format!("-metadata:s:t:{}", index),
+                    "mimetype=image/jpeg".to_string(),
+                    format!("-metadata:s:t:{}", index),
+                    format!("filename={}", file_name),
+                ]);
+                index += 1
+            }
+        }
+        args
+    }
+}
+
+async fn download_image(ctx: &Context, url: &str) -> Result<TempPath> {
+    let image = ctx
+        .crunchy
+        .client()
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    let (mut file, path) = tempfile(".jpg")?.into_parts();
+    file.write_all(&image)?;
+    debug!("Downloaded image {}", url);
+
+    Ok(path)
+}
@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use crunchyroll_rs::crunchyroll::SessionToken;
+use keyring::Entry;
+use log::{debug, warn};
+use std::fs;
+use std::path::PathBuf;
+
+/// Convert a session token to the format in which it's stored. Anonymous sessions cannot be stored,
+/// [`None`] is returned for them.
+pub fn session_to_string(session_token: SessionToken) -> Option<String> {
+    match session_token {
+        SessionToken::RefreshToken(refresh_token) => {
+            Some(format!("refresh_token:{}", refresh_token))
+        }
+        SessionToken::EtpRt(etp_rt) => Some(format!("etp_rt:{}", etp_rt)),
+        SessionToken::Anonymous => None,
+    }
+}
+
+const KEYRING_SERVICE: &str = "crunchy-cli";
+const KEYRING_USER: &str = "session";
+
+/// Persistent storage of the session token. The token is stored in the platform keyring (Secret
+/// Service on Linux, Keychain on macOS, Credential Manager on Windows). If no keyring is available,
+/// it's stored in a plaintext file in the config directory, which was the only storage in earlier
+/// versions.
+pub struct Credentials {
+    keyring: Option<Entry>,
+    file: Option<PathBuf>,
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Credentials {
+    pub fn new() -> Self {
+        let keyring = match Entry::new(KEYRING_SERVICE, KEYRING_USER) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                debug!("Keyring is not available: {}", e);
+                None
+            }
+        };
+
+        Self {
+            keyring,
+            file: dirs::config_dir().map(|config_dir| config_dir.join(".crunchy-cli-core")),
+        }
+    }
+
+    /// Load the stored session. The keyring takes precedence over the plaintext file.
+    pub fn load(&self) -> Result<Option<String>> {
+        if let Some(keyring) = &self.keyring {
+            match keyring.get_password() {
+                Ok(session) => return Ok(Some(session)),
+                Err(keyring::Error::NoEntry) => (),
+                Err(e) => debug!("Failed to read session from keyring: {}", e),
+            }
+        }
+
+        if let Some(file) = self.file.as_ref().filter(|f| f.exists()) {
+            return Ok(Some(fs::read_to_string(file)?));
+        }
+        Ok(None)
+    }
+
+    /// Store the session. If it was successfully stored in the keyring, a plaintext file from
+    /// earlier versions gets removed.
+    pub fn store(&self, session: &str) -> Result<()> {
+        if let Some(keyring) = &self.keyring {
+            match keyring.set_password(session) {
+                Ok(()) => {
+                    if let Some(file) = self.file.as_ref().filter(|f| f.exists()) {
+                        fs::remove_file(file)?
+                    }
+                    return Ok(());
+                }
+                Err(e) => warn!(
+                    "Failed to store session in keyring ({}), storing it in a plaintext file instead",
+                    e
+                ),
+            }
+        }
+
+        if let Some(file) = &self.file {
+            Ok(fs::write(file, session)?)
+        } else {
+            bail!("Cannot find config path")
+        }
+    }
+
+    /// Remove the stored session from the keyring and the plaintext file.
+    pub fn remove(&self) -> Result<()> {
+        if let Some(keyring) = &self.keyring {
+            match keyring.delete_password() {
+                Ok(()) | Err(keyring::Error::NoEntry) => (),
+                Err(e) => bail!("Failed to remove session from keyring: {}", e),
+            }
+        }
+
+        if let Some(file) = self.file.as_ref().filter(|f| f.exists()) {
+            fs::remove_file(file)?
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{Client, ClientBuilder, Url};
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+
+/// Fixed address of a host, see '--resolve'.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HostOverride {
+    pub host: String,
+    pub addr: IpAddr,
+}
+
+impl HostOverride {
+    /// Parse a `host:ip` pair. IPv6 addresses may be wrapped in brackets, e.g. `host:[::1]`.
+    pub fn parse(s: &str) -> Result<HostOverride, String> {
+        let Some((host, addr)) = s.split_once(':') else {
+            return Err(format!("'{}' is not in the format 'host:ip'", s));
+        };
+        if host.is_empty() {
+            return Err(format!("'{}' has no host", s));
+        }
+        let addr = addr.trim_start_matches('[').trim_end_matches(']');
+        match addr.parse() {
+            Ok(addr) => Ok(HostOverride {
+                host: host.to_lowercase(),
+                addr,
+            }),
+            Err(_) => Err(format!("'{}' is not a valid ip address", addr)),
+        }
+    }
+
+    pub fn apply(&self, builder: ClientBuilder) -> ClientBuilder {
+        // the port is ignored by reqwest, the one of the request url is used
+        builder.resolve(&self.host, SocketAddr::new(self.addr, 0))
+    }
+}
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    /// Record type, 1 is A and 28 is AAAA. CNAME records (5) are part of the answer too if the
+    /// name is an alias, they're skipped since the server already resolves them.
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+/// Resolves hosts with DNS-over-HTTPS instead of the system resolver, see '--doh-url'. The json
+/// api which Cloudflare, Google, Quad9 and most other public resolvers support is used.
+pub struct DohResolver {
+    client: Client,
+    url: Url,
+}
+
+impl DohResolver {
+    /// `overrides` are also applied to the client which sends the queries, so the host of the
+    /// resolver itself can be pinned if the system resolver cannot be trusted at all.
+    pub fn new(url: Url, overrides: &[HostOverride]) -> reqwest::Result<Self> {
+        let mut builder = Client::builder();
+        for host_override in overrides {
+            builder = host_override.apply(builder)
+        }
+        Ok(Self {
+            client: builder.build()?,
+            url,
+        })
+    }
+}
+
+impl Resolve for DohResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let client = self.client.clone();
+        let url = self.url.clone();
+        Box::pin(async move {
+            let mut addrs: Vec<SocketAddr> = vec![];
+            for record_type in ["A", "AAAA"] {
+                let body = client
+                    .get(url.clone())
+                    .query(&[("name", name.as_str()), ("type", record_type)])
+                    .header("Accept", "application/dns-json")
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .bytes()
+                    .await?;
+                let response: DohResponse = serde_json::from_slice(&body)?;
+                // 3 is NXDOMAIN
+                if response.status != 0 && response.status != 3 {
+                    return Err(format!(
+                        "DNS-over-HTTPS query for {} failed with status {}",
+                        name.as_str(),
+                        response.status
+                    )
+                    .into());
+                }
+                addrs.extend(
+                    response
+                        .answer
+                        .iter()
+                        .filter(|a| a.record_type == 1 || a.record_type == 28)
+                        .filter_map(|a| a.data.parse::<IpAddr>().ok())
+                        .map(|ip| SocketAddr::new(ip, 0)),
+                )
+            }
+            if addrs.is_empty() {
+                return Err(
+                    format!("DNS-over-HTTPS found no address for {}", name.as_str()).into(),
+                );
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
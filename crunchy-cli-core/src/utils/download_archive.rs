@@ -0,0 +1,108 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Extractor name yt-dlp uses in the download archive for Crunchyroll episodes.
+const YT_DLP_EXTRACTOR: &str = "crunchyrollbeta";
+
+/// Line format of the download archive.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    /// One episode id per line.
+    Plain,
+    /// yt-dlp's `<extractor> <id>` lines, so the archive can be shared with yt-dlp.
+    YtDlp,
+}
+
+impl ArchiveFormat {
+    pub fn parse(s: &str) -> Result<ArchiveFormat, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "plain" => ArchiveFormat::Plain,
+            "yt-dlp" | "ytdlp" | "youtube-dl" => ArchiveFormat::YtDlp,
+            _ => return Err(format!("'{}' is not a valid archive format", s)),
+        })
+    }
+
+    /// The format of a single archive line.
+    fn of_line(line: &str) -> ArchiveFormat {
+        if line.contains(' ') {
+            ArchiveFormat::YtDlp
+        } else {
+            ArchiveFormat::Plain
+        }
+    }
+}
+
+/// Text file which records the ids of all already downloaded episodes, so they can be skipped on
+/// subsequent runs. Lines starting with `#` are ignored. The ids are either stored one per line
+/// or as `<extractor> <id>` like yt-dlp does (see [`ArchiveFormat`]). Both formats are read
+/// regardless of the format new ids are written in, so archives of yt-dlp based workflows can be
+/// used as is. Entries of other yt-dlp extractors than Crunchyroll ones are ignored.
+pub struct DownloadArchive {
+    ids: HashSet<String>,
+    format: ArchiveFormat,
+    file: Mutex<File>,
+}
+
+impl DownloadArchive {
+    /// Open the download archive at the given path. The file (and its parent directories) is
+    /// created if it does not exist. Without an explicit `format`, new ids are written in the
+    /// format of the existing entries, or one per line if the archive is empty.
+    pub fn open(path: &Path, format: Option<ArchiveFormat>) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                std::fs::create_dir_all(parent)?
+            }
+        }
+
+        let lines: Vec<String> = if path.exists() {
+            std::fs::read_to_string(path)?
+                .lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(|l| l.to_string())
+                .collect()
+        } else {
+            vec![]
+        };
+        let format = format.unwrap_or_else(|| {
+            lines
+                .first()
+                .map_or(ArchiveFormat::Plain, |l| ArchiveFormat::of_line(l))
+        });
+        let ids = lines
+            .iter()
+            .filter_map(|l| match l.split_once(' ') {
+                Some((extractor, id)) => extractor
+                    .starts_with("crunchyroll")
+                    .then(|| id.trim().to_string()),
+                None => Some(l.clone()),
+            })
+            .collect();
+        let file = File::options().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            ids,
+            format,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Check if the episode with the given id was already downloaded.
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// Record that the episode with the given id was downloaded.
+    pub fn record(&self, id: &str) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        match self.format {
+            ArchiveFormat::Plain => writeln!(file, "{}", id)?,
+            ArchiveFormat::YtDlp => writeln!(file, "{} {}", YT_DLP_EXTRACTOR, id)?,
+        }
+        Ok(file.flush()?)
+    }
+}
@@ -0,0 +1,73 @@
+use crate::utils::format::largest_image;
+use crate::utils::nfo::escape_xml;
+use chrono::Utc;
+use crunchyroll_rs::{Episode, Media};
+
+/// Generate a RSS 2.0 feed with the given episodes as items. The episodes are added in the given
+/// order, so they should already be sorted (newest first).
+pub fn rss_feed(title: &str, link: &str, description: &str, episodes: &[Media<Episode>]) -> String {
+    let mut rss = vec![
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+        r#"<rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/">"#.to_string(),
+        "  <channel>".to_string(),
+        format!("    <title>{}</title>", escape_xml(title)),
+        format!("    <link>{}</link>", escape_xml(link)),
+        format!("    <description>{}</description>", escape_xml(description)),
+        format!(
+            "    <lastBuildDate>{}</lastBuildDate>",
+            Utc::now().to_rfc2822()
+        ),
+        "    <generator>crunchy-cli</generator>".to_string(),
+    ];
+    for episode in episodes {
+        rss.extend(rss_item(episode))
+    }
+    rss.push("  </channel>".to_string());
+    rss.push("</rss>".to_string());
+
+    rss.join("\n") + "\n"
+}
+
+fn rss_item(episode: &Media<Episode>) -> Vec<String> {
+    let link = format!("https://www.crunchyroll.com/watch/{}", episode.id);
+
+    let mut item = vec![
+        "    <item>".to_string(),
+        format!(
+            "      <title>{}</title>",
+            escape_xml(&format!(
+                "{} - S{:02}E{:02} - {}",
+                episode.metadata.series_title,
+                episode.metadata.season_number,
+                episode.metadata.episode_number,
+                episode.title
+            ))
+        ),
+        format!("      <link>{}</link>", escape_xml(&link)),
+        format!(
+            r#"      <guid isPermaLink="false">{}</guid>"#,
+            escape_xml(&episode.id)
+        ),
+        format!(
+            "      <description>{}</description>",
+            escape_xml(&episode.description)
+        ),
+        format!(
+            "      <pubDate>{}</pubDate>",
+            episode.metadata.episode_air_date.to_rfc2822()
+        ),
+        format!(
+            "      <category>{}</category>",
+            escape_xml(&episode.metadata.audio_locale.to_string())
+        ),
+    ];
+    if let Some(thumbnail) = largest_image(&episode.images.thumbnail) {
+        item.push(format!(
+            r#"      <media:thumbnail url="{}"/>"#,
+            escape_xml(&thumbnail)
+        ))
+    }
+    item.push("    </item>".to_string());
+
+    item
+}
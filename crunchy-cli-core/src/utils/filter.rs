@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+use crunchyroll_rs::{Episode, Media, Season};
+
+/// If the episode is a special (e.g. a recap, PV or an OVA which is released within a season).
+/// Crunchyroll has no dedicated flag for specials, so they're recognized by being a clip or by an
+/// episode "number" which is no plain number (e.g. 'SP' or '12.5').
+pub fn is_special(episode: &Media<Episode>) -> bool {
+    episode.metadata.is_clip
+        || episode.metadata.episode.parse::<u32>().is_err()
+        || episode.metadata.sequence_number.fract() != 0.0
+}
+
+/// If the episode should be skipped because of `--skip-specials` or `--only-specials`.
+pub fn skip_special(episode: &Media<Episode>, skip_specials: bool, only_specials: bool) -> bool {
+    let special = is_special(episode);
+    (skip_specials && special) || (only_specials && !special)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MovieFilter {
+    Include,
+    Skip,
+    Only,
+}
+
+impl ToString for MovieFilter {
+    fn to_string(&self) -> String {
+        match self {
+            MovieFilter::Include => "include",
+            MovieFilter::Skip => "skip",
+            MovieFilter::Only => "only",
+        }
+        .to_string()
+    }
+}
+
+impl MovieFilter {
+    pub(crate) fn parse(s: &str) -> Result<MovieFilter, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "include" => MovieFilter::Include,
+            "skip" => MovieFilter::Skip,
+            "only" => MovieFilter::Only,
+            _ => return Err(format!("'{}' is not a valid movie filter", s)),
+        })
+    }
+}
+
+/// If the season is a movie which is bundled with a series. Like specials, these seasons have no
+/// dedicated flag, they're recognized by their title (e.g. 'Jujutsu Kaisen 0 (Movie)').
+pub fn is_movie_season(season: &Media<Season>) -> bool {
+    let title = season.title.to_lowercase();
+    title.contains("movie") || title.contains("film")
+}
+
+/// If the season should be skipped because of the movie filter.
+pub fn skip_movie_season(season: &Media<Season>, filter: &MovieFilter) -> bool {
+    let movie = is_movie_season(season);
+    (filter == &MovieFilter::Skip && movie) || (filter == &MovieFilter::Only && !movie)
+}
+
+/// If the episode was released within `after` and `before` (both inclusive). [`None`] means no
+/// limit.
+pub fn is_released_between(
+    episode: &Media<Episode>,
+    after: Option<&DateTime<Utc>>,
+    before: Option<&DateTime<Utc>>,
+) -> bool {
+    let air_date = &episode.metadata.episode_air_date;
+    after.map_or(true, |after| air_date >= after)
+        && before.map_or(true, |before| air_date <= before)
+}
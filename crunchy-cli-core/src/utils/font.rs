@@ -0,0 +1,125 @@
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+
+/// A TrueType / OpenType font which is attached to matroska files, so subtitles which use it look
+/// the same on every player, regardless of the fonts installed on the device.
+pub struct Font {
+    pub path: PathBuf,
+    /// Family name of the font, subtitle styles reference fonts by it.
+    pub family: String,
+}
+
+impl Font {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let Some(family) = family_name(&data) else {
+            bail!(
+                "'{}' is not a TrueType or OpenType font",
+                path.to_string_lossy()
+            )
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            family,
+        })
+    }
+
+    pub fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self
+            .path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase()
+            .as_str()
+        {
+            "otf" => "font/otf",
+            "ttc" => "font/collection",
+            _ => "font/ttf",
+        }
+    }
+
+    /// Ffmpeg output arguments to attach the fonts to a matroska file. `attachment_offset` is the
+    /// number of attachments which are already added to the output.
+    pub fn matroska_args(fonts: &[Font], attachment_offset: usize) -> Vec<String> {
+        let mut args = vec![];
+        for (i, font) in fonts.iter().enumerate() {
+            args.extend([
+                "-attach".to_string(),
+                font.path.to_string_lossy().to_string(),
+                format!("-metadata:s:t:{}", attachment_offset + i),
+                format!("mimetype={}", font.mime_type()),
+                format!("-metadata:s:t:{}", attachment_offset + i),
+                format!("filename={}", font.file_name()),
+            ])
+        }
+        args
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Read the family name from the `name` table of a font. Font collections use the name of their
+/// first font.
+fn family_name(data: &[u8]) -> Option<String> {
+    let font_offset = if data.get(..4)? == b"ttcf" {
+        u32_at(data, 12)? as usize
+    } else {
+        0
+    };
+
+    let table_count = u16_at(data, font_offset + 4)? as usize;
+    let name_table = (0..table_count)
+        .map(|i| font_offset + 12 + i * 16)
+        .find(|record| data.get(*record..*record + 4) == Some(&b"name"[..]))
+        .and_then(|record| u32_at(data, record + 8))? as usize;
+
+    let record_count = u16_at(data, name_table + 2)? as usize;
+    let strings = name_table + u16_at(data, name_table + 4)? as usize;
+
+    let mut fallback = None;
+    for i in 0..record_count {
+        let record = name_table + 6 + i * 12;
+        let platform = u16_at(data, record)?;
+        // name id 1 is the font family
+        if u16_at(data, record + 6)? != 1 {
+            continue;
+        }
+        let length = u16_at(data, record + 8)? as usize;
+        let offset = strings + u16_at(data, record + 10)? as usize;
+        let raw = data.get(offset..offset + length)?;
+
+        match platform {
+            // windows and unicode names are utf-16
+            0 | 3 => {
+                let utf16: Vec<u16> = raw
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                return String::from_utf16(&utf16).ok();
+            }
+            // macintosh names are (mostly ascii compatible) mac roman
+            1 => fallback = Some(raw.iter().map(|b| *b as char).collect()),
+            _ => (),
+        }
+    }
+    fallback
+}
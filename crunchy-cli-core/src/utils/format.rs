@@ -1,10 +1,30 @@
-use crunchyroll_rs::media::VariantData;
-use crunchyroll_rs::{Episode, Locale, Media, Movie};
+use crate::utils::filter::is_special;
+use crate::utils::os::{is_special_file, path_overflow};
+use crate::utils::sanitize::Sanitizer;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Datelike, Utc};
+use crunchyroll_rs::common::Image;
+use crunchyroll_rs::media::{StreamSubtitle, VariantData};
+use crunchyroll_rs::{Concert, Episode, Locale, Media, Movie, MusicVideo};
+use log::{debug, warn};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+/// Type of the media a [`Format`] was created from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MediaKind {
+    Episode,
+    Movie,
+    MusicVideo,
+    Concert,
+}
+
+/// A single episode, movie, music video or concert with the streams which are downloaded.
 #[derive(Clone)]
 pub struct Format {
     pub id: String,
+    /// Type of the media [`Format::id`] belongs to.
+    pub kind: MediaKind,
     pub title: String,
     pub description: String,
     pub number: u32,
@@ -12,6 +32,27 @@ pub struct Format {
 
     pub duration: Duration,
     pub stream: VariantData,
+    /// Separate audio stream if `stream` is a DASH stream (which only contains video). HLS streams
+    /// contain video and audio, it's [`None`] then.
+    pub audio_stream: Option<VariantData>,
+    /// Stream whose segments are used if segments of `stream` still fail after they were retried,
+    /// see '--segment-fallback'.
+    pub fallback_stream: Option<VariantData>,
+    /// Number of segments of `stream` which must fail in a row until the rest of the stream is
+    /// downloaded from `fallback_stream`, see '--downgrade-after'.
+    pub downgrade_after: Option<u32>,
+    /// Subtitles which are muxed as separate tracks into the output file.
+    pub subtitles: Vec<StreamSubtitle>,
+    /// Closed captions (their locale and the url of the vtt file) which are muxed as separate
+    /// tracks into the output file.
+    pub closed_captions: Vec<(Locale, String)>,
+    /// Streams of other audio languages which are muxed as audio only tracks into the output file.
+    pub additional_audio: Vec<(Locale, VariantData)>,
+    /// Subtitle which is burned into the video.
+    pub hardsub: Option<Locale>,
+
+    pub air_date: Option<DateTime<Utc>>,
+    pub thumbnail: Option<String>,
 
     pub series_id: String,
     pub series_name: String,
@@ -19,12 +60,22 @@ pub struct Format {
     pub season_id: String,
     pub season_title: String,
     pub season_number: u32,
+
+    /// Artist of music videos and concerts.
+    pub artist: Option<String>,
+    /// If the format is a movie. This includes movies which are bundled as season of a series.
+    pub movie: bool,
+    /// If the format is a special episode, see [`is_special`].
+    pub special: bool,
+    pub release_year: Option<u32>,
 }
 
 impl Format {
     pub fn new_from_episode(episode: Media<Episode>, stream: VariantData) -> Self {
+        let special = is_special(&episode);
         Self {
             id: episode.id,
+            kind: MediaKind::Episode,
             title: episode.title,
             description: episode.description,
             number: episode.metadata.episode_number,
@@ -32,6 +83,16 @@ impl Format {
 
             duration: episode.metadata.duration.to_std().unwrap(),
             stream,
+            audio_stream: None,
+            fallback_stream: None,
+            downgrade_after: None,
+            subtitles: vec![],
+            closed_captions: vec![],
+            additional_audio: vec![],
+            hardsub: None,
+
+            air_date: Some(episode.metadata.episode_air_date),
+            thumbnail: largest_image(&episode.images.thumbnail),
 
             series_id: episode.metadata.series_id,
             series_name: episode.metadata.series_title,
@@ -39,12 +100,18 @@ impl Format {
             season_id: episode.metadata.season_id,
             season_title: episode.metadata.season_title,
             season_number: episode.metadata.season_number,
+
+            artist: None,
+            movie: false,
+            special,
+            release_year: Some(episode.metadata.episode_air_date.year() as u32),
         }
     }
 
     pub fn new_from_movie(movie: Media<Movie>, stream: VariantData) -> Self {
         Self {
             id: movie.id,
+            kind: MediaKind::Movie,
             title: movie.title,
             description: movie.description,
             number: 1,
@@ -52,6 +119,16 @@ impl Format {
 
             duration: movie.metadata.duration.to_std().unwrap(),
             stream,
+            audio_stream: None,
+            fallback_stream: None,
+            downgrade_after: None,
+            subtitles: vec![],
+            closed_captions: vec![],
+            additional_audio: vec![],
+            hardsub: None,
+
+            air_date: None,
+            thumbnail: largest_image(&movie.images.thumbnail),
 
             series_id: movie.metadata.movie_listing_id.clone(),
             series_name: movie.metadata.movie_listing_title.clone(),
@@ -59,45 +136,338 @@ impl Format {
             season_id: movie.metadata.movie_listing_id,
             season_title: movie.metadata.movie_listing_title,
             season_number: 1,
+
+            artist: None,
+            movie: true,
+            special: false,
+            release_year: None,
+        }
+    }
+
+    /// Music videos are named '<artist> – <title>'. The artist takes the place of the series.
+    pub fn new_from_music_video(music_video: Media<MusicVideo>, stream: VariantData) -> Self {
+        let artist = music_video.metadata.artist.name;
+        Self {
+            id: music_video.id,
+            kind: MediaKind::MusicVideo,
+            title: format!("{} – {}", artist, music_video.title),
+            description: music_video.description,
+            number: 1,
+            audio: Locale::ja_JP,
+
+            duration: music_video.metadata.duration.to_std().unwrap(),
+            stream,
+            audio_stream: None,
+            fallback_stream: None,
+            downgrade_after: None,
+            subtitles: vec![],
+            closed_captions: vec![],
+            additional_audio: vec![],
+            hardsub: None,
+
+            air_date: Some(music_video.metadata.original_release),
+            thumbnail: largest_image(&music_video.images.thumbnail),
+
+            series_id: music_video.metadata.artist.id.clone(),
+            series_name: artist.clone(),
+
+            season_id: music_video.metadata.artist.id,
+            season_title: artist.clone(),
+            season_number: 1,
+
+            artist: Some(artist),
+            movie: false,
+            special: false,
+            release_year: Some(music_video.metadata.original_release.year() as u32),
+        }
+    }
+
+    /// Concerts are named like music videos, see [`Format::new_from_music_video`].
+    pub fn new_from_concert(concert: Media<Concert>, stream: VariantData) -> Self {
+        let artist = concert.metadata.artist.name;
+        Self {
+            id: concert.id,
+            kind: MediaKind::Concert,
+            title: format!("{} – {}", artist, concert.title),
+            description: concert.description,
+            number: 1,
+            audio: Locale::ja_JP,
+
+            duration: concert.metadata.duration.to_std().unwrap(),
+            stream,
+            audio_stream: None,
+            fallback_stream: None,
+            downgrade_after: None,
+            subtitles: vec![],
+            closed_captions: vec![],
+            additional_audio: vec![],
+            hardsub: None,
+
+            air_date: Some(concert.metadata.original_release),
+            thumbnail: largest_image(&concert.images.thumbnail),
+
+            series_id: concert.metadata.artist.id.clone(),
+            series_name: artist.clone(),
+
+            season_id: concert.metadata.artist.id,
+            season_title: artist.clone(),
+            season_number: 1,
+
+            artist: Some(artist),
+            movie: false,
+            special: false,
+            release_year: Some(concert.metadata.original_release.year() as u32),
+        }
+    }
+
+    /// Estimated size of the episode in bytes, calculated from the bandwidth and the duration of the
+    /// streams. This is the same estimation the progress bar starts with.
+    pub fn estimated_size(&self) -> u64 {
+        let secs = self.duration.as_secs();
+        (self.stream.bandwidth / 8) * secs
+            + self
+                .audio_stream
+                .as_ref()
+                .map_or(0, |stream| (stream.bandwidth / 8) * secs)
+            + self
+                .additional_audio
+                .iter()
+                .map(|(_, stream)| (stream.bandwidth / 8) * secs)
+                .sum::<u64>()
+    }
+}
+
+/// Url of the image with the highest resolution. Crunchyroll delivers every image in multiple
+/// resolutions.
+pub fn largest_image(images: &[Vec<Image>]) -> Option<String> {
+    images
+        .iter()
+        .flatten()
+        .max_by_key(|i| i.width)
+        .map(|i| i.source.clone())
+}
+
+enum Token<'a> {
+    Literal(&'a str),
+    Pattern {
+        raw: &'a str,
+        name: &'a str,
+        spec: Option<&'a str>,
+    },
+}
+
+/// Split a format string into literal text and patterns. A pattern has the form `{name}` or
+/// `{name:spec}`.
+fn tokenize(s: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut rest = s;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        if start > 0 {
+            tokens.push(Token::Literal(&rest[..start]))
         }
+
+        let raw = &rest[start..=end];
+        let (name, spec) = match raw[1..raw.len() - 1].split_once(':') {
+            Some((name, spec)) => (name, Some(spec)),
+            None => (&raw[1..raw.len() - 1], None),
+        };
+        tokens.push(Token::Pattern { raw, name, spec });
+
+        rest = &rest[end + 1..]
     }
+    if !rest.is_empty() {
+        tokens.push(Token::Literal(rest))
+    }
+
+    tokens
+}
+
+/// All pattern names which are supported by [`format_string`].
+const PATTERNS: &[&str] = &[
+    "title",
+    "series",
+    "series_name",
+    "season_name",
+    "season_title",
+    "audio",
+    "resolution",
+    "season",
+    "padded_season_number",
+    "season_number",
+    "episode",
+    "padded_episode_number",
+    "episode_number",
+    "series_id",
+    "season_id",
+    "episode_id",
+    "artist",
+    "movie_title",
+    "year",
+];
+
+fn pattern_value(name: &str, format: &Format) -> Option<String> {
+    Some(match name {
+        "title" => format.title.clone(),
+        "series" | "series_name" => format.series_name.clone(),
+        "season_name" | "season_title" => format.season_title.clone(),
+        "audio" => format.audio.to_string(),
+        "resolution" => format.stream.resolution.to_string(),
+        "padded_season_number" => format!("{:0>2}", format.season_number),
+        "season" | "season_number" => format.season_number.to_string(),
+        "padded_episode_number" => format!("{:0>2}", format.number),
+        "episode" | "episode_number" => format.number.to_string(),
+        "series_id" => format.series_id.clone(),
+        "season_id" => format.season_id.clone(),
+        "episode_id" => format.id.clone(),
+        "artist" => format.artist.clone().unwrap_or_default(),
+        "movie_title" => {
+            if format.movie {
+                format.title.clone()
+            } else {
+                String::new()
+            }
+        }
+        "year" => format
+            .release_year
+            .map_or(String::new(), |year| year.to_string()),
+        _ => return None,
+    })
+}
+
+/// Apply a format spec to a value. `0N` pads the value with zeros to a width of `N`, `N` pads it
+/// with spaces.
+fn apply_spec(value: String, spec: &str) -> Option<String> {
+    let width: usize = spec.parse().ok()?;
+    Some(if spec.starts_with('0') {
+        format!("{:0>width$}", value, width = width)
+    } else {
+        format!("{:>width$}", value, width = width)
+    })
+}
+
+/// Check if all patterns in the given string are known and have a valid format spec.
+pub fn check_format_string(s: &str) -> Result<()> {
+    for token in tokenize(s) {
+        if let Token::Pattern { raw, name, spec } = token {
+            if !PATTERNS.contains(&name) {
+                bail!("Unknown pattern '{}'", raw)
+            } else if let Some(spec) = spec {
+                if spec.parse::<usize>().is_err() {
+                    bail!("Invalid format spec in pattern '{}'", raw)
+                }
+            }
+        }
+    }
+    Ok(())
 }
 
 /// Formats the given string if it has specific pattern in it. It's possible to sanitize it which
-/// removes characters which can cause failures if the output string is used as a file name.
-pub fn format_string(s: String, format: &Format, sanitize: bool) -> String {
-    let sanitize_func = if sanitize {
-        |s: &str| sanitize_filename::sanitize(s)
+/// replaces characters which can cause failures if the output string is used as a file name (see
+/// [`Sanitizer`]).
+/// Only the values which are inserted for patterns get sanitized, so the format string itself may
+/// contain path separators to create a directory structure.
+///
+/// A pattern has the form `{name}` or `{name:spec}`, e.g. `{season:02}` inserts the season number
+/// padded with zeros to two digits. Unknown patterns are left as they are.
+pub fn format_string(s: String, format: &Format, sanitizer: Option<&Sanitizer>) -> String {
+    let mut formatted = String::new();
+    for token in tokenize(&s) {
+        match token {
+            Token::Literal(literal) => formatted.push_str(literal),
+            Token::Pattern { raw, name, spec } => {
+                let value = pattern_value(name, format).and_then(|value| match spec {
+                    Some(spec) => apply_spec(value, spec),
+                    None => Some(value),
+                });
+                match value {
+                    Some(value) => match sanitizer {
+                        Some(sanitizer) => formatted.push_str(&sanitizer.sanitize(&value)),
+                        None => formatted.push_str(&value),
+                    },
+                    None => formatted.push_str(raw),
+                }
+            }
+        }
+    }
+    formatted
+}
+
+/// Formats an output path. The path may contain directories, which are formatted too. If the path
+/// has no file name (e.g. it ends with `..`), the formatted `fallback` is used as file name.
+///
+/// If the path is too long for the platform (see [`path_overflow`]), the episode title is
+/// shortened and ends with an ellipsis. All other parts of the path, like the extension or the
+/// episode number, stay as they are.
+pub fn format_path(path: &str, fallback: &str, format: &Format, sanitizer: &Sanitizer) -> PathBuf {
+    let formatted = format_path_unchecked(path, fallback, format, sanitizer);
+    if path == "-" || is_special_file(&formatted) {
+        return formatted;
+    }
+    let mut overflow = path_overflow(&formatted);
+    if overflow == 0 {
+        return formatted;
+    }
+
+    let mut shortened = format.clone();
+    let mut title: Vec<char> = format.title.chars().collect();
+    while !title.is_empty() {
+        // the ellipsis takes the place of one more character
+        title.truncate(title.len().saturating_sub(overflow + 1));
+        shortened.title = format!("{}…", title.iter().collect::<String>().trim_end());
+
+        let shortened_path = format_path_unchecked(path, fallback, &shortened, sanitizer);
+        overflow = path_overflow(&shortened_path);
+        if overflow == 0 {
+            debug!(
+                "Shortened output path '{}' to '{}'",
+                formatted.to_string_lossy(),
+                shortened_path.to_string_lossy()
+            );
+            return shortened_path;
+        }
+    }
+
+    // the title is either not part of the path or the path is too long without it
+    warn!(
+        "Output path '{}' is too long and cannot be shortened",
+        formatted.to_string_lossy()
+    );
+    formatted
+}
+
+fn format_path_unchecked(
+    path: &str,
+    fallback: &str,
+    format: &Format,
+    sanitizer: &Sanitizer,
+) -> PathBuf {
+    let mut formatted = PathBuf::from(format_string(path.to_string(), format, Some(sanitizer)));
+    if formatted.file_name().is_none() {
+        formatted.push(format_string(fallback.to_string(), format, Some(sanitizer)))
+    }
+    formatted
+}
+
+/// Output template which puts `output` into the directory `dir_template`. Specials are put into
+/// `specials_dir` instead, if it is set. Output to stdout or to an absolute path (e.g.
+/// `/dev/null`) is never moved into a directory.
+pub fn output_template(
+    output: &str,
+    dir_template: Option<&str>,
+    specials_dir: Option<&str>,
+    format: &Format,
+) -> String {
+    let dir = if format.special {
+        specials_dir.or(dir_template)
     } else {
-        // converting this to a string is actually unnecessary
-        |s: &str| s.to_string()
+        dir_template
     };
-
-    s.replace("{title}", &sanitize_func(&format.title))
-        .replace("{series_name}", &sanitize_func(&format.series_name))
-        .replace("{season_name}", &sanitize_func(&format.season_title))
-        .replace("{audio}", &sanitize_func(&format.audio.to_string()))
-        .replace(
-            "{resolution}",
-            &sanitize_func(&format.stream.resolution.to_string()),
-        )
-        .replace(
-            "{padded_season_number}",
-            &sanitize_func(&format!("{:0>2}", format.season_number.to_string())),
-        )
-        .replace(
-            "{season_number}",
-            &sanitize_func(&format.season_number.to_string()),
-        )
-        .replace(
-            "{padded_episode_number}",
-            &sanitize_func(&format!("{:0>2}", format.number.to_string())),
-        )
-        .replace(
-            "{episode_number}",
-            &sanitize_func(&format.number.to_string()),
-        )
-        .replace("{series_id}", &sanitize_func(&format.series_id))
-        .replace("{season_id}", &sanitize_func(&format.season_id))
-        .replace("{episode_id}", &sanitize_func(&format.id))
+    match dir {
+        Some(dir) if output != "-" => Path::new(dir).join(output).to_string_lossy().to_string(),
+        _ => output.to_string(),
+    }
 }
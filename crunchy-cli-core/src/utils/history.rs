@@ -0,0 +1,103 @@
+use crate::utils::format::Format;
+use anyhow::Result;
+use crunchyroll_rs::list::WatchHistoryOptions;
+use crunchyroll_rs::{Crunchyroll, Episode, Media, MediaCollection};
+use log::debug;
+use std::cmp::Ordering;
+
+/// The next `count` unwatched episodes of every series in the watch history of the account.
+/// Requires a logged in account.
+pub async fn continue_watching(crunchy: &Crunchyroll, count: usize) -> Result<Vec<Media<Episode>>> {
+    let mut series_ids = vec![];
+    let mut episodes = vec![];
+
+    // the history is ordered by the time an episode was played, so the first entry of a series is
+    // the episode which was watched last
+    for entry in crunchy
+        .watch_history(WatchHistoryOptions::default())
+        .await?
+    {
+        let episode = match entry.panel {
+            MediaCollection::Episode(episode) => episode,
+            media_collection => {
+                debug!("Skipping unsupported history entry {:?}", media_collection);
+                continue;
+            }
+        };
+        if series_ids.contains(&episode.metadata.series_id) {
+            continue;
+        }
+        series_ids.push(episode.metadata.series_id.clone());
+
+        episodes.extend(next_episodes(&episode, entry.fully_watched, count).await?)
+    }
+
+    Ok(episodes)
+}
+
+/// The episodes following `last` in the same audio language. `last` itself is included if it
+/// wasn't watched completely.
+async fn next_episodes(
+    last: &Media<Episode>,
+    fully_watched: bool,
+    count: usize,
+) -> Result<Vec<Media<Episode>>> {
+    let audio = &last.metadata.audio_locale;
+    let mut seasons: Vec<_> = last
+        .series()
+        .await?
+        .seasons()
+        .await?
+        .into_iter()
+        .filter(|s| {
+            s.metadata.audio_locales.contains(audio)
+                && s.metadata.season_number >= last.metadata.season_number
+        })
+        .collect();
+    seasons.sort_by_key(|s| s.metadata.season_number);
+
+    let mut next = vec![];
+    for season in seasons {
+        for episode in season.episodes().await? {
+            if episode.id == last.id {
+                if !fully_watched {
+                    next.push(episode)
+                }
+            } else if is_after(&episode, last) {
+                next.push(episode)
+            }
+            if next.len() >= count {
+                return Ok(next);
+            }
+        }
+    }
+
+    Ok(next)
+}
+
+/// Report `format` as completely watched to the playheads API, so the progress on the website
+/// matches the downloaded episodes. Requires a logged in account.
+pub async fn mark_watched(crunchy: &Crunchyroll, format: &Format) -> Result<()> {
+    let position = format.duration.as_secs() as u32;
+    match crunchy.media_collection_from_id(&format.id).await? {
+        MediaCollection::Episode(episode) => episode.set_playhead(position).await?,
+        MediaCollection::Movie(movie) => movie.set_playhead(position).await?,
+        media_collection => debug!(
+            "Cannot mark {:?} as watched, it has no playhead",
+            media_collection
+        ),
+    }
+    Ok(())
+}
+
+fn is_after(episode: &Media<Episode>, other: &Media<Episode>) -> bool {
+    match episode
+        .metadata
+        .season_number
+        .cmp(&other.metadata.season_number)
+    {
+        Ordering::Greater => true,
+        Ordering::Less => false,
+        Ordering::Equal => episode.metadata.sequence_number > other.metadata.sequence_number,
+    }
+}
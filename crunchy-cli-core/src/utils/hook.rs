@@ -0,0 +1,59 @@
+use crate::utils::format::Format;
+use anyhow::{bail, Result};
+use log::debug;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Environment variables which describe an episode. They're passed to the `--exec-after` command.
+pub fn episode_env(format: &Format, path: &Path) -> Vec<(&'static str, String)> {
+    vec![
+        ("CRUNCHY_PATH", path.to_string_lossy().to_string()),
+        ("CRUNCHY_TITLE", format.title.clone()),
+        ("CRUNCHY_ID", format.id.clone()),
+        ("CRUNCHY_SERIES", format.series_name.clone()),
+        ("CRUNCHY_SERIES_ID", format.series_id.clone()),
+        ("CRUNCHY_SEASON", format.season_number.to_string()),
+        ("CRUNCHY_SEASON_TITLE", format.season_title.clone()),
+        ("CRUNCHY_EPISODE", format.number.to_string()),
+        ("CRUNCHY_AUDIO", format.audio.to_string()),
+    ]
+}
+
+/// Environment variables which describe a batch of episodes which is about to be downloaded. They're
+/// passed to the `--exec-before` command.
+pub fn batch_env(url: &str, formats: &[&Format]) -> Vec<(&'static str, String)> {
+    vec![
+        ("CRUNCHY_URL", url.to_string()),
+        (
+            "CRUNCHY_SERIES",
+            formats
+                .first()
+                .map_or(String::new(), |f| f.series_name.clone()),
+        ),
+        ("CRUNCHY_EPISODES", formats.len().to_string()),
+    ]
+}
+
+/// Run a user given command through the system shell with the given environment variables.
+pub fn run_hook(command: &str, env: Vec<(&'static str, String)>) -> Result<()> {
+    debug!("Executing hook '{}'", command);
+
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    };
+    #[cfg(not(windows))]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    };
+
+    let status = shell.envs(env).stdin(Stdio::null()).status()?;
+    if !status.success() {
+        bail!("Command '{}' failed ({})", command, status)
+    }
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use crunchyroll_rs::Locale;
+use std::fmt;
 
 /// Return the locale of the system.
 pub fn system_locale() -> Locale {
@@ -13,3 +14,78 @@ pub fn system_locale() -> Locale {
         Locale::en_US
     }
 }
+
+/// Ordered list of locales, given as comma separated list on the command line (e.g. `en-US,ja-JP`).
+/// The first locale which is available is used, all others are fallbacks. Subtitles can have a
+/// `:forced` suffix (e.g. `en-US:forced`) to select only their forced (signs & songs) variant.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LocaleChain {
+    locales: Vec<Locale>,
+    forced: bool,
+}
+
+impl LocaleChain {
+    pub(crate) fn parse(s: &str) -> Result<LocaleChain, String> {
+        let (s, forced) = match s.strip_suffix(":forced") {
+            Some(s) => (s, true),
+            None => (s, false),
+        };
+        let locales: Vec<Locale> = s
+            .split(',')
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .map(|l| Locale::from(l.to_string()))
+            .collect();
+        if locales.is_empty() {
+            return Err("must contain at least one language".to_string());
+        }
+        Ok(LocaleChain { locales, forced })
+    }
+
+    /// The locale which is used if it's available.
+    pub fn preferred(&self) -> &Locale {
+        &self.locales[0]
+    }
+
+    /// If only the forced (signs & songs) subtitle variant should be selected.
+    pub fn forced(&self) -> bool {
+        self.forced
+    }
+
+    pub fn contains(&self, locale: &Locale) -> bool {
+        self.locales.contains(locale)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Locale> {
+        self.locales.iter()
+    }
+
+    /// The first locale of the chain for which `available` returns true.
+    pub fn find<F: Fn(&Locale) -> bool>(&self, available: F) -> Option<&Locale> {
+        self.locales.iter().find(|l| available(l))
+    }
+}
+
+impl From<Locale> for LocaleChain {
+    fn from(locale: Locale) -> Self {
+        Self {
+            locales: vec![locale],
+            forced: false,
+        }
+    }
+}
+
+impl fmt::Display for LocaleChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            self.locales
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+            if self.forced { ":forced" } else { "" }
+        )
+    }
+}
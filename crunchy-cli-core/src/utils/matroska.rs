@@ -0,0 +1,780 @@
+use crate::utils::mpegts::{Demuxer, Packet, StreamKind};
+use anyhow::{bail, Result};
+use crunchyroll_rs::Locale;
+use std::collections::VecDeque;
+use std::io::{Seek, SeekFrom, Write};
+use std::time::Duration;
+
+const EBML: u32 = 0x1a45dfa3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42f7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42f2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42f3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const VOID: u32 = 0xec;
+
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114d9b74;
+const SEEK: u32 = 0x4dbb;
+const SEEK_ID: u32 = 0x53ab;
+const SEEK_POSITION: u32 = 0x53ac;
+
+const INFO: u32 = 0x1549a966;
+const TIMESTAMP_SCALE: u32 = 0x2ad7b1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4d80;
+const WRITING_APP: u32 = 0x5741;
+
+const TRACKS: u32 = 0x1654ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const TRACK_UID: u32 = 0x73c5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_DEFAULT: u32 = 0x88;
+const FLAG_FORCED: u32 = 0x55aa;
+const FLAG_LACING: u32 = 0x9c;
+const LANGUAGE: u32 = 0x22b59c;
+const LANGUAGE_BCP47: u32 = 0x22b59d;
+const NAME: u32 = 0x536e;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const AUDIO: u32 = 0xe1;
+const SAMPLING_FREQUENCY: u32 = 0xb5;
+const CHANNELS: u32 = 0x9f;
+
+const CLUSTER: u32 = 0x1f43b675;
+const TIMESTAMP: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+const BLOCK_GROUP: u32 = 0xa0;
+const BLOCK: u32 = 0xa1;
+const BLOCK_DURATION: u32 = 0x9b;
+
+const ATTACHMENTS: u32 = 0x1941a469;
+const ATTACHED_FILE: u32 = 0x61a7;
+const FILE_NAME: u32 = 0x466e;
+const FILE_MIME_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465c;
+const FILE_UID: u32 = 0x46ae;
+
+const CUES: u32 = 0x1c53bb6b;
+const CUE_POINT: u32 = 0xbb;
+const CUE_TIME: u32 = 0xb3;
+const CUE_TRACK_POSITIONS: u32 = 0xb7;
+const CUE_TRACK: u32 = 0xf7;
+const CUE_CLUSTER_POSITION: u32 = 0xf1;
+
+const VIDEO_TRACK: u64 = 1;
+const AUDIO_TRACK: u64 = 2;
+/// Space which is reserved at the beginning of the segment for the seek head. It's written at the
+/// end, when the position of the cues is known.
+const SEEK_HEAD_SPACE: usize = 96;
+/// Size of an element with unknown size.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+fn id_bytes(id: u32) -> Vec<u8> {
+    id.to_be_bytes()
+        .into_iter()
+        .skip_while(|b| *b == 0)
+        .collect()
+}
+
+fn size_bytes(size: u64) -> Vec<u8> {
+    // a size with all bits set is reserved for unknown sizes, hence `- 1`
+    let len = (1..=8).find(|l| size < (1 << (7 * l)) - 1).unwrap_or(8);
+    let mut bytes = size.to_be_bytes()[8 - len..].to_vec();
+    bytes[0] |= 1 << (8 - len);
+    bytes
+}
+
+fn element(id: u32, data: &[u8]) -> Vec<u8> {
+    let mut element = id_bytes(id);
+    element.extend(size_bytes(data.len() as u64));
+    element.extend_from_slice(data);
+    element
+}
+
+fn uint_element(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(7);
+    element(id, &bytes[start..])
+}
+
+fn float_element(id: u32, value: f64) -> Vec<u8> {
+    element(id, &value.to_be_bytes())
+}
+
+fn string_element(id: u32, value: &str) -> Vec<u8> {
+    element(id, value.as_bytes())
+}
+
+fn master_element(id: u32, children: Vec<Vec<u8>>) -> Vec<u8> {
+    element(id, &children.concat())
+}
+
+/// Content of a `Block` or `SimpleBlock` element.
+fn block(track: u64, relative_timestamp: i16, keyframe: bool, data: &[u8]) -> Vec<u8> {
+    let mut block = size_bytes(track);
+    block.extend(relative_timestamp.to_be_bytes());
+    block.push(if keyframe { 0x80 } else { 0x00 });
+    block.extend_from_slice(data);
+    block
+}
+
+/// Split an Annex B byte stream into its nal units.
+fn nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3
+        } else {
+            i += 1
+        }
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(n, start)| {
+            let end = starts.get(n + 1).map_or(data.len(), |next| next - 3);
+            let mut nal = &data[*start..end];
+            // zero bytes before the next start code (4 byte start codes or trailing zeros)
+            while let Some((0, rest)) = nal.split_last() {
+                nal = rest
+            }
+            nal
+        })
+        .filter(|nal| !nal.is_empty())
+        .collect()
+}
+
+struct VideoConfig {
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+}
+
+impl VideoConfig {
+    /// `AVCDecoderConfigurationRecord`, the codec private data of h264 tracks.
+    fn avcc(&self) -> Vec<u8> {
+        let mut avcc = vec![1, self.sps[1], self.sps[2], self.sps[3], 0xff, 0xe1];
+        avcc.extend((self.sps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(&self.sps);
+        avcc.push(1);
+        avcc.extend((self.pps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(&self.pps);
+        avcc
+    }
+}
+
+struct AudioConfig {
+    /// AAC audio object type minus one, like it's stored in the ADTS header.
+    profile: u8,
+    sample_rate_index: u8,
+    channels: u8,
+}
+
+impl AudioConfig {
+    /// `AudioSpecificConfig`, the codec private data of aac tracks.
+    fn audio_specific_config(&self) -> Vec<u8> {
+        vec![
+            ((self.profile + 1) << 3) | (self.sample_rate_index >> 1),
+            ((self.sample_rate_index & 0x01) << 7) | (self.channels << 3),
+        ]
+    }
+
+    fn sample_rate(&self) -> u32 {
+        AAC_SAMPLE_RATES
+            .get(self.sample_rate_index as usize)
+            .copied()
+            .unwrap_or(48000)
+    }
+}
+
+/// Split ADTS data into its raw aac frames.
+fn adts_frames(data: &[u8]) -> Result<Vec<(AudioConfig, &[u8])>> {
+    let mut frames = vec![];
+    let mut data = data;
+    while data.len() >= 7 {
+        if data[0] != 0xff || data[1] & 0xf0 != 0xf0 {
+            bail!("Invalid ADTS frame")
+        }
+        let header_length = if data[1] & 0x01 != 0 { 7 } else { 9 };
+        let frame_length = (((data[3] & 0x03) as usize) << 11)
+            | ((data[4] as usize) << 3)
+            | (data[5] as usize >> 5);
+        if frame_length < header_length || frame_length > data.len() {
+            bail!("Invalid ADTS frame length")
+        }
+        let config = AudioConfig {
+            profile: data[2] >> 6,
+            sample_rate_index: (data[2] >> 2) & 0x0f,
+            channels: ((data[2] & 0x01) << 2) | (data[3] >> 6),
+        };
+        frames.push((config, &data[header_length..frame_length]));
+        data = &data[frame_length..]
+    }
+    Ok(frames)
+}
+
+/// Parse an ass timestamp (`h:mm:ss.cc`) to milliseconds.
+fn ass_time(time: &str) -> Option<u64> {
+    let mut parts = time.trim().split(':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some((hours * 3600 + minutes * 60) * 1000 + (seconds * 1000.0).round() as u64)
+}
+
+struct SubtitleEvent {
+    start: u64,
+    duration: u64,
+    /// Block data as specified for `S_TEXT/ASS` tracks: `ReadOrder, Layer, Style, Name, MarginL,
+    /// MarginR, MarginV, Effect, Text`.
+    data: String,
+}
+
+/// A file (e.g. a font) which is attached by [`MatroskaRemuxer`].
+pub struct Attachment {
+    pub file_name: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+/// An ass subtitle which is added as separate track by [`MatroskaRemuxer`].
+pub struct SubtitleInput {
+    pub locale: Locale,
+    /// Name of the track, shown by players in the subtitle selection.
+    pub name: String,
+    /// Forced (signs & songs) subtitles are shown by default.
+    pub forced: bool,
+    pub ass: String,
+}
+
+struct SubtitleTrack {
+    locale: Locale,
+    name: String,
+    /// Forced (signs & songs) subtitles are shown by default.
+    forced: bool,
+    /// Everything of the ass file except the dialogue lines, the codec private data of the track.
+    header: String,
+    /// Events which weren't written yet, sorted by their start.
+    events: VecDeque<SubtitleEvent>,
+}
+
+impl SubtitleTrack {
+    fn parse(input: SubtitleInput) -> Self {
+        let mut header = String::new();
+        let mut events = vec![];
+        for line in input.ass.lines() {
+            let Some(dialogue) = line.strip_prefix("Dialogue:") else {
+                header.push_str(line);
+                header.push('\n');
+                continue;
+            };
+            // Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+            let fields: Vec<&str> = dialogue.trim_start().splitn(10, ',').collect();
+            if fields.len() != 10 {
+                continue;
+            }
+            let (Some(start), Some(end)) = (ass_time(fields[1]), ass_time(fields[2])) else {
+                continue;
+            };
+            events.push(SubtitleEvent {
+                start,
+                duration: end.saturating_sub(start),
+                data: format!("{},{},{}", events.len(), fields[0], fields[3..].join(",")),
+            })
+        }
+        events.sort_by_key(|e| e.start);
+
+        Self {
+            locale: input.locale,
+            name: input.name,
+            forced: input.forced,
+            header,
+            events: events.into(),
+        }
+    }
+}
+
+struct Cluster {
+    /// Timestamp in milliseconds.
+    timestamp: u64,
+    /// If the cluster starts with a video keyframe. Only those clusters are added to the cues.
+    keyframe: bool,
+    blocks: Vec<u8>,
+}
+
+/// Remuxes the mpeg transport stream of a HLS stream (h264 video and aac audio) together with ass
+/// subtitles into a matroska file. The stream is written into it (it implements [`Write`]),
+/// [`MatroskaRemuxer::finish`] must be called after the last byte.
+pub struct MatroskaRemuxer<W: Write + Seek> {
+    writer: W,
+    demuxer: Demuxer,
+
+    audio_locale: Locale,
+    width: u64,
+    height: u64,
+    duration: Duration,
+    subtitles: Vec<SubtitleTrack>,
+    attachments: Vec<Attachment>,
+
+    video_config: Option<VideoConfig>,
+    audio_config: Option<AudioConfig>,
+    /// Packets which arrive before the codec configuration of both tracks is known.
+    pending: Vec<Packet>,
+    /// Timestamp of the first packet, all timestamps are relative to it.
+    first_pts: Option<u64>,
+
+    /// Position of the segment size and of the segment content. Only known after the header is
+    /// written.
+    segment_positions: Option<(u64, u64)>,
+    info_position: u64,
+    tracks_position: u64,
+    /// Position of the attachments, [`None`] if there are no attachments.
+    attachments_position: Option<u64>,
+    cluster: Option<Cluster>,
+    /// Timestamps and positions of the clusters which start with a keyframe.
+    cues: Vec<(u64, u64)>,
+}
+
+impl<W: Write + Seek> MatroskaRemuxer<W> {
+    /// `subtitles` are added as separate tracks.
+    pub fn new(
+        writer: W,
+        audio_locale: Locale,
+        width: u64,
+        height: u64,
+        duration: Duration,
+        subtitles: Vec<SubtitleInput>,
+    ) -> Self {
+        Self {
+            writer,
+            demuxer: Demuxer::new(),
+            audio_locale,
+            width,
+            height,
+            duration,
+            subtitles: subtitles.into_iter().map(SubtitleTrack::parse).collect(),
+            attachments: vec![],
+            video_config: None,
+            audio_config: None,
+            pending: vec![],
+            first_pts: None,
+            segment_positions: None,
+            info_position: 0,
+            tracks_position: 0,
+            attachments_position: None,
+            cluster: None,
+            cues: vec![],
+        }
+    }
+
+    /// Attach a file. Must be called before the first byte of the stream is written.
+    pub fn attach(&mut self, attachment: Attachment) {
+        self.attachments.push(attachment)
+    }
+
+    /// Write everything which is still buffered, the cues and the seek head. Fails if the stream
+    /// contained no video or audio.
+    pub fn finish(mut self) -> Result<W> {
+        for packet in self.demuxer.finish()? {
+            self.add_packet(packet)?
+        }
+        if self.segment_positions.is_none() {
+            if self.video_config.is_none() {
+                bail!("The stream contains no h264 video")
+            }
+            self.start()?
+        }
+        self.flush_cluster(None)?;
+
+        let (segment_size_position, segment_start) = self.segment_positions.unwrap();
+        let cues_position = self.writer.stream_position()? - segment_start;
+        let cue_points = self
+            .cues
+            .iter()
+            .map(|(timestamp, position)| {
+                master_element(
+                    CUE_POINT,
+                    vec![
+                        uint_element(CUE_TIME, *timestamp),
+                        master_element(
+                            CUE_TRACK_POSITIONS,
+                            vec![
+                                uint_element(CUE_TRACK, VIDEO_TRACK),
+                                uint_element(CUE_CLUSTER_POSITION, *position),
+                            ],
+                        ),
+                    ],
+                )
+            })
+            .collect();
+        self.writer.write_all(&master_element(CUES, cue_points))?;
+        let end = self.writer.stream_position()?;
+
+        let seek = |id: u32, position: u64| {
+            master_element(
+                SEEK,
+                vec![
+                    element(SEEK_ID, &id_bytes(id)),
+                    uint_element(SEEK_POSITION, position),
+                ],
+            )
+        };
+        let mut seeks = vec![
+            seek(INFO, self.info_position),
+            seek(TRACKS, self.tracks_position),
+        ];
+        if let Some(attachments_position) = self.attachments_position {
+            seeks.push(seek(ATTACHMENTS, attachments_position))
+        }
+        seeks.push(seek(CUES, cues_position));
+        let mut seek_head = master_element(SEEK_HEAD, seeks);
+        // the rest of the reserved space is filled with a void element
+        let void_length = SEEK_HEAD_SPACE - seek_head.len() - 2;
+        seek_head.extend(element(VOID, &vec![0; void_length]));
+        self.writer.seek(SeekFrom::Start(segment_start))?;
+        self.writer.write_all(&seek_head)?;
+
+        // the segment size is written with 8 bytes, like the unknown size placeholder
+        let mut segment_size = (end - segment_start).to_be_bytes();
+        segment_size[0] = 0x01;
+        self.writer.seek(SeekFrom::Start(segment_size_position))?;
+        self.writer.write_all(&segment_size)?;
+
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<()> {
+        for packet in self.demuxer.push(bytes)? {
+            self.add_packet(packet)?
+        }
+        Ok(())
+    }
+
+    fn add_packet(&mut self, packet: Packet) -> Result<()> {
+        if self.first_pts.is_none() {
+            self.first_pts = Some(packet.pts)
+        }
+
+        if self.segment_positions.is_none() {
+            match packet.kind {
+                StreamKind::H264 if self.video_config.is_none() => {
+                    let nal_units = nal_units(&packet.data);
+                    let sps = nal_units.iter().find(|nal| nal[0] & 0x1f == 7);
+                    let pps = nal_units.iter().find(|nal| nal[0] & 0x1f == 8);
+                    if let (Some(sps), Some(pps)) = (sps, pps) {
+                        if sps.len() >= 4 {
+                            self.video_config = Some(VideoConfig {
+                                sps: sps.to_vec(),
+                                pps: pps.to_vec(),
+                            })
+                        }
+                    }
+                }
+                StreamKind::Aac if self.audio_config.is_none() => {
+                    if let Some((config, _)) = adts_frames(&packet.data)?.into_iter().next() {
+                        self.audio_config = Some(config)
+                    }
+                }
+                _ => (),
+            }
+            self.pending.push(packet);
+
+            // the header needs the codec configuration of both tracks. If a stream has no audio,
+            // the header is written after a few seconds of video without it
+            let waited_too_long = self.pending.len() > 1000;
+            if self.video_config.is_some() && (self.audio_config.is_some() || waited_too_long) {
+                self.start()?
+            }
+            return Ok(());
+        }
+
+        self.write_packet(packet)
+    }
+
+    /// Write the header and all packets which arrived before it.
+    fn start(&mut self) -> Result<()> {
+        self.write_header()?;
+        for packet in std::mem::take(&mut self.pending) {
+            self.write_packet(packet)?
+        }
+        Ok(())
+    }
+
+    fn timestamp(&self, pts: u64) -> u64 {
+        pts.saturating_sub(self.first_pts.unwrap_or_default()) / 90
+    }
+
+    fn write_packet(&mut self, packet: Packet) -> Result<()> {
+        let timestamp = self.timestamp(packet.pts);
+        match packet.kind {
+            StreamKind::H264 => {
+                let mut frame = vec![];
+                let mut keyframe = false;
+                for nal in nal_units(&packet.data) {
+                    match nal[0] & 0x1f {
+                        // access unit delimiters are not used in matroska
+                        9 => continue,
+                        5 => keyframe = true,
+                        _ => (),
+                    }
+                    frame.extend((nal.len() as u32).to_be_bytes());
+                    frame.extend_from_slice(nal)
+                }
+                if !frame.is_empty() {
+                    self.add_block(VIDEO_TRACK, timestamp, keyframe, &frame)?
+                }
+            }
+            StreamKind::Aac => {
+                if self.audio_config.is_none() {
+                    return Ok(());
+                }
+                for (i, (config, frame)) in adts_frames(&packet.data)?.into_iter().enumerate() {
+                    // every aac frame contains 1024 samples
+                    let offset = i as u64 * 1024 * 1000 / config.sample_rate() as u64;
+                    self.add_block(AUDIO_TRACK, timestamp + offset, true, frame)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        self.writer.write_all(&master_element(
+            EBML,
+            vec![
+                uint_element(EBML_VERSION, 1),
+                uint_element(EBML_READ_VERSION, 1),
+                uint_element(EBML_MAX_ID_LENGTH, 4),
+                uint_element(EBML_MAX_SIZE_LENGTH, 8),
+                string_element(DOC_TYPE, "matroska"),
+                uint_element(DOC_TYPE_VERSION, 4),
+                uint_element(DOC_TYPE_READ_VERSION, 2),
+            ],
+        ))?;
+
+        self.writer.write_all(&id_bytes(SEGMENT))?;
+        let segment_size_position = self.writer.stream_position()?;
+        self.writer.write_all(&UNKNOWN_SIZE)?;
+        let segment_start = self.writer.stream_position()?;
+        self.segment_positions = Some((segment_size_position, segment_start));
+        self.writer
+            .write_all(&element(VOID, &[0; SEEK_HEAD_SPACE - 2]))?;
+
+        let app = format!("crunchy-cli {}", env!("CARGO_PKG_VERSION"));
+        self.info_position = self.writer.stream_position()? - segment_start;
+        self.writer.write_all(&master_element(
+            INFO,
+            vec![
+                uint_element(TIMESTAMP_SCALE, 1_000_000),
+                float_element(DURATION, self.duration.as_millis() as f64),
+                string_element(MUXING_APP, &app),
+                string_element(WRITING_APP, &app),
+            ],
+        ))?;
+
+        let mut tracks = vec![];
+        if let Some(video_config) = &self.video_config {
+            tracks.push(master_element(
+                TRACK_ENTRY,
+                vec![
+                    uint_element(TRACK_NUMBER, VIDEO_TRACK),
+                    uint_element(TRACK_UID, VIDEO_TRACK),
+                    uint_element(TRACK_TYPE, 1),
+                    uint_element(FLAG_LACING, 0),
+                    string_element(LANGUAGE, "und"),
+                    string_element(LANGUAGE_BCP47, &self.audio_locale.to_string()),
+                    string_element(CODEC_ID, "V_MPEG4/ISO/AVC"),
+                    element(CODEC_PRIVATE, &video_config.avcc()),
+                    master_element(
+                        VIDEO,
+                        vec![
+                            uint_element(PIXEL_WIDTH, self.width),
+                            uint_element(PIXEL_HEIGHT, self.height),
+                        ],
+                    ),
+                ],
+            ))
+        }
+        if let Some(audio_config) = &self.audio_config {
+            tracks.push(master_element(
+                TRACK_ENTRY,
+                vec![
+                    uint_element(TRACK_NUMBER, AUDIO_TRACK),
+                    uint_element(TRACK_UID, AUDIO_TRACK),
+                    uint_element(TRACK_TYPE, 2),
+                    uint_element(FLAG_LACING, 0),
+                    string_element(LANGUAGE, "und"),
+                    string_element(LANGUAGE_BCP47, &self.audio_locale.to_string()),
+                    string_element(NAME, &self.audio_locale.to_human_readable()),
+                    string_element(CODEC_ID, "A_AAC"),
+                    element(CODEC_PRIVATE, &audio_config.audio_specific_config()),
+                    master_element(
+                        AUDIO,
+                        vec![
+                            float_element(SAMPLING_FREQUENCY, audio_config.sample_rate() as f64),
+                            uint_element(CHANNELS, audio_config.channels as u64),
+                        ],
+                    ),
+                ],
+            ))
+        }
+        for (i, subtitle) in self.subtitles.iter().enumerate() {
+            let track = AUDIO_TRACK + 1 + i as u64;
+            tracks.push(master_element(
+                TRACK_ENTRY,
+                vec![
+                    uint_element(TRACK_NUMBER, track),
+                    uint_element(TRACK_UID, track),
+                    uint_element(TRACK_TYPE, 0x11),
+                    uint_element(FLAG_LACING, 0),
+                    // only forced subtitles should be shown by default
+                    uint_element(FLAG_DEFAULT, subtitle.forced as u64),
+                    uint_element(FLAG_FORCED, subtitle.forced as u64),
+                    string_element(LANGUAGE, "und"),
+                    string_element(LANGUAGE_BCP47, &subtitle.locale.to_string()),
+                    string_element(NAME, &subtitle.name),
+                    string_element(CODEC_ID, "S_TEXT/ASS"),
+                    element(CODEC_PRIVATE, subtitle.header.as_bytes()),
+                ],
+            ))
+        }
+        self.tracks_position = self.writer.stream_position()? - segment_start;
+        self.writer.write_all(&master_element(TRACKS, tracks))?;
+
+        if !self.attachments.is_empty() {
+            let attached_files = self
+                .attachments
+                .iter()
+                .enumerate()
+                .map(|(i, attachment)| {
+                    master_element(
+                        ATTACHED_FILE,
+                        vec![
+                            string_element(FILE_NAME, &attachment.file_name),
+                            string_element(FILE_MIME_TYPE, &attachment.mime_type),
+                            element(FILE_DATA, &attachment.data),
+                            uint_element(FILE_UID, i as u64 + 1),
+                        ],
+                    )
+                })
+                .collect();
+            self.attachments_position = Some(self.writer.stream_position()? - segment_start);
+            self.writer
+                .write_all(&master_element(ATTACHMENTS, attached_files))?;
+        }
+
+        Ok(())
+    }
+
+    fn add_block(&mut self, track: u64, timestamp: u64, keyframe: bool, data: &[u8]) -> Result<()> {
+        let new_cluster = match &self.cluster {
+            None => true,
+            Some(cluster) => {
+                (track == VIDEO_TRACK && keyframe)
+                    || i16::try_from(timestamp as i64 - cluster.timestamp as i64).is_err()
+            }
+        };
+        if new_cluster {
+            self.flush_cluster(Some(timestamp))?;
+            self.cluster = Some(Cluster {
+                timestamp,
+                keyframe: track == VIDEO_TRACK && keyframe,
+                blocks: vec![],
+            })
+        }
+
+        let cluster = self.cluster.as_mut().unwrap();
+        let relative_timestamp = (timestamp as i64 - cluster.timestamp as i64) as i16;
+        cluster.blocks.extend(element(
+            SIMPLE_BLOCK,
+            &block(track, relative_timestamp, keyframe, data),
+        ));
+        Ok(())
+    }
+
+    /// Write the current cluster. Subtitle events which start before `until` (or all if [`None`])
+    /// are added to it first, or to additional clusters if they're too far away from it.
+    fn flush_cluster(&mut self, until: Option<u64>) -> Result<()> {
+        let Some(mut cluster) = self.cluster.take() else {
+            return Ok(());
+        };
+
+        for i in 0..self.subtitles.len() {
+            let track = AUDIO_TRACK + 1 + i as u64;
+            while let Some(event) = self.subtitles[i].events.front() {
+                if until.map_or(false, |until| event.start >= until) {
+                    break;
+                }
+                let event = self.subtitles[i].events.pop_front().unwrap();
+
+                let relative_timestamp =
+                    match i16::try_from(event.start as i64 - cluster.timestamp as i64) {
+                        Ok(relative_timestamp) => relative_timestamp,
+                        Err(_) => {
+                            self.write_cluster(cluster)?;
+                            cluster = Cluster {
+                                timestamp: event.start,
+                                keyframe: false,
+                                blocks: vec![],
+                            };
+                            0
+                        }
+                    };
+                cluster.blocks.extend(master_element(
+                    BLOCK_GROUP,
+                    vec![
+                        element(
+                            BLOCK,
+                            &block(track, relative_timestamp, false, event.data.as_bytes()),
+                        ),
+                        uint_element(BLOCK_DURATION, event.duration),
+                    ],
+                ))
+            }
+        }
+
+        self.write_cluster(cluster)
+    }
+
+    fn write_cluster(&mut self, cluster: Cluster) -> Result<()> {
+        let (_, segment_start) = self.segment_positions.unwrap();
+        if cluster.keyframe {
+            self.cues.push((
+                cluster.timestamp,
+                self.writer.stream_position()? - segment_start,
+            ))
+        }
+
+        let mut content = uint_element(TIMESTAMP, cluster.timestamp);
+        content.extend(cluster.blocks);
+        self.writer.write_all(&element(CLUSTER, &content))?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Write for MatroskaRemuxer<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.push(buf)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
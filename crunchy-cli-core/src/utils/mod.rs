@@ -1,8 +1,37 @@
+pub mod buffer;
+pub mod cancel;
+pub mod cdn;
+pub mod chapters;
 pub mod clap;
+pub mod client;
+pub mod config;
 pub mod context;
+pub mod cover;
+pub mod credentials;
+pub mod dns;
+pub mod download_archive;
+pub mod feed;
+pub mod filter;
+pub mod font;
 pub mod format;
+pub mod history;
+pub mod hook;
 pub mod locale;
 pub mod log;
+pub mod matroska;
+pub mod mpegts;
+pub mod nfo;
+pub mod notify;
 pub mod os;
 pub mod parse;
+pub mod pause;
+pub mod progress;
+pub mod rate_limit;
+pub mod resume;
+pub mod retry;
+pub mod sanitize;
 pub mod sort;
+pub mod table;
+pub mod subtitle;
+pub mod verify;
+pub mod writer;
@@ -0,0 +1,215 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+/// Elementary stream types which are supported by [`Demuxer`]. These are the ones Crunchyroll uses
+/// in its HLS streams.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StreamKind {
+    H264,
+    Aac,
+}
+
+/// A complete PES packet. For video this is one access unit (Annex B byte stream), for audio one or
+/// more ADTS frames.
+pub struct Packet {
+    pub kind: StreamKind,
+    /// Presentation timestamp in 90 kHz units.
+    pub pts: u64,
+    pub data: Vec<u8>,
+}
+
+struct PendingPes {
+    kind: StreamKind,
+    data: Vec<u8>,
+}
+
+/// Minimal MPEG transport stream demuxer. It extracts the PES packets of the H.264 and AAC streams
+/// of the first program, everything else is ignored.
+#[derive(Default)]
+pub struct Demuxer {
+    /// Bytes of an incomplete transport stream packet.
+    buf: Vec<u8>,
+    pmt_pid: Option<u16>,
+    streams: HashMap<u16, StreamKind>,
+    pending: HashMap<u16, PendingPes>,
+    /// Last timestamp of every stream, used for PES packets without timestamp.
+    last_pts: HashMap<u16, u64>,
+}
+
+impl Demuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next bytes of the transport stream. Returns all PES packets which were completed by
+    /// them.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<Packet>> {
+        self.buf.extend_from_slice(bytes);
+
+        let mut packets = vec![];
+        let mut pos = 0;
+        while self.buf.len() - pos >= PACKET_SIZE {
+            let ts_packet: Vec<u8> = self.buf[pos..pos + PACKET_SIZE].to_vec();
+            pos += PACKET_SIZE;
+            if ts_packet[0] != SYNC_BYTE {
+                bail!("Invalid transport stream packet (lost sync)")
+            }
+            if let Some(packet) = self.parse_ts_packet(&ts_packet)? {
+                packets.push(packet)
+            }
+        }
+        self.buf.drain(..pos);
+
+        Ok(packets)
+    }
+
+    /// Returns the PES packets which are still incomplete at the end of the stream.
+    pub fn finish(&mut self) -> Result<Vec<Packet>> {
+        let mut packets = vec![];
+        let pids: Vec<u16> = self.pending.keys().copied().collect();
+        for pid in pids {
+            let pes = self.pending.remove(&pid).unwrap();
+            if let Some(packet) = self.parse_pes(pid, pes)? {
+                packets.push(packet)
+            }
+        }
+        packets.sort_by_key(|p| p.pts);
+        Ok(packets)
+    }
+
+    fn parse_ts_packet(&mut self, ts_packet: &[u8]) -> Result<Option<Packet>> {
+        let payload_unit_start = ts_packet[1] & 0x40 != 0;
+        let pid = ((ts_packet[1] as u16 & 0x1f) << 8) | ts_packet[2] as u16;
+        let adaptation_field_control = (ts_packet[3] >> 4) & 0x03;
+
+        let mut offset = 4;
+        if adaptation_field_control & 0x02 != 0 {
+            offset += 1 + ts_packet[4] as usize
+        }
+        if adaptation_field_control & 0x01 == 0 || offset >= PACKET_SIZE {
+            return Ok(None);
+        }
+        let payload = &ts_packet[offset..];
+
+        if pid == 0 {
+            if payload_unit_start {
+                self.parse_pat(payload)
+            }
+            return Ok(None);
+        } else if Some(pid) == self.pmt_pid {
+            if payload_unit_start {
+                self.parse_pmt(payload)
+            }
+            return Ok(None);
+        }
+
+        let Some(kind) = self.streams.get(&pid).copied() else {
+            return Ok(None);
+        };
+        if payload_unit_start {
+            let previous = self.pending.insert(
+                pid,
+                PendingPes {
+                    kind,
+                    data: payload.to_vec(),
+                },
+            );
+            if let Some(previous) = previous {
+                return self.parse_pes(pid, previous);
+            }
+        } else if let Some(pes) = self.pending.get_mut(&pid) {
+            pes.data.extend_from_slice(payload)
+        }
+        Ok(None)
+    }
+
+    /// The PSI section of a payload which starts with a pointer field.
+    fn section(payload: &[u8]) -> Option<&[u8]> {
+        let start = 1 + *payload.first()? as usize;
+        let section = payload.get(start..)?;
+        let length = ((*section.get(1)? as usize & 0x0f) << 8) | *section.get(2)? as usize;
+        // the section ends with a 4 byte crc
+        section.get(..3 + length).filter(|_| length >= 4)
+    }
+
+    fn parse_pat(&mut self, payload: &[u8]) {
+        let Some(section) = Self::section(payload) else {
+            return;
+        };
+        let Some(programs) = section.get(8..section.len() - 4) else {
+            return;
+        };
+        for program in programs.chunks_exact(4) {
+            let program_number = ((program[0] as u16) << 8) | program[1] as u16;
+            // program number 0 is the network information table
+            if program_number != 0 {
+                self.pmt_pid = Some(((program[2] as u16 & 0x1f) << 8) | program[3] as u16);
+                return;
+            }
+        }
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        let Some(section) = Self::section(payload) else {
+            return;
+        };
+        if section.len() < 16 {
+            return;
+        }
+        let program_info_length = ((section[10] as usize & 0x0f) << 8) | section[11] as usize;
+        let end = section.len() - 4;
+
+        let mut i = 12 + program_info_length;
+        while i + 5 <= end {
+            let stream_type = section[i];
+            let pid = ((section[i + 1] as u16 & 0x1f) << 8) | section[i + 2] as u16;
+            let es_info_length = ((section[i + 3] as usize & 0x0f) << 8) | section[i + 4] as usize;
+            match stream_type {
+                0x1b => {
+                    self.streams.insert(pid, StreamKind::H264);
+                }
+                0x0f => {
+                    self.streams.insert(pid, StreamKind::Aac);
+                }
+                _ => (),
+            }
+            i += 5 + es_info_length
+        }
+    }
+
+    fn parse_pes(&mut self, pid: u16, pes: PendingPes) -> Result<Option<Packet>> {
+        let data = pes.data;
+        if data.len() < 9 || data[..3] != [0, 0, 1] {
+            bail!("Invalid PES packet in stream {}", pid)
+        }
+        let has_pts = data[7] & 0x80 != 0;
+        let header_end = 9 + data[8] as usize;
+        if header_end > data.len() {
+            bail!("Invalid PES header in stream {}", pid)
+        }
+
+        let pts = if has_pts && data.len() >= 14 {
+            let pts = (((data[9] as u64 >> 1) & 0x07) << 30)
+                | ((data[10] as u64) << 22)
+                | ((data[11] as u64 >> 1) << 15)
+                | ((data[12] as u64) << 7)
+                | (data[13] as u64 >> 1);
+            self.last_pts.insert(pid, pts);
+            pts
+        } else if let Some(pts) = self.last_pts.get(&pid) {
+            *pts
+        } else {
+            // without any timestamp the packet cannot be placed
+            return Ok(None);
+        };
+
+        Ok(Some(Packet {
+            kind: pes.kind,
+            pts,
+            data: data[header_end..].to_vec(),
+        }))
+    }
+}
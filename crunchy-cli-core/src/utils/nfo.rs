@@ -0,0 +1,210 @@
+use crate::utils::context::Context;
+use crate::utils::format::{format_string, largest_image, Format};
+use crate::utils::os::OverwritePolicy;
+use crate::utils::sanitize::Sanitizer;
+use anyhow::Result;
+use crunchyroll_rs::{Media, MediaCollection, Series};
+use log::debug;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Write the nfo files for the given episode. Media centers like Kodi or Jellyfin read them to get
+/// the metadata of the video without scraping it themselves. The episode nfo is written next to
+/// `path` and the `tvshow.nfo` in the series directory (see [`series_directory`]) if it does not
+/// exist already. Movies, music videos and concerts only get their own nfo next to `path`. An
+/// existing nfo next to `path` is handled according to `overwrite_policy`.
+pub async fn write_nfo(
+    ctx: &Context,
+    output: &str,
+    path: &Path,
+    format: &Format,
+    overwrite_policy: OverwritePolicy,
+    sanitizer: &Sanitizer,
+) -> Result<()> {
+    let nfo = if let Some(artist) = &format.artist {
+        // music videos and concerts belong to no series
+        music_video_nfo(artist, format)
+    } else if format.movie {
+        // movies are no episodes, even if they're bundled as season of a series
+        movie_nfo(format)
+    } else {
+        episode_nfo(format)
+    };
+    match overwrite_policy.resolve(path.with_extension("nfo")) {
+        Some(nfo_path) => {
+            fs::write(&nfo_path, nfo)?;
+            debug!("Wrote nfo file {}", nfo_path.to_string_lossy())
+        }
+        None => debug!("Nfo file of {} already exists, skipping it", format.title),
+    }
+    if format.artist.is_some() || format.movie {
+        return Ok(());
+    }
+
+    // the tvshow nfo is shared by all episodes of the series, so it's written only once and not
+    // affected by the overwrite policy
+    let tvshow_nfo_path = series_directory(output, path, format, sanitizer).join("tvshow.nfo");
+    if !tvshow_nfo_path.exists() {
+        // movie listings are not series, they don't have a tvshow nfo
+        if let MediaCollection::Series(series) = ctx
+            .crunchy
+            .media_collection_from_id(&format.series_id)
+            .await?
+        {
+            fs::write(&tvshow_nfo_path, tvshow_nfo(&series))?;
+            debug!("Wrote nfo file {}", tvshow_nfo_path.to_string_lossy());
+        }
+    }
+
+    Ok(())
+}
+
+/// Directory of the series. If the output path contains a directory with the series name (e.g.
+/// `{series}/Season {season}/{title}.mkv`), this directory is used. Otherwise it's the directory
+/// in which the episode itself is stored.
+fn series_directory(output: &str, path: &Path, format: &Format, sanitizer: &Sanitizer) -> PathBuf {
+    let output_path = PathBuf::from(output);
+    let mut series_dir = None;
+    let mut current = PathBuf::new();
+    if let Some(parent) = output_path.parent() {
+        for component in parent.components() {
+            current.push(component);
+            if let Component::Normal(name) = component {
+                if name.to_string_lossy().contains("{series") {
+                    series_dir = Some(current.clone())
+                }
+            }
+        }
+    }
+
+    if let Some(series_dir) = series_dir {
+        PathBuf::from(format_string(
+            series_dir.to_string_lossy().to_string(),
+            format,
+            Some(sanitizer),
+        ))
+    } else {
+        path.parent().map_or(PathBuf::new(), |p| p.to_path_buf())
+    }
+}
+
+fn episode_nfo(format: &Format) -> String {
+    let mut nfo = vec![
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#.to_string(),
+        "<episodedetails>".to_string(),
+        format!("  <title>{}</title>", escape_xml(&format.title)),
+        format!(
+            "  <showtitle>{}</showtitle>",
+            escape_xml(&format.series_name)
+        ),
+        format!("  <season>{}</season>", format.season_number),
+        format!("  <episode>{}</episode>", format.number),
+        format!("  <plot>{}</plot>", escape_xml(&format.description)),
+        format!("  <runtime>{}</runtime>", format.duration.as_secs() / 60),
+    ];
+    if let Some(air_date) = &format.air_date {
+        nfo.push(format!("  <aired>{}</aired>", air_date.format("%F")))
+    }
+    if let Some(thumbnail) = &format.thumbnail {
+        nfo.push(format!("  <thumb>{}</thumb>", escape_xml(thumbnail)))
+    }
+    nfo.push(format!(
+        r#"  <uniqueid type="crunchyroll" default="true">{}</uniqueid>"#,
+        escape_xml(&format.id)
+    ));
+    nfo.push("</episodedetails>".to_string());
+
+    nfo.join("\n") + "\n"
+}
+
+fn movie_nfo(format: &Format) -> String {
+    let mut nfo = vec![
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#.to_string(),
+        "<movie>".to_string(),
+        format!("  <title>{}</title>", escape_xml(&format.title)),
+        format!("  <set>{}</set>", escape_xml(&format.series_name)),
+        format!("  <plot>{}</plot>", escape_xml(&format.description)),
+        format!("  <runtime>{}</runtime>", format.duration.as_secs() / 60),
+    ];
+    if let Some(release_year) = format.release_year {
+        nfo.push(format!("  <year>{}</year>", release_year))
+    }
+    if let Some(thumbnail) = &format.thumbnail {
+        nfo.push(format!("  <thumb>{}</thumb>", escape_xml(thumbnail)))
+    }
+    nfo.push(format!(
+        r#"  <uniqueid type="crunchyroll" default="true">{}</uniqueid>"#,
+        escape_xml(&format.id)
+    ));
+    nfo.push("</movie>".to_string());
+
+    nfo.join("\n") + "\n"
+}
+
+fn music_video_nfo(artist: &str, format: &Format) -> String {
+    // the title of the format is prefixed with the artist
+    let title = format
+        .title
+        .strip_prefix(&format!("{} – ", artist))
+        .unwrap_or(&format.title);
+    let mut nfo = vec![
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#.to_string(),
+        "<musicvideo>".to_string(),
+        format!("  <title>{}</title>", escape_xml(title)),
+        format!("  <artist>{}</artist>", escape_xml(artist)),
+        format!("  <plot>{}</plot>", escape_xml(&format.description)),
+        format!("  <runtime>{}</runtime>", format.duration.as_secs() / 60),
+    ];
+    if let Some(air_date) = &format.air_date {
+        nfo.push(format!(
+            "  <premiered>{}</premiered>",
+            air_date.format("%F")
+        ))
+    }
+    if let Some(thumbnail) = &format.thumbnail {
+        nfo.push(format!("  <thumb>{}</thumb>", escape_xml(thumbnail)))
+    }
+    nfo.push(format!(
+        r#"  <uniqueid type="crunchyroll" default="true">{}</uniqueid>"#,
+        escape_xml(&format.id)
+    ));
+    nfo.push("</musicvideo>".to_string());
+
+    nfo.join("\n") + "\n"
+}
+
+fn tvshow_nfo(series: &Media<Series>) -> String {
+    let mut nfo = vec![
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#.to_string(),
+        "<tvshow>".to_string(),
+        format!("  <title>{}</title>", escape_xml(&series.title)),
+        format!("  <plot>{}</plot>", escape_xml(&series.description)),
+    ];
+    if let Some(poster) = largest_image(&series.images.poster_tall) {
+        nfo.push(format!(
+            r#"  <thumb aspect="poster">{}</thumb>"#,
+            escape_xml(&poster)
+        ))
+    }
+    if let Some(fanart) = largest_image(&series.images.poster_wide) {
+        nfo.push(format!(
+            "  <fanart><thumb>{}</thumb></fanart>",
+            escape_xml(&fanart)
+        ))
+    }
+    nfo.push(format!(
+        r#"  <uniqueid type="crunchyroll" default="true">{}</uniqueid>"#,
+        escape_xml(&series.id)
+    ));
+    nfo.push("</tvshow>".to_string());
+
+    nfo.join("\n") + "\n"
+}
+
+pub fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
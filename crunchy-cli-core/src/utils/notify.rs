@@ -0,0 +1,161 @@
+use crate::utils::format::Format;
+use indicatif::HumanBytes;
+use log::{debug, warn};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification {
+    EpisodeFinished {
+        series: String,
+        season: u32,
+        episode: u32,
+        title: String,
+        path: PathBuf,
+        thumbnail: Option<String>,
+        /// Size of the output file in bytes. [`None`] if the output is not a regular file (e.g.
+        /// stdout).
+        size: Option<u64>,
+    },
+    BatchFinished {
+        episodes: usize,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Notification {
+    pub fn episode_finished(format: &Format, path: &Path) -> Self {
+        Notification::EpisodeFinished {
+            series: format.series_name.clone(),
+            season: format.season_number,
+            episode: format.number,
+            title: format.title.clone(),
+            path: path.to_path_buf(),
+            thumbnail: format.thumbnail.clone(),
+            size: path
+                .metadata()
+                .ok()
+                .filter(|m| m.is_file())
+                .map(|m| m.len()),
+        }
+    }
+
+    /// Convert the notification to a discord webhook message with a rich embed. Returns [`None`] if
+    /// the notification should not be sent to discord.
+    fn discord_message(&self) -> Option<Value> {
+        let embed = match self {
+            Notification::EpisodeFinished {
+                series,
+                season,
+                episode,
+                title,
+                path,
+                thumbnail,
+                size,
+            } => {
+                let mut fields = vec![
+                    json!({"name": "Series", "value": series, "inline": true}),
+                    json!({"name": "Episode", "value": format!("S{:02}E{:02}", season, episode), "inline": true}),
+                ];
+                if let Some(size) = size {
+                    fields.push(
+                        json!({"name": "Size", "value": HumanBytes(*size).to_string(), "inline": true}),
+                    )
+                }
+                fields.push(json!({"name": "File", "value": path.to_string_lossy()}));
+
+                let mut embed = json!({
+                    "title": title,
+                    "description": "Download finished",
+                    // crunchyroll orange
+                    "color": 0xf47521,
+                    "fields": fields,
+                });
+                if let Some(thumbnail) = thumbnail {
+                    embed["image"] = json!({ "url": thumbnail })
+                }
+                embed
+            }
+            Notification::BatchFinished { .. } => return None,
+            Notification::Error { message } => json!({
+                "title": "Download failed",
+                "description": message,
+                "color": 0xed4245,
+            }),
+        };
+
+        Some(json!({
+            "username": "crunchy-cli",
+            "embeds": [embed],
+        }))
+    }
+}
+
+#[derive(Clone)]
+enum Webhook {
+    /// Receives the plain json serialized [`Notification`].
+    Json(Url),
+    Discord(Url),
+}
+
+/// Sends [`Notification`]s to webhooks.
+#[derive(Clone)]
+pub struct Notifier {
+    client: Client,
+    webhooks: Vec<Webhook>,
+}
+
+impl Notifier {
+    /// Create a new notifier. Returns [`None`] if no webhook is given.
+    pub fn new(client: Client, webhook: Option<Url>, discord_webhook: Option<Url>) -> Option<Self> {
+        let webhooks: Vec<Webhook> = webhook
+            .map(Webhook::Json)
+            .into_iter()
+            .chain(discord_webhook.map(Webhook::Discord))
+            .collect();
+
+        if webhooks.is_empty() {
+            None
+        } else {
+            Some(Self { client, webhooks })
+        }
+    }
+
+    /// Send a notification. Failures are only logged, a unreachable webhook should never abort a
+    /// download.
+    pub async fn notify(&self, notification: Notification) {
+        debug!("Sending notification {:?}", notification);
+
+        for webhook in &self.webhooks {
+            let (url, body) = match webhook {
+                Webhook::Json(url) => (url, serde_json::to_string(&notification).unwrap()),
+                Webhook::Discord(url) => {
+                    let Some(message) = notification.discord_message() else {
+                        continue;
+                    };
+                    (url, message.to_string())
+                }
+            };
+
+            let result = self
+                .client
+                .post(url.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .body(body)
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!("Failed to send notification to webhook: {}", e)
+            }
+        }
+    }
+}
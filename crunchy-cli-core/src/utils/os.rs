@@ -1,6 +1,8 @@
+use anyhow::{bail, Result};
+use indicatif::HumanBytes;
 use log::debug;
 use std::io::ErrorKind;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::{env, io};
 use tempfile::{Builder, NamedTempFile};
@@ -19,6 +21,20 @@ pub fn has_ffmpeg() -> bool {
     }
 }
 
+pub fn has_ffprobe() -> bool {
+    if let Err(e) = Command::new("ffprobe").stderr(Stdio::null()).spawn() {
+        if ErrorKind::NotFound != e.kind() {
+            debug!(
+                "unknown error occurred while checking if ffprobe exists: {}",
+                e.kind()
+            )
+        }
+        false
+    } else {
+        true
+    }
+}
+
 /// Any tempfile should be created with this function. The prefix and directory of every file
 /// created with this method stays the same which is helpful to query all existing tempfiles and
 /// e.g. remove them in a case of ctrl-c. Having one function also good to prevent mistakes like
@@ -35,6 +51,35 @@ pub fn tempfile<S: AsRef<str>>(suffix: S) -> io::Result<NamedTempFile> {
     Ok(tempfile)
 }
 
+/// What happens if an output file already exists.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OverwritePolicy {
+    /// The existing file is replaced.
+    Overwrite,
+    /// The file is not written.
+    Skip,
+    /// The file is written to a new file with ` (1)`, ` (2)`, ... appended to its name.
+    #[default]
+    Rename,
+}
+
+impl OverwritePolicy {
+    /// The path the file should be written to, [`None`] if it should not be written at all.
+    pub fn resolve(&self, path: PathBuf) -> Option<PathBuf> {
+        match self {
+            OverwritePolicy::Overwrite => Some(path),
+            OverwritePolicy::Skip => {
+                if path.exists() && !is_special_file(&path) {
+                    None
+                } else {
+                    Some(path)
+                }
+            }
+            OverwritePolicy::Rename => Some(free_file(path)),
+        }
+    }
+}
+
 /// Check if the given path exists and rename it until the new (renamed) file does not exist.
 pub fn free_file(mut path: PathBuf) -> PathBuf {
     // if it's a special file does not rename it
@@ -58,8 +103,88 @@ pub fn free_file(mut path: PathBuf) -> PathBuf {
     path
 }
 
+/// Path of the file which is written while the given output file is downloaded. It's only renamed
+/// to the output file after it was completely written, so that interrupted downloads do not leave
+/// files behind which look complete.
+pub fn part_file<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut part = path.as_ref().as_os_str().to_os_string();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Check if there is enough free disk space to write `size` bytes to `path`. The path and its
+/// parent directories may not exist yet, so the free space of the first existing parent directory
+/// is checked.
+pub fn check_free_space<P: AsRef<Path>>(path: P, size: u64) -> Result<()> {
+    let dir = path
+        .as_ref()
+        .ancestors()
+        .skip(1)
+        .map(|p| {
+            if p.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                p
+            }
+        })
+        .find(|p| p.is_dir())
+        .unwrap_or_else(|| Path::new("."));
+    let available = fs2::available_space(dir)?;
+    debug!(
+        "Estimated download size is {}, {} are available in {}",
+        HumanBytes(size),
+        HumanBytes(available),
+        dir.to_string_lossy()
+    );
+
+    if size > available {
+        bail!(
+            "Not enough disk space in {} ({} needed, {} available)",
+            dir.to_string_lossy(),
+            HumanBytes(size),
+            HumanBytes(available)
+        )
+    }
+    Ok(())
+}
+
 /// Check if the given path is a special file. On Linux this is probably a pipe and on Windows
 /// ¯\_(ツ)_/¯
 pub fn is_special_file<P: AsRef<Path>>(path: P) -> bool {
     path.as_ref().exists() && !path.as_ref().is_file() && !path.as_ref().is_dir()
 }
+
+/// Maximum length of a full path. Windows limits paths to 260 characters (including the
+/// terminating null) unless long paths are enabled, other systems allow way more.
+const MAX_PATH_LENGTH: usize = if cfg!(windows) { 259 } else { 4095 };
+/// Maximum length of a single file or directory name in bytes, which applies to nearly every
+/// filesystem.
+const MAX_NAME_LENGTH: usize = 255;
+/// Room which is left for suffixes that are appended to the output path, like ` (1)` for renamed
+/// files, `.part` or `.<locale>.forced.ass` for subtitle files.
+const SUFFIX_RESERVE: usize = 20;
+
+/// By how many characters the given path (including [`SUFFIX_RESERVE`]) exceeds the platform
+/// limits, 0 if it fits. Relative paths are checked as if they were in the current directory.
+pub fn path_overflow(path: &Path) -> usize {
+    let absolute = env::current_dir().map_or(path.to_path_buf(), |dir| dir.join(path));
+    let mut overflow = (absolute.to_string_lossy().chars().count() + SUFFIX_RESERVE)
+        .saturating_sub(MAX_PATH_LENGTH);
+    if let Some(Component::Normal(name)) = path.components().last() {
+        overflow = overflow.max((name.len() + SUFFIX_RESERVE).saturating_sub(MAX_NAME_LENGTH))
+    }
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            overflow = overflow.max(name.len().saturating_sub(MAX_NAME_LENGTH))
+        }
+    }
+    overflow
+}
+
+/// Check if the error was caused by writing to a closed pipe, e.g. because the player which reads
+/// the output from stdout was closed.
+pub fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .map_or(false, |e| e.kind() == ErrorKind::BrokenPipe)
+}
@@ -1,14 +1,18 @@
 use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, NaiveDate, Utc};
 use crunchyroll_rs::media::Resolution;
 use crunchyroll_rs::{Crunchyroll, MediaCollection, UrlType};
 use log::debug;
 use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
 
 /// Define a filter, based on season and episode number to filter episodes / movies.
 /// If a struct instance equals the [`Default::default()`] it's considered that no filter is applied.
 /// If `from_*` is [`None`] they're set to [`u32::MIN`].
 /// If `to_*` is [`None`] they're set to [`u32::MAX`].
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct InnerUrlFilter {
     from_episode: Option<u32>,
     to_episode: Option<u32>,
@@ -16,34 +20,116 @@ pub struct InnerUrlFilter {
     to_season: Option<u32>,
 }
 
-#[derive(Debug, Default)]
+impl InnerUrlFilter {
+    fn is_season_valid(&self, season: u32) -> bool {
+        season >= self.from_season.unwrap_or(u32::MIN)
+            && season <= self.to_season.unwrap_or(u32::MAX)
+    }
+
+    /// If a season is given, the episode bounds only apply to the first / last season of the range,
+    /// so `S2E3-S3E5` contains every episode from season 2, episode 3 until season 3, episode 5.
+    /// Without a season, the episode bounds apply to every season.
+    fn is_episode_valid(&self, episode: u32, season: u32) -> bool {
+        let from_episode = self.from_episode.unwrap_or(u32::MIN);
+        let to_episode = self.to_episode.unwrap_or(u32::MAX);
+
+        let after_start = match self.from_season {
+            Some(from_season) => {
+                season > from_season || (season == from_season && episode >= from_episode)
+            }
+            None => episode >= from_episode,
+        };
+        let before_end = match self.to_season {
+            Some(to_season) => season < to_season || (season == to_season && episode <= to_episode),
+            None => episode <= to_episode,
+        };
+
+        self.is_season_valid(season) && after_start && before_end
+    }
+}
+
+/// Filter which seasons and episodes of an url are downloaded. Created by [`parse_filter`] or from
+/// the filter appended to an url (e.g. `[S1E1-5]`).
+#[derive(Clone, Debug, Default)]
 pub struct UrlFilter {
     inner: Vec<InnerUrlFilter>,
+    /// Filter which must be matched additionally, e.g. the one given via `--filter`.
+    and: Option<Box<UrlFilter>>,
 }
 
 impl UrlFilter {
-    pub fn is_season_valid(&self, season: u32) -> bool {
-        self.inner.iter().any(|f| {
-            let from_season = f.from_season.unwrap_or(u32::MIN);
-            let to_season = f.to_season.unwrap_or(u32::MAX);
+    /// Combine this filter with `other`. Seasons / episodes are only valid if they're valid for both
+    /// filters.
+    pub fn and(mut self, other: UrlFilter) -> UrlFilter {
+        self.and = Some(Box::new(match self.and.take() {
+            Some(and) => and.and(other),
+            None => other,
+        }));
+        self
+    }
 
-            season >= from_season && season <= to_season
-        })
+    pub fn is_season_valid(&self, season: u32) -> bool {
+        self.inner.iter().any(|f| f.is_season_valid(season))
+            && self
+                .and
+                .as_ref()
+                .map_or(true, |f| f.is_season_valid(season))
     }
 
     pub fn is_episode_valid(&self, episode: u32, season: u32) -> bool {
-        self.inner.iter().any(|f| {
-            let from_episode = f.from_episode.unwrap_or(u32::MIN);
-            let to_episode = f.to_episode.unwrap_or(u32::MAX);
-            let from_season = f.from_season.unwrap_or(u32::MIN);
-            let to_season = f.to_season.unwrap_or(u32::MAX);
-
-            episode >= from_episode
-                && episode <= to_episode
-                && season >= from_season
-                && season <= to_season
+        self.inner
+            .iter()
+            .any(|f| f.is_episode_valid(episode, season))
+            && self
+                .and
+                .as_ref()
+                .map_or(true, |f| f.is_episode_valid(episode, season))
+    }
+}
+
+/// Parse a season / episode filter. See [`parse_url`] for the syntax. An empty filter matches
+/// everything.
+pub fn parse_filter(filter: &str) -> Result<UrlFilter> {
+    let filter = filter.replace(' ', "");
+    let filter_regex = Regex::new(r"^((S(?P<from_season>\d+))?(E(?P<from_episode>\d+))?)(((?P<dash>-)((S(?P<to_season>\d+))?(E(?P<to_episode>\d+))?))|(?P<plus>\+))?$").unwrap();
+
+    let mut filters = vec![];
+
+    for part in filter.to_uppercase().split(',') {
+        let Some(capture) = filter_regex.captures(part) else {
+            bail!("Invalid filter '{}'", part)
+        };
+
+        let dash = capture.name("dash").is_some() || capture.name("plus").is_some();
+        let from_episode = capture
+            .name("from_episode")
+            .map_or(anyhow::Ok(None), |fe| Ok(Some(fe.as_str().parse()?)))?;
+        let to_episode = capture
+            .name("to_episode")
+            .map_or(anyhow::Ok(if dash { None } else { from_episode }), |te| {
+                Ok(Some(te.as_str().parse()?))
+            })?;
+        let from_season = capture
+            .name("from_season")
+            .map_or(anyhow::Ok(None), |fs| Ok(Some(fs.as_str().parse()?)))?;
+        let to_season = capture
+            .name("to_season")
+            .map_or(anyhow::Ok(if dash { None } else { from_season }), |ts| {
+                Ok(Some(ts.as_str().parse()?))
+            })?;
+
+        filters.push(InnerUrlFilter {
+            from_episode,
+            to_episode,
+            from_season,
+            to_season,
         })
     }
+
+    Ok(UrlFilter {
+        inner: filters,
+        and: None,
+    })
 }
 
 /// Parse a url and return all [`crunchyroll_rs::Media<crunchyroll_rs::Episode>`] &
@@ -59,7 +145,8 @@ impl UrlFilter {
 /// - `...[-S2]` - Download all seasons up to and including season 2.
 /// - `...[S3E4-]` - Download all episodes from and including season 3, episode 4.
 /// - `...[S1E4-S3]` - Download all episodes from and including season 1, episode 4, until andincluding season 3.
-/// - `...[S3,S5]` - Download episode 3 and 5.
+/// - `...[E10+]` - Download all episodes from and including episode 10 (same as `...[E10-]`).
+/// - `...[S3,S5]` - Download season 3 and 5.
 /// - `...[S1-S3,S4E2-S4E6]` - Download season 1 to 3 and episode 2 to episode 6 of season 4.
 
 /// In practice, it would look like this: `https://beta.crunchyroll.com/series/12345678/example[S1E5-S3E2]`.
@@ -82,38 +169,7 @@ pub async fn parse_url(
             "".to_string()
         };
 
-        let filter_regex = Regex::new(r"((S(?P<from_season>\d+))?(E(?P<from_episode>\d+))?)(((?P<dash>-)((S(?P<to_season>\d+))?(E(?P<to_episode>\d+))?))?)(,|$)").unwrap();
-
-        let mut filters = vec![];
-
-        for capture in filter_regex.captures_iter(&filter) {
-            let dash = capture.name("dash").is_some();
-            let from_episode = capture
-                .name("from_episode")
-                .map_or(anyhow::Ok(None), |fe| Ok(Some(fe.as_str().parse()?)))?;
-            let to_episode = capture
-                .name("to_episode")
-                .map_or(anyhow::Ok(if dash { None } else { from_episode }), |te| {
-                    Ok(Some(te.as_str().parse()?))
-                })?;
-            let from_season = capture
-                .name("from_season")
-                .map_or(anyhow::Ok(None), |fs| Ok(Some(fs.as_str().parse()?)))?;
-            let to_season = capture
-                .name("to_season")
-                .map_or(anyhow::Ok(if dash { None } else { from_season }), |ts| {
-                    Ok(Some(ts.as_str().parse()?))
-                })?;
-
-            filters.push(InnerUrlFilter {
-                from_episode,
-                to_episode,
-                from_season,
-                to_season,
-            })
-        }
-
-        let url_filter = UrlFilter { inner: filters };
+        let url_filter = parse_filter(&filter)?;
 
         debug!("Url filter: {:?}", url_filter);
 
@@ -133,6 +189,26 @@ pub async fn parse_url(
     Ok((media_collection, url_filter))
 }
 
+/// Music videos and concerts have their own url scheme and are not part of [`MediaCollection`].
+#[derive(Clone, Debug)]
+pub enum MusicUrl {
+    MusicVideo(String),
+    Concert(String),
+}
+
+/// Parse a music video or concert url (e.g.
+/// `https://www.crunchyroll.com/watch/musicvideo/MV2D9B6F45/...`). Returns [`None`] if the url
+/// isn't a music url.
+pub fn parse_music_url(url: &str) -> Option<MusicUrl> {
+    let re = Regex::new(r"^(?:https?://)?(?:www\.)?crunchyroll\.com/(?:[a-z]{2}(?:-[a-z]{2})?/)?(?:music|watch)/(?P<type>musicvideo|concert)/(?P<id>[A-Z0-9]+)").unwrap();
+    let captures = re.captures(url)?;
+    let id = captures.name("id").unwrap().as_str().to_string();
+    Some(match captures.name("type").unwrap().as_str() {
+        "concert" => MusicUrl::Concert(id),
+        _ => MusicUrl::MusicVideo(id),
+    })
+}
+
 /// Parse a resolution given as a [`String`] to a [`crunchyroll_rs::media::Resolution`].
 pub fn parse_resolution(mut resolution: String) -> Result<Resolution> {
     resolution = resolution.to_lowercase();
@@ -168,3 +244,136 @@ pub fn parse_resolution(mut resolution: String) -> Result<Resolution> {
         bail!("Could not parse resolution")
     }
 }
+
+/// Parse a size given as a [`String`] to bytes. The size can either be given as plain bytes
+/// (e.g. `1048576`) or with a binary unit suffix (`K`, `M` or `G`, e.g. `1M` or `500K`).
+pub fn parse_size(mut size: String) -> Result<u64> {
+    size = size.trim().to_uppercase();
+    // allow suffixes like 'MB' or 'MiB' too
+    size = size.trim_end_matches('B').trim_end_matches('I').to_string();
+
+    let (number, multiplier) = if let Some(number) = size.strip_suffix('K') {
+        (number, 1024)
+    } else if let Some(number) = size.strip_suffix('M') {
+        (number, 1024 * 1024)
+    } else if let Some(number) = size.strip_suffix('G') {
+        (number, 1024 * 1024 * 1024)
+    } else {
+        (size.as_str(), 1)
+    };
+
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Could not parse size"))?;
+    if number < 0f64 {
+        bail!("Size must not be negative")
+    }
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parse a bandwidth given as a [`String`] to bits per second. The bandwidth can either be given
+/// as plain bits (e.g. `5000000`) or with a decimal unit suffix (`k`, `M` or `G`, e.g. `5M`).
+pub fn parse_bandwidth(mut bandwidth: String) -> Result<u64> {
+    bandwidth = bandwidth.trim().to_uppercase();
+    // allow suffixes like 'Mbit' or 'Mbps' too
+    bandwidth = bandwidth
+        .trim_end_matches("BIT")
+        .trim_end_matches("BPS")
+        .to_string();
+
+    let (number, multiplier) = if let Some(number) = bandwidth.strip_suffix('K') {
+        (number, 1000)
+    } else if let Some(number) = bandwidth.strip_suffix('M') {
+        (number, 1000 * 1000)
+    } else if let Some(number) = bandwidth.strip_suffix('G') {
+        (number, 1000 * 1000 * 1000)
+    } else {
+        (bandwidth.as_str(), 1)
+    };
+
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Could not parse bandwidth"))?;
+    if number < 0f64 {
+        bail!("Bandwidth must not be negative")
+    }
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parse a duration given as a [`String`]. The duration can either be given as plain seconds
+/// (e.g. `90`) or with a unit suffix (`s`, `m`, `h` or `d`, e.g. `30m` or `2h`).
+pub fn parse_duration(mut duration: String) -> Result<Duration> {
+    duration = duration.trim().to_lowercase();
+
+    let (number, multiplier) = if let Some(number) = duration.strip_suffix('s') {
+        (number, 1)
+    } else if let Some(number) = duration.strip_suffix('m') {
+        (number, 60)
+    } else if let Some(number) = duration.strip_suffix('h') {
+        (number, 60 * 60)
+    } else if let Some(number) = duration.strip_suffix('d') {
+        (number, 60 * 60 * 24)
+    } else {
+        (duration.as_str(), 1)
+    };
+
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Could not parse duration"))?;
+    if number <= 0f64 {
+        bail!("Duration must be positive")
+    }
+
+    Ok(Duration::from_secs_f64(number * multiplier as f64))
+}
+
+/// Parse a date given as a [`String`]. The date can either be given as absolute date (`YYYY-MM-DD`,
+/// midnight UTC) or relative to now as duration (see [`parse_duration`], e.g. `7d` for a week ago).
+pub fn parse_date(date: String) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") {
+        return Ok(DateTime::from_utc(date.and_hms_opt(0, 0, 0).unwrap(), Utc));
+    }
+
+    let duration = parse_duration(date).map_err(|_| {
+        anyhow!("Could not parse date, expected YYYY-MM-DD or a duration (e.g. 7d)")
+    })?;
+    Ok(Utc::now() - chrono::Duration::from_std(duration)?)
+}
+
+/// Parse a batch file. Every non-empty line which doesn't start with `#` contains a url, optionally
+/// followed by additional arguments for this url (e.g. `https://... -r 720p -a en-US`). The lines are
+/// split like a shell would do, so arguments can be quoted.
+pub fn parse_batch_file(path: &Path) -> Result<Vec<Vec<String>>> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        anyhow!(
+            "Could not read batch file {}: {}",
+            path.to_string_lossy(),
+            e
+        )
+    })?;
+
+    let mut entries = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        entries.push(
+            shlex::split(line)
+                .ok_or_else(|| anyhow!("Invalid quoting in line {} of batch file", i + 1))?,
+        )
+    }
+
+    Ok(entries)
+}
+
+/// Split raw ffmpeg arguments like a shell would do, so that arguments containing whitespaces can be
+/// quoted (e.g. `-vf "scale=1280:-2, unsharp"`).
+pub fn parse_ffmpeg_args(args: &str) -> Result<Vec<String>> {
+    shlex::split(args).ok_or_else(|| anyhow!("Invalid quoting in ffmpeg arguments '{}'", args))
+}
@@ -0,0 +1,142 @@
+use anyhow::Result;
+use log::{debug, info};
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+struct PauseState {
+    /// When the current pause started, [`None`] if not paused.
+    since: Option<Instant>,
+    /// Duration of all finished pauses.
+    total: Duration,
+}
+
+/// Shared switch which suspends all segment downloads. Segments which are already being downloaded
+/// are finished, new ones are only started after the pause is over.
+#[derive(Clone, Debug, Default)]
+pub struct Pause {
+    state: Arc<Mutex<PauseState>>,
+    notify: Arc<Notify>,
+}
+
+impl Pause {
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.since.is_none() {
+            state.since = Some(Instant::now())
+        }
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(since) = state.since.take() {
+            state.total += since.elapsed();
+            drop(state);
+            self.notify.notify_waiters()
+        }
+    }
+
+    /// Pause if running, resume if paused. Returns if it's paused afterwards.
+    pub fn toggle(&self) -> bool {
+        if self.is_paused() {
+            self.resume();
+            false
+        } else {
+            self.pause();
+            true
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().since.is_some()
+    }
+
+    /// Total time everything was paused, including the current pause. Progress clocks subtract
+    /// it so that speed and eta aren't distorted by pauses.
+    pub fn paused_time(&self) -> Duration {
+        let state = self.state.lock().unwrap();
+        state.total + state.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// Start measuring time which doesn't include pauses.
+    pub fn stopwatch(&self) -> Stopwatch {
+        Stopwatch {
+            start: Instant::now(),
+            paused_at_start: self.paused_time(),
+            pause: self.clone(),
+        }
+    }
+
+    /// Wait until the pause is over. Returns immediately if not paused.
+    pub async fn wait(&self) {
+        loop {
+            // the future must be created before the state is checked, otherwise a resume between
+            // the check and the await would be missed
+            let notified = self.notify.notified();
+            if !self.is_paused() {
+                return;
+            }
+            notified.await
+        }
+    }
+
+    /// Toggle the pause if SIGUSR1 is received (unix only) and, if `keyboard` is set and stdin is
+    /// a terminal, if 'p' is entered.
+    pub fn listen(&self, keyboard: bool) -> Result<()> {
+        #[cfg(unix)]
+        {
+            let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR1])?;
+            let pause = self.clone();
+            std::thread::spawn(move || {
+                for _ in signals.forever() {
+                    pause.toggle_logged()
+                }
+            });
+            debug!("Created SIGUSR1 handler");
+        }
+
+        // stdin is read line by line, reading single keys would require the raw mode of the
+        // terminal which disables ctrl-c
+        if keyboard && std::io::stdin().is_terminal() {
+            let pause = self.clone();
+            std::thread::spawn(move || {
+                for line in std::io::stdin().lines().map_while(|l| l.ok()) {
+                    if line.trim().eq_ignore_ascii_case("p") {
+                        pause.toggle_logged()
+                    }
+                }
+            });
+            debug!("Listening for pause key");
+        }
+
+        Ok(())
+    }
+
+    fn toggle_logged(&self) {
+        if self.toggle() {
+            info!("Paused, enter 'p' or send SIGUSR1 again to resume")
+        } else {
+            info!("Resumed")
+        }
+    }
+}
+
+/// Measures the time since it was created (see [`Pause::stopwatch`]) without the time everything
+/// was paused.
+pub struct Stopwatch {
+    start: Instant,
+    paused_at_start: Duration,
+    pause: Pause,
+}
+
+impl Stopwatch {
+    pub fn elapsed(&self) -> Duration {
+        let paused = self
+            .pause
+            .paused_time()
+            .saturating_sub(self.paused_at_start);
+        self.start.elapsed().saturating_sub(paused)
+    }
+}
@@ -0,0 +1,193 @@
+use indicatif::{FormattedDuration, HumanBytes};
+use serde::Serialize;
+use std::fmt;
+use std::io::{stderr, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProgressFormat {
+    Bar,
+    Json,
+    /// Nothing is printed, the progress is only reported to the progress callback of the
+    /// [`crate::Downloader`]. Cannot be selected on the command line.
+    Callback,
+}
+
+impl ToString for ProgressFormat {
+    fn to_string(&self) -> String {
+        match self {
+            ProgressFormat::Bar => "bar",
+            ProgressFormat::Json => "json",
+            ProgressFormat::Callback => "callback",
+        }
+        .to_string()
+    }
+}
+
+impl ProgressFormat {
+    pub fn parse(s: &str) -> Result<ProgressFormat, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "bar" => ProgressFormat::Bar,
+            "json" => ProgressFormat::Json,
+            _ => return Err(format!("'{}' is not a valid progress format", s)),
+        })
+    }
+}
+
+/// Callback which receives every progress event. Used by library users of [`crate::Downloader`].
+pub type ProgressCallback = Arc<dyn Fn(&ProgressEvent) + Send + Sync>;
+
+/// Events which are printed as newline-delimited json if [`ProgressFormat::Json`] is used.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent<'a> {
+    /// A segment was downloaded. `estimated_size` gets more accurate with every downloaded
+    /// segment since the real size of the not yet downloaded segments is unknown.
+    Segment {
+        name: Option<&'a str>,
+        segment: usize,
+        completed_segments: usize,
+        total_segments: usize,
+        downloaded_bytes: u64,
+        estimated_size: u64,
+        percent: f64,
+        /// Average download speed in bytes per second.
+        speed: u64,
+        /// Estimated remaining seconds, based on the smoothed download speed. [`None`] if no
+        /// speed could be calculated yet.
+        eta: Option<u64>,
+    },
+    MuxStarted {
+        output: &'a Path,
+    },
+    MuxFinished {
+        output: &'a Path,
+    },
+    EpisodeFinished {
+        output: &'a Path,
+        size: u64,
+        /// Elapsed seconds.
+        elapsed: f64,
+        /// Average download speed in bytes per second.
+        speed: u64,
+    },
+    BatchFinished {
+        episodes: usize,
+        size: u64,
+        /// Elapsed seconds.
+        elapsed: f64,
+        /// Average download speed in bytes per second.
+        speed: u64,
+    },
+}
+
+/// Print a progress event as single json line to stderr. Stdout is not used because it may
+/// already be occupied by the downloaded video (if the output is `-`).
+pub fn emit_progress(event: ProgressEvent) {
+    if let Ok(json) = serde_json::to_string(&event) {
+        let _ = writeln!(stderr().lock(), "{}", json);
+    }
+}
+
+/// Time constant of the exponential smoothing. Segments are downloaded by multiple threads and
+/// arrive in bursts, the speed of a single segment is way too jumpy to be shown directly.
+const SMOOTHING_TIME: f64 = 5.0;
+
+/// Exponentially smoothed transfer rate.
+#[derive(Clone, Debug)]
+pub struct SmoothedRate {
+    rate: Option<f64>,
+    pending: u64,
+    last_update: Instant,
+}
+
+impl Default for SmoothedRate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SmoothedRate {
+    pub fn new() -> Self {
+        Self {
+            rate: None,
+            pending: 0,
+            last_update: Instant::now(),
+        }
+    }
+
+    /// Record that `bytes` were transferred since the last update.
+    pub fn update(&mut self, bytes: u64) {
+        self.pending += bytes;
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_update).as_secs_f64();
+        // bytes which arrive (nearly) at the same time are collected, otherwise their rate would
+        // be nearly infinite
+        if elapsed < 0.1 {
+            return;
+        }
+
+        let current = self.pending as f64 / elapsed;
+        self.rate = Some(match self.rate {
+            Some(rate) => {
+                let weight = 1.0 - (-elapsed / SMOOTHING_TIME).exp();
+                rate + weight * (current - rate)
+            }
+            None => current,
+        });
+        self.pending = 0;
+        self.last_update = now
+    }
+
+    /// Restart the clock of the current measurement, e.g. because the transfer was paused and the
+    /// pause should not lower the rate.
+    pub fn restart_clock(&mut self) {
+        self.last_update = Instant::now()
+    }
+
+    /// Smoothed rate in bytes per second. [`None`] if not enough data was transferred yet.
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Estimated time until `remaining` bytes are transferred.
+    pub fn eta(&self, remaining: u64) -> Option<Duration> {
+        self.rate
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| Duration::from_secs_f64(remaining as f64 / rate))
+    }
+}
+
+/// Summary of a finished download (a single episode or a whole batch).
+#[derive(Clone, Debug)]
+pub struct TransferSummary {
+    pub size: u64,
+    pub elapsed: Duration,
+}
+
+impl TransferSummary {
+    /// Average speed in bytes per second.
+    pub fn speed(&self) -> u64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            (self.size as f64 / secs) as u64
+        } else {
+            0
+        }
+    }
+}
+
+impl fmt::Display for TransferSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in {} ({}/s)",
+            HumanBytes(self.size),
+            FormattedDuration(self.elapsed),
+            HumanBytes(self.speed())
+        )
+    }
+}
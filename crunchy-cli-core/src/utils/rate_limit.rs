@@ -0,0 +1,57 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct RateLimiterState {
+    /// Bytes which can be consumed without waiting. May be negative if more bytes were consumed
+    /// than were available, the next caller has to wait until this debt is paid off.
+    available: f64,
+    last_update: Instant,
+}
+
+/// Token bucket which limits the throughput of everything that consumes bytes through it. One
+/// instance is shared between all download tasks, so the limit applies to the sum of all of them.
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    state: Mutex<RateLimiterState>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            state: Mutex::new(RateLimiterState {
+                available: bytes_per_second as f64,
+                last_update: Instant::now(),
+            }),
+        }
+    }
+
+    /// Consume the given amount of bytes. Sleeps until the bytes are within the rate limit. A limit
+    /// of 0 bytes per second doesn't limit anything.
+    pub async fn acquire(&self, bytes: u64) {
+        if self.bytes_per_second == 0 {
+            return;
+        }
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(state.last_update).as_secs_f64();
+
+            // allow bursts of up to one second worth of bytes
+            state.available = (state.available + elapsed * self.bytes_per_second as f64)
+                .min(self.bytes_per_second as f64);
+            state.available -= bytes as f64;
+            state.last_update = now;
+
+            if state.available < 0f64 {
+                Duration::from_secs_f64(-state.available / self.bytes_per_second as f64)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::Result;
+use crunchyroll_rs::media::VariantData;
+use log::{debug, warn};
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Journal which records every segment that was written to an output file. The journal lives next
+/// to the output file while the download is running. If the download gets interrupted (network
+/// drop, ctrl-c, crash, ...) the journal stays on disk and the next download to the same output
+/// file continues with the first segment which was not written yet instead of starting over.
+pub struct ResumeJournal {
+    path: PathBuf,
+    file: File,
+
+    next_segment: usize,
+    written_bytes: u64,
+}
+
+impl ResumeJournal {
+    /// Path of the journal which belongs to the given output file.
+    pub fn path_for<P: AsRef<Path>>(output: P) -> PathBuf {
+        let output = output.as_ref();
+        output.with_file_name(format!(
+            ".{}.crunchy-resume",
+            output.file_name().unwrap_or_default().to_string_lossy()
+        ))
+    }
+
+    /// Check if an (unfinished) journal exists for the given output file.
+    pub fn exists_for<P: AsRef<Path>>(output: P) -> bool {
+        Self::path_for(output).is_file()
+    }
+
+    /// Open the journal for the given output file. If a journal already exists and was created for
+    /// the same stream (same media id, variant and number of segments), the download state is
+    /// loaded from it. Otherwise a new, empty journal is created.
+    pub fn open<P: AsRef<Path>>(
+        output: P,
+        media_id: &str,
+        variant_data: &VariantData,
+        segment_count: usize,
+    ) -> Result<Self> {
+        let path = Self::path_for(&output);
+        let header = stream_identifier(media_id, variant_data, segment_count);
+
+        let mut entries: Vec<u64> = vec![];
+        if path.is_file() {
+            let content = fs::read_to_string(&path)?;
+            let mut lines = content.lines();
+
+            if lines.next() == Some(header.as_str()) {
+                for line in lines {
+                    // an entry may be incomplete if the process was killed while writing it, in
+                    // this case all following entries are ignored too
+                    let Some((index, bytes)) = line.split_once(':') else {
+                        break;
+                    };
+                    let (Ok(index), Ok(bytes)) = (index.parse::<usize>(), bytes.parse::<u64>())
+                    else {
+                        break;
+                    };
+                    if index != entries.len() {
+                        break;
+                    }
+                    entries.push(bytes)
+                }
+                if entries.len() > segment_count {
+                    warn!(
+                        "Resume journal {} has more segments than the stream, starting from scratch",
+                        path.to_string_lossy()
+                    );
+                    entries.clear()
+                }
+
+                // the output file might have been deleted or truncated in the meantime, the journal
+                // is useless then
+                let output_len = fs::metadata(&output).map_or(0, |m| m.len());
+                if output_len < entries.iter().sum() {
+                    entries.clear()
+                }
+
+                debug!(
+                    "Loaded resume journal {} ({} segments / {} bytes already written)",
+                    path.to_string_lossy(),
+                    entries.len(),
+                    entries.iter().sum::<u64>()
+                )
+            } else {
+                // e.g. another episode which is written to the same output file
+                warn!(
+                    "Resume journal {} belongs to another stream, starting from scratch",
+                    path.to_string_lossy()
+                )
+            }
+        }
+
+        // rewrite the journal so that it only contains valid entries
+        let mut file = File::create(&path)?;
+        writeln!(file, "{}", header)?;
+        for (index, bytes) in entries.iter().enumerate() {
+            writeln!(file, "{}:{}", index, bytes)?;
+        }
+
+        Ok(Self {
+            path,
+            file,
+            next_segment: entries.len(),
+            written_bytes: entries.iter().sum(),
+        })
+    }
+
+    /// Index of the first segment which has not been written yet.
+    pub fn next_segment(&self) -> usize {
+        self.next_segment
+    }
+
+    /// Number of bytes which were already written to the output file. Everything after this
+    /// position in the output file is garbage of an interrupted download.
+    pub fn written_bytes(&self) -> u64 {
+        self.written_bytes
+    }
+
+    /// Record that the segment with the given index was written to the output.
+    pub fn record(&mut self, index: usize, bytes: u64) -> Result<()> {
+        writeln!(self.file, "{}:{}", index, bytes)?;
+        self.file.sync_data()?;
+        self.next_segment = index + 1;
+        self.written_bytes += bytes;
+        Ok(())
+    }
+
+    /// Remove the journal. Must be called after the download has finished successfully.
+    pub fn finish(self) -> Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)?;
+        debug!("Removed resume journal {}", self.path.to_string_lossy());
+        Ok(())
+    }
+}
+
+fn stream_identifier(media_id: &str, variant_data: &VariantData, segment_count: usize) -> String {
+    format!(
+        "{}@{}@{}@{:.2}@{}",
+        media_id, variant_data.resolution, variant_data.bandwidth, variant_data.fps, segment_count
+    )
+}
@@ -0,0 +1,98 @@
+use chrono::{DateTime, Utc};
+use log::warn;
+use rand::Rng;
+use reqwest::{Response, StatusCode};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Describes how often and with which delay failed requests are retried. The delay grows
+/// exponentially with every attempt and is randomized (jitter) so that multiple failing tasks do
+/// not retry at the exact same time.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// How often a single request may be rate limited before it fails. Rate limited requests
+    /// don't count as retry since the server explicitly asks to try again later.
+    pub max_rate_limited: u32,
+    /// Upper bound of the wait a `Retry-After` header can request, so a misbehaving server can't
+    /// stall all downloads indefinitely.
+    pub max_rate_limit_wait: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_rate_limited: 20,
+            max_rate_limit_wait: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the given retry attempt (starting at 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        // equal jitter, anything between half and the full exponential delay
+        exponential.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// Pause which is shared between all download tasks. If one task gets rate limited by Crunchyroll
+/// (HTTP 429 or 420), every task waits before its next request instead of hammering the server
+/// further.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    paused_until: Mutex<Option<Instant>>,
+}
+
+impl Backoff {
+    /// If the response signals that the client is rate limited, all tasks are paused for the time
+    /// the server requested with the `Retry-After` header (at most
+    /// [`RetryPolicy::max_rate_limit_wait`]), or for [`RetryPolicy::max_delay`] if the header is
+    /// missing. Returns true if the response was rate limited.
+    pub fn check(&self, response: &Response, policy: &RetryPolicy) -> bool {
+        // 420 is not an official status code but used by some cdns for rate limiting
+        if response.status() != StatusCode::TOO_MANY_REQUESTS && response.status().as_u16() != 420 {
+            return false;
+        }
+
+        let wait = retry_after(response)
+            .unwrap_or(policy.max_delay)
+            .min(policy.max_rate_limit_wait);
+        let until = Instant::now() + wait;
+        let mut paused_until = self.paused_until.lock().unwrap();
+        // only log if the pause gets extended, otherwise every task would log the same message
+        if paused_until.map_or(true, |p| p < until) {
+            warn!("Rate limited, waiting {}s", wait.as_secs().max(1));
+            *paused_until = Some(until)
+        }
+        true
+    }
+
+    /// Sleep until a pause, caused by a rate limited request, is over.
+    pub async fn wait(&self) {
+        let paused_until = *self.paused_until.lock().unwrap();
+        if let Some(until) = paused_until {
+            tokio::time::sleep_until(until.into()).await
+        }
+    }
+}
+
+/// Duration from the `Retry-After` header. The header contains either the seconds to wait or a
+/// http date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get("retry-after")?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
@@ -0,0 +1,139 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Names which are reserved for devices on Windows, with or without extension.
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Which characters are removed from values which are inserted into file names.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum SanitizeMode {
+    /// Everything which is not allowed on Windows (and SMB shares): `<>:"/\|?*`, control
+    /// characters, trailing dots and spaces and reserved device names like `CON`.
+    #[default]
+    Windows,
+    /// Only `/` and the null character, which are the only characters posix forbids.
+    Posix,
+    /// Only the path separators `/` and `\`.
+    Minimal,
+}
+
+impl SanitizeMode {
+    pub fn parse(s: &str) -> Result<SanitizeMode, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "windows" => SanitizeMode::Windows,
+            "posix" | "unix" => SanitizeMode::Posix,
+            "minimal" => SanitizeMode::Minimal,
+            _ => return Err(format!("'{}' is not a valid sanitize mode", s)),
+        })
+    }
+
+    fn is_invalid(&self, c: char) -> bool {
+        match self {
+            SanitizeMode::Windows => {
+                c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+            }
+            SanitizeMode::Posix => c == '/' || c == '\0',
+            SanitizeMode::Minimal => c == '/' || c == '\\',
+        }
+    }
+}
+
+/// Unicode normalization form of file names. macOS for example stores file names decomposed (NFD)
+/// while most other systems use the composed form (NFC), which makes names with accents or
+/// dakuten look the same but compare differently.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Normalization {
+    Nfc,
+    Nfd,
+    /// Like NFC but also replaces compatibility characters, e.g. full-width latin letters and
+    /// digits with their ascii counterparts.
+    Nfkc,
+    Nfkd,
+}
+
+impl Normalization {
+    pub fn parse(s: &str) -> Result<Normalization, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "nfc" => Normalization::Nfc,
+            "nfd" => Normalization::Nfd,
+            "nfkc" => Normalization::Nfkc,
+            "nfkd" => Normalization::Nfkd,
+            _ => return Err(format!("'{}' is not a valid unicode normalization form", s)),
+        })
+    }
+
+    fn normalize(&self, s: &str) -> String {
+        match self {
+            Normalization::Nfc => s.nfc().collect(),
+            Normalization::Nfd => s.nfd().collect(),
+            Normalization::Nfkc => s.nfkc().collect(),
+            Normalization::Nfkd => s.nfkd().collect(),
+        }
+    }
+}
+
+/// Makes values safe to use as (part of) a file name by replacing invalid characters with
+/// `replacement`, see [`SanitizeMode`]. Before that, the value is normalized and / or
+/// transliterated to ascii if requested.
+#[derive(Clone, Debug, Default)]
+pub struct Sanitizer {
+    pub mode: SanitizeMode,
+    pub replacement: String,
+    pub normalization: Option<Normalization>,
+    /// Transliterate all non-ascii characters. Kana become romaji, kanji and other characters
+    /// their closest ascii representation (kanji are transliterated by their chinese reading).
+    pub ascii: bool,
+}
+
+impl Sanitizer {
+    pub fn sanitize(&self, s: &str) -> String {
+        let mut value = match &self.normalization {
+            Some(normalization) => normalization.normalize(s),
+            None => s.to_string(),
+        };
+        if self.ascii {
+            // transliterated kanji are followed by a space, which would end up at the end of the
+            // file name otherwise
+            value = deunicode::deunicode(&value).trim().to_string()
+        }
+
+        let mut sanitized = String::with_capacity(value.len());
+        for c in value.chars() {
+            if self.mode.is_invalid(c) {
+                sanitized.push_str(&self.replacement)
+            } else {
+                sanitized.push(c)
+            }
+        }
+
+        if self.mode == SanitizeMode::Windows {
+            let trimmed_len = sanitized.trim_end_matches(['.', ' ']).len();
+            if trimmed_len != sanitized.len() {
+                sanitized.truncate(trimmed_len);
+                sanitized.push_str(&self.replacement)
+            }
+            let stem = sanitized.split('.').next().unwrap_or_default();
+            if WINDOWS_RESERVED.contains(&stem.to_uppercase().as_str()) {
+                sanitized.insert(stem.len(), '_')
+            }
+        }
+
+        // most filesystems limit file names to 255 bytes
+        if sanitized.len() > 255 {
+            let mut end = 255;
+            while !sanitized.is_char_boundary(end) {
+                end -= 1
+            }
+            sanitized.truncate(end)
+        }
+        sanitized
+    }
+
+    /// If the replacement itself is a valid file name part in the sanitize mode.
+    pub fn is_valid_replacement(&self) -> bool {
+        !self.replacement.chars().any(|c| self.mode.is_invalid(c))
+            && (self.mode != SanitizeMode::Windows || !self.replacement.ends_with(['.', ' ']))
+    }
+}
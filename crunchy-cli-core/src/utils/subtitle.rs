@@ -0,0 +1,356 @@
+use crunchyroll_rs::Locale;
+use regex::Regex;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SubtitleFormat {
+    Ass,
+    Srt,
+    Vtt,
+}
+
+impl SubtitleFormat {
+    pub fn parse(s: &str) -> Result<SubtitleFormat, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "ass" | "ssa" => SubtitleFormat::Ass,
+            "srt" => SubtitleFormat::Srt,
+            "vtt" | "webvtt" => SubtitleFormat::Vtt,
+            _ => return Err(format!("'{}' is not a valid subtitle format", s)),
+        })
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Ass => "ass",
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt => "vtt",
+        }
+    }
+}
+
+/// Convert a WebVTT subtitle to SubRip (srt). SubRip has no concept of cue positions, so the
+/// positioning settings of a cue (`line`, `align`) are translated to the `{\anX}` alignment tags
+/// which most players (and TVs) understand. Without them, captions which are placed at the top of
+/// the screen (e.g. to not cover on-screen text) would end up at the bottom. Formatting other than
+/// italic, bold and underline is removed since it isn't supported by srt.
+pub fn vtt_to_srt(vtt: &str) -> String {
+    let timing =
+        Regex::new(r"^\s*(?P<start>[\d:.]+)\s+-->\s+(?P<end>[\d:.]+)(?P<settings>.*)$").unwrap();
+    let tags = Regex::new(r"</?([^>]*)>").unwrap();
+
+    let mut srt = String::new();
+    let mut index = 0;
+    let mut lines = vtt.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let Some(captures) = timing.captures(line) else {
+            // header, cue identifiers, notes and style blocks
+            continue;
+        };
+
+        let mut text = vec![];
+        while let Some(line) = lines.next_if(|l| !l.trim().is_empty()) {
+            let line = tags.replace_all(line, |c: &regex::Captures| match &c[1] {
+                "i" | "b" | "u" => c[0].to_string(),
+                _ => String::new(),
+            });
+            text.push(
+                line.replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&nbsp;", " ")
+                    .replace("&amp;", "&"),
+            )
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        index += 1;
+        srt.push_str(&format!(
+            "{}\n{} --> {}\n{}{}\n\n",
+            index,
+            srt_timestamp(&captures["start"]),
+            srt_timestamp(&captures["end"]),
+            alignment_tag(&captures["settings"]),
+            text.join("\n")
+        ))
+    }
+
+    srt
+}
+
+/// Convert a vtt timestamp (`mm:ss.ttt` or `hh:mm:ss.ttt`) to a srt timestamp (`hh:mm:ss,ttt`).
+fn srt_timestamp(timestamp: &str) -> String {
+    let timestamp = if timestamp.matches(':').count() == 1 {
+        format!("00:{}", timestamp)
+    } else {
+        timestamp.to_string()
+    };
+    timestamp.replace('.', ",")
+}
+
+/// Translate vtt cue settings to a `{\anX}` tag. The numbers follow the numpad layout, 2 (bottom
+/// center) is the default position and doesn't need a tag.
+fn alignment_tag(settings: &str) -> String {
+    let mut top = false;
+    let mut horizontal = 2;
+
+    for setting in settings.split_whitespace() {
+        let Some((name, value)) = setting.split_once(':') else {
+            continue;
+        };
+        // values may have additional options separated by a comma (e.g. `line:10%,start`)
+        let value = value.split(',').next().unwrap_or_default();
+
+        match name {
+            "line" => {
+                top = if let Some(percent) = value.strip_suffix('%') {
+                    percent.parse::<f64>().map_or(false, |p| p < 50.0)
+                } else {
+                    // positive line numbers count from the top, negative ones from the bottom
+                    value.parse::<i32>().map_or(false, |l| l >= 0)
+                }
+            }
+            "align" => {
+                horizontal = match value {
+                    "start" | "left" => 1,
+                    "end" | "right" => 3,
+                    _ => 2,
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let alignment = if top { horizontal + 6 } else { horizontal };
+    if alignment == 2 {
+        String::new()
+    } else {
+        format!("{{\\an{}}}", alignment)
+    }
+}
+
+/// Offset (in milliseconds) which is applied to subtitle timestamps, either for all subtitles or
+/// only for the subtitles of one language.
+#[derive(Clone, Debug)]
+pub struct SubtitleOffset {
+    pub locale: Option<Locale>,
+    pub offset: i64,
+}
+
+impl SubtitleOffset {
+    /// Parse `<ms>` or `<locale>=<ms>`, e.g. `500` or `de-DE=-300`.
+    pub fn parse(s: &str) -> Result<SubtitleOffset, String> {
+        let (locale, offset) = match s.rsplit_once('=') {
+            Some((locale, offset)) => (Some(Locale::from(locale.to_string())), offset),
+            None => (None, s),
+        };
+        let Ok(offset) = offset.trim().parse::<i64>() else {
+            return Err(format!("'{}' is not a valid subtitle offset", s));
+        };
+        Ok(SubtitleOffset { locale, offset })
+    }
+
+    /// The offset of the given locale. An offset for this specific locale takes precedence over
+    /// one without locale.
+    pub fn for_locale(offsets: &[SubtitleOffset], locale: &Locale) -> i64 {
+        offsets
+            .iter()
+            .find(|o| o.locale.as_ref() == Some(locale))
+            .or_else(|| offsets.iter().find(|o| o.locale.is_none()))
+            .map_or(0, |o| o.offset)
+    }
+}
+
+/// Shift the cue timestamps of a WebVTT subtitle by `offset` milliseconds. Timestamps which would
+/// become negative are set to zero.
+pub fn shift_vtt(vtt: &str, offset: i64) -> String {
+    if offset == 0 {
+        return vtt.to_string();
+    }
+    let timing = Regex::new(
+        r"^(?P<indent>\s*)(?P<start>[\d:.]+)(?P<arrow>\s+-->\s+)(?P<end>[\d:.]+)(?P<settings>.*)$",
+    )
+    .unwrap();
+
+    let mut shifted = vec![];
+    for line in vtt.lines() {
+        let Some(captures) = timing.captures(line) else {
+            shifted.push(line.to_string());
+            continue;
+        };
+        let (Some(start), Some(end)) = (
+            parse_timestamp(&captures["start"]),
+            parse_timestamp(&captures["end"]),
+        ) else {
+            shifted.push(line.to_string());
+            continue;
+        };
+        shifted.push(format!(
+            "{}{}{}{}{}",
+            &captures["indent"],
+            vtt_timestamp(start + offset),
+            &captures["arrow"],
+            vtt_timestamp(end + offset),
+            &captures["settings"]
+        ))
+    }
+
+    let mut shifted = shifted.join("\n");
+    if vtt.ends_with('\n') {
+        shifted.push('\n')
+    }
+    shifted
+}
+
+/// Shift the start and end of all `Dialogue` events of an ass subtitle by `offset` milliseconds.
+/// Timestamps which would become negative are set to zero.
+pub fn shift_ass(ass: &str, offset: i64) -> String {
+    if offset == 0 {
+        return ass.to_string();
+    }
+    let dialogue = Regex::new(
+        r"^(?P<prefix>Dialogue:\s*[^,]*,)(?P<start>\d+:\d+:\d+\.\d+),(?P<end>\d+:\d+:\d+\.\d+),",
+    )
+    .unwrap();
+
+    ass.split('\n')
+        .map(|line| {
+            let Some(captures) = dialogue.captures(line) else {
+                return line.to_string();
+            };
+            let (Some(start), Some(end)) = (
+                parse_timestamp(&captures["start"]),
+                parse_timestamp(&captures["end"]),
+            ) else {
+                return line.to_string();
+            };
+            format!(
+                "{}{},{},{}",
+                &captures["prefix"],
+                ass_timestamp(start + offset),
+                ass_timestamp(end + offset),
+                &line[captures.get(0).unwrap().end()..]
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Parse a `hh:mm:ss.fff`, `mm:ss.fff` or `h:mm:ss.cc` timestamp to milliseconds.
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let (time, fraction) = timestamp.split_once('.').unwrap_or((timestamp, "0"));
+    let mut millis = 0;
+    for part in time.split(':') {
+        millis = millis * 60 + part.parse::<i64>().ok()?
+    }
+    // the fraction may have any precision, e.g. centiseconds in ass
+    let fraction = format!("{:0<3}", fraction);
+    Some(millis * 1000 + fraction.get(..3)?.parse::<i64>().ok()?)
+}
+
+fn vtt_timestamp(millis: i64) -> String {
+    let millis = millis.max(0);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+fn ass_timestamp(millis: i64) -> String {
+    let millis = millis.max(0);
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000 / 10
+    )
+}
+
+/// Overrides for the style of ass subtitles which are converted from vtt. The default conversion
+/// produces very small text, which is barely readable on TVs.
+#[derive(Clone, Debug, Default)]
+pub struct SubtitleStyle {
+    pub font: Option<String>,
+    pub size: Option<u32>,
+    /// Color in the ass format (`&HAABBGGRR`).
+    pub color: Option<String>,
+    /// Vertical margin in pixels.
+    pub margin: Option<u32>,
+}
+
+impl SubtitleStyle {
+    pub fn is_empty(&self) -> bool {
+        self.font.is_none() && self.size.is_none() && self.color.is_none() && self.margin.is_none()
+    }
+
+    /// Parse a `#RRGGBB` color to the ass color format.
+    pub fn parse_color(s: &str) -> Result<String, String> {
+        let hex = s.trim_start_matches('#');
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!(
+                "'{}' is not a valid color, use the '#RRGGBB' format",
+                s
+            ));
+        }
+        // ass stores colors in reversed order with a leading alpha value
+        Ok(format!("&H00{}{}{}", &hex[4..6], &hex[2..4], &hex[0..2]).to_uppercase())
+    }
+
+    /// Apply the overrides to every style definition of an ass subtitle. Only the styles are
+    /// changed, the events (and therefore the cues) stay as they are.
+    pub fn apply(&self, ass: &str) -> String {
+        let overrides = [
+            ("Fontname", self.font.clone()),
+            ("Fontsize", self.size.map(|s| s.to_string())),
+            ("PrimaryColour", self.color.clone()),
+            ("MarginV", self.margin.map(|m| m.to_string())),
+        ];
+
+        let mut styles_section = false;
+        let mut fields: Vec<String> = vec![];
+        ass.split('\n')
+            .map(|line| {
+                let trimmed = line.trim();
+                if trimmed.starts_with('[') {
+                    styles_section = trimmed.eq_ignore_ascii_case("[V4+ Styles]")
+                        || trimmed.eq_ignore_ascii_case("[V4 Styles]");
+                    return line.to_string();
+                }
+                if !styles_section {
+                    return line.to_string();
+                }
+
+                if let Some(format) = trimmed.strip_prefix("Format:") {
+                    fields = format.split(',').map(|f| f.trim().to_string()).collect();
+                } else if let Some(style) = trimmed.strip_prefix("Style:") {
+                    let mut values: Vec<String> = style
+                        .splitn(fields.len().max(1), ',')
+                        .map(|v| v.trim().to_string())
+                        .collect();
+                    for (name, value) in &overrides {
+                        let Some(value) = value else { continue };
+                        if let Some(i) = fields.iter().position(|f| f == name) {
+                            if let Some(v) = values.get_mut(i) {
+                                *v = value.clone()
+                            }
+                        }
+                    }
+                    return format!("Style: {}", values.join(","));
+                }
+                line.to_string()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// If a subtitle is a forced (signs & songs) subtitle. Crunchyroll delivers the subtitle in the
+/// language of a dub only with the signs and songs, since everything spoken is already in this
+/// language. The original Japanese audio has no such subtitles.
+pub fn is_forced_subtitle(subtitle: &Locale, audio: &Locale) -> bool {
+    subtitle == audio && audio != &Locale::ja_JP
+}
@@ -0,0 +1,44 @@
+use std::io::{stdout, Write};
+
+/// Print the rows as table with aligned columns to stdout. Every row must have as many columns as
+/// the header.
+pub fn print_table(header: &[&str], rows: &[Vec<String>]) -> std::io::Result<()> {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (i, column) in row.iter().enumerate() {
+            widths[i] = widths[i].max(column.chars().count())
+        }
+    }
+
+    let mut stdout = stdout().lock();
+    write_row(
+        &mut stdout,
+        &header
+            .iter()
+            .map(|h| h.to_string())
+            .collect::<Vec<String>>(),
+        &widths,
+    )?;
+    write_row(
+        &mut stdout,
+        &widths
+            .iter()
+            .map(|w| "-".repeat(*w))
+            .collect::<Vec<String>>(),
+        &widths,
+    )?;
+    for row in rows {
+        write_row(&mut stdout, row, &widths)?
+    }
+    Ok(())
+}
+
+fn write_row(writer: &mut impl Write, row: &[String], widths: &[usize]) -> std::io::Result<()> {
+    let line = row
+        .iter()
+        .zip(widths)
+        .map(|(column, width)| format!("{:<width$}", column, width = width))
+        .collect::<Vec<String>>()
+        .join("  ");
+    writeln!(writer, "{}", line.trim_end())
+}
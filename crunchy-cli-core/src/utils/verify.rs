@@ -0,0 +1,93 @@
+use crate::utils::format::Format;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: ProbeFormat,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    codec_type: String,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    duration: Option<String>,
+}
+
+/// Check if the downloaded file of the given format is complete. The file must not be empty, must be
+/// readable by ffprobe, must contain all video, audio and subtitle tracks of the format and must
+/// roughly be as long as the episode. If `audio_only` is set, no video track is required.
+/// Requires ffprobe.
+pub fn verify_output(path: &Path, format: &Format, audio_only: bool) -> Result<()> {
+    if path.metadata()?.len() == 0 {
+        bail!("file is empty")
+    }
+
+    let output = Command::new("ffprobe")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration:stream=codec_type",
+            "-of",
+            "json",
+        ])
+        .arg(path)
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "file cannot be read by ffprobe: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+    let probe: Probe = serde_json::from_slice(&output.stdout)?;
+
+    let count = |codec_type: &str| {
+        probe
+            .streams
+            .iter()
+            .filter(|s| s.codec_type == codec_type)
+            .count()
+    };
+    if !audio_only && count("video") < 1 {
+        bail!("file has no video track")
+    } else if count("audio") < 1 + format.additional_audio.len() {
+        bail!(
+            "file has {} audio tracks but should have {}",
+            count("audio"),
+            1 + format.additional_audio.len()
+        )
+    } else if count("subtitle") < format.subtitles.len() {
+        bail!(
+            "file has {} subtitle tracks but should have {}",
+            count("subtitle"),
+            format.subtitles.len()
+        )
+    }
+
+    let Some(duration) = probe.format.duration.and_then(|d| d.parse::<f64>().ok()) else {
+        bail!("file has no duration")
+    };
+    // the duration crunchyroll reports is not always exactly the duration of the stream, only
+    // larger differences are a sign of a truncated download
+    let tolerance = Duration::from_secs(10).max(format.duration / 50);
+    if Duration::from_secs_f64(duration) + tolerance < format.duration {
+        bail!(
+            "file is {:.0} seconds long but should be {} seconds long",
+            duration,
+            format.duration.as_secs()
+        )
+    }
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use crate::utils::resume::ResumeJournal;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use std::io::Write;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Writes downloaded segments to a synchronous writer (file, pipe, remuxer, ...) on a blocking
+/// thread. Writing to a slow disk, a network share or a pipe whose reader can't keep up would
+/// otherwise block the runtime thread and with it every download task scheduled on it.
+pub struct SegmentWriter<W: Write + Send + 'static> {
+    sender: mpsc::Sender<(usize, Bytes)>,
+    handle: Option<JoinHandle<Result<(W, Option<ResumeJournal>)>>>,
+}
+
+impl<W: Write + Send + 'static> SegmentWriter<W> {
+    /// Start the writer thread. Up to `capacity` segments are queued before [`SegmentWriter::write`]
+    /// waits for the writer. If `resume` is set, every segment is flushed and recorded in the
+    /// journal after it was written.
+    pub fn new(mut writer: W, mut resume: Option<ResumeJournal>, capacity: usize) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<(usize, Bytes)>(capacity.max(1));
+
+        let handle = tokio::task::spawn_blocking(move || {
+            while let Some((pos, bytes)) = receiver.blocking_recv() {
+                writer.write_all(&bytes)?;
+                if let Some(r) = &mut resume {
+                    writer.flush()?;
+                    r.record(pos, bytes.len() as u64)?
+                }
+            }
+            // the writer might be buffered (e.g. stdout), everything must be written before
+            // returning
+            writer.flush()?;
+            Ok((writer, resume))
+        });
+
+        Self {
+            sender,
+            handle: Some(handle),
+        }
+    }
+
+    /// Queue a segment for writing. Segments must be passed in the order they should be written.
+    pub async fn write(&mut self, pos: usize, bytes: Bytes) -> Result<()> {
+        if self.sender.send((pos, bytes)).await.is_ok() {
+            return Ok(());
+        }
+        // the writer thread only stops before the sender is dropped if writing failed
+        match self.handle.take() {
+            Some(handle) => {
+                handle.await??;
+                bail!("Writer stopped unexpectedly")
+            }
+            None => bail!("Writer already failed"),
+        }
+    }
+
+    /// Wait until all queued segments are written but keep the resume journal, used if the
+    /// download is stopped before all segments were downloaded.
+    pub async fn close(self) -> Result<()> {
+        let Self { sender, handle } = self;
+        drop(sender);
+        if let Some(handle) = handle {
+            handle.await??;
+        }
+        Ok(())
+    }
+
+    /// Wait until all queued segments are written and return the writer. The resume journal is
+    /// only removed here, if the writer is dropped without finishing (e.g. because a download
+    /// failed) it's kept so that the download can be resumed.
+    pub async fn finish(self) -> Result<W> {
+        let Self { sender, handle } = self;
+        drop(sender);
+        let Some(handle) = handle else {
+            bail!("Writer already failed")
+        };
+        let (writer, resume) = handle.await??;
+        if let Some(r) = resume {
+            r.finish()?
+        }
+        Ok(writer)
+    }
+}